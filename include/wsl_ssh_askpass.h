@@ -0,0 +1,34 @@
+/* C interface to wsl-ssh-askpass, built with:
+ *   cargo rustc --release --lib --features ffi --crate-type cdylib
+ * All strings are NUL-terminated UTF-16.
+ */
+#ifndef WSL_SSH_ASKPASS_H
+#define WSL_SSH_ASKPASS_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define ASKPASS_OK 0
+#define ASKPASS_CANCELLED 1
+#define ASKPASS_BUFFER_TOO_SMALL 2
+#define ASKPASS_INVALID_ARGUMENT 3
+#define ASKPASS_FAILED 4
+
+/* out_len: capacity of out in wchar units on entry, required length
+ * (including the terminator) on return. */
+int32_t askpass_prompt(const wchar_t *prompt, wchar_t *out, uint32_t *out_len);
+
+/* 1 = yes, 0 = no, < 0 = invalid argument */
+int32_t askpass_confirm(const wchar_t *prompt);
+
+/* 1 = removed, 0 = not cached, < 0 = invalid argument */
+int32_t askpass_clear(const wchar_t *key);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
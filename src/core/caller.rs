@@ -0,0 +1,288 @@
+//! Who is asking: walk the Windows process ancestry of this invocation to
+//! recognise the application that triggered the prompt.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::OnceLock;
+
+use windows::{
+    core::PWSTR,
+    Win32::Foundation::{CloseHandle, BOOL, FILETIME, HWND, LPARAM},
+    Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    },
+    Win32::System::Threading::{
+        GetCurrentProcessId, GetProcessTimes, OpenProcess, QueryFullProcessImageNameW,
+        PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+    },
+    Win32::UI::WindowsAndMessaging::{
+        EnumWindows, GetForegroundWindow, GetWindowTextLengthW, GetWindowThreadProcessId, IsIconic,
+        IsWindowVisible,
+    },
+};
+
+const CALLER_ENV: &str = "WSL_SSH_ASKPASS_CALLER";
+
+/// Deep enough for IDE -> wsl.exe -> interop host -> us, with room to spare.
+const MAX_ANCESTRY_DEPTH: usize = 16;
+
+/// JetBrains launcher executables and the product names shown in captions.
+const JETBRAINS_IDES: &[(&str, &str)] = &[
+    ("idea64.exe", "IntelliJ IDEA"),
+    ("pycharm64.exe", "PyCharm"),
+    ("goland64.exe", "GoLand"),
+    ("clion64.exe", "CLion"),
+    ("webstorm64.exe", "WebStorm"),
+    ("phpstorm64.exe", "PhpStorm"),
+    ("rubymine64.exe", "RubyMine"),
+    ("rider64.exe", "Rider"),
+    ("datagrip64.exe", "DataGrip"),
+    ("rustrover64.exe", "RustRover"),
+];
+
+/// Windows executables mapped to the application names used in policy rules.
+const KNOWN_APPS: &[(&str, &str)] = &[
+    ("git.exe", "git"),
+    ("scp.exe", "scp"),
+    ("sftp.exe", "sftp"),
+    ("rsync.exe", "rsync"),
+    ("ssh.exe", "ssh"),
+    ("code.exe", "vscode"),
+];
+
+/// A JetBrains IDE found among our ancestors, with its main window.
+pub(crate) struct Ide {
+    pub(crate) name: &'static str,
+    hwnd: isize,
+}
+
+impl Ide {
+    pub(crate) fn hwnd(&self) -> HWND {
+        HWND(self.hwnd as *mut _)
+    }
+}
+
+/// Process ids and lowercased executable names of our ancestors, nearest first.
+pub(crate) fn ancestry() -> &'static [(u32, String)] {
+    static ANCESTRY: OnceLock<Vec<(u32, String)>> = OnceLock::new();
+    ANCESTRY.get_or_init(|| unsafe { walk_ancestry() }.unwrap_or_default())
+}
+
+/// The JetBrains IDE that (indirectly) launched us, if any.
+pub(crate) fn jetbrains_ide() -> Option<&'static Ide> {
+    static IDE: OnceLock<Option<Ide>> = OnceLock::new();
+    IDE.get_or_init(|| {
+        ancestry().iter().find_map(|(pid, exe)| {
+            let (_, name) = JETBRAINS_IDES.iter().find(|(bin, _)| bin == exe)?;
+            let hwnd = main_window(*pid)?;
+            Some(Ide {
+                name,
+                hwnd: hwnd.0 as isize,
+            })
+        })
+    })
+    .as_ref()
+}
+
+/// The application a prompt is for.
+pub(crate) struct Application {
+    /// `git`, `scp`, `sftp`, `rsync`, `ssh`, `vscode`, `jetbrains`, or
+    /// whatever a WSL-side wrapper calls itself
+    pub(crate) name: String,
+    /// Recognised among our Windows ancestors, rather than named in
+    /// `WSL_SSH_ASKPASS_CALLER`, which whoever runs us can set to anything
+    pub(crate) verified: bool,
+}
+
+/// The application the prompt is for, or `None` when unrecognised.
+///
+/// Linux processes inside WSL are invisible to the Windows process tree, so
+/// WSL-side wrappers can name themselves with `WSL_SSH_ASKPASS_CALLER`
+/// (shared through `WSLENV`); otherwise the nearest recognised Windows
+/// ancestor decides. The ancestors of the daemon and of `http-serve` say
+/// nothing about their clients, so they recognise none.
+pub(crate) fn application() -> Option<Application> {
+    if crate::ipc::daemon::serving() || crate::ipc::http::serving() {
+        return None;
+    }
+    if let Ok(name) = env::var(CALLER_ENV) {
+        let name = name.trim().to_lowercase();
+        if !name.is_empty() {
+            return Some(Application {
+                name,
+                verified: false,
+            });
+        }
+    }
+    let name = ancestry().iter().find_map(|(_, exe)| {
+        if let Some((_, app)) = KNOWN_APPS.iter().find(|(bin, _)| bin == exe) {
+            Some(app.to_string())
+        } else if JETBRAINS_IDES.iter().any(|(bin, _)| bin == exe) {
+            Some("jetbrains".to_string())
+        } else {
+            None
+        }
+    })?;
+    Some(Application {
+        name,
+        verified: true,
+    })
+}
+
+/// Terminal emulators, and the console clients a classic console window is
+/// reported as belonging to.
+const TERMINALS: &[&str] = &[
+    "windowsterminal.exe",
+    "openconsole.exe",
+    "conhost.exe",
+    "wezterm-gui.exe",
+    "alacritty.exe",
+    "mintty.exe",
+    "tabby.exe",
+    "wsl.exe",
+    "bash.exe",
+];
+
+/// Whether the window in front is a terminal's, so someone is looking at a
+/// terminal and can answer there.
+pub(crate) fn terminal_in_front() -> bool {
+    unsafe {
+        let hwnd = GetForegroundWindow();
+        if hwnd.is_invalid() || !IsWindowVisible(hwnd).as_bool() || IsIconic(hwnd).as_bool() {
+            return false;
+        }
+        let mut pid = 0u32;
+        GetWindowThreadProcessId(hwnd, Some(&mut pid));
+        identity(pid).is_some_and(|id| is_terminal(&id.image))
+    }
+}
+
+/// Whether the executable at `image` is one of [`TERMINALS`].
+fn is_terminal(image: &str) -> bool {
+    let exe = image.rsplit(['\\', '/']).next().unwrap_or(image);
+    TERMINALS
+        .iter()
+        .any(|terminal| exe.eq_ignore_ascii_case(terminal))
+}
+
+/// A running process, told apart from any later one that reuses its id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Identity {
+    pub(crate) pid: u32,
+    /// Full path of the executable
+    pub(crate) image: String,
+    /// Creation time as a FILETIME
+    created: u64,
+}
+
+/// Who process `pid` is, or `None` once it has exited.
+pub(crate) fn identity(pid: u32) -> Option<Identity> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let mut image = vec![0u16; 32 * 1024];
+        let mut len = image.len() as u32;
+        let named = QueryFullProcessImageNameW(
+            process,
+            PROCESS_NAME_WIN32,
+            PWSTR(image.as_mut_ptr()),
+            &mut len,
+        );
+        let (mut created, mut exited) = (FILETIME::default(), FILETIME::default());
+        let (mut kernel, mut user) = (FILETIME::default(), FILETIME::default());
+        let timed = GetProcessTimes(process, &mut created, &mut exited, &mut kernel, &mut user);
+        let _ = CloseHandle(process);
+        named.ok()?;
+        timed.ok()?;
+        // A process that has exited can be opened while handles to it remain
+        if exited.dwLowDateTime != 0 || exited.dwHighDateTime != 0 {
+            return None;
+        }
+        Some(Identity {
+            pid,
+            image: String::from_utf16_lossy(&image[..len as usize]),
+            created: (u64::from(created.dwHighDateTime) << 32) | u64::from(created.dwLowDateTime),
+        })
+    }
+}
+
+/// Ids of the running processes whose lowercased executable name is `exe`.
+pub(crate) fn pids_of(exe: &str) -> Vec<u32> {
+    unsafe { process_table() }
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, (_, name))| name == exe)
+        .map(|(pid, _)| pid)
+        .collect()
+}
+
+/// Every process, mapped to its parent id and lowercased executable name.
+unsafe fn process_table() -> windows::core::Result<HashMap<u32, (u32, String)>> {
+    let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)?;
+    let mut processes: HashMap<u32, (u32, String)> = HashMap::new();
+    let mut entry = PROCESSENTRY32W {
+        dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+        ..Default::default()
+    };
+    let mut more = Process32FirstW(snapshot, &mut entry).is_ok();
+    while more {
+        let len = entry
+            .szExeFile
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(entry.szExeFile.len());
+        let exe = String::from_utf16_lossy(&entry.szExeFile[..len]).to_lowercase();
+        processes.insert(entry.th32ProcessID, (entry.th32ParentProcessID, exe));
+        more = Process32NextW(snapshot, &mut entry).is_ok();
+    }
+    let _ = CloseHandle(snapshot);
+    Ok(processes)
+}
+
+unsafe fn walk_ancestry() -> windows::core::Result<Vec<(u32, String)>> {
+    let processes = process_table()?;
+    let mut chain = Vec::new();
+    let mut pid = GetCurrentProcessId();
+    while chain.len() < MAX_ANCESTRY_DEPTH {
+        let Some((parent, _)) = processes.get(&pid) else {
+            break;
+        };
+        let Some((_, exe)) = processes.get(parent) else {
+            break;
+        };
+        // Parent ids can be recycled; stop rather than loop
+        if chain.iter().any(|(p, _)| p == parent) {
+            break;
+        }
+        chain.push((*parent, exe.clone()));
+        pid = *parent;
+    }
+    Ok(chain)
+}
+
+/// First visible, titled top-level window owned by `pid`.
+pub(crate) fn main_window(pid: u32) -> Option<HWND> {
+    struct Search {
+        pid: u32,
+        found: Option<HWND>,
+    }
+
+    unsafe extern "system" fn visit(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let search = &mut *(lparam.0 as *mut Search);
+        let mut owner = 0u32;
+        GetWindowThreadProcessId(hwnd, Some(&mut owner));
+        if owner == search.pid && IsWindowVisible(hwnd).as_bool() && GetWindowTextLengthW(hwnd) > 0
+        {
+            search.found = Some(hwnd);
+            return BOOL(0);
+        }
+        BOOL(1)
+    }
+
+    let mut search = Search { pid, found: None };
+    unsafe {
+        // Stopping early reports an error, so the result is ignored
+        let _ = EnumWindows(Some(visit), LPARAM(&mut search as *mut Search as isize));
+    }
+    search.found
+}
@@ -0,0 +1,157 @@
+//! Arithmetic on the timestamps kept in Credential Manager: TTLs, grace
+//! windows and deadlines.
+//!
+//! The stamps are Unix seconds from the wall clock, which can be turned
+//! back (by hand, by a dual boot, by a VM restored from a snapshot), so a
+//! stamp can be later than now. One a little in the future is taken as
+//! just written; beyond [`MAX_SKEW_SECS`] it proves nothing and counts as
+//! expired, so a clock turned back never stretches a window by more than
+//! that.
+//!
+//! Every reading of the wall clock as Unix seconds goes through
+//! [`now_secs`] and [`unix_secs`], which saturate rather than fail for a
+//! time before 1970.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How far in the future a stamp may be and still count as just written.
+pub(crate) const MAX_SKEW_SECS: u64 = 60;
+
+/// The wall clock now, in Unix seconds.
+pub fn now_secs() -> u64 {
+    unix_secs(SystemTime::now())
+}
+
+/// `time` in Unix seconds, or 0 for a time before 1970.
+pub(crate) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// How long ago `stamp` was at `now`, or `None` for a stamp too far in the
+/// future to trust.
+pub fn age(stamp: u64, now: u64) -> Option<u64> {
+    match now.checked_sub(stamp) {
+        Some(age) => Some(age),
+        None if stamp - now <= MAX_SKEW_SECS => Some(0),
+        None => None,
+    }
+}
+
+/// Seconds left at `now` of a `ttl` that started at `stamp`, or `None` once
+/// it has run out.
+pub(crate) fn remaining(stamp: u64, ttl: u64, now: u64) -> Option<u64> {
+    ttl.checked_sub(age(stamp, now)?).filter(|&left| left > 0)
+}
+
+/// Whether a `ttl` that started at `stamp` is still running at `now`.
+pub(crate) fn within(stamp: u64, ttl: u64, now: u64) -> bool {
+    remaining(stamp, ttl, now).is_some()
+}
+
+/// The time `secs` after `now`.
+pub(crate) fn deadline(now: u64, secs: u64) -> u64 {
+    now.saturating_add(secs)
+}
+
+/// Seconds from `now` to `deadline`, or `None` once it has passed or when it
+/// is further off than `longest`, the most anything sets one ahead: such a
+/// deadline comes from a clock turned back since.
+pub(crate) fn until(deadline: u64, now: u64, longest: u64) -> Option<u64> {
+    deadline
+        .checked_sub(now)
+        .filter(|&left| left > 0 && left <= longest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn windows() {
+        assert_eq!(age(1000, 1300), Some(300));
+        assert_eq!(age(1030, 1000), Some(0));
+        assert_eq!(age(5000, 1000), None);
+        assert_eq!(remaining(1000, 300, 1100), Some(200));
+        assert_eq!(remaining(1000, 300, 1300), None);
+        assert_eq!(remaining(1030, 300, 1000), Some(300));
+        assert!(!within(5000, 300, 1000));
+        assert_eq!(until(1600, 1000, 3600), Some(600));
+        assert_eq!(until(1000, 1000, 3600), None);
+        assert_eq!(until(9000, 1000, 3600), None);
+        assert_eq!(deadline(u64::MAX - 1, 10), u64::MAX);
+        assert_eq!(unix_secs(UNIX_EPOCH - std::time::Duration::from_secs(5)), 0);
+        assert_eq!(unix_secs(UNIX_EPOCH + std::time::Duration::from_secs(5)), 5);
+    }
+
+    proptest! {
+        #[test]
+        fn age_is_elapsed_time(stamp in any::<u64>(), now in any::<u64>()) {
+            match age(stamp, now) {
+                Some(0) => prop_assert!(stamp >= now && stamp - now <= MAX_SKEW_SECS),
+                Some(age) => prop_assert_eq!(stamp + age, now),
+                None => prop_assert!(stamp > now.saturating_add(MAX_SKEW_SECS)),
+            }
+        }
+
+        #[test]
+        fn a_window_never_outgrows_its_ttl(
+            stamp in any::<u64>(),
+            ttl in any::<u64>(),
+            now in any::<u64>(),
+        ) {
+            if let Some(left) = remaining(stamp, ttl, now) {
+                prop_assert!(left > 0 && left <= ttl);
+                // Nor runs past the stamp plus the ttl, skew aside
+                prop_assert!(now < stamp.saturating_add(ttl));
+            }
+        }
+
+        #[test]
+        fn a_turned_back_clock_expires_the_window(
+            now in any::<u64>(),
+            ahead in MAX_SKEW_SECS + 1..u64::MAX,
+            ttl in any::<u64>(),
+        ) {
+            let Some(stamp) = now.checked_add(ahead) else {
+                return Ok(());
+            };
+            prop_assert_eq!(remaining(stamp, ttl, now), None);
+        }
+
+        #[test]
+        fn a_window_only_shrinks(
+            stamp in any::<u64>(),
+            ttl in any::<u64>(),
+            elapsed in 0..u64::MAX / 2,
+            later in 0..u64::MAX / 2,
+        ) {
+            let now = stamp.saturating_add(elapsed);
+            let left = remaining(stamp, ttl, now);
+            let then = remaining(stamp, ttl, now.saturating_add(later));
+            prop_assert!(then <= left);
+        }
+
+        #[test]
+        fn a_window_closes_on_time(stamp in any::<u64>(), ttl in 1..u64::MAX) {
+            prop_assume!(stamp.checked_add(ttl).is_some());
+            prop_assert!(within(stamp, ttl, stamp + ttl - 1));
+            prop_assert!(!within(stamp, ttl, stamp + ttl));
+        }
+
+        #[test]
+        fn deadlines_stay_in_range(
+            now in any::<u64>(),
+            secs in any::<u64>(),
+            longest in any::<u64>(),
+        ) {
+            let end = deadline(now, secs);
+            prop_assert!(end >= now);
+            if let Some(left) = until(end, now, longest) {
+                prop_assert!(left > 0 && left <= longest && left <= secs);
+            }
+            prop_assert_eq!(until(end, now.saturating_add(secs), longest), None);
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! Decoding prompt text that did not arrive as UTF-8, and encoding answers
+//! for the distro.
+//!
+//! A distro running a legacy locale (`ja_JP.SJIS`, `de_DE.ISO-8859-1`) hands
+//! ssh's prompt over in that encoding. Valid UTF-8 is used as is; anything
+//! else is converted from the configured codepage, and failing that decoded
+//! lossily. Nothing here panics on malformed input.
+//!
+//! Answers are written as UTF-8 bytes unless such a locale is known to be in
+//! use, in which case they are converted to it when every character fits.
+
+use std::env;
+use std::ffi::OsStr;
+
+use windows::{
+    core::PCSTR,
+    Win32::Foundation::BOOL,
+    Win32::Globalization::{
+        GetACP, MultiByteToWideChar, WideCharToMultiByte, CP_UTF8, MB_ERR_INVALID_CHARS,
+        WC_NO_BEST_FIT_CHARS,
+    },
+};
+
+const CODEPAGE_ENV: &str = "WSL_SSH_ASKPASS_CODEPAGE";
+
+/// GB18030 covers all of Unicode and refuses the best-fit checks.
+const GB18030: u32 = 54936;
+
+/// Locale charsets (as in `LANG=ja_JP.SJIS`) and the matching Windows codepages.
+const CHARSETS: &[(&str, u32)] = &[
+    ("sjis", 932),
+    ("shiftjis", 932),
+    ("cp932", 932),
+    ("eucjp", 20932),
+    ("gbk", 936),
+    ("gb2312", 936),
+    ("gb18030", 54936),
+    ("big5", 950),
+    ("euckr", 51949),
+    ("iso88591", 28591),
+    ("latin1", 28591),
+    ("iso885915", 28605),
+    ("cp1252", 1252),
+    ("koi8r", 20866),
+    ("utf8", 65001),
+];
+
+/// Decode prompt bytes: UTF-8 when valid, else the configured codepage.
+pub fn decode(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => decode_with(bytes, configured()),
+    }
+}
+
+/// Encode an answer as bytes for ssh: UTF-8, unless `WSL_SSH_ASKPASS_CODEPAGE`
+/// or the shared locale names another charset that can hold all of it.
+/// Unlike prompts, the Windows ANSI codepage is never assumed.
+pub fn encode(text: &str) -> Vec<u8> {
+    match distro_codepage() {
+        Some(codepage) if codepage != CP_UTF8 => {
+            encode_with(text, codepage).unwrap_or_else(|| text.as_bytes().to_vec())
+        }
+        _ => text.as_bytes().to_vec(),
+    }
+}
+
+/// A command-line argument as text. Windows hands these over as UTF-16, so
+/// only unpaired surrogates can be wrong; they become U+FFFD.
+pub fn arg_to_string(arg: &OsStr) -> String {
+    arg.to_string_lossy().into_owned()
+}
+
+/// The codepage for non-UTF-8 prompts: `WSL_SSH_ASKPASS_CODEPAGE` (a number
+/// or a charset name), else the charset of the distro's `LC_ALL`,
+/// `LC_CTYPE` or `LANG` when shared through `WSLENV`, else the Windows ANSI
+/// codepage.
+fn configured() -> u32 {
+    distro_codepage().unwrap_or_else(|| unsafe { GetACP() })
+}
+
+/// `WSL_SSH_ASKPASS_CODEPAGE`, else the charset of the shared locale.
+fn distro_codepage() -> Option<u32> {
+    if let Some(codepage) = env::var(CODEPAGE_ENV).ok().and_then(|v| parse_codepage(&v)) {
+        return Some(codepage);
+    }
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|locale| locale_codepage(&locale))
+}
+
+/// `932`, `shift_jis` or `Latin-1` style codepage names.
+fn parse_codepage(value: &str) -> Option<u32> {
+    let value = value.trim();
+    if let Ok(number) = value.parse() {
+        return Some(number);
+    }
+    let name = value
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    CHARSETS
+        .iter()
+        .find(|(charset, _)| *charset == name)
+        .map(|(_, codepage)| *codepage)
+}
+
+/// The codepage for a POSIX locale such as `ja_JP.SJIS` or `de_DE.ISO-8859-1@euro`.
+fn locale_codepage(locale: &str) -> Option<u32> {
+    let charset = locale.split_once('.')?.1;
+    let charset = charset.split('@').next().unwrap_or(charset);
+    parse_codepage(charset)
+}
+
+fn decode_with(bytes: &[u8], codepage: u32) -> String {
+    convert(bytes, codepage).unwrap_or_else(|| String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn convert(bytes: &[u8], codepage: u32) -> Option<String> {
+    if bytes.is_empty() {
+        return Some(String::new());
+    }
+    unsafe {
+        let len = MultiByteToWideChar(codepage, MB_ERR_INVALID_CHARS, bytes, None);
+        if len <= 0 {
+            return None;
+        }
+        let mut wide = vec![0u16; len as usize];
+        let written = MultiByteToWideChar(codepage, MB_ERR_INVALID_CHARS, bytes, Some(&mut wide));
+        if written <= 0 {
+            return None;
+        }
+        wide.truncate(written as usize);
+        Some(String::from_utf16_lossy(&wide))
+    }
+}
+
+/// `text` in `codepage`, or `None` if any character would be replaced.
+fn encode_with(text: &str, codepage: u32) -> Option<Vec<u8>> {
+    if text.is_empty() {
+        return Some(Vec::new());
+    }
+    let wide: Vec<u16> = text.encode_utf16().collect();
+    let mut replaced = BOOL(0);
+    let (flags, replaced_ptr) = if codepage == GB18030 {
+        (0, None)
+    } else {
+        (WC_NO_BEST_FIT_CHARS, Some(&mut replaced as *mut BOOL))
+    };
+    unsafe {
+        let len = WideCharToMultiByte(codepage, flags, &wide, None, PCSTR::null(), replaced_ptr);
+        if len <= 0 || replaced.as_bool() {
+            return None;
+        }
+        let mut bytes = vec![0u8; len as usize];
+        let written = WideCharToMultiByte(
+            codepage,
+            flags,
+            &wide,
+            Some(&mut bytes),
+            PCSTR::null(),
+            replaced_ptr,
+        );
+        if written <= 0 || replaced.as_bool() {
+            return None;
+        }
+        bytes.truncate(written as usize);
+        Some(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_is_untouched() {
+        let prompt = "Enter passphrase for key '/home/me/.ssh/café':";
+        assert_eq!(decode(prompt.as_bytes()), prompt);
+    }
+
+    #[test]
+    fn latin1_prompt() {
+        let bytes = b"Enter passphrase for key '/home/me/.ssh/caf\xe9':";
+        assert_eq!(
+            decode_with(bytes, 28591),
+            "Enter passphrase for key '/home/me/.ssh/café':"
+        );
+    }
+
+    #[test]
+    fn shift_jis_prompt() {
+        // "パスフレーズ:" in Shift-JIS
+        let bytes = b"\x83p\x83X\x83t\x83\x8c\x81[\x83Y:";
+        assert_eq!(decode_with(bytes, 932), "パスフレーズ:");
+    }
+
+    #[test]
+    fn undecodable_bytes_never_panic() {
+        // A lone Shift-JIS lead byte fails conversion and falls back to lossy UTF-8
+        let decoded = decode_with(b"key \x83", 932);
+        assert!(decoded.starts_with("key "));
+        assert!(!decode_with(b"\xff\xfe\xfd", 65001).is_empty());
+    }
+
+    #[test]
+    fn answers_in_legacy_codepages() {
+        assert_eq!(encode_with("café", 28591).unwrap(), b"caf\xe9");
+        assert_eq!(
+            encode_with("パスフレーズ", 932).unwrap(),
+            b"\x83p\x83X\x83t\x83\x8c\x81[\x83Y"
+        );
+        // Latin-1 has no place for Japanese, so the caller keeps UTF-8
+        assert_eq!(encode_with("鍵", 28591), None);
+    }
+
+    #[test]
+    fn codepage_names() {
+        assert_eq!(parse_codepage("932"), Some(932));
+        assert_eq!(parse_codepage("Shift_JIS"), Some(932));
+        assert_eq!(parse_codepage("ISO-8859-1"), Some(28591));
+        assert_eq!(parse_codepage("klingon"), None);
+        assert_eq!(locale_codepage("ja_JP.SJIS"), Some(932));
+        assert_eq!(locale_codepage("de_DE.ISO-8859-15@euro"), Some(28605));
+        assert_eq!(locale_codepage("C"), None);
+    }
+}
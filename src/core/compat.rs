@@ -0,0 +1,150 @@
+//! Output and exit-status quirks of other askpass programs, so wrapper
+//! scripts written against them keep working when pointed at this one.
+//!
+//! Chosen with `WSL_SSH_ASKPASS_COMPAT=x11-ssh-askpass|ksshaskpass`, or by
+//! copying the executable under one of those names.
+
+use std::env;
+
+use crate::{PromptError, PromptKind};
+
+const COMPAT_ENV: &str = "WSL_SSH_ASKPASS_COMPAT";
+
+/// Exit status of `--passthrough` when a terminal is in front: nothing was
+/// shown, and the wrapper should ask in the terminal. No mode uses it for
+/// anything else.
+pub const PASSTHROUGH_EXIT: i32 = 4;
+
+/// Whose conventions the answer follows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compat {
+    /// The secret without a newline; exit 1 when cancelled, 2 on failure
+    #[default]
+    Native,
+    /// `x11-ssh-askpass`: the secret and a newline, an empty line to confirm;
+    /// exit 1 when cancelled, 3 on failure
+    X11,
+    /// `ksshaskpass`: the secret without a newline, nothing to confirm;
+    /// exit 1 when cancelled or on failure
+    Ksshaskpass,
+}
+
+impl Compat {
+    /// Look up a mode by program name; `native` selects the default.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "native" | "wsl-ssh-askpass" => Some(Compat::Native),
+            "x11-ssh-askpass" | "x11" => Some(Compat::X11),
+            "ksshaskpass" | "kde" => Some(Compat::Ksshaskpass),
+            _ => None,
+        }
+    }
+
+    /// The mode named by the environment, else by the executable's own name.
+    /// An unknown name is reported on stderr and the native mode used.
+    pub fn selected() -> Self {
+        if let Ok(name) = env::var(COMPAT_ENV) {
+            return Compat::from_name(&name).unwrap_or_else(|| {
+                eprintln!("wsl-ssh-askpass: unknown {} '{}'", COMPAT_ENV, name);
+                Compat::Native
+            });
+        }
+        env::current_exe()
+            .ok()
+            .and_then(|exe| Compat::from_name(&exe.file_stem()?.to_string_lossy()))
+            .unwrap_or_default()
+    }
+
+    /// What to write on stdout for `answer` to a `kind` prompt. `newline`
+    /// (`--newline`, `--no-newline` or the config file) overrides whether it
+    /// ends with a newline.
+    pub fn output(self, kind: PromptKind, answer: &str, newline: Option<bool>) -> String {
+        let mut output = match (self, kind) {
+            (Compat::Native, _) => answer.to_string(),
+            (Compat::X11, PromptKind::Confirm) => "\n".into(),
+            (Compat::X11, _) => format!("{}\n", answer),
+            (Compat::Ksshaskpass, PromptKind::Confirm) => String::new(),
+            (Compat::Ksshaskpass, _) => answer.to_string(),
+        };
+        match newline {
+            // A notice has no answer to end
+            _ if kind == PromptKind::Notify => {}
+            Some(true) if !output.ends_with('\n') => output.push('\n'),
+            Some(false) if output.ends_with('\n') => {
+                output.pop();
+            }
+            _ => {}
+        }
+        output
+    }
+
+    /// The exit status for a prompt that produced no answer.
+    pub fn exit_code(self, error: &PromptError) -> i32 {
+        match (self, error) {
+            (_, PromptError::Cancelled) => 1,
+            (Compat::Native, PromptError::Failed(_)) => 2,
+            (Compat::X11, PromptError::Failed(_)) => 3,
+            (Compat::Ksshaskpass, PromptError::Failed(_)) => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(Compat::from_name("x11-ssh-askpass"), Some(Compat::X11));
+        assert_eq!(Compat::from_name("KSSHASKPASS"), Some(Compat::Ksshaskpass));
+        assert_eq!(Compat::from_name("native"), Some(Compat::Native));
+        assert_eq!(Compat::from_name("gnome-ssh-askpass"), None);
+    }
+
+    #[test]
+    fn newlines() {
+        let entry = PromptKind::Entry;
+        assert_eq!(Compat::Native.output(entry, "secret", None), "secret");
+        assert_eq!(Compat::X11.output(entry, "secret", None), "secret\n");
+        assert_eq!(Compat::Ksshaskpass.output(entry, "secret", None), "secret");
+        assert_eq!(
+            Compat::Native.output(PromptKind::Confirm, "yes", None),
+            "yes"
+        );
+        assert_eq!(Compat::X11.output(PromptKind::Confirm, "yes", None), "\n");
+        assert_eq!(
+            Compat::Ksshaskpass.output(PromptKind::Confirm, "yes", None),
+            ""
+        );
+    }
+
+    #[test]
+    fn exit_codes() {
+        let failed = PromptError::Failed("no display".into());
+        assert_eq!(Compat::Native.exit_code(&failed), 2);
+        assert_eq!(Compat::X11.exit_code(&failed), 3);
+        assert_eq!(Compat::Ksshaskpass.exit_code(&failed), 1);
+        for compat in [Compat::Native, Compat::X11, Compat::Ksshaskpass] {
+            assert_eq!(compat.exit_code(&PromptError::Cancelled), 1);
+        }
+    }
+
+    #[test]
+    fn newline_overrides() {
+        let entry = PromptKind::Entry;
+        assert_eq!(
+            Compat::Native.output(entry, "secret", Some(true)),
+            "secret\n"
+        );
+        assert_eq!(Compat::X11.output(entry, "secret", Some(false)), "secret");
+        assert_eq!(Compat::X11.output(entry, "secret", Some(true)), "secret\n");
+        assert_eq!(
+            Compat::X11.output(PromptKind::Confirm, "yes", Some(false)),
+            ""
+        );
+        assert_eq!(
+            Compat::Native.output(PromptKind::Notify, "", Some(true)),
+            ""
+        );
+    }
+}
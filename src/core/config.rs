@@ -0,0 +1,907 @@
+//! Optional settings file at `%APPDATA%\wsl-ssh-askpass\config.toml`:
+//!
+//! ```toml
+//! passphrase_ttl = 86400      # seconds a cached key passphrase stays usable
+//! hello_window = 300          # seconds a Windows Hello verification is trusted
+//! persistence = "session"     # or "local-machine" (the default)
+//! require_vbs = "vbs"         # or "credential-guard", to keep secrets on disk at all
+//! hotkey = "Ctrl+Alt+P"       # servers: bring a pending prompt to the front
+//! hello_encryption = false    # encrypt cached secrets with a Windows Hello key
+//! hello_entropy = true        # also bind the DPAPI layer to the Hello key
+//! signed_images_only = true   # load only Microsoft-signed DLLs
+//! event_log = true            # log every secret handed out to the Event Log
+//! host_key_randomart = true   # draw new host keys' randomart
+//! remember_host_keys = true   # remember every host key answered "Yes"
+//! host_key_ttl = 604800       # seconds a remembered host key is trusted
+//! passphrase_dialog = "credui" # or "builtin" (the default)
+//! empty_passphrase = "pass"   # or "cancel" (the default)
+//! newline = true              # end the printed answer with a newline
+//! timeout = 120               # give up on a prompt nobody answers
+//! timeout_toast = false       # ...without a notification saying so
+//! debug_log = true            # write diagnostics to debug.log
+//! require_device = ["1050:0407"] # serve cached secrets only while it is plugged in
+//! proximity_device = "AA:BB:CC:DD:EE:FF" # ...or while this phone is nearby
+//! proximity_rssi = -70        # weakest signal, in dBm, that counts as nearby
+//! approval_url = "https://ntfy.sh/my-topic" # ask a phone before releasing...
+//! approval_keys = ["id_prod"] # ...these keys' cached secrets
+//! approval_timeout = 60       # seconds to wait for the answer
+//! snooze_keys = ["id_work*"]  # what `snooze` pre-authorizes
+//!
+//! [captions]
+//! passphrase = "Unlock SSH key"
+//!
+//! [app]
+//! git = "silent"              # or "confirm" or "prompt", by calling application
+//!
+//! [key.id_prod]
+//! require_typing = true       # never serve this key's passphrase from the cache
+//!
+//! [key."id_work*"]            # every key whose name or path matches the glob
+//! hello = "always"            # or "grace" (the default) or "never"
+//! persistence = "session"
+//! ttl = 28800                 # seconds, if sooner than the default for the key
+//!
+//! [distro.scratch]
+//! mode = "terminal"           # or "isolated", for prompts from this distro
+//!
+//! [distro.Ubuntu-test]
+//! mode = "isolated"
+//! ttl = 600                   # seconds its cached secrets stay usable
+//! ```
+//!
+//! Every setting is optional. A file that cannot be parsed, or holds an
+//! unknown key or an out-of-range value, is reported on stderr with line
+//! numbers and ignored, so a typo never locks anyone out; `config validate`
+//! reports the same problems and fails. `config set` edits the file in
+//! place, keeping its comments, and refuses a change that would not validate.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+/// Default Windows Hello grace window.
+const HELLO_WINDOW_SECS: u64 = 60 * 5; // 5 minutes
+/// Longest Windows Hello grace window accepted.
+const MAX_HELLO_WINDOW_SECS: u64 = 24 * 60 * 60;
+
+/// Where cached key passphrases are kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Persistence {
+    /// On this machine until deleted
+    #[default]
+    LocalMachine,
+    /// Until the user signs out of Windows
+    Session,
+}
+
+/// Which dialog asks for passphrases.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum PassphraseDialog {
+    /// Masked box with a reveal toggle and a Caps Lock warning
+    #[default]
+    Builtin,
+    /// The Windows credential prompt (CredUI)
+    Credui,
+}
+
+/// What an empty answer typed into the passphrase or password dialog does.
+/// It is never cached either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum EmptyPassphrase {
+    /// Taken as closing the dialog: usually Enter pressed too soon
+    #[default]
+    Cancel,
+    /// Handed to ssh as typed, for servers that accept an empty password
+    Pass,
+}
+
+/// Dialog window titles.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Captions {
+    pub(crate) passphrase: String,
+    pub(crate) host_key: String,
+    pub(crate) two_factor: String,
+    pub(crate) pin: String,
+    pub(crate) confirmation: String,
+    pub(crate) notification: String,
+}
+
+impl Default for Captions {
+    fn default() -> Self {
+        Captions {
+            passphrase: "SSH Passphrase".into(),
+            host_key: "SSH Host Verification".into(),
+            two_factor: "SSH Two-Factor Authentication".into(),
+            pin: "Smartcard PIN".into(),
+            confirmation: "SSH Confirmation".into(),
+            notification: "SSH Notification".into(),
+        }
+    }
+}
+
+/// What must be running for secrets to be kept on disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RequireVbs {
+    /// Nothing; local-machine persistence is always allowed
+    #[default]
+    Off,
+    /// Virtualization-based security
+    Vbs,
+    /// Credential Guard, which runs under VBS
+    CredentialGuard,
+}
+
+/// When a cached secret needs Windows Hello, under `[key.<name>]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum HelloRule {
+    /// A fresh verification every time
+    Always,
+    /// Within the grace window, or after a verification
+    Grace,
+    /// Never; the secret is served as soon as it is asked for
+    Never,
+}
+
+/// What prompts from one calling application take, under `[app]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum AppRule {
+    /// Cache hits are served without Windows Hello
+    Silent,
+    /// A fresh Hello verification every time, ignoring the grace window
+    Confirm,
+    /// The cache is ignored and the dialog always shown
+    Prompt,
+}
+
+impl AppRule {
+    pub(crate) fn parse(text: &str) -> Option<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "silent" => Some(AppRule::Silent),
+            "confirm" => Some(AppRule::Confirm),
+            "prompt" => Some(AppRule::Prompt),
+            _ => None,
+        }
+    }
+}
+
+/// How prompts from one distro are handled, under `[distro.<name>]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum DistroMode {
+    /// Asked on the terminal instead of in a dialog; nothing is cached
+    Terminal,
+    /// Cached under a namespace of the distro's own
+    Isolated,
+}
+
+/// Settings for the prompts of one distro, `[distro.<name>]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DistroSettings {
+    pub(crate) mode: DistroMode,
+    /// Seconds an isolated distro's cached secrets stay usable
+    pub(crate) ttl: Option<u64>,
+}
+
+/// Settings for the keys matching `[key.<name>]`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct KeySettings {
+    /// Always show the dialog, even when the passphrase is cached
+    pub(crate) require_typing: bool,
+    pub(crate) hello: Option<HelloRule>,
+    pub(crate) persistence: Option<Persistence>,
+    /// Seconds the cached secret stays usable, if sooner than the default for its kind
+    pub(crate) ttl: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    /// Seconds a cached key passphrase stays usable; unset keeps it until deleted
+    pub(crate) passphrase_ttl: Option<u64>,
+    hello_window: Option<u64>,
+    pub(crate) persistence: Persistence,
+    /// What must be running for local-machine persistence to be allowed
+    pub(crate) require_vbs: RequireVbs,
+    /// Global hotkey for `ps-serve` and `http-serve`, e.g. `Ctrl+Alt+P`
+    pub(crate) hotkey: Option<String>,
+    /// Store new secrets encrypted to a Windows Hello key credential
+    pub(crate) hello_encryption: bool,
+    /// Mix entropy from a Windows Hello key signature into the DPAPI layer
+    pub(crate) hello_entropy: bool,
+    /// Load only Microsoft-signed DLLs into the process
+    pub(crate) signed_images_only: bool,
+    /// Log every secret handed out to the Windows Application log
+    pub(crate) event_log: bool,
+    /// Show the randomart of a new host key beside its fingerprint
+    pub(crate) host_key_randomart: bool,
+    /// Remember host keys answered "Yes" as if "Yes, and remember" was chosen
+    pub(crate) remember_host_keys: bool,
+    /// Seconds a remembered host key is trusted; unset trusts it until deleted
+    pub(crate) host_key_ttl: Option<u64>,
+    pub(crate) passphrase_dialog: PassphraseDialog,
+    pub(crate) empty_passphrase: EmptyPassphrase,
+    /// End the printed answer with a newline; unset follows the compatibility mode
+    pub(crate) newline: Option<bool>,
+    /// Seconds a prompt waits for an answer; unset waits as long as it takes
+    pub(crate) timeout: Option<u64>,
+    /// Raise a notification when a prompt times out; unset means yes
+    timeout_toast: Option<bool>,
+    /// Write diagnostics to `debug.log` in the data directory
+    pub(crate) debug_log: bool,
+    /// USB ids (`1050:0407`) or `smartcard`, one of which must be present
+    /// for cached secrets to be served
+    pub(crate) require_device: Vec<String>,
+    /// Bluetooth address of the phone that must be nearby for the Hello
+    /// grace window to count
+    pub(crate) proximity_device: Option<String>,
+    /// Weakest signal from that phone, in dBm, taken as nearby; unset means -70
+    pub(crate) proximity_rssi: Option<i16>,
+    /// ntfy topic to which phone approval requests are sent
+    pub(crate) approval_url: Option<String>,
+    /// Keys whose cached secrets are only released once approved on the phone
+    pub(crate) approval_keys: Vec<String>,
+    /// Seconds to wait for an approval before refusing; unset means 60
+    pub(crate) approval_timeout: Option<u64>,
+    /// Keys whose cached secrets a `snooze` serves without Windows Hello
+    pub(crate) snooze_keys: Vec<String>,
+    pub(crate) captions: Captions,
+    /// Rules by calling application, e.g. `git`, `ssh` or `vscode`
+    app: BTreeMap<String, AppRule>,
+    /// Per-key settings by cache key, e.g. `id_ed25519`, or by a glob
+    /// over cache keys and key file paths, e.g. `id_work*`
+    key: BTreeMap<String, KeySettings>,
+    /// Settings by the distro a prompt comes from, e.g. `Ubuntu`
+    distro: BTreeMap<String, DistroSettings>,
+}
+
+impl Config {
+    /// Seconds a Windows Hello verification is trusted for.
+    pub(crate) fn hello_window_secs(&self) -> u64 {
+        self.hello_window.unwrap_or(HELLO_WINDOW_SECS)
+    }
+
+    /// The `[app]` rule for the calling application `app`.
+    pub(crate) fn app_rule(&self, app: &str) -> Option<AppRule> {
+        self.app
+            .iter()
+            .find_map(|(name, rule)| name.eq_ignore_ascii_case(app).then_some(*rule))
+    }
+
+    /// The `[distro.<name>]` settings for the distro `distro`.
+    pub(crate) fn distro_settings(&self, distro: &str) -> Option<DistroSettings> {
+        self.distro
+            .iter()
+            .find_map(|(name, settings)| name.eq_ignore_ascii_case(distro).then_some(*settings))
+    }
+
+    /// Whether a prompt that times out is announced with a notification.
+    pub(crate) fn timeout_toast(&self) -> bool {
+        self.timeout_toast.unwrap_or(true)
+    }
+
+    /// The settings for cache key `key`, whose key file is at `path` as
+    /// the prompt gave it, merged from every `[key.<name>]` that matches.
+    ///
+    /// A name matches the key, the key file's bare name (`id_prod` for
+    /// `id_prod#3c1f9a2e`, so it applies in every directory) or the path;
+    /// an isolated distro's namespace is left out. `*` and `?` are globs.
+    /// Each setting comes from the most specific entry that has it: an exact
+    /// name before any glob, then the longer glob. `require_typing` from
+    /// any entry holds.
+    pub(crate) fn key_settings(&self, key: &str, path: Option<&str>) -> KeySettings {
+        let bare = key.split_once('/').filter(|_| key.starts_with("distro:"));
+        let bare = bare.map_or(key, |(_, rest)| rest);
+        let candidates = [key, bare, crate::core::prompt::key_label(bare)];
+        let mut matching: Vec<(&String, &KeySettings)> = self
+            .key
+            .iter()
+            .filter(|(name, _)| {
+                candidates
+                    .iter()
+                    .chain(path.iter())
+                    .any(|candidate| glob_matches(name, candidate))
+            })
+            .collect();
+        let is_glob = |name: &str| name.contains(['*', '?']);
+        matching.sort_by_key(|(name, _)| (!is_glob(name), name.len()));
+        // Least specific first, so later entries overwrite
+        let mut merged = KeySettings::default();
+        for (_, settings) in matching {
+            merged.require_typing |= settings.require_typing;
+            merged.hello = settings.hello.or(merged.hello);
+            merged.persistence = settings.persistence.or(merged.persistence);
+            merged.ttl = settings.ttl.or(merged.ttl);
+        }
+        merged
+    }
+
+    /// Values that parse but make no sense, described against `text`.
+    fn problems(&self, text: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut report = |path: &[&str], message: String| {
+            problems.push(describe(line_of_value(text, path), &message));
+        };
+        if self.passphrase_ttl == Some(0) {
+            report(
+                &["passphrase_ttl"],
+                "passphrase_ttl must be at least 1 second".into(),
+            );
+        }
+        if self
+            .hello_window
+            .is_some_and(|secs| secs > MAX_HELLO_WINDOW_SECS)
+        {
+            report(
+                &["hello_window"],
+                format!(
+                    "hello_window must be at most {} seconds",
+                    MAX_HELLO_WINDOW_SECS
+                ),
+            );
+        }
+        if self.host_key_ttl == Some(0) {
+            report(
+                &["host_key_ttl"],
+                "host_key_ttl must be at least 1 second".into(),
+            );
+        }
+        for (name, settings) in &self.key {
+            if settings.ttl == Some(0) {
+                report(
+                    &["key", name, "ttl"],
+                    format!("key.{}.ttl must be at least 1 second", name),
+                );
+            }
+        }
+        for (name, settings) in &self.distro {
+            if settings.ttl == Some(0) {
+                report(
+                    &["distro", name, "ttl"],
+                    format!("distro.{}.ttl must be at least 1 second", name),
+                );
+            }
+        }
+        if self.timeout == Some(0) {
+            report(&["timeout"], "timeout must be at least 1 second".into());
+        }
+        if let Some(device) = &self.proximity_device {
+            if crate::core::proximity::parse_bluetooth_address(device).is_none() {
+                report(
+                    &["proximity_device"],
+                    format!("proximity_device '{}' is not a Bluetooth address", device),
+                );
+            }
+        }
+        if let Some(hotkey) = &self.hotkey {
+            if crate::ui::hotkey::parse(hotkey).is_none() {
+                report(
+                    &["hotkey"],
+                    format!("hotkey '{}' is not a valid key combination", hotkey),
+                );
+            }
+        }
+        problems
+    }
+}
+
+/// Whether `text` matches `pattern`, in which `*` stands for any run of
+/// characters and `?` for one.
+pub(crate) fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // Where the last `*` was, and how much of `text` it has taken
+    let mut star = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Parse and check a config file, describing each problem with its line.
+fn parse(text: &str) -> Result<Config, Vec<String>> {
+    let config: Config = toml::from_str(text).map_err(|e: toml::de::Error| {
+        let line = e.span().map(|span| line_at(text, span.start));
+        vec![describe(line, e.message())]
+    })?;
+    let problems = config.problems(text);
+    if problems.is_empty() {
+        Ok(config)
+    } else {
+        Err(problems)
+    }
+}
+
+fn describe(line: Option<usize>, message: &str) -> String {
+    match line {
+        Some(line) => format!("line {}: {}", line, message),
+        None => message.to_string(),
+    }
+}
+
+/// 1-based line number of byte `offset` in `text`.
+fn line_at(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+/// 1-based line of the value at `path` (the tables it is in, then its
+/// key) in `text`, wherever and however the tables are written.
+fn line_of_value(text: &str, path: &[&str]) -> Option<usize> {
+    let document = toml_edit::ImDocument::parse(text).ok()?;
+    let mut item = document.as_item();
+    for name in path {
+        item = item.get(name)?;
+    }
+    Some(line_at(text, item.span()?.start))
+}
+
+/// `%APPDATA%\wsl-ssh-askpass\config.toml`
+pub(crate) fn path() -> Option<PathBuf> {
+    Some(
+        PathBuf::from(env::var_os("APPDATA")?)
+            .join("wsl-ssh-askpass")
+            .join("config.toml"),
+    )
+}
+
+/// The settings, read once on first use.
+pub(crate) fn get() -> &'static Config {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        let Some(path) = path() else {
+            return Config::default();
+        };
+        let Ok(text) = fs::read_to_string(&path) else {
+            return Config::default();
+        };
+        parse(&text).unwrap_or_else(|problems| {
+            for problem in problems {
+                eprintln!("wsl-ssh-askpass: {}: {}", path.display(), problem);
+            }
+            eprintln!("wsl-ssh-askpass: ignoring {}", path.display());
+            Config::default()
+        })
+    })
+}
+
+/// Check the config file without using it: a description of what was
+/// found, or every problem with its line number.
+pub(crate) fn validate() -> Result<String, String> {
+    let path = path().ok_or("APPDATA is not set")?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(format!("{} does not exist; defaults apply", path.display()));
+        }
+        Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+    };
+    match parse(&text) {
+        Ok(_) => Ok(format!("{} is valid", path.display())),
+        Err(problems) => Err(problems
+            .iter()
+            .map(|problem| format!("{}: {}", path.display(), problem))
+            .collect::<Vec<_>>()
+            .join("\n")),
+    }
+}
+
+/// Set the setting at the dotted `path` (`hello_window`,
+/// `captions.passphrase`, `key.<name>.require_typing`) to `value` in the
+/// config file, creating the file if needed.
+pub(crate) fn set(path: &str, value: &str) -> Result<String, String> {
+    let file = self::path().ok_or("APPDATA is not set")?;
+    let text = match fs::read_to_string(&file) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("cannot read {}: {}", file.display(), e)),
+    };
+    let edited = edit(&text, path, value).map_err(|problems| {
+        problems
+            .iter()
+            .map(|problem| format!("{} not changed: {}", file.display(), problem))
+            .collect::<Vec<_>>()
+            .join("\n")
+    })?;
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    }
+    fs::write(&file, edited).map_err(|e| format!("cannot write {}: {}", file.display(), e))?;
+    Ok(format!("{}: {} = {}", file.display(), path, value))
+}
+
+/// `text` with `path` set to `value`, if the result still validates.
+/// `true`, `false` and integers are written as such, anything else as a
+/// string. In `key.<name>.require_typing` the name may contain dots.
+fn edit(text: &str, path: &str, value: &str) -> Result<String, Vec<String>> {
+    let mut document: toml_edit::DocumentMut = text
+        .parse()
+        .map_err(|e: toml_edit::TomlError| vec![e.message().to_string()])?;
+    let segments: Vec<&str> = match path.split_once('.') {
+        None => vec![path],
+        Some((table, rest)) => match rest.rsplit_once('.') {
+            Some((name, field)) => vec![table, name, field],
+            None => vec![table, rest],
+        },
+    };
+    if segments.iter().any(|segment| segment.is_empty()) {
+        return Err(vec![format!("'{}' is not a setting", path)]);
+    }
+    let (field, tables) = segments.split_last().expect("at least one segment");
+    let mut item = document.as_item_mut();
+    for name in tables {
+        let table = item
+            .as_table_like_mut()
+            .ok_or_else(|| vec![format!("'{}' is not a setting", path)])?;
+        item = table.entry(name).or_insert_with(|| {
+            let mut table = toml_edit::Table::new();
+            // `[key.id_prod]` alone, without an empty `[key]` above it
+            table.set_implicit(true);
+            toml_edit::Item::Table(table)
+        });
+    }
+    let table = item
+        .as_table_like_mut()
+        .ok_or_else(|| vec![format!("'{}' is not a setting", path)])?;
+    let mut value = match value {
+        "true" => toml_edit::Value::from(true),
+        "false" => toml_edit::Value::from(false),
+        _ => match value.parse::<i64>() {
+            Ok(number) => toml_edit::Value::from(number),
+            Err(_) => toml_edit::Value::from(value),
+        },
+    };
+    match table.get_mut(field).and_then(toml_edit::Item::as_value_mut) {
+        // Replacing only the value keeps the comments around it
+        Some(existing) => {
+            *value.decor_mut() = existing.decor().clone();
+            *existing = value;
+        }
+        None => {
+            table.insert(field, toml_edit::Item::Value(value));
+        }
+    }
+    let edited = document.to_string();
+    parse(&edited)?;
+    Ok(edited)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_file_means_defaults() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.passphrase_ttl, None);
+        assert_eq!(config.hello_window_secs(), HELLO_WINDOW_SECS);
+        assert_eq!(config.persistence, Persistence::LocalMachine);
+        assert_eq!(config.require_vbs, RequireVbs::Off);
+        assert!(!config.hello_encryption);
+        assert!(!config.hello_entropy);
+        assert!(!config.signed_images_only);
+        assert!(!config.event_log);
+        assert!(!config.host_key_randomart);
+        assert!(!config.remember_host_keys);
+        assert_eq!(config.host_key_ttl, None);
+        assert_eq!(config.passphrase_dialog, PassphraseDialog::Builtin);
+        assert_eq!(config.empty_passphrase, EmptyPassphrase::Cancel);
+        assert_eq!(config.timeout, None);
+        assert!(config.timeout_toast());
+        assert!(!config.debug_log);
+        assert!(config.require_device.is_empty());
+        assert_eq!(config.proximity_device, None);
+        assert_eq!(config.proximity_rssi, None);
+        assert_eq!(config.approval_url, None);
+        assert!(config.approval_keys.is_empty());
+        assert!(config.snooze_keys.is_empty());
+        assert_eq!(config.distro_settings("Ubuntu"), None);
+        assert_eq!(config.captions.passphrase, "SSH Passphrase");
+    }
+
+    #[test]
+    fn full_file() {
+        let config: Config = toml::from_str(
+            "passphrase_ttl = 86400\n\
+             hello_window = 60\n\
+             persistence = \"session\"\n\
+             require_vbs = \"credential-guard\"\n\
+             hello_encryption = true\n\
+             hello_entropy = true\n\
+             signed_images_only = true\n\
+             event_log = true\n\
+             host_key_randomart = true\n\
+             remember_host_keys = true\n\
+             host_key_ttl = 604800\n\
+             passphrase_dialog = \"credui\"\n\
+             empty_passphrase = \"pass\"\n\
+             newline = false\n\
+             timeout = 90\n\
+             timeout_toast = false\n\
+             debug_log = true\n\
+             require_device = [\"1050:0407\", \"smartcard\"]\n\
+             proximity_device = \"AA:BB:CC:DD:EE:FF\"\n\
+             proximity_rssi = -60\n\
+             approval_url = \"https://ntfy.sh/topic\"\n\
+             approval_keys = [\"id_prod\", \"password:*\"]\n\
+             approval_timeout = 30\n\
+             snooze_keys = [\"id_work*\"]\n\
+             [captions]\n\
+             passphrase = \"Unlock SSH key\"\n\
+             [app]\n\
+             Git = \"silent\"\n\
+             rsync = \"prompt\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.passphrase_ttl, Some(86400));
+        assert_eq!(config.hello_window_secs(), 60);
+        assert_eq!(config.persistence, Persistence::Session);
+        assert_eq!(config.require_vbs, RequireVbs::CredentialGuard);
+        assert!(config.hello_encryption);
+        assert!(config.hello_entropy);
+        assert!(config.signed_images_only);
+        assert!(config.event_log);
+        assert!(config.host_key_randomart);
+        assert!(config.remember_host_keys);
+        assert_eq!(config.host_key_ttl, Some(604800));
+        assert_eq!(config.passphrase_dialog, PassphraseDialog::Credui);
+        assert_eq!(config.empty_passphrase, EmptyPassphrase::Pass);
+        assert_eq!(config.newline, Some(false));
+        assert_eq!(config.timeout, Some(90));
+        assert!(!config.timeout_toast());
+        assert!(config.debug_log);
+        assert_eq!(config.require_device, ["1050:0407", "smartcard"]);
+        assert_eq!(
+            config.proximity_device.as_deref(),
+            Some("AA:BB:CC:DD:EE:FF")
+        );
+        assert_eq!(config.proximity_rssi, Some(-60));
+        assert_eq!(
+            config.approval_url.as_deref(),
+            Some("https://ntfy.sh/topic")
+        );
+        assert_eq!(config.approval_keys, ["id_prod", "password:*"]);
+        assert_eq!(config.approval_timeout, Some(30));
+        assert_eq!(config.snooze_keys, ["id_work*"]);
+        assert_eq!(config.captions.passphrase, "Unlock SSH key");
+        assert_eq!(config.captions.host_key, "SSH Host Verification");
+        assert_eq!(config.app_rule("git"), Some(AppRule::Silent));
+        assert_eq!(config.app_rule("rsync"), Some(AppRule::Prompt));
+        assert_eq!(config.app_rule("ssh"), None);
+    }
+
+    #[test]
+    fn typos_are_rejected() {
+        assert!(toml::from_str::<Config>("hello_windw = 60").is_err());
+        assert!(toml::from_str::<Config>("persistence = \"forever\"").is_err());
+        assert!(toml::from_str::<Config>("[app]\ngit = \"quiet\"").is_err());
+    }
+
+    #[test]
+    fn problems_name_their_line() {
+        let problems = parse("hello_window = 60\n\nhello_windw = 60\n").unwrap_err();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("line 3: "), "{}", problems[0]);
+
+        let problems =
+            parse("passphrase_ttl = 0\nhello_window = 999999\ntimeout = 0\n").unwrap_err();
+        assert_eq!(
+            problems,
+            [
+                "line 1: passphrase_ttl must be at least 1 second",
+                "line 2: hello_window must be at most 86400 seconds",
+                "line 3: timeout must be at least 1 second",
+            ]
+        );
+    }
+
+    #[test]
+    fn key_problems_name_their_own_line() {
+        let problems =
+            parse("[key.id_work]\nttl = 3600\n\n[key.\"id.prod\"]\nhello = \"always\"\nttl = 0\n")
+                .unwrap_err();
+        assert_eq!(
+            problems,
+            ["line 6: key.id.prod.ttl must be at least 1 second"]
+        );
+        let problems = parse("timeout = 60\nkey.id_work = { ttl = 0 }\n").unwrap_err();
+        assert_eq!(
+            problems,
+            ["line 2: key.id_work.ttl must be at least 1 second"]
+        );
+    }
+
+    #[test]
+    fn bad_hotkeys_are_problems() {
+        let problems = parse("\nhotkey = \"P\"\n").unwrap_err();
+        assert_eq!(
+            problems,
+            ["line 2: hotkey 'P' is not a valid key combination"]
+        );
+        assert!(parse("hotkey = \"Ctrl+Alt+P\"").is_ok());
+    }
+
+    #[test]
+    fn keys_that_require_typing() {
+        let config = parse(
+            "[key.id_prod]\n\
+             require_typing = true\n\
+             [key.\"password:root@db.example.com\"]\n\
+             require_typing = true\n",
+        )
+        .unwrap();
+        assert!(config.key_settings("id_prod", None).require_typing);
+        assert!(config.key_settings("id_prod#0badf00d", None).require_typing);
+        assert!(
+            config
+                .key_settings("password:root@db.example.com", None)
+                .require_typing
+        );
+        assert!(!config.key_settings("id_ed25519", None).require_typing);
+        assert!(parse("[key.id_prod]\nrequire_typng = true\n").is_err());
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("id_work*", "id_work"));
+        assert!(glob_matches("id_work*", "id_work_laptop#0badf00d"));
+        assert!(glob_matches("*/work/*", "/home/me/.ssh/work/id_ed25519"));
+        assert!(glob_matches(
+            "password:*@db?.example.com",
+            "password:me@db1.example.com"
+        ));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("id_work*", "my_id_work"));
+        assert!(!glob_matches("id_?", "id_10"));
+        assert!(!glob_matches("id_prod", "id_prod2"));
+    }
+
+    #[test]
+    fn key_settings_merge_by_specificity() {
+        let config = parse(
+            "[key.\"id_*\"]\n\
+             hello = \"never\"\n\
+             ttl = 28800\n\
+             [key.\"id_work*\"]\n\
+             hello = \"always\"\n\
+             persistence = \"session\"\n\
+             [key.id_work_ci]\n\
+             hello = \"grace\"\n\
+             [key.\"*/secure/*\"]\n\
+             require_typing = true\n",
+        )
+        .unwrap();
+        let scratch = config.key_settings("id_scratch#0badf00d", None);
+        assert_eq!(scratch.hello, Some(HelloRule::Never));
+        assert_eq!(scratch.ttl, Some(28800));
+        assert_eq!(scratch.persistence, None);
+
+        let work = config.key_settings("distro:Ubuntu/id_work#0badf00d", None);
+        assert_eq!(work.hello, Some(HelloRule::Always));
+        assert_eq!(work.persistence, Some(Persistence::Session));
+        assert_eq!(work.ttl, Some(28800));
+
+        let ci = config.key_settings("id_work_ci", None);
+        assert_eq!(ci.hello, Some(HelloRule::Grace));
+        assert_eq!(ci.persistence, Some(Persistence::Session));
+
+        let secure = config.key_settings("id_ed25519#12345678", Some("/home/me/secure/id_ed25519"));
+        assert!(secure.require_typing);
+        assert!(
+            !config
+                .key_settings("id_ed25519#12345678", None)
+                .require_typing
+        );
+        assert_eq!(
+            config.key_settings("password:me@host", None),
+            KeySettings::default()
+        );
+
+        assert_eq!(
+            parse("[key.id_prod]\nttl = 0\n").unwrap_err(),
+            ["line 2: key.id_prod.ttl must be at least 1 second"]
+        );
+        assert!(parse("[key.id_prod]\nhello = \"sometimes\"\n").is_err());
+    }
+
+    #[test]
+    fn distro_tables() {
+        let config = parse(
+            "[distro.scratch]\n\
+             mode = \"terminal\"\n\
+             [distro.Ubuntu-test]\n\
+             mode = \"isolated\"\n\
+             ttl = 600\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.distro_settings("Scratch"),
+            Some(DistroSettings {
+                mode: DistroMode::Terminal,
+                ttl: None,
+            })
+        );
+        assert_eq!(
+            config.distro_settings("ubuntu-test"),
+            Some(DistroSettings {
+                mode: DistroMode::Isolated,
+                ttl: Some(600),
+            })
+        );
+        assert_eq!(config.distro_settings("Ubuntu"), None);
+
+        assert_eq!(
+            parse("[distro.x]\nmode = \"isolated\"\nttl = 0\n").unwrap_err(),
+            ["line 3: distro.x.ttl must be at least 1 second"]
+        );
+        assert!(parse("[distro.x]\nmode = \"shared\"\n").is_err());
+        assert!(parse("[distro.x]\nttl = 60\n").is_err());
+    }
+
+    #[test]
+    fn set_keeps_comments_and_validates() {
+        let text = "# mine\nhello_window = 60 # short\n";
+        let edited = edit(text, "key.id_prod.require_typing", "true").unwrap();
+        assert!(edited.starts_with(text), "{}", edited);
+        assert!(edited.contains("[key.id_prod]\nrequire_typing = true\n"));
+        assert!(!edited.contains("[key]\n"));
+        assert!(
+            parse(&edited)
+                .unwrap()
+                .key_settings("id_prod", None)
+                .require_typing
+        );
+
+        let edited = edit(
+            &edited,
+            "key.password:me@db.example.com.require_typing",
+            "true",
+        );
+        assert!(
+            parse(&edited.unwrap())
+                .unwrap()
+                .key_settings("password:me@db.example.com", None)
+                .require_typing
+        );
+
+        let edited = edit(text, "hello_window", "120").unwrap();
+        assert_eq!(edited, "# mine\nhello_window = 120 # short\n");
+        let edited = edit("", "captions.passphrase", "Unlock").unwrap();
+        assert_eq!(parse(&edited).unwrap().captions.passphrase, "Unlock");
+
+        assert!(edit(text, "hello_window", "0").is_ok());
+        assert!(edit(text, "hello_window", "999999").is_err());
+        assert!(edit(text, "key.id_prod.require_typng", "true").is_err());
+        assert!(edit(text, "hello_window.x", "1").is_err());
+        assert!(edit(text, "key..require_typing", "true").is_err());
+    }
+}
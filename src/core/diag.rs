@@ -0,0 +1,137 @@
+//! `--verbose` diagnostics: how a prompt was classified, whether the cache
+//! answered, what Windows Hello said, and the error code of any Windows call
+//! that failed, so a prompt that misbehaves can be explained.
+//!
+//! Lines go to stderr with `--verbose` (or `WSL_SSH_ASKPASS_VERBOSE`, for
+//! runs started by ssh), and to `%LOCALAPPDATA%\wsl-ssh-askpass\debug.log`
+//! with `debug_log = true` in the config file; the file is moved aside to
+//! `debug.log.1` once it passes 1 MiB. Secrets are never handed to this
+//! module, and prompt text goes through [`redact`] first.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{core::clock::now_secs, core::config, data_dir};
+
+/// Size at which `debug.log` is rotated.
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+/// Longest stretch of prompt text written.
+const MAX_PROMPT_CHARS: usize = 160;
+
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Copy diagnostics to stderr for the rest of the process.
+pub fn enable() {
+    STDERR.store(true, Ordering::Relaxed);
+}
+
+fn to_file() -> bool {
+    config::get().debug_log
+}
+
+/// Record `message` under `topic` ("prompt", "cache", "hello", ...). The
+/// message is only built when diagnostics are on.
+pub(crate) fn note(topic: &str, message: impl FnOnce() -> String) {
+    let stderr = STDERR.load(Ordering::Relaxed);
+    let file = to_file();
+    if !stderr && !file {
+        return;
+    }
+    let line = format!("[{}] {}", topic, message());
+    if stderr {
+        eprintln!("wsl-ssh-askpass: {}", line);
+    }
+    if file {
+        append(&line);
+    }
+}
+
+/// Record that the Windows call `call` failed with `error`.
+pub(crate) fn failure(topic: &str, call: &str, error: &windows::core::Error) {
+    note(topic, || {
+        format!(
+            "{} failed: {:#010x} {}",
+            call,
+            error.code().0,
+            error.message()
+        )
+    });
+}
+
+/// Failures to log are ignored; diagnostics must never block a prompt.
+fn append(line: &str) {
+    let Some(dir) = data_dir() else {
+        return;
+    };
+    let _ = fs::create_dir_all(&dir);
+    let path = dir.join("debug.log");
+    if fs::metadata(&path).is_ok_and(|metadata| metadata.len() > MAX_LOG_BYTES) {
+        let _ = fs::rename(&path, dir.join("debug.log.1"));
+    }
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&path) {
+        let _ = writeln!(file, "{}\t{}\t{}", now_secs(), std::process::id(), line);
+    }
+}
+
+/// `prompt` as written to the log: on one line, cut short, and with every
+/// free-standing run of four or more digits masked, since in two-factor
+/// prompts those are codes and phone numbers. Digits inside a word, as in
+/// `id_ed25519`, are kept.
+pub(crate) fn redact(prompt: &str) -> String {
+    fn flush(out: &mut String, digits: &mut String, next: Option<char>) {
+        let in_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        if digits.len() >= 4 && !in_word(out.chars().last()) && !in_word(next) {
+            out.push_str(&"#".repeat(digits.len()));
+        } else {
+            out.push_str(digits);
+        }
+        digits.clear();
+    }
+    let mut out = String::new();
+    let mut digits = String::new();
+    for c in prompt
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+    {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        flush(&mut out, &mut digits, Some(c));
+        out.push(c);
+    }
+    flush(&mut out, &mut digits, None);
+    if out.chars().count() > MAX_PROMPT_CHARS {
+        out = out.chars().take(MAX_PROMPT_CHARS).collect();
+        out.push('…');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompts_are_redacted() {
+        assert_eq!(
+            redact("Enter passphrase for key '/home/me/.ssh/id_ed25519':"),
+            "Enter passphrase for key '/home/me/.ssh/id_ed25519':"
+        );
+        assert_eq!(
+            redact(" 1. Duo Push to XXX-XXX-1234\n 2. SMS\nPasscode or option (1-2): "),
+            "1. Duo Push to XXX-XXX-#### 2. SMS Passcode or option (1-2):"
+        );
+        assert_eq!(
+            redact("Verification code 123456:"),
+            "Verification code ######:"
+        );
+        assert_eq!(redact("key 'id_2024':"), "key 'id_2024':");
+        let long = redact(&"a".repeat(500));
+        assert_eq!(long.chars().count(), MAX_PROMPT_CHARS + 1);
+        assert!(long.ends_with('…'));
+    }
+}
@@ -0,0 +1,623 @@
+//! Host key verification prompts: recognising them and pulling out the
+//! host, port, key type and fingerprint ssh is asking about.
+//!
+//! OpenSSH's wording has shifted between releases (MD5 hex fingerprints
+//! before 6.8, `[fingerprint]` answers since 8.5, known-by-other-names
+//! notes since 8.5), Windows builds use CRLF, and localized wrappers swap
+//! in typographic quotes, so matching is deliberately loose.
+
+use crate::core::clock;
+
+/// The key ssh is asking the user to accept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HostKey {
+    pub(crate) host: String,
+    /// Only set when ssh shows one, i.e. for non-default ports
+    pub(crate) port: Option<u16>,
+    /// `ED25519`, `ECDSA`, `RSA`, `ED25519-SK`, ...
+    pub(crate) key_type: Option<String>,
+    /// As shown, e.g. `SHA256:...` or `MD5:aa:bb:...`
+    pub(crate) fingerprint: String,
+}
+
+impl HostKey {
+    /// The host as `known_hosts` writes it: `host`, or `[host]:port`.
+    pub(crate) fn known_hosts_name(&self) -> String {
+        match self.port {
+            Some(port) => format!("[{}]:{}", self.host, port),
+            None => self.host.clone(),
+        }
+    }
+}
+
+/// Host key verification prompts ask for "yes/no" or show a fingerprint.
+pub(crate) fn is_prompt(prompt: &str) -> bool {
+    let prompt_lower = prompt.to_lowercase();
+    prompt_lower.contains("yes/no") || prompt_lower.contains("fingerprint")
+}
+
+/// Newer OpenSSH also accepts the expected fingerprint as the answer.
+pub(crate) fn accepts_fingerprint_answer(prompt: &str) -> bool {
+    prompt.contains("[fingerprint]")
+}
+
+/// Parse a first-connection prompt such as:
+///
+/// ```text
+/// The authenticity of host 'github.com (140.82.121.4)' can't be established.
+/// ED25519 key fingerprint is SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU.
+/// ```
+///
+/// Returns `None` for other yes/no questions, which name no single new key.
+pub(crate) fn parse(prompt: &str) -> Option<HostKey> {
+    // ASCII lowercasing keeps byte offsets valid for slicing `prompt`
+    let lower = prompt.to_ascii_lowercase();
+
+    let start = lower.find("authenticity of host ")? + "authenticity of host ".len();
+    let quoted = unquote(&prompt[start..])?;
+    let target = quoted.split(" (").next().unwrap_or(quoted).trim();
+    let (host, port) = split_host_port(target)?;
+
+    let marker = " key fingerprint is ";
+    let at = lower.find(marker)?;
+    let line_start = prompt[..at].rfind('\n').map_or(0, |i| i + 1);
+    let key_type = prompt[line_start..at]
+        .split_whitespace()
+        .last()
+        .map(str::to_string);
+    let fingerprint = prompt[at + marker.len()..]
+        .split_whitespace()
+        .next()?
+        .trim_end_matches('.');
+    if fingerprint.is_empty() {
+        return None;
+    }
+
+    Some(HostKey {
+        host: host.to_string(),
+        port,
+        key_type,
+        fingerprint: fingerprint.to_string(),
+    })
+}
+
+/// The lines of a host key prompt besides the host, the fingerprint, any
+/// randomart and the closing question, e.g. "This key is not known by any
+/// other names."
+pub(crate) fn remarks(prompt: &str) -> Vec<&str> {
+    let lower = |line: &str| line.to_ascii_lowercase();
+    prompt
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty() && !is_randomart_line(line))
+        .filter(|line| {
+            let line = lower(line);
+            !line.contains("authenticity of host ")
+                && !line.contains(" key fingerprint is ")
+                && !line.contains("yes/no")
+        })
+        .collect()
+}
+
+/// The randomart ssh printed itself (`VisualHostKey yes`), if it did.
+pub(crate) fn shown_randomart(prompt: &str) -> Option<String> {
+    let art: Vec<&str> = prompt
+        .lines()
+        .map(str::trim)
+        .filter(|line| is_randomart_line(line))
+        .collect();
+    (art.len() == RANDOMART_HEIGHT + 2).then(|| art.join("\n"))
+}
+
+fn is_randomart_line(line: &str) -> bool {
+    let line = line.trim();
+    let framed = |edge: char| line.starts_with(edge) && line.ends_with(edge);
+    line.chars().count() == RANDOMART_WIDTH + 2 && (framed('+') || framed('|'))
+}
+
+// The field of `ssh-keygen -lv` and `VisualHostKey`
+const RANDOMART_WIDTH: usize = 17;
+const RANDOMART_HEIGHT: usize = 9;
+/// Visit counts 0 to 14, then the start and the end of the walk.
+const RANDOMART_SYMBOLS: &[u8] = b" .o+=*BOX@%&#/^SE";
+
+/// The randomart OpenSSH draws for `key`: the "drunken bishop" walk over
+/// the fingerprint's digest. The bit count ssh puts in the title is not in
+/// the prompt, so it is only shown for the fixed-size Ed25519 keys.
+pub(crate) fn randomart(key: &HostKey) -> Option<String> {
+    let (algorithm, digest) = fingerprint_digest(&key.fingerprint)?;
+    let key_type = key.key_type.as_deref().unwrap_or("");
+    let title = match key_type {
+        "ED25519" | "ED25519-SK" => format!("[{} 256]", key_type),
+        "" => String::new(),
+        _ => format!("[{}]", key_type),
+    };
+
+    let symbols = RANDOMART_SYMBOLS.len() - 1;
+    let mut field = [[0usize; RANDOMART_HEIGHT]; RANDOMART_WIDTH];
+    let (mut x, mut y) = (RANDOMART_WIDTH / 2, RANDOMART_HEIGHT / 2);
+    for byte in digest {
+        let mut input = byte;
+        for _ in 0..4 {
+            x = if input & 1 != 0 {
+                (x + 1).min(RANDOMART_WIDTH - 1)
+            } else {
+                x.saturating_sub(1)
+            };
+            y = if input & 2 != 0 {
+                (y + 1).min(RANDOMART_HEIGHT - 1)
+            } else {
+                y.saturating_sub(1)
+            };
+            if field[x][y] < symbols - 2 {
+                field[x][y] += 1;
+            }
+            input >>= 2;
+        }
+    }
+    field[RANDOMART_WIDTH / 2][RANDOMART_HEIGHT / 2] = symbols - 1;
+    field[x][y] = symbols;
+
+    let border = |label: &str| {
+        let before = (RANDOMART_WIDTH - label.len().min(RANDOMART_WIDTH)) / 2;
+        let after = RANDOMART_WIDTH.saturating_sub(before + label.len());
+        format!("+{}{}{}+", "-".repeat(before), label, "-".repeat(after))
+    };
+    let mut lines = vec![border(&title)];
+    for y in 0..RANDOMART_HEIGHT {
+        let row: String = (0..RANDOMART_WIDTH)
+            .map(|x| RANDOMART_SYMBOLS[field[x][y]] as char)
+            .collect();
+        lines.push(format!("|{}|", row));
+    }
+    lines.push(border(&format!("[{}]", algorithm)));
+    Some(lines.join("\n"))
+}
+
+/// The hash algorithm and raw digest behind a fingerprint as ssh shows it:
+/// `SHA256:<base64>`, `MD5:<hex>` or, before OpenSSH 6.8, bare MD5 hex.
+fn fingerprint_digest(fingerprint: &str) -> Option<(&'static str, Vec<u8>)> {
+    if let Some(encoded) = fingerprint.strip_prefix("SHA256:") {
+        let digest = decode_base64(encoded);
+        return (digest.len() == 32).then_some(("SHA256", digest));
+    }
+    let hex = fingerprint.strip_prefix("MD5:").unwrap_or(fingerprint);
+    let digest = hex
+        .split(':')
+        .map(|pair| {
+            (pair.len() == 2)
+                .then(|| u8::from_str_radix(pair, 16).ok())
+                .flatten()
+        })
+        .collect::<Option<Vec<u8>>>()?;
+    (digest.len() == 16).then_some(("MD5", digest))
+}
+
+/// Standard base64, stopping at the first character outside the alphabet
+/// and dropping any trailing partial byte.
+pub(crate) fn decode_base64(text: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut bits = 0u32;
+    let mut count = 0;
+    for byte in text.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => break,
+        };
+        bits = (bits << 6) | u32::from(value);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    out
+}
+
+/// The text inside the quotes `s` starts with: `'...'`, `"..."`, `‘...’`,
+/// `“...”` or `«...»`.
+fn unquote(s: &str) -> Option<&str> {
+    let open = s.chars().next()?;
+    let close = match open {
+        '\'' => '\'',
+        '"' => '"',
+        '‘' => '’',
+        '“' => '”',
+        '«' => '»',
+        _ => return None,
+    };
+    let inner = &s[open.len_utf8()..];
+    let end = inner.find(close)?;
+    Some(&inner[..end])
+}
+
+/// `host`, `[host]:port` or a bare IPv6 address.
+fn split_host_port(target: &str) -> Option<(&str, Option<u16>)> {
+    if let Some(rest) = target.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']')?;
+        let port = match rest.strip_prefix(':') {
+            Some(port) => Some(port.parse().ok()?),
+            None => None,
+        };
+        return (!host.is_empty()).then_some((host, port));
+    }
+    (!target.is_empty()).then_some((target, None))
+}
+
+/// What the keys remembered for a host say about the one offered now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Remembered {
+    /// Accepted before, and no longer ago than the TTL
+    Trusted,
+    /// Never accepted, or so long ago that the answer expired
+    Unknown,
+    /// A different key of the same type was accepted for this host
+    Changed { previous: String },
+}
+
+/// Check `key` against `pins`, one remembered key per line as
+/// `<fingerprint>\t<key type>\t<unix time accepted>`. A bare fingerprint
+/// was pinned before pins expired: it is trusted until a `ttl` is set, and
+/// is never taken as evidence of a change since its type is unknown.
+pub(crate) fn check_pins(pins: &str, key: &HostKey, ttl: Option<u64>, now: u64) -> Remembered {
+    let mut previous = None;
+    for pin in pins.lines().filter_map(Pin::parse) {
+        if pin.fingerprint == key.fingerprint {
+            let fresh = match (ttl, pin.accepted) {
+                (None, _) => true,
+                (Some(ttl), Some(accepted)) => {
+                    clock::age(accepted, now).is_some_and(|age| age <= ttl)
+                }
+                (Some(_), None) => false,
+            };
+            if fresh {
+                return Remembered::Trusted;
+            }
+        } else if pin.replaces(key) {
+            previous.get_or_insert(pin.fingerprint);
+        }
+    }
+    match previous {
+        Some(previous) => Remembered::Changed {
+            previous: previous.to_string(),
+        },
+        None => Remembered::Unknown,
+    }
+}
+
+/// `pins` with `key` accepted at `now`, in place of any earlier pin of the
+/// same key or of the key it replaces.
+pub(crate) fn add_pin(pins: &str, key: &HostKey, now: u64) -> String {
+    let mut kept: Vec<String> = pins
+        .lines()
+        .filter(|line| {
+            Pin::parse(line)
+                .is_some_and(|pin| pin.fingerprint != key.fingerprint && !pin.replaces(key))
+        })
+        .map(str::to_string)
+        .collect();
+    kept.push(format!(
+        "{}\t{}\t{}",
+        key.fingerprint,
+        key.key_type.as_deref().unwrap_or(""),
+        now
+    ));
+    kept.join("\n")
+}
+
+/// One line of a host's pins.
+struct Pin<'a> {
+    fingerprint: &'a str,
+    key_type: Option<&'a str>,
+    accepted: Option<u64>,
+}
+
+impl<'a> Pin<'a> {
+    fn parse(line: &'a str) -> Option<Pin<'a>> {
+        let mut fields = line.split('\t');
+        let fingerprint = fields.next().filter(|f| !f.is_empty())?;
+        let key_type = fields.next().filter(|t| !t.is_empty());
+        let accepted = fields.next().and_then(|t| t.parse().ok());
+        Some(Pin {
+            fingerprint,
+            key_type,
+            accepted,
+        })
+    }
+
+    /// Whether `key` takes this pin's place: the same type of key, its
+    /// fingerprint shown with the same hash, but not the same key.
+    fn replaces(&self, key: &HostKey) -> bool {
+        let sha256 = |fingerprint: &str| fingerprint.starts_with("SHA256:");
+        self.fingerprint != key.fingerprint
+            && sha256(self.fingerprint) == sha256(&key.fingerprint)
+            && self
+                .key_type
+                .zip(key.key_type.as_deref())
+                .is_some_and(|(pinned, offered)| pinned.eq_ignore_ascii_case(offered))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! fixture {
+        ($name:literal) => {
+            include_str!(concat!("../../tests/fixtures/hostkey/", $name))
+        };
+    }
+
+    fn key(host: &str, port: Option<u16>, key_type: &str, fingerprint: &str) -> Option<HostKey> {
+        Some(HostKey {
+            host: host.to_string(),
+            port,
+            key_type: Some(key_type.to_string()),
+            fingerprint: fingerprint.to_string(),
+        })
+    }
+
+    #[test]
+    fn fixtures() {
+        let cases = [
+            (
+                fixture!("openssh-9-ed25519.txt"),
+                key(
+                    "github.com",
+                    None,
+                    "ED25519",
+                    "SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU",
+                ),
+            ),
+            (
+                fixture!("openssh-9-port.txt"),
+                key(
+                    "git.example.com",
+                    Some(2222),
+                    "ECDSA",
+                    "SHA256:p2QAMXNIC1TJYWeIOttrVc98/R1BUFWu3/LiyKgUfQM",
+                ),
+            ),
+            (
+                fixture!("openssh-7-ecdsa.txt"),
+                key(
+                    "build01",
+                    None,
+                    "ECDSA",
+                    "SHA256:4QK0pTJtPmAY6ZqbNsG9JmnxIY3XNwyyhJKWWqdxmRE",
+                ),
+            ),
+            (
+                fixture!("openssh-6-md5.txt"),
+                key(
+                    "legacy.example.org",
+                    None,
+                    "RSA",
+                    "16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48",
+                ),
+            ),
+            (
+                fixture!("openssh-md5-prefixed.txt"),
+                key(
+                    "legacy.example.org",
+                    None,
+                    "RSA",
+                    "MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48",
+                ),
+            ),
+            (
+                fixture!("openssh-sk.txt"),
+                key(
+                    "secure.example.net",
+                    None,
+                    "ED25519-SK",
+                    "SHA256:9cT2bmcT1TIYyQwUGQU7Z2aS3PzlXY+QGmDJqhBnkzk",
+                ),
+            ),
+            (
+                fixture!("windows-openssh-crlf.txt"),
+                key(
+                    "win.example.com",
+                    None,
+                    "ED25519",
+                    "SHA256:Wm0uVmfhwd6vKoh2iL0CrWTj5aDDzgbUg1K1mZkAYAA",
+                ),
+            ),
+            (
+                fixture!("ipv6-port.txt"),
+                key(
+                    "2001:db8::1",
+                    Some(2222),
+                    "ED25519",
+                    "SHA256:M7mJYZ6I8Zb9n0kPH0mQitU+y/YTrzRfjY8QHCfXqgE",
+                ),
+            ),
+            (
+                fixture!("ipv6.txt"),
+                key(
+                    "2001:db8::5",
+                    None,
+                    "ED25519",
+                    "SHA256:M7mJYZ6I8Zb9n0kPH0mQitU+y/YTrzRfjY8QHCfXqgE",
+                ),
+            ),
+            (
+                fixture!("other-names.txt"),
+                key(
+                    "new.example.com",
+                    None,
+                    "ED25519",
+                    "SHA256:Q9rxa/sEY9I0mTPS8Xtj5GJQ3hc8P5tFRj9oZ9sPmDk",
+                ),
+            ),
+            (
+                fixture!("curly-quotes.txt"),
+                key(
+                    "intranet.example.de",
+                    None,
+                    "ED25519",
+                    "SHA256:L2b8xXoPJx4dmx2vMXnqk5rHs1AJx1mEoPe0cB6r0Zs",
+                ),
+            ),
+            (fixture!("ip-differs.txt"), None),
+            (fixture!("updated-hostkeys.txt"), None),
+        ];
+        for (prompt, expected) in cases {
+            assert!(is_prompt(prompt), "not recognised: {}", prompt);
+            assert_eq!(parse(prompt), expected, "{}", prompt);
+        }
+    }
+
+    #[test]
+    fn fingerprint_answers() {
+        assert!(accepts_fingerprint_answer(fixture!(
+            "openssh-9-ed25519.txt"
+        )));
+        assert!(!accepts_fingerprint_answer(fixture!("openssh-7-ecdsa.txt")));
+    }
+
+    #[test]
+    fn randomart_matches_ssh_keygen() {
+        // ssh-keygen -lv, and -E md5, for one Ed25519 key
+        let key = HostKey {
+            host: "example.com".into(),
+            port: None,
+            key_type: Some("ED25519".into()),
+            fingerprint: "SHA256:cgD4nTurrPFSlE7BnvWmSw7l+eum4pqcPRowWs7FRac".into(),
+        };
+        assert_eq!(
+            randomart(&key).unwrap(),
+            "+--[ED25519 256]--+\n\
+             |   o. . .        |\n\
+             |  . oo.o         |\n\
+             |   o *Eo         |\n\
+             |   .B.+.o        |\n\
+             |o .+oo.=S        |\n\
+             |.* .+ Bo         |\n\
+             |. =. + =         |\n\
+             | ..Oo + o        |\n\
+             |  B**+.+o.       |\n\
+             +----[SHA256]-----+"
+        );
+        let md5 = HostKey {
+            fingerprint: "MD5:b7:fc:e8:1f:e8:fa:cc:b1:ea:b7:96:ba:ec:cb:7b:ce".into(),
+            ..key.clone()
+        };
+        let art = randomart(&md5).unwrap();
+        assert!(art.ends_with("|       o%#Eoo    |\n+------[MD5]------+"));
+        let unknown = HostKey {
+            fingerprint: "SHA256:short".into(),
+            ..key
+        };
+        assert_eq!(randomart(&unknown), None);
+    }
+
+    #[test]
+    fn remarks_leave_out_what_the_dialog_shows() {
+        assert_eq!(
+            remarks(fixture!("openssh-9-ed25519.txt")),
+            ["This key is not known by any other names."]
+        );
+        assert_eq!(
+            remarks(fixture!("other-names.txt")),
+            [
+                "This host key is known by the following other names/addresses:",
+                "    ~/.ssh/known_hosts:3: old.example.com",
+            ]
+        );
+        let visual = "The authenticity of host 'h (192.0.2.1)' can't be established.\n\
+            ED25519 key fingerprint is SHA256:cgD4nTurrPFSlE7BnvWmSw7l+eum4pqcPRowWs7FRac.\n\
+            +--[ED25519 256]--+\n\
+            |   o. . .        |\n\
+            |  . oo.o         |\n\
+            |   o *Eo         |\n\
+            |   .B.+.o        |\n\
+            |o .+oo.=S        |\n\
+            |.* .+ Bo         |\n\
+            |. =. + =         |\n\
+            | ..Oo + o        |\n\
+            |  B**+.+o.       |\n\
+            +----[SHA256]-----+\n\
+            Are you sure you want to continue connecting (yes/no/[fingerprint])? ";
+        assert!(remarks(visual).is_empty());
+        assert_eq!(shown_randomart(visual), randomart(&parse(visual).unwrap()));
+        assert_eq!(shown_randomart(fixture!("openssh-9-ed25519.txt")), None);
+    }
+
+    #[test]
+    fn known_hosts_names() {
+        let standard = parse(fixture!("openssh-9-ed25519.txt")).unwrap();
+        assert_eq!(standard.known_hosts_name(), "github.com");
+        let ported = parse(fixture!("ipv6-port.txt")).unwrap();
+        assert_eq!(ported.known_hosts_name(), "[2001:db8::1]:2222");
+    }
+
+    #[test]
+    fn pins_expire_and_notice_changed_keys() {
+        let offered = key("h", None, "ED25519", "SHA256:new").unwrap();
+        let rsa = "SHA256:rsa\tRSA\t100";
+        // Accepted at 1000, checked at 1500
+        let pins = add_pin(rsa, &offered, 1000);
+        assert_eq!(pins, "SHA256:rsa\tRSA\t100\nSHA256:new\tED25519\t1000");
+        assert_eq!(check_pins(&pins, &offered, None, 1500), Remembered::Trusted);
+        assert_eq!(
+            check_pins(&pins, &offered, Some(600), 1500),
+            Remembered::Trusted
+        );
+        assert_eq!(
+            check_pins(&pins, &offered, Some(60), 1500),
+            Remembered::Unknown
+        );
+        assert_eq!(check_pins("", &offered, None, 1500), Remembered::Unknown);
+
+        let rotated = key("h", None, "ED25519", "SHA256:other").unwrap();
+        let changed = Remembered::Changed {
+            previous: "SHA256:new".into(),
+        };
+        assert_eq!(check_pins(&pins, &rotated, None, 1500), changed);
+        // Still a change once the old answer expired
+        assert_eq!(check_pins(&pins, &rotated, Some(60), 1500), changed);
+        assert_eq!(
+            add_pin(&pins, &rotated, 2000),
+            "SHA256:rsa\tRSA\t100\nSHA256:other\tED25519\t2000"
+        );
+
+        // Pins without a type or time, and other hashes, prove nothing
+        let legacy = "SHA256:new\nMD5:aa:bb\tED25519\t100";
+        assert_eq!(
+            check_pins(legacy, &offered, None, 1500),
+            Remembered::Trusted
+        );
+        assert_eq!(
+            check_pins(legacy, &offered, Some(600), 1500),
+            Remembered::Unknown
+        );
+        assert_eq!(
+            check_pins(legacy, &rotated, None, 1500),
+            Remembered::Unknown
+        );
+    }
+
+    #[test]
+    fn not_host_key_prompts() {
+        assert!(!is_prompt(
+            "Enter passphrase for key '/home/me/.ssh/id_ed25519':"
+        ));
+        assert_eq!(
+            parse("The authenticity of host 'x' can't be established."),
+            None
+        );
+        assert_eq!(parse("The authenticity of host ''"), None);
+    }
+
+    #[test]
+    fn decodes_base64() {
+        assert_eq!(decode_base64("bm9uZQ=="), b"none");
+        assert_eq!(decode_base64("YWJj"), b"abc");
+        assert_eq!(decode_base64(""), b"");
+    }
+}
@@ -0,0 +1,46 @@
+//! Tie spawned helpers (`wsl.exe` and friends) to our lifetime with a job
+//! object, so a killed or crashed askpass never leaves stray children
+//! holding the console.
+
+use std::os::windows::io::AsRawHandle;
+use std::process::Child;
+use std::sync::OnceLock;
+
+use windows::{
+    core::PCWSTR,
+    Win32::Foundation::HANDLE,
+    Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+        SetInformationJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    },
+};
+
+/// The job handle, kept open (and so the job alive) until the process exits;
+/// closing it at exit terminates every process still assigned to it.
+fn job() -> Option<HANDLE> {
+    static JOB: OnceLock<Option<isize>> = OnceLock::new();
+    let raw = JOB.get_or_init(|| unsafe {
+        let job = CreateJobObjectW(None, PCWSTR::null()).ok()?;
+        let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        SetInformationJobObject(
+            job,
+            JobObjectExtendedLimitInformation,
+            &limits as *const _ as *const _,
+            std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        )
+        .ok()?;
+        Some(job.0 as isize)
+    });
+    raw.map(|raw| HANDLE(raw as *mut _))
+}
+
+/// Put `child` in the kill-on-close job. Best effort: if the job cannot be
+/// created the child simply runs unmanaged, as before.
+pub(crate) fn adopt(child: &Child) {
+    if let Some(job) = job() {
+        let process = HANDLE(child.as_raw_handle());
+        let _ = unsafe { AssignProcessToJobObject(job, process) };
+    }
+}
@@ -0,0 +1,104 @@
+//! Exploit mitigations for the process that holds plaintext passphrases.
+//!
+//! DEP and ASLR come from the image (`/NXCOMPAT`, `/DYNAMICBASE`,
+//! `/HIGHENTROPYVA`, on by default) and CFG from `-C control-flow-guard`
+//! in `.cargo/config.toml`. [`apply`] adds what only the running process
+//! can ask for: forced relocation of DLLs, no new executable memory, no
+//! legacy extension points (AppInit DLLs, global hooks, legacy IMEs), and
+//! with `signed_images_only` in the config file, no DLLs that Microsoft did
+//! not sign. A policy, once on, is on for the rest of the process.
+
+use std::ffi::c_void;
+use std::mem::size_of;
+
+use windows::Win32::System::Threading::{
+    GetCurrentProcess, GetProcessMitigationPolicy, ProcessASLRPolicy,
+    ProcessControlFlowGuardPolicy, ProcessDEPPolicy, ProcessDynamicCodePolicy,
+    ProcessExtensionPointDisablePolicy, ProcessSignaturePolicy, SetProcessMitigationPolicy,
+    PROCESS_MITIGATION_POLICY,
+};
+
+use crate::core::config;
+
+// Bits of the policies' flag words that are turned on or checked
+const DEP_ENABLE: u32 = 1 << 0;
+const ASLR_BOTTOM_UP: u32 = 1 << 0;
+const ASLR_FORCE_RELOCATE: u32 = 1 << 1;
+const ASLR_HIGH_ENTROPY: u32 = 1 << 2;
+const CFG_ENABLE: u32 = 1 << 0;
+const PROHIBIT_DYNAMIC_CODE: u32 = 1 << 0;
+const DISABLE_EXTENSION_POINTS: u32 = 1 << 0;
+const MICROSOFT_SIGNED_ONLY: u32 = 1 << 0;
+
+/// Turn on the process mitigations. Call first thing in `main`, before any
+/// extension point has a chance to load; a policy this version of Windows
+/// does not know is skipped, and [`report`] shows what took effect.
+pub fn apply() {
+    set(
+        ProcessASLRPolicy,
+        ASLR_BOTTOM_UP | ASLR_FORCE_RELOCATE | ASLR_HIGH_ENTROPY,
+    );
+    set(ProcessDynamicCodePolicy, PROHIBIT_DYNAMIC_CODE);
+    set(ProcessExtensionPointDisablePolicy, DISABLE_EXTENSION_POINTS);
+    if config::get().signed_images_only {
+        // Also refuses third-party credential providers and IMEs
+        set(ProcessSignaturePolicy, MICROSOFT_SIGNED_ONLY);
+    }
+}
+
+/// Each mitigation by name, and whether it is in force in this process.
+pub fn report() -> Vec<(&'static str, bool)> {
+    let has = |policy, bits| get(policy).is_some_and(|flags| flags & bits == bits);
+    vec![
+        ("DEP", has(ProcessDEPPolicy, DEP_ENABLE)),
+        (
+            "ASLR",
+            has(ProcessASLRPolicy, ASLR_BOTTOM_UP | ASLR_HIGH_ENTROPY),
+        ),
+        ("CFG", has(ProcessControlFlowGuardPolicy, CFG_ENABLE)),
+        (
+            "no dynamic code",
+            has(ProcessDynamicCodePolicy, PROHIBIT_DYNAMIC_CODE),
+        ),
+        (
+            "no extension points",
+            has(ProcessExtensionPointDisablePolicy, DISABLE_EXTENSION_POINTS),
+        ),
+        (
+            "Microsoft-signed DLLs only",
+            has(ProcessSignaturePolicy, MICROSOFT_SIGNED_ONLY),
+        ),
+    ]
+}
+
+/// Every policy used here is a single flag word, except DEP's, which is
+/// followed by a `Permanent` flag that is not needed.
+fn set(policy: PROCESS_MITIGATION_POLICY, flags: u32) {
+    let _ = unsafe {
+        SetProcessMitigationPolicy(
+            policy,
+            &flags as *const u32 as *const c_void,
+            size_of::<u32>(),
+        )
+    };
+}
+
+fn get(policy: PROCESS_MITIGATION_POLICY) -> Option<u32> {
+    // Room for DEP's two words; the others fill only the first
+    let mut buffer = [0u32; 2];
+    let len = if policy == ProcessDEPPolicy {
+        size_of::<[u32; 2]>()
+    } else {
+        size_of::<u32>()
+    };
+    unsafe {
+        GetProcessMitigationPolicy(
+            GetCurrentProcess(),
+            policy,
+            buffer.as_mut_ptr() as *mut c_void,
+            len,
+        )
+    }
+    .ok()?;
+    Some(buffer[0])
+}
@@ -0,0 +1,27 @@
+//! What the rest of the crate decides with: classifying the text ssh (or
+//! git, or sudo) sent, the cache policy and per-key settings that apply to
+//! it, the configuration file, the time arithmetic behind every window, and
+//! the facts those decisions read — who the caller is, which logon session
+//! this is, which security keys and phones are around. Also the process
+//! plumbing everything shares: diagnostics, timing, job objects, WinRT
+//! waits and exploit mitigations.
+//!
+//! Nothing here shows a window or writes to Credential Manager. `policy`
+//! does read the registry, and the VBS status through `store::vbs`.
+
+pub(crate) mod caller;
+pub mod clock;
+pub mod codepage;
+pub mod compat;
+pub(crate) mod config;
+pub mod diag;
+pub(crate) mod hostkey;
+pub(crate) mod job;
+pub mod mitigation;
+pub(crate) mod policy;
+pub(crate) mod presence;
+pub(crate) mod prompt;
+pub(crate) mod proximity;
+pub(crate) mod session;
+pub mod timing;
+pub(crate) mod winrt;
@@ -0,0 +1,621 @@
+use std::env;
+
+use windows::{
+    core::{w, PCWSTR},
+    Win32::Security::Credentials::{
+        CRED_PERSIST, CRED_PERSIST_LOCAL_MACHINE, CRED_PERSIST_SESSION,
+    },
+    Win32::System::Registry::{
+        RegGetValueW, HKEY, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, RRF_RT_REG_DWORD, RRF_RT_REG_SZ,
+    },
+};
+
+use crate::core::caller::Application;
+use crate::core::clock;
+use crate::core::config::{AppRule, DistroMode, HelloRule, KeySettings, Persistence, RequireVbs};
+use crate::store::vbs::VbsStatus;
+
+const PASSWORD_CACHE_TTL_SECS: u64 = 60 * 10; // 10 minutes
+const PASSWORD_TTL_ENV: &str = "WSL_SSH_ASKPASS_PASSWORD_TTL";
+/// ssh's `NumberOfPasswordPrompts` default.
+const PASSWORD_PROMPTS: u32 = 3;
+const PASSWORD_PROMPTS_ENV: &str = "WSL_SSH_ASKPASS_PASSWORD_PROMPTS";
+/// ssh asks again within seconds of a rejected password.
+const PASSWORD_ATTEMPT_WINDOW_SECS: u64 = 20;
+const KRB5_CACHE_TTL_SECS: u64 = 60 * 60; // 1 hour
+const KRB5_TTL_ENV: &str = "WSL_SSH_ASKPASS_KRB5_TTL";
+const SUDO_CACHE_TTL_SECS: u64 = 60 * 5; // 5 minutes
+const SUDO_TTL_ENV: &str = "WSL_SSH_ASKPASS_SUDO_TTL";
+const READ_ONLY_ENV: &str = "WSL_SSH_ASKPASS_READONLY";
+const PIN_PAD_ENV: &str = "WSL_SSH_ASKPASS_PINPAD";
+const HOST_PINNING_ENV: &str = "WSL_SSH_ASKPASS_HOST_PINNING";
+const UPDATE_KNOWN_HOSTS_ENV: &str = "WSL_SSH_ASKPASS_UPDATE_KNOWN_HOSTS";
+const DISTRO_POLICY_ENV: &str = "WSL_SSH_ASKPASS_DISTRO_POLICY";
+const DISTRO_ENV: &str = "WSL_DISTRO_NAME";
+/// Group Policy style settings, in `HKLM` or `HKCU`.
+const POLICY_KEY: PCWSTR = w!("SOFTWARE\\Policies\\wsl-ssh-askpass");
+
+/// How prompts from one distro are handled, from a `[distro.<name>]` table or
+/// `WSL_SSH_ASKPASS_DISTRO_POLICY`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DistroRule {
+    /// Ask on the terminal instead of in a dialog; nothing is cached
+    Terminal,
+    /// Cache under a namespace of the distro's own, optionally with a TTL in seconds
+    Isolated(Option<u64>),
+    /// An isolated distro named only by `WSL_DISTRO_NAME`, which any distro
+    /// can set: ask without the cache rather than open its namespace
+    Unverified,
+}
+
+/// What it takes to release a cached secret.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Unlock {
+    /// Within the Hello grace window, or after a Hello verification
+    Grace,
+    /// A fresh Hello verification every time
+    Fresh,
+    /// No verification at all
+    Silent,
+    /// No verification during a `snooze` period; each release is logged
+    Snoozed,
+}
+
+/// How a cached secret may be stored and how long it stays usable.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CachePolicy {
+    pub(crate) persist: CRED_PERSIST,
+    pub(crate) max_age_secs: Option<u64>,
+    /// Whether cached secrets may be served for this request
+    pub(crate) read: bool,
+    pub(crate) unlock: Unlock,
+    /// Whether this request may write to the credential store at all
+    pub(crate) store: bool,
+}
+
+impl CachePolicy {
+    /// Key passphrases persist on this machine until manually deleted, unless
+    /// the VBS requirement is unmet or the config file asks for session
+    /// persistence, in which case they last for the session. The config file
+    /// can also give them a TTL.
+    pub(crate) fn passphrase() -> Self {
+        let config = crate::core::config::get();
+        let persist = if config.persistence == Persistence::LocalMachine
+            && local_machine_persistence_allowed()
+        {
+            CRED_PERSIST_LOCAL_MACHINE
+        } else {
+            CRED_PERSIST_SESSION
+        };
+        CachePolicy {
+            persist,
+            max_age_secs: config.passphrase_ttl,
+            read: true,
+            unlock: Unlock::Grace,
+            store: true,
+        }
+    }
+
+    /// Remote account passwords live for the logon session only and expire
+    /// quickly; the environment can only make that sooner.
+    pub(crate) fn password() -> Self {
+        CachePolicy {
+            persist: CRED_PERSIST_SESSION,
+            max_age_secs: Some(env_shorter_secs(PASSWORD_TTL_ENV, PASSWORD_CACHE_TTL_SECS)),
+            read: true,
+            unlock: Unlock::Grace,
+            store: true,
+        }
+    }
+
+    /// Kerberos principal passwords are session-only, with a TTL of their own
+    /// that the environment can only shorten.
+    pub(crate) fn kerberos() -> Self {
+        CachePolicy {
+            persist: CRED_PERSIST_SESSION,
+            max_age_secs: Some(env_shorter_secs(KRB5_TTL_ENV, KRB5_CACHE_TTL_SECS)),
+            read: true,
+            unlock: Unlock::Grace,
+            store: true,
+        }
+    }
+
+    /// sudo passwords unlock root, so they are session-only and expire
+    /// sooner than any other secret; the environment can only make that
+    /// sooner still.
+    pub(crate) fn sudo() -> Self {
+        CachePolicy {
+            persist: CRED_PERSIST_SESSION,
+            max_age_secs: Some(env_shorter_secs(SUDO_TTL_ENV, SUDO_CACHE_TTL_SECS)),
+            read: true,
+            unlock: Unlock::Grace,
+            store: true,
+        }
+    }
+
+    /// How long a secret stored under this policy lives, for display.
+    pub(crate) fn describe_lifetime(&self) -> String {
+        let until = if self.persist == CRED_PERSIST_LOCAL_MACHINE {
+            "until you delete it"
+        } else {
+            "until you sign out of Windows"
+        };
+        match self.max_age_secs {
+            Some(secs) => format!("{}, and at most {} minutes", until, secs.div_ceil(60)),
+            None => until.to_string(),
+        }
+    }
+
+    /// Apply the `[key.<name>]` settings of one key. Its lifetime and
+    /// storage can only be tightened: a `ttl` shortens the one for its kind
+    /// of secret, and `persistence = "local-machine"` never puts a
+    /// session-only secret (a password, or any under a VBS requirement that
+    /// is unmet) on disk. `hello = "never"` only loosens the default grace
+    /// window, never an application rule or a snooze, while `always` and
+    /// `require_typing` tighten whatever was decided.
+    pub(crate) fn apply_key_settings(&mut self, settings: &KeySettings) {
+        if settings.require_typing {
+            self.read = false;
+        }
+        match settings.hello {
+            Some(HelloRule::Always) => self.unlock = Unlock::Fresh,
+            Some(HelloRule::Never) if self.unlock == Unlock::Grace => self.unlock = Unlock::Silent,
+            _ => {}
+        }
+        if settings.persistence == Some(Persistence::Session) {
+            self.persist = CRED_PERSIST_SESSION;
+        }
+        if let Some(ttl) = settings.ttl {
+            self.max_age_secs = Some(self.max_age_secs.map_or(ttl, |age| age.min(ttl)));
+        }
+    }
+
+    /// Apply the rule for the calling application: the `AppPolicy` string
+    /// policy (a comma separated list such as `git=silent,ssh=confirm`),
+    /// else `[app]` in the config file. Neither can be set by whoever runs
+    /// us, and `silent` only applies to an application recognised from the
+    /// process tree, never to one that named itself.
+    ///
+    /// - `silent` serves cache hits without any Hello verification;
+    /// - `confirm` requires a fresh Hello verification, ignoring the grace window;
+    /// - `prompt` never serves cached secrets and always shows the dialog.
+    pub(crate) fn apply_app_rule(&mut self, app: &Application) {
+        let rule = [HKEY_LOCAL_MACHINE, HKEY_CURRENT_USER]
+            .into_iter()
+            .find_map(|root| parse_app_rule(&policy_string(root, "AppPolicy")?, &app.name))
+            .or_else(|| crate::core::config::get().app_rule(&app.name));
+        match rule {
+            Some(AppRule::Silent) if app.verified => self.unlock = Unlock::Silent,
+            Some(AppRule::Confirm) => self.unlock = Unlock::Fresh,
+            Some(AppRule::Prompt) => self.read = false,
+            _ => {}
+        }
+    }
+}
+
+/// The rule for `app` in a comma separated list such as
+/// `git=silent,ssh=confirm,rsync=prompt`.
+fn parse_app_rule(rules: &str, app: &str) -> Option<AppRule> {
+    rules.split(',').find_map(|rule| {
+        let (name, action) = rule.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case(app) {
+            return None;
+        }
+        AppRule::parse(action)
+    })
+}
+
+/// Whether `key` matches one of `patterns`, globs as in a `[key.<name>]`
+/// name: `*` for any run of characters and `?` for one. A key file's bare
+/// name matches it in every directory; `id_prod#3c1f9a2e` names just one.
+/// An isolated distro's namespace is left out.
+pub(crate) fn key_matches(patterns: &[String], key: &str) -> bool {
+    let bare = key.split_once('/').filter(|_| key.starts_with("distro:"));
+    let bare = bare.map_or(key, |(_, rest)| rest);
+    let candidates = [key, bare, crate::core::prompt::key_label(bare)];
+    patterns
+        .iter()
+        .map(|pattern| pattern.trim())
+        .filter(|pattern| !pattern.is_empty())
+        .any(|pattern| {
+            candidates
+                .iter()
+                .any(|candidate| crate::core::config::glob_matches(pattern, candidate))
+        })
+}
+
+/// Whether `key` is one of the low-risk keys that a `snooze`
+/// pre-authorizes: those in the `SnoozeKeys` policy, else `snooze_keys` in
+/// the config file. Never the environment's, or a caller could snooze `*`.
+pub(crate) fn snoozable(key: &str) -> bool {
+    let keys = policy_list("SnoozeKeys", &crate::core::config::get().snooze_keys);
+    key_matches(&keys, key)
+}
+
+/// `WSL_SSH_ASKPASS_PINPAD=1` answers smartcard PIN prompts with the
+/// on-screen PIN pad instead of the keyboard.
+pub(crate) fn pin_pad_enabled() -> bool {
+    env::var(PIN_PAD_ENV).is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Host key pinning ("Yes, and remember this host") is on unless
+/// `WSL_SSH_ASKPASS_HOST_PINNING=0`, or read-only mode is in force.
+pub(crate) fn host_pinning_enabled() -> bool {
+    !env::var(HOST_PINNING_ENV).is_ok_and(|v| v == "0") && !read_only()
+}
+
+/// `WSL_SSH_ASKPASS_UPDATE_KNOWN_HOSTS=1` also writes host keys accepted in
+/// the dialog to the distro's `known_hosts`; it needs host key pinning on.
+pub(crate) fn update_known_hosts() -> bool {
+    env::var(UPDATE_KNOWN_HOSTS_ENV).is_ok_and(|v| !v.is_empty() && v != "0")
+        && host_pinning_enabled()
+}
+
+/// The rule for the distro the prompt came from, from its `[distro.<name>]`
+/// table, else from `WSL_SSH_ASKPASS_DISTRO_POLICY`, a comma separated list
+/// such as `scratch=terminal,Ubuntu-test=isolated:600`.
+pub(crate) fn distro_rule() -> Option<(String, DistroRule)> {
+    let (distro, known) = distro_name()?;
+    let rule = match crate::core::config::get().distro_settings(&distro) {
+        Some(settings) => match settings.mode {
+            DistroMode::Terminal => DistroRule::Terminal,
+            DistroMode::Isolated => DistroRule::Isolated(settings.ttl),
+        },
+        None => parse_distro_rule(&env::var(DISTRO_POLICY_ENV).ok()?, &distro)?,
+    };
+    match rule {
+        DistroRule::Isolated(_) if !known => Some((distro, DistroRule::Unverified)),
+        rule => Some((distro, rule)),
+    }
+}
+
+/// The distro the prompt came from, and whether that is known rather than
+/// only claimed. A process started in a distro's own files runs in its
+/// `\\wsl.localhost\<distro>` share, which no other distro can hand it; from
+/// a Windows drive there is only `WSL_DISTRO_NAME`. The daemon takes the
+/// name its client forwarded, which the client set from its directory.
+fn distro_name() -> Option<(String, bool)> {
+    let serving = crate::ipc::daemon::serving();
+    if let Some(distro) = directory_distro().filter(|_| !serving) {
+        return Some((distro, true));
+    }
+    let distro = env::var(DISTRO_ENV).ok().filter(|d| !d.is_empty())?;
+    Some((distro, serving))
+}
+
+/// The distro whose files this process was started in.
+pub(crate) fn directory_distro() -> Option<String> {
+    let dir = env::current_dir().ok()?;
+    distro_of_dir(&dir.to_string_lossy()).map(str::to_string)
+}
+
+/// `Ubuntu` for `\\wsl.localhost\Ubuntu\home` or `\\wsl$\Ubuntu`, with or
+/// without the `\\?\UNC\` prefix.
+fn distro_of_dir(dir: &str) -> Option<&str> {
+    let share = dir
+        .strip_prefix(r"\\?\UNC\")
+        .or_else(|| dir.strip_prefix(r"\\"))?;
+    let (server, rest) = share.split_once('\\')?;
+    if !server.eq_ignore_ascii_case("wsl$") && !server.eq_ignore_ascii_case("wsl.localhost") {
+        return None;
+    }
+    let distro = rest.split('\\').next()?;
+    (!distro.is_empty()).then_some(distro)
+}
+
+fn parse_distro_rule(rules: &str, distro: &str) -> Option<DistroRule> {
+    rules.split(',').find_map(|rule| {
+        let (name, action) = rule.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case(distro) {
+            return None;
+        }
+        let action = action.trim().to_lowercase();
+        match action.split_once(':') {
+            None if action == "terminal" => Some(DistroRule::Terminal),
+            None if action == "isolated" => Some(DistroRule::Isolated(None)),
+            Some(("isolated", ttl)) => Some(DistroRule::Isolated(Some(ttl.trim().parse().ok()?))),
+            _ => None,
+        }
+    })
+}
+
+/// Read-only mode for shared or kiosk machines: never touch the credential
+/// store and always prompt. Enabled by the `ReadOnly` DWORD policy under
+/// `SOFTWARE\Policies\wsl-ssh-askpass` (in `HKLM` or `HKCU`), or by
+/// `WSL_SSH_ASKPASS_READONLY=1`.
+pub(crate) fn read_only() -> bool {
+    if env::var(READ_ONLY_ENV).is_ok_and(|v| !v.is_empty() && v != "0") {
+        return true;
+    }
+    [HKEY_LOCAL_MACHINE, HKEY_CURRENT_USER]
+        .into_iter()
+        .any(|root| policy_dword(root, "ReadOnly").is_some_and(|v| v != 0))
+}
+
+fn policy_dword(root: HKEY, name: &str) -> Option<u32> {
+    let name = crate::to_wide(name);
+    let mut value = 0u32;
+    let mut size = std::mem::size_of::<u32>() as u32;
+    let status = unsafe {
+        RegGetValueW(
+            root,
+            POLICY_KEY,
+            PCWSTR(name.as_ptr()),
+            RRF_RT_REG_DWORD,
+            None,
+            Some(&mut value as *mut u32 as *mut _),
+            Some(&mut size),
+        )
+    };
+    status.is_ok().then_some(value)
+}
+
+/// The string policy `name`, in `HKLM` or else `HKCU`.
+pub(crate) fn policy_text(name: &str) -> Option<String> {
+    [HKEY_LOCAL_MACHINE, HKEY_CURRENT_USER]
+        .into_iter()
+        .find_map(|root| policy_string(root, name))
+}
+
+/// The comma separated list in the string policy `name`, or else
+/// `configured`, the same setting from the config file.
+pub(crate) fn policy_list(name: &str, configured: &[String]) -> Vec<String> {
+    let Some(list) = policy_text(name) else {
+        return configured.to_vec();
+    };
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn policy_string(root: HKEY, name: &str) -> Option<String> {
+    let name = crate::to_wide(name);
+    let mut size = 0u32;
+    unsafe {
+        let status = RegGetValueW(
+            root,
+            POLICY_KEY,
+            PCWSTR(name.as_ptr()),
+            RRF_RT_REG_SZ,
+            None,
+            None,
+            Some(&mut size),
+        );
+        if status.is_err() {
+            return None;
+        }
+        let mut value = vec![0u16; (size as usize).div_ceil(2)];
+        let status = RegGetValueW(
+            root,
+            POLICY_KEY,
+            PCWSTR(name.as_ptr()),
+            RRF_RT_REG_SZ,
+            None,
+            Some(value.as_mut_ptr() as *mut _),
+            Some(&mut size),
+        );
+        if status.is_err() {
+            return None;
+        }
+        let len = value.iter().position(|&c| c == 0).unwrap_or(value.len());
+        Some(String::from_utf16_lossy(&value[..len]))
+    }
+}
+
+/// The `RequireVbs` string policy, else `require_vbs` in the config file,
+/// set to `vbs` (or `credential-guard`) only allows secrets to be persisted
+/// to disk while that protection is running. Never from the environment,
+/// which a caller could clear.
+fn local_machine_persistence_allowed() -> bool {
+    let required = policy_text("RequireVbs").map_or(crate::core::config::get().require_vbs, |v| {
+        parse_require_vbs(&v)
+    });
+    required == RequireVbs::Off
+        || vbs_satisfies(required, crate::store::vbs::query().unwrap_or_default())
+}
+
+/// The `RequireVbs` policy's value. One not understood asks for VBS, so a
+/// typo never turns the requirement off.
+fn parse_require_vbs(text: &str) -> RequireVbs {
+    match text.trim().to_lowercase().as_str() {
+        "" | "0" | "off" => RequireVbs::Off,
+        "credential-guard" => RequireVbs::CredentialGuard,
+        _ => RequireVbs::Vbs,
+    }
+}
+
+fn vbs_satisfies(required: RequireVbs, status: VbsStatus) -> bool {
+    match required {
+        RequireVbs::Off => true,
+        RequireVbs::Vbs => status.vbs_running,
+        RequireVbs::CredentialGuard => status.credential_guard_running,
+    }
+}
+
+/// How many times ssh asks for a password before giving up, as its
+/// `NumberOfPasswordPrompts` says; set `WSL_SSH_ASKPASS_PASSWORD_PROMPTS`
+/// when it is not the default.
+pub(crate) fn password_prompts() -> u32 {
+    env::var(PASSWORD_PROMPTS_ENV)
+        .ok()
+        .and_then(|v| v.trim().parse::<u32>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(PASSWORD_PROMPTS)
+}
+
+/// Which of `total` attempts a password prompt at `now` is, given the time
+/// and number of the previous one for the same account. A prompt soon
+/// after one that was not the last is ssh asking again; anything else
+/// starts over.
+pub(crate) fn password_attempt(previous: Option<(u64, u32)>, now: u64, total: u32) -> u32 {
+    match previous {
+        Some((at, attempt))
+            if clock::within(at, PASSWORD_ATTEMPT_WINDOW_SECS, now) && attempt < total =>
+        {
+            attempt + 1
+        }
+        _ => 1,
+    }
+}
+
+/// Seconds from the variable `name`, which can only shorten `default`: the
+/// caller's environment may cache a secret for less time, never longer.
+fn env_shorter_secs(name: &str, default: u64) -> u64 {
+    shorter_secs(env::var(name).ok().as_deref(), default)
+}
+
+fn shorter_secs(value: Option<&str>, default: u64) -> u64 {
+    value
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map_or(default, |secs| secs.min(default))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_password_attempts() {
+        assert_eq!(password_attempt(None, 1000, 3), 1);
+        assert_eq!(password_attempt(Some((995, 1)), 1000, 3), 2);
+        assert_eq!(password_attempt(Some((995, 2)), 1000, 3), 3);
+        // ssh gave up after the last one, so this is a new connection
+        assert_eq!(password_attempt(Some((995, 3)), 1000, 3), 1);
+        assert_eq!(password_attempt(Some((900, 1)), 1000, 3), 1);
+        assert_eq!(password_attempt(Some((995, 1)), 1000, 1), 1);
+    }
+
+    #[test]
+    fn matches_keys() {
+        let patterns = [
+            "id_work*".to_string(),
+            " id_prod ".to_string(),
+            "password:*".to_string(),
+        ];
+        assert!(key_matches(&patterns, "id_work_laptop#0badf00d"));
+        assert!(key_matches(&patterns, "id_prod#3c1f9a2e"));
+        assert!(key_matches(&patterns, "distro:Ubuntu/id_prod#3c1f9a2e"));
+        assert!(key_matches(&patterns, "password:git@example.com"));
+        assert!(!key_matches(&patterns, "my_id_work#0badf00d"));
+        assert!(!key_matches(&patterns, "id_prod_old#3c1f9a2e"));
+        assert!(key_matches(&["id_?".to_string()], "id_a#3c1f9a2e"));
+        assert!(!key_matches(&["".to_string()], ""));
+    }
+
+    #[test]
+    fn key_settings_only_tighten() {
+        let sudo = CachePolicy {
+            persist: CRED_PERSIST_SESSION,
+            max_age_secs: Some(SUDO_CACHE_TTL_SECS),
+            read: true,
+            unlock: Unlock::Grace,
+            store: true,
+        };
+        let everything = KeySettings {
+            persistence: Some(Persistence::LocalMachine),
+            ttl: Some(86400),
+            ..KeySettings::default()
+        };
+        let mut policy = sudo;
+        policy.apply_key_settings(&everything);
+        assert_eq!(policy.persist, CRED_PERSIST_SESSION);
+        assert_eq!(policy.max_age_secs, Some(SUDO_CACHE_TTL_SECS));
+
+        let mut passphrase = CachePolicy {
+            persist: CRED_PERSIST_LOCAL_MACHINE,
+            max_age_secs: None,
+            ..sudo
+        };
+        passphrase.apply_key_settings(&KeySettings {
+            persistence: Some(Persistence::Session),
+            ttl: Some(60),
+            ..KeySettings::default()
+        });
+        assert_eq!(passphrase.persist, CRED_PERSIST_SESSION);
+        assert_eq!(passphrase.max_age_secs, Some(60));
+    }
+
+    #[test]
+    fn ttls_only_shorten() {
+        assert_eq!(shorter_secs(None, 600), 600);
+        assert_eq!(shorter_secs(Some(" 120 "), 600), 120);
+        assert_eq!(shorter_secs(Some("86400"), 600), 600);
+        assert_eq!(shorter_secs(Some("ten"), 600), 600);
+    }
+
+    #[test]
+    fn distro_rules() {
+        let rules =
+            "scratch=terminal, Ubuntu-test = isolated ,Debian=isolated:600,Alpine=isolated:ten";
+        assert_eq!(
+            parse_distro_rule(rules, "Scratch"),
+            Some(DistroRule::Terminal)
+        );
+        assert_eq!(
+            parse_distro_rule(rules, "ubuntu-test"),
+            Some(DistroRule::Isolated(None))
+        );
+        assert_eq!(
+            parse_distro_rule(rules, "Debian"),
+            Some(DistroRule::Isolated(Some(600)))
+        );
+        assert_eq!(parse_distro_rule(rules, "Alpine"), None);
+        assert_eq!(parse_distro_rule(rules, "Ubuntu"), None);
+        assert_eq!(parse_distro_rule("Ubuntu=shared", "Ubuntu"), None);
+    }
+
+    #[test]
+    fn distros_of_directories() {
+        assert_eq!(
+            distro_of_dir(r"\\wsl.localhost\Ubuntu\home\me"),
+            Some("Ubuntu")
+        );
+        assert_eq!(distro_of_dir(r"\\wsl$\Debian"), Some("Debian"));
+        assert_eq!(
+            distro_of_dir(r"\\?\UNC\WSL.localhost\Ubuntu-22.04\tmp"),
+            Some("Ubuntu-22.04")
+        );
+        assert_eq!(distro_of_dir(r"\\wsl.localhost\"), None);
+        assert_eq!(distro_of_dir(r"\\fileserver\Ubuntu\home"), None);
+        assert_eq!(distro_of_dir(r"C:\Users\me"), None);
+    }
+
+    #[test]
+    fn vbs_requirements() {
+        assert_eq!(parse_require_vbs(" Off "), RequireVbs::Off);
+        assert_eq!(parse_require_vbs("0"), RequireVbs::Off);
+        assert_eq!(
+            parse_require_vbs("credential-guard"),
+            RequireVbs::CredentialGuard
+        );
+        assert_eq!(parse_require_vbs("vbs"), RequireVbs::Vbs);
+        assert_eq!(parse_require_vbs("vsb"), RequireVbs::Vbs);
+
+        let none = VbsStatus::default();
+        let vbs = VbsStatus {
+            vbs_running: true,
+            credential_guard_running: false,
+        };
+        let guarded = VbsStatus {
+            vbs_running: true,
+            credential_guard_running: true,
+        };
+        assert!(vbs_satisfies(RequireVbs::Off, none));
+        assert!(!vbs_satisfies(RequireVbs::Vbs, none));
+        assert!(vbs_satisfies(RequireVbs::Vbs, vbs));
+        assert!(!vbs_satisfies(RequireVbs::CredentialGuard, vbs));
+        assert!(vbs_satisfies(RequireVbs::CredentialGuard, guarded));
+    }
+
+    #[test]
+    fn app_rules() {
+        let rules = "git=silent, SSH = Confirm ,rsync=prompt,scp=quiet";
+        assert_eq!(parse_app_rule(rules, "git"), Some(AppRule::Silent));
+        assert_eq!(parse_app_rule(rules, "ssh"), Some(AppRule::Confirm));
+        assert_eq!(parse_app_rule(rules, "rsync"), Some(AppRule::Prompt));
+        assert_eq!(parse_app_rule(rules, "scp"), None);
+        assert_eq!(parse_app_rule(rules, "vscode"), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! "Something you have" factor: cached secrets are only served while a
+//! required USB device or an inserted smartcard is present.
+
+use windows::{
+    core::{w, PCWSTR, PWSTR},
+    Win32::Devices::DeviceAndDriverInstallation::{
+        SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInfo, SetupDiGetClassDevsW,
+        SetupDiGetDeviceInstanceIdW, DIGCF_ALLCLASSES, DIGCF_PRESENT, SP_DEVINFO_DATA,
+    },
+    Win32::Foundation::HWND,
+    Win32::Security::Credentials::{
+        SCardEstablishContext, SCardGetStatusChangeW, SCardListReadersW, SCardReleaseContext,
+        SCARD_READERSTATEW, SCARD_SCOPE_USER, SCARD_STATE_PRESENT, SCARD_STATE_UNAWARE,
+    },
+};
+
+use crate::core::{config, policy};
+
+const SMARTCARD: &str = "SMARTCARD";
+
+/// Returns `true` when no device is required or any of the required devices
+/// is present. They are USB ids (`VID_1050&PID_0407` or `1050:0407`) and/or
+/// `smartcard`, from the `RequireDevice` policy or `require_device` in the
+/// config file; never from the environment, which the caller controls.
+pub(crate) fn required_device_present() -> bool {
+    let wanted: Vec<String> = policy::policy_list("RequireDevice", &config::get().require_device)
+        .iter()
+        .map(|id| normalize_device_id(id))
+        .filter(|id| !id.is_empty())
+        .collect();
+    if wanted.is_empty() {
+        return true;
+    }
+
+    wanted.iter().any(|id| {
+        if id == SMARTCARD {
+            smartcard_present()
+        } else {
+            usb_device_present(id)
+        }
+    })
+}
+
+/// Normalize `1050:0407` to the `VID_1050&PID_0407` form used in instance ids.
+fn normalize_device_id(id: &str) -> String {
+    let id = id.trim().to_uppercase();
+    match id.split_once(':') {
+        Some((vid, pid)) => format!("VID_{}&PID_{}", vid, pid),
+        None => id,
+    }
+}
+
+fn usb_device_present(id: &str) -> bool {
+    unsafe {
+        let Ok(devices) = SetupDiGetClassDevsW(
+            None,
+            w!("USB"),
+            HWND::default(),
+            DIGCF_PRESENT | DIGCF_ALLCLASSES,
+        ) else {
+            return false;
+        };
+
+        let mut found = false;
+        let mut index = 0;
+        loop {
+            let mut info = SP_DEVINFO_DATA {
+                cbSize: std::mem::size_of::<SP_DEVINFO_DATA>() as u32,
+                ..Default::default()
+            };
+            if SetupDiEnumDeviceInfo(devices, index, &mut info).is_err() {
+                break;
+            }
+            index += 1;
+
+            let mut buf = [0u16; 512];
+            if SetupDiGetDeviceInstanceIdW(devices, &info, Some(&mut buf), None).is_ok() {
+                let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+                let instance = String::from_utf16_lossy(&buf[..len]).to_uppercase();
+                if instance.contains(id) {
+                    found = true;
+                    break;
+                }
+            }
+        }
+        let _ = SetupDiDestroyDeviceInfoList(devices);
+        found
+    }
+}
+
+fn smartcard_present() -> bool {
+    unsafe {
+        let mut context = 0usize;
+        if SCardEstablishContext(SCARD_SCOPE_USER, None, None, &mut context) != 0 {
+            return false;
+        }
+
+        let mut len: u32 = 0;
+        let mut present = false;
+        if SCardListReadersW(context, PCWSTR::null(), PWSTR::null(), &mut len) == 0 && len > 0 {
+            let mut readers = vec![0u16; len as usize];
+            if SCardListReadersW(
+                context,
+                PCWSTR::null(),
+                PWSTR(readers.as_mut_ptr()),
+                &mut len,
+            ) == 0
+            {
+                // Multi-string: names separated by NUL, terminated by an empty name
+                let mut states: Vec<SCARD_READERSTATEW> = readers
+                    .split(|&c| c == 0)
+                    .take_while(|name| !name.is_empty())
+                    .map(|name| SCARD_READERSTATEW {
+                        szReader: PCWSTR(name.as_ptr()),
+                        dwCurrentState: SCARD_STATE_UNAWARE,
+                        ..Default::default()
+                    })
+                    .collect();
+                if !states.is_empty()
+                    && SCardGetStatusChangeW(context, 0, states.as_mut_ptr(), states.len() as u32)
+                        == 0
+                {
+                    present = states
+                        .iter()
+                        .any(|s| s.dwEventState.0 & SCARD_STATE_PRESENT.0 != 0);
+                }
+            }
+        }
+        SCardReleaseContext(context);
+        present
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_device_ids() {
+        assert_eq!(normalize_device_id("1050:0407"), "VID_1050&PID_0407");
+        assert_eq!(
+            normalize_device_id(" vid_1050&pid_0407 "),
+            "VID_1050&PID_0407"
+        );
+        assert_eq!(normalize_device_id("smartcard"), SMARTCARD);
+        assert_eq!(normalize_device_id(" "), "");
+    }
+}
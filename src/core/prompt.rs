@@ -0,0 +1,749 @@
+use std::env;
+
+const NOCACHE_PREFIX: &str = "[nocache]";
+const NOCACHE_ENV: &str = "WSL_SSH_ASKPASS_NOCACHE";
+const DISTRO_ENV: &str = "WSL_DISTRO_NAME";
+/// How ssh-add asks again after a wrong passphrase.
+const RETRY_PREFIX: &str = "Bad passphrase, try again";
+/// ssh-add's prompts, which name the key file without quotes.
+const SSH_ADD_PREFIXES: &[&str] = &["Enter passphrase for ", "Bad passphrase, try again for "];
+const CONFIRM_SUFFIX: &str = " (will confirm each use)";
+/// sudo's prompt when it runs `SUDO_ASKPASS`, before the user name.
+const SUDO_PREFIX: &str = "[sudo] password for ";
+
+/// Wording of keyboard-interactive questions for a one-time code, matched
+/// anywhere in the question, and words matched on their own.
+const ONE_TIME_CODE_PHRASES: &[&str] = &[
+    "verification code",
+    "one-time",
+    "one time password",
+    "authenticator code",
+    "authentication code",
+    "security code",
+    "token code",
+    "passcode",
+];
+const ONE_TIME_CODE_WORDS: &[&str] = &["otp", "totp", "hotp", "2fa", "mfa"];
+
+/// What ssh wants back, as told by `SSH_ASKPASS_PROMPT` (OpenSSH 8.4+).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromptKind {
+    /// Unset: a passphrase, password or other answer, told apart by the text
+    Entry,
+    /// `confirm`: a yes/no question, e.g. an agent key with a confirm constraint
+    Confirm,
+    /// `none`: a notice with nothing to answer, e.g. "Confirm user presence";
+    /// ssh ends the process once it is no longer needed
+    Notify,
+}
+
+impl PromptKind {
+    /// Interpret the value of `SSH_ASKPASS_PROMPT`.
+    pub fn from_hint(hint: Option<&str>) -> Self {
+        match hint {
+            Some("confirm") => PromptKind::Confirm,
+            Some("none") => PromptKind::Notify,
+            _ => PromptKind::Entry,
+        }
+    }
+}
+
+/// Strip a leading `[nocache]` hint injected by wrapper scripts. The hint can
+/// also be given as `WSL_SSH_ASKPASS_NOCACHE=1` passed through WSLENV.
+pub(crate) fn strip_nocache_hint(prompt: &str) -> (&str, bool) {
+    let env_hint = env::var(NOCACHE_ENV).is_ok_and(|v| !v.is_empty() && v != "0");
+    match prompt.trim_start().strip_prefix(NOCACHE_PREFIX) {
+        Some(rest) => (rest.trim_start(), true),
+        None => (prompt, env_hint),
+    }
+}
+
+/// Extract the key path quoted in prompts like "Enter passphrase for key '/path/to/key':",
+/// or named by ssh-add as in "Enter passphrase for /path/to/key:".
+pub(crate) fn extract_key_path(prompt: &str) -> Option<&str> {
+    let mut parts = prompt.splitn(3, '\'');
+    parts.next();
+    if let (Some(key_path), Some(_)) = (parts.next(), parts.next()) {
+        return Some(key_path);
+    }
+    let trimmed = prompt.trim();
+    let rest = SSH_ADD_PREFIXES
+        .iter()
+        .find_map(|prefix| trimmed.strip_prefix(prefix))?
+        .strip_suffix(':')?;
+    let key_path = rest.strip_suffix(CONFIRM_SUFFIX).unwrap_or(rest);
+    (!key_path.is_empty() && !key_path.contains('\n')).then_some(key_path)
+}
+
+/// Whether `prompt` is ssh-add asking again because the passphrase it was
+/// given did not open the key.
+pub(crate) fn is_retry_prompt(prompt: &str) -> bool {
+    prompt.trim_start().starts_with(RETRY_PREFIX)
+}
+
+/// Extract key name from prompt like "Enter passphrase for key '/path/to/key':"
+///
+/// Works on whole characters only, so any Unicode path is safe. WSL and
+/// Windows spellings of the same file (`/mnt/c/...`, `C:\...`, `C:/...`)
+/// yield the same name.
+pub(crate) fn extract_key_name(prompt: &str) -> String {
+    if let Some(key_path) = extract_key_path(prompt) {
+        // Return just the filename
+        let wsl = wsl_form(key_path);
+        let name = wsl.rsplit('/').next().unwrap_or(&wsl);
+        if !name.is_empty() {
+            return name.to_string();
+        }
+    }
+    "default".to_string()
+}
+
+/// The cache key for the key file named in `prompt`: its file name and a
+/// hash of its full path, e.g. `id_ed25519#3c1f9a2e`, so files of the same
+/// name in different directories are cached apart. WSL and Windows
+/// spellings of one file hash alike, as do case variants of a path on a
+/// Windows drive. Without a path it is `default`, like [`extract_key_name`].
+pub(crate) fn extract_key_id(prompt: &str) -> String {
+    extract_key_path(prompt)
+        .and_then(key_id)
+        .unwrap_or_else(|| "default".to_string())
+}
+
+/// The cache key for the key file at `key_path`, as [`extract_key_id`]
+/// makes it; `None` for a directory.
+pub(crate) fn key_id(key_path: &str) -> Option<String> {
+    let wsl = wsl_form(key_path);
+    let name = wsl.rsplit('/').next().unwrap_or(&wsl);
+    (!name.is_empty()).then(|| format!("{}#{:08x}", name, path_hash(key_path)))
+}
+
+/// The file name part of a cache key made by [`extract_key_id`]; other keys
+/// are returned unchanged.
+pub(crate) fn key_label(key: &str) -> &str {
+    match key.rsplit_once('#') {
+        Some((label, hash)) if hash.len() == 8 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            label
+        }
+        _ => key,
+    }
+}
+
+/// 64-bit FNV-1a of the path as seen from WSL, folded to 32 bits. Stable
+/// across builds and platforms, which `std`'s hasher does not promise.
+fn path_hash(path: &str) -> u32 {
+    let mut wsl = wsl_form(path);
+    if windows_drive_form(&wsl).is_some() {
+        // Windows drives ignore case
+        wsl = wsl.to_lowercase();
+    }
+    let hash = wsl.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    (hash ^ (hash >> 32)) as u32
+}
+
+/// The prompt as shown in the dialog, with the key path also named in its
+/// other spelling so users can tell which file is being unlocked: `C:\...`
+/// for keys under `/mnt/c`, `/mnt/c/...` for Windows paths, and
+/// `\\wsl$\<distro>\...` for files inside the distro.
+///
+/// The distro comes from `WSL_DISTRO_NAME`, and `~` expands using `HOME`;
+/// both only reach Windows when listed in `WSLENV`.
+pub(crate) fn display_prompt(prompt: &str) -> String {
+    let distro = env::var(DISTRO_ENV).ok();
+    let home = env::var("HOME").ok();
+    display_prompt_in(prompt, distro.as_deref(), home.as_deref())
+}
+
+fn display_prompt_in(prompt: &str, distro: Option<&str>, home: Option<&str>) -> String {
+    let Some(key_path) = extract_key_path(prompt) else {
+        return prompt.to_string();
+    };
+    let other = if split_drive(key_path).is_some() {
+        Some(wsl_form(key_path))
+    } else {
+        windows_drive_form(key_path).or_else(|| unc_form(key_path, distro?, home))
+    };
+    match other {
+        Some(other) => format!("{}\n({})", prompt, other),
+        None => prompt.to_string(),
+    }
+}
+
+/// `\\wsl$\<distro>\...` for an absolute (or `~`-relative) path inside the
+/// distro; `home` must be a Linux path for `~` to expand.
+pub(crate) fn unc_form(path: &str, distro: &str, home: Option<&str>) -> Option<String> {
+    let absolute = if let Some(rest) = path.strip_prefix("~/") {
+        let home = home.filter(|h| h.starts_with('/'))?;
+        format!("{}/{}", home.trim_end_matches('/'), rest)
+    } else if path.starts_with('/') {
+        path.to_string()
+    } else {
+        return None;
+    };
+    if distro.is_empty() {
+        return None;
+    }
+    Some(format!(r"\\wsl$\{}{}", distro, absolute.replace('/', r"\")))
+}
+
+/// Split a leading drive letter off `C:\...` or `C:/...` (or drive-relative `C:file`).
+fn split_drive(path: &str) -> Option<(char, &str)> {
+    let mut chars = path.chars();
+    let drive = chars.next().filter(char::is_ascii_alphabetic)?;
+    let rest = chars.as_str().strip_prefix(':')?;
+    Some((drive.to_ascii_lowercase(), rest))
+}
+
+/// The path as seen from WSL: Windows drive paths become `/mnt/<drive>/...`
+/// and backslashes become slashes.
+pub(crate) fn wsl_form(path: &str) -> String {
+    match split_drive(path) {
+        Some((drive, rest)) => {
+            let rest = rest.replace('\\', "/");
+            format!("/mnt/{}/{}", drive, rest.trim_start_matches('/'))
+        }
+        None => path.replace('\\', "/"),
+    }
+}
+
+/// The path as seen from Windows, when it lives on a Windows drive:
+/// `/mnt/c/Users/me/.ssh/id_rsa` and `C:/Users/me/.ssh/id_rsa` both become
+/// `C:\Users\me\.ssh\id_rsa`.
+pub(crate) fn windows_drive_form(path: &str) -> Option<String> {
+    let wsl = wsl_form(path);
+    let rest = wsl.strip_prefix("/mnt/")?;
+    let mut chars = rest.chars();
+    let drive = chars.next().filter(char::is_ascii_alphabetic)?;
+    let tail = chars.as_str();
+    if !(tail.is_empty() || tail.starts_with('/')) {
+        return None;
+    }
+    Some(format!(
+        "{}:\\{}",
+        drive.to_ascii_uppercase(),
+        tail.trim_start_matches('/').replace('/', "\\")
+    ))
+}
+
+/// Extract the account from password prompts like "user@host's password:"
+/// or the keyboard-interactive form "(user@host) Password:".
+pub(crate) fn extract_password_target(prompt: &str) -> Option<String> {
+    let trimmed = prompt.trim();
+    let target = if let Some(rest) = trimmed.strip_prefix('(') {
+        let (target, tail) = rest.split_once(')')?;
+        if !tail.trim().eq_ignore_ascii_case("password:") {
+            return None;
+        }
+        target
+    } else {
+        trimmed.strip_suffix("'s password:")?
+    };
+    if target.contains('@') && !target.contains(char::is_whitespace) {
+        Some(format!("password:{}", target))
+    } else {
+        None
+    }
+}
+
+/// Extract the principal from kinit-style prompts like "Password for user@REALM:",
+/// namespaced by realm so each realm's passwords are cached separately.
+pub(crate) fn extract_kerberos_target(prompt: &str) -> Option<String> {
+    let principal = prompt
+        .trim()
+        .strip_prefix("Password for ")?
+        .strip_suffix(':')?
+        .trim();
+    let (user, realm) = principal.rsplit_once('@')?;
+    if user.is_empty() || realm.is_empty() || principal.contains(char::is_whitespace) {
+        return None;
+    }
+    Some(format!("krb5:{}:{}", realm, user))
+}
+
+/// Extract the account from sudo's default prompt, "[sudo] password for user:",
+/// as asked through `SUDO_ASKPASS`. The same user name in another distro is
+/// another account, so the distro from `WSL_DISTRO_NAME` is part of the key.
+pub(crate) fn extract_sudo_target(prompt: &str) -> Option<String> {
+    let distro = env::var(DISTRO_ENV).ok().filter(|d| !d.is_empty());
+    sudo_target_in(prompt, distro.as_deref())
+}
+
+fn sudo_target_in(prompt: &str, distro: Option<&str>) -> Option<String> {
+    let user = prompt
+        .trim()
+        .strip_prefix(SUDO_PREFIX)?
+        .strip_suffix(':')?
+        .trim();
+    if user.is_empty() || user.contains(char::is_whitespace) {
+        return None;
+    }
+    Some(match distro {
+        Some(distro) => format!("sudo:{}:{}", distro, user),
+        None => format!("sudo:{}", user),
+    })
+}
+
+/// PKCS#11 / smartcard PIN requests such as "Enter PIN for 'PIV Card Holder pin':".
+pub(crate) fn is_pin_prompt(prompt: &str) -> bool {
+    prompt
+        .trim_start()
+        .to_lowercase()
+        .starts_with("enter pin for")
+}
+
+/// Short questions answered with a letter, such as ssh-keygen's
+/// "Overwrite (y/n)?" or "Continue? [Y/n]", asked on their last line.
+pub(crate) fn is_yes_no_prompt(prompt: &str) -> bool {
+    prompt.trim_end().lines().last().is_some_and(|last| {
+        let last = last.to_ascii_lowercase();
+        last.contains("(y/n)") || last.contains("[y/n]")
+    })
+}
+
+/// Keyboard-interactive questions for a one-time code, such as Google
+/// Authenticator's "Verification code:", "OTP:" or "Duo passcode:", asked on
+/// their last line. ssh's "(user@host) " prefix is not looked at, and
+/// neither is any prompt for a key file's passphrase.
+pub(crate) fn is_one_time_code_prompt(prompt: &str) -> bool {
+    if extract_key_path(prompt).is_some() {
+        return false;
+    }
+    let Some(last) = prompt.lines().rev().find(|line| !line.trim().is_empty()) else {
+        return false;
+    };
+    let last = last.trim().to_lowercase();
+    let question = match last.split_once(") ") {
+        Some((account, question)) if account.starts_with('(') => question,
+        _ => &last,
+    };
+    ONE_TIME_CODE_PHRASES
+        .iter()
+        .any(|phrase| question.contains(phrase))
+        || question
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .any(|word| ONE_TIME_CODE_WORDS.contains(&word))
+}
+
+/// A numbered menu embedded in a keyboard-interactive prompt.
+pub(crate) struct MenuPrompt {
+    pub(crate) header: String,
+    /// (option number, label) pairs in display order
+    pub(crate) options: Vec<(String, String)>,
+    /// A passcode may be typed instead, as in Duo's "Passcode or option (1-3):"
+    pub(crate) accepts_passcode: bool,
+}
+
+/// Words a question below a menu asks with ("Passcode or option (1-3):",
+/// "Enter choice:").
+const MENU_QUESTION_WORDS: &[&str] = &["option", "choice", "choose", "select", "number"];
+
+/// Parse the numbered menus of keyboard-interactive two-factor prompts:
+/// Duo's, which lists options like " 1. Duo Push to XXX-XXX-1234" above
+/// "Passcode or option (1-3):", and other gateways', numbered `1)` or `[1]`
+/// or with the whole menu on one line ("1. push 2. phone 3. passcode:").
+///
+/// Options must be numbered from 1 without gaps, and the prompt must end
+/// asking, with ':' or '?'. A question on a line of its own must ask for
+/// a choice, and a lone option only counts when the question names it as
+/// one, since a numbered banner above a password prompt is no menu.
+pub(crate) fn parse_menu(prompt: &str) -> Option<MenuPrompt> {
+    let prompt = prompt.trim_end();
+    if !prompt.ends_with(':') && !prompt.ends_with('?') {
+        return None;
+    }
+
+    let mut header = Vec::new();
+    let mut options = Vec::new();
+    let mut question = String::new();
+    for line in prompt
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let found = numbered(line, options.len() + 1);
+        if !found.is_empty() {
+            options.extend(found);
+        } else if options.is_empty() {
+            header.push(line);
+        } else {
+            question = line.to_lowercase();
+        }
+    }
+
+    let asks_for_choice = question.is_empty()
+        || MENU_QUESTION_WORDS
+            .iter()
+            .any(|word| question.contains(word));
+    if options.is_empty()
+        || !asks_for_choice
+        || (options.len() == 1 && !question.contains("option"))
+    {
+        return None;
+    }
+    Some(MenuPrompt {
+        header: header.join("\n"),
+        options,
+        accepts_passcode: question.contains("passcode"),
+    })
+}
+
+/// The options on `line`: one ("1. push") or several in a row ("1. push
+/// 2. phone"), the first numbered `first`.
+fn numbered(line: &str, first: usize) -> Vec<(String, String)> {
+    let mut options = Vec::new();
+    let Some(len) = marker_len(line, first) else {
+        return options;
+    };
+    let mut number = first;
+    let mut rest = &line[len..];
+    loop {
+        // The next number's marker, at the start of a word
+        let next = rest.char_indices().find_map(|(i, c)| {
+            let after = i + c.len_utf8();
+            (c.is_whitespace())
+                .then(|| marker_len(&rest[after..], number + 1).map(|len| (i, after + len)))
+                .flatten()
+        });
+        let label = match next {
+            Some((end, _)) => &rest[..end],
+            None => rest,
+        };
+        let label = label.trim().trim_end_matches([':', '?']).trim_end();
+        if label.is_empty() {
+            return Vec::new();
+        }
+        options.push((number.to_string(), label.to_string()));
+        match next {
+            Some((_, start)) => {
+                number += 1;
+                rest = &rest[start..];
+            }
+            None => return options,
+        }
+    }
+}
+
+/// Length of the marker for option `number` (`1.`, `1)` or `[1]`) that
+/// `text` starts with, if a space follows it.
+fn marker_len(text: &str, number: usize) -> Option<usize> {
+    [
+        format!("{}.", number),
+        format!("{})", number),
+        format!("[{}]", number),
+    ]
+    .into_iter()
+    .find_map(|marker| {
+        text.strip_prefix(marker.as_str())?
+            .starts_with(char::is_whitespace)
+            .then_some(marker.len())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn prompt_kinds() {
+        assert_eq!(PromptKind::from_hint(None), PromptKind::Entry);
+        assert_eq!(PromptKind::from_hint(Some("confirm")), PromptKind::Confirm);
+        assert_eq!(PromptKind::from_hint(Some("none")), PromptKind::Notify);
+        assert_eq!(PromptKind::from_hint(Some("")), PromptKind::Entry);
+    }
+
+    #[test]
+    fn duo_menus() {
+        let menu = parse_menu(
+            "Duo two-factor login for me\n\n\
+             Enter a passcode or select one of the following options:\n\n \
+             1. Duo Push to XXX-XXX-1234\n \
+             2. Phone call to XXX-XXX-1234\n \
+             3. SMS passcodes to XXX-XXX-1234\n\n\
+             Passcode or option (1-3): ",
+        )
+        .unwrap();
+        assert_eq!(
+            menu.header,
+            "Duo two-factor login for me\nEnter a passcode or select one of the following options:"
+        );
+        assert_eq!(menu.options.len(), 3);
+        assert_eq!(
+            menu.options[1],
+            ("2".into(), "Phone call to XXX-XXX-1234".into())
+        );
+        assert!(menu.accepts_passcode);
+
+        let single = parse_menu(" 1. Duo Push to iOS\nPasscode or option (1-1): ").unwrap();
+        assert_eq!(single.options, [("1".into(), "Duo Push to iOS".into())]);
+    }
+
+    #[test]
+    fn other_numeric_menus() {
+        let inline = parse_menu("1. push 2. phone 3. passcode:").unwrap();
+        assert_eq!(
+            inline.options,
+            [
+                ("1".into(), "push".into()),
+                ("2".into(), "phone".into()),
+                ("3".into(), "passcode".into()),
+            ]
+        );
+        assert_eq!(inline.header, "");
+        assert!(!inline.accepts_passcode);
+
+        let bracketed =
+            parse_menu("Select a method:\n[1] Okta Verify push\n[2] SMS to 555-0100\nChoice? ")
+                .unwrap();
+        assert_eq!(bracketed.header, "Select a method:");
+        assert_eq!(bracketed.options[1], ("2".into(), "SMS to 555-0100".into()));
+
+        let parens = parse_menu("1) Push 2) Token code\nEnter choice:").unwrap();
+        assert_eq!(parens.options.len(), 2);
+
+        // Not menus: numbering that skips, one stray option, no question
+        assert!(parse_menu("1. push 3. phone:").is_none());
+        assert!(parse_menu("1. Reset your password first\nPassword:").is_none());
+        assert!(parse_menu("1. Authorized use only\n2. Activity is logged\nPassword:").is_none());
+        assert!(parse_menu("1. push 2. phone\nEnter a number:").is_some());
+        assert!(parse_menu("1. push 2. phone").is_none());
+        assert!(parse_menu("Enter passphrase for key '/home/me/.ssh/id_rsa':").is_none());
+        assert!(parse_menu("10.0.0.1 2. x:").is_none());
+    }
+
+    #[test]
+    fn yes_no_questions() {
+        assert!(is_yes_no_prompt(
+            "/home/me/.ssh/id_ed25519 already exists.\nOverwrite (y/n)? "
+        ));
+        assert!(is_yes_no_prompt("Continue? [Y/n]"));
+        assert!(is_yes_no_prompt("Replace it? [y/N]\r\n"));
+        assert!(!is_yes_no_prompt("Overwrite (y/n)?\nEnter passphrase:"));
+        assert!(!is_yes_no_prompt(
+            "Are you sure you want to continue connecting (yes/no)? "
+        ));
+        assert!(!is_yes_no_prompt("Enter passphrase for key 'y/n':"));
+    }
+
+    #[test]
+    fn one_time_code_questions() {
+        assert!(is_one_time_code_prompt("Verification code: "));
+        assert!(is_one_time_code_prompt("(me@bastion) Verification code:"));
+        assert!(is_one_time_code_prompt("OTP:"));
+        assert!(is_one_time_code_prompt("Duo passcode:"));
+        assert!(is_one_time_code_prompt("Enter PASSCODE:"));
+        assert!(is_one_time_code_prompt("Password + 2FA token:"));
+        assert!(is_one_time_code_prompt(
+            "Welcome to bastion\r\nOne-time password (OATH) for `me':"
+        ));
+        assert!(!is_one_time_code_prompt("(me@otp.example.com) Password:"));
+        assert!(!is_one_time_code_prompt(
+            "Enter passphrase for key '/home/me/.ssh/otp':"
+        ));
+        assert!(!is_one_time_code_prompt("[sudo] password for me:"));
+        assert!(!is_one_time_code_prompt("Password for hotpot@EXAMPLE.COM:"));
+        assert!(!is_one_time_code_prompt(""));
+    }
+
+    #[test]
+    fn key_name_from_unicode_paths() {
+        assert_eq!(
+            extract_key_name("Enter passphrase for key '/home/jürgen/.ssh/id_ed25519':"),
+            "id_ed25519"
+        );
+        assert_eq!(
+            extract_key_name("Enter passphrase for key '/home/me/.ssh/ключ_é':"),
+            "ключ_é"
+        );
+        assert_eq!(
+            extract_key_name("Enter passphrase for key '/home/me/.ssh/鍵🔑':"),
+            "鍵🔑"
+        );
+        assert_eq!(extract_key_name("日本語'é'"), "é");
+    }
+
+    #[test]
+    fn key_name_from_backslash_paths() {
+        assert_eq!(
+            extract_key_name(r"Enter passphrase for key 'C:\Users\me\.ssh\id_rsa':"),
+            "id_rsa"
+        );
+    }
+
+    #[test]
+    fn windows_and_wsl_paths_name_the_same_key() {
+        let windows = r"Enter passphrase for key 'C:\Users\me\.ssh\id_rsa':";
+        let forward = "Enter passphrase for key 'C:/Users/me/.ssh/id_rsa':";
+        let wsl = "Enter passphrase for key '/mnt/c/Users/me/.ssh/id_rsa':";
+        assert_eq!(extract_key_name(windows), "id_rsa");
+        assert_eq!(extract_key_name(forward), "id_rsa");
+        assert_eq!(extract_key_name(wsl), "id_rsa");
+        assert_eq!(
+            extract_key_name("Enter passphrase for key 'C:id_rsa':"),
+            "id_rsa"
+        );
+    }
+
+    #[test]
+    fn path_forms_round_trip() {
+        assert_eq!(
+            wsl_form(r"C:\Users\me\.ssh\id_rsa"),
+            "/mnt/c/Users/me/.ssh/id_rsa"
+        );
+        assert_eq!(wsl_form("D:/keys/id"), "/mnt/d/keys/id");
+        assert_eq!(wsl_form("/home/me/.ssh/id"), "/home/me/.ssh/id");
+        assert_eq!(
+            windows_drive_form("/mnt/c/Users/me/.ssh/id_rsa").as_deref(),
+            Some(r"C:\Users\me\.ssh\id_rsa")
+        );
+        assert_eq!(
+            windows_drive_form(r"c:\Users\me\id").as_deref(),
+            Some(r"C:\Users\me\id")
+        );
+        assert_eq!(windows_drive_form("/home/me/.ssh/id"), None);
+        assert_eq!(windows_drive_form("/mnt/wsl/shared/id"), None);
+    }
+
+    #[test]
+    fn display_names_both_drive_forms() {
+        assert_eq!(
+            display_prompt_in("Enter passphrase for key '/mnt/c/Users/me/.ssh/id_rsa':", None, None),
+            "Enter passphrase for key '/mnt/c/Users/me/.ssh/id_rsa':\n(C:\\Users\\me\\.ssh\\id_rsa)"
+        );
+        assert_eq!(
+            display_prompt_in(r"Enter passphrase for key 'C:\Users\me\.ssh\id_rsa':", None, None),
+            "Enter passphrase for key 'C:\\Users\\me\\.ssh\\id_rsa':\n(/mnt/c/Users/me/.ssh/id_rsa)"
+        );
+        let linux = "Enter passphrase for key '/home/me/.ssh/id_rsa':";
+        assert_eq!(display_prompt_in(linux, None, None), linux);
+    }
+
+    #[test]
+    fn display_names_the_wsl_share_path() {
+        let home = "Enter passphrase for key '~/.ssh/id_ed25519':";
+        assert_eq!(
+            display_prompt_in(home, Some("Ubuntu"), Some("/home/me")),
+            format!("{}\n({})", home, r"\\wsl$\Ubuntu\home\me\.ssh\id_ed25519")
+        );
+        let absolute = "Enter passphrase for key '/home/me/.ssh/id_ed25519':";
+        assert_eq!(
+            display_prompt_in(absolute, Some("Ubuntu"), None),
+            format!(
+                "{}\n({})",
+                absolute, r"\\wsl$\Ubuntu\home\me\.ssh\id_ed25519"
+            )
+        );
+        // Without the distro or a Linux home there is nothing reliable to show
+        assert_eq!(display_prompt_in(absolute, None, None), absolute);
+        assert_eq!(display_prompt_in(home, Some("Ubuntu"), None), home);
+        assert_eq!(
+            display_prompt_in(home, Some("Ubuntu"), Some(r"C:\Users\me")),
+            home
+        );
+    }
+
+    #[test]
+    fn key_name_defaults() {
+        assert_eq!(extract_key_name("Enter passphrase:"), "default");
+        assert_eq!(
+            extract_key_name("Enter passphrase for key '/unterminated"),
+            "default"
+        );
+        assert_eq!(
+            extract_key_name("Enter passphrase for key '/dir/':"),
+            "default"
+        );
+    }
+
+    #[test]
+    fn key_ids_tell_directories_apart() {
+        let work = extract_key_id("Enter passphrase for key '/home/me/work/id_ed25519':");
+        let personal = extract_key_id("Enter passphrase for key '/home/me/personal/id_ed25519':");
+        assert!(work.starts_with("id_ed25519#"), "{}", work);
+        assert_eq!(work.len(), "id_ed25519#".len() + 8);
+        assert_ne!(work, personal);
+        assert_eq!(key_label(&work), "id_ed25519");
+        assert_eq!(extract_key_id("Enter passphrase:"), "default");
+        assert_eq!(
+            extract_key_id("Enter passphrase for key '/dir/':"),
+            "default"
+        );
+    }
+
+    #[test]
+    fn key_ids_follow_the_file_not_its_spelling() {
+        let windows = extract_key_id(r"Enter passphrase for key 'C:\Users\Me\.ssh\id_rsa':");
+        let forward = extract_key_id("Enter passphrase for key 'c:/users/me/.ssh/id_rsa':");
+        let wsl = extract_key_id("Enter passphrase for key '/mnt/c/Users/me/.ssh/id_rsa':");
+        assert_eq!(windows, forward);
+        assert_eq!(windows, wsl);
+        // Linux paths are case-sensitive
+        assert_ne!(
+            extract_key_id("Enter passphrase for key '/home/me/.ssh/ID_RSA':"),
+            extract_key_id("Enter passphrase for key '/home/ME/.ssh/ID_RSA':")
+        );
+    }
+
+    #[test]
+    fn sudo_prompts_name_user_and_distro() {
+        assert_eq!(
+            sudo_target_in("[sudo] password for me: ", Some("Ubuntu")),
+            Some("sudo:Ubuntu:me".to_string())
+        );
+        assert_eq!(
+            sudo_target_in("[sudo] password for me:", None),
+            Some("sudo:me".to_string())
+        );
+        assert_eq!(sudo_target_in("[sudo] password for : ", None), None);
+        assert_eq!(sudo_target_in("Password: ", Some("Ubuntu")), None);
+        assert_eq!(sudo_target_in("me@host's password:", None), None);
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(key_label("id_rsa#0badf00d"), "id_rsa");
+        assert_eq!(key_label("a#b#12345678"), "a#b");
+        assert_eq!(key_label("id_rsa"), "id_rsa");
+        assert_eq!(key_label("id#rsa"), "id#rsa");
+        assert_eq!(key_label("password:me@host"), "password:me@host");
+    }
+
+    #[test]
+    fn ssh_add_prompts_name_their_key() {
+        let first = "Enter passphrase for /home/me/.ssh/id_ed25519: ";
+        let retry = "Bad passphrase, try again for /home/me/.ssh/id_ed25519: ";
+        let confirm =
+            "Bad passphrase, try again for /home/me/.ssh/id_ed25519 (will confirm each use): ";
+        assert_eq!(extract_key_path(first), Some("/home/me/.ssh/id_ed25519"));
+        assert_eq!(extract_key_id(retry), extract_key_id(first));
+        assert_eq!(extract_key_id(confirm), extract_key_id(first));
+        assert!(is_retry_prompt(retry));
+        assert!(!is_retry_prompt(first));
+        assert!(!is_retry_prompt(
+            "Enter passphrase for key '/home/me/.ssh/id_ed25519':"
+        ));
+        assert_eq!(extract_key_path("Enter passphrase for : "), None);
+    }
+
+    proptest! {
+        #[test]
+        fn key_name_never_panics(prompt in any::<String>()) {
+            let _ = extract_key_name(&prompt);
+        }
+
+        #[test]
+        fn key_name_is_the_last_path_component(
+            dirs in prop::collection::vec("[^'/\\\\]*", 0..4),
+            name in "[^'/\\\\]+",
+            backslash in any::<bool>(),
+        ) {
+            let sep = if backslash { "\\" } else { "/" };
+            let mut path = dirs.join(sep);
+            path.push_str(sep);
+            path.push_str(&name);
+            let prompt = format!("Enter passphrase for key '{}':", path);
+            prop_assert_eq!(extract_key_name(&prompt), name);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Bluetooth proximity factor, mirroring Windows dynamic lock: the Hello
+//! grace window only counts while a paired phone is advertising nearby.
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use windows::{
+    Devices::Bluetooth::Advertisement::{
+        BluetoothLEAdvertisementReceivedEventArgs, BluetoothLEAdvertisementWatcher,
+        BluetoothLEScanningMode,
+    },
+    Foundation::TypedEventHandler,
+};
+
+use crate::core::{config, policy};
+
+const DEFAULT_MIN_RSSI: i16 = -70; // dBm, roughly "same room"
+const SCAN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Returns `true` when no proximity device is configured, or the configured
+/// device (`AA:BB:CC:DD:EE:FF`) is heard with at least the configured dBm
+/// within a short scan. Both come from the `ProximityDevice` and
+/// `ProximityRssi` string policies, else `proximity_device` and
+/// `proximity_rssi` in the config file; never from the environment, where
+/// the caller could leave the device out.
+pub(crate) fn device_in_range() -> bool {
+    let config = config::get();
+    let Some(spec) =
+        policy::policy_text("ProximityDevice").or_else(|| config.proximity_device.clone())
+    else {
+        return true;
+    };
+    let Some(address) = parse_bluetooth_address(&spec) else {
+        // A configured but unparsable address must not silently disable the factor
+        return false;
+    };
+    let min_rssi = policy::policy_text("ProximityRssi")
+        .and_then(|v| v.trim().parse::<i16>().ok())
+        .or(config.proximity_rssi)
+        .unwrap_or(DEFAULT_MIN_RSSI);
+
+    scan_for(address, min_rssi).unwrap_or(false)
+}
+
+/// Parse `AA:BB:CC:DD:EE:FF` (or `-` separated) into the 48-bit address.
+pub(crate) fn parse_bluetooth_address(s: &str) -> Option<u64> {
+    let hex: String = s
+        .trim()
+        .chars()
+        .filter(|c| *c != ':' && *c != '-')
+        .collect();
+    if hex.len() != 12 {
+        return None;
+    }
+    u64::from_str_radix(&hex, 16).ok()
+}
+
+fn scan_for(address: u64, min_rssi: i16) -> windows::core::Result<bool> {
+    let watcher = BluetoothLEAdvertisementWatcher::new()?;
+    watcher.SetScanningMode(BluetoothLEScanningMode::Passive)?;
+
+    let (tx, rx) = mpsc::channel();
+    let token = watcher.Received(&TypedEventHandler::new(
+        move |_, args: &Option<BluetoothLEAdvertisementReceivedEventArgs>| {
+            if let Some(args) = args {
+                if args.BluetoothAddress()? == address && args.RawSignalStrengthInDBm()? >= min_rssi
+                {
+                    let _ = tx.send(());
+                }
+            }
+            Ok(())
+        },
+    ))?;
+
+    watcher.Start()?;
+    let found = rx.recv_timeout(SCAN_TIMEOUT).is_ok();
+    let _ = watcher.Stop();
+    let _ = watcher.RemoveReceived(token);
+    Ok(found)
+}
@@ -0,0 +1,118 @@
+//! Fingerprint of the current desktop session, so per-session state (the
+//! Hello grace window) is worthless if copied to another logon or boot, and
+//! the user SIDs that keep one user's daemon apart from another's.
+
+use windows::{
+    core::{w, PWSTR},
+    Win32::Foundation::{CloseHandle, LocalFree, HANDLE, HLOCAL},
+    Win32::Security::Authorization::ConvertSidToStringSidW,
+    Win32::Security::{
+        GetTokenInformation, TokenStatistics, TokenUser, TOKEN_QUERY, TOKEN_STATISTICS, TOKEN_USER,
+    },
+    Win32::System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_DWORD},
+    Win32::System::RemoteDesktop::ProcessIdToSessionId,
+    Win32::System::Threading::{
+        GetCurrentProcess, GetCurrentProcessId, OpenProcess, OpenProcessToken,
+        PROCESS_QUERY_LIMITED_INFORMATION,
+    },
+};
+
+/// `<logon session LUID>.<terminal session id>.<boot id>`. Parts that cannot
+/// be read are left empty, which still never matches a fully read print.
+pub(crate) fn fingerprint() -> String {
+    let logon =
+        logon_session().map_or(String::new(), |(high, low)| format!("{:x}-{:x}", high, low));
+    let session = terminal_session().map_or(String::new(), |id| id.to_string());
+    let boot = boot_id().map_or(String::new(), |id| id.to_string());
+    format!("{}.{}.{}", logon, session, boot)
+}
+
+fn logon_session() -> Option<(i32, u32)> {
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token).ok()?;
+        let mut stats = TOKEN_STATISTICS::default();
+        let mut len = 0u32;
+        let ok = GetTokenInformation(
+            token,
+            TokenStatistics,
+            Some(&mut stats as *mut _ as *mut _),
+            std::mem::size_of::<TOKEN_STATISTICS>() as u32,
+            &mut len,
+        )
+        .is_ok();
+        let _ = CloseHandle(token);
+        ok.then_some((
+            stats.AuthenticationId.HighPart,
+            stats.AuthenticationId.LowPart,
+        ))
+    }
+}
+
+/// The current user's SID, as `S-1-5-21-...`.
+pub(crate) fn user_sid() -> Option<String> {
+    token_sid(unsafe { GetCurrentProcess() })
+}
+
+/// The SID of the user running process `pid`, if this user may look.
+pub(crate) fn process_sid(pid: u32) -> Option<String> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let sid = token_sid(process);
+        let _ = CloseHandle(process);
+        sid
+    }
+}
+
+fn token_sid(process: HANDLE) -> Option<String> {
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(process, TOKEN_QUERY, &mut token).ok()?;
+        let mut len = 0u32;
+        let _ = GetTokenInformation(token, TokenUser, None, 0, &mut len);
+        // u64s keep the SID pointer inside the buffer aligned
+        let mut buffer = vec![0u64; (len as usize).div_ceil(8)];
+        let ok = GetTokenInformation(
+            token,
+            TokenUser,
+            Some(buffer.as_mut_ptr() as *mut _),
+            len,
+            &mut len,
+        )
+        .is_ok();
+        let _ = CloseHandle(token);
+        if !ok {
+            return None;
+        }
+        let user = &*(buffer.as_ptr() as *const TOKEN_USER);
+        let mut text = PWSTR::null();
+        ConvertSidToStringSidW(user.User.Sid, &mut text).ok()?;
+        let sid = text.to_string().ok();
+        let _ = LocalFree(HLOCAL(text.0 as *mut _));
+        sid
+    }
+}
+
+fn terminal_session() -> Option<u32> {
+    let mut id = 0u32;
+    unsafe { ProcessIdToSessionId(GetCurrentProcessId(), &mut id).ok()? };
+    Some(id)
+}
+
+/// Incremented by Windows on every boot.
+fn boot_id() -> Option<u32> {
+    let mut value = 0u32;
+    let mut size = std::mem::size_of::<u32>() as u32;
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            w!("SYSTEM\\CurrentControlSet\\Control\\Session Manager\\Memory Management\\PrefetchParameters"),
+            w!("BootId"),
+            RRF_RT_REG_DWORD,
+            None,
+            Some(&mut value as *mut u32 as *mut _),
+            Some(&mut size),
+        )
+    };
+    status.is_ok().then_some(value)
+}
@@ -0,0 +1,79 @@
+//! `--time` diagnostics: where the wall time of one invocation goes, to
+//! quantify WSL interop latency.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use windows::{
+    Win32::Foundation::FILETIME,
+    Win32::System::Threading::{GetCurrentProcess, GetProcessTimes},
+};
+
+/// Difference between the FILETIME epoch (1601) and the Unix epoch, in 100ns units.
+const FILETIME_UNIX_OFFSET: u64 = 116_444_736_000_000_000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static PHASES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+static STARTED: Mutex<Option<(Instant, Duration)>> = Mutex::new(None);
+
+/// Start collecting timings. Call first thing in `main`; the time from
+/// process creation until now is reported as startup.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+    *STARTED.lock().unwrap() = Some((Instant::now(), since_process_creation()));
+}
+
+/// Run `f`, adding its duration to `phase` when timing is enabled.
+pub(crate) fn time<T>(phase: &'static str, f: impl FnOnce() -> T) -> T {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return f();
+    }
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    let mut phases = PHASES.lock().unwrap();
+    match phases.iter_mut().find(|(name, _)| *name == phase) {
+        Some((_, total)) => *total += elapsed,
+        None => phases.push((phase, elapsed)),
+    }
+    result
+}
+
+/// Print the breakdown to stderr, if timing was enabled.
+pub fn report() {
+    let Some((enabled_at, startup)) = *STARTED.lock().unwrap() else {
+        return;
+    };
+    let total = startup + enabled_at.elapsed();
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    eprintln!("wsl-ssh-askpass timing:");
+    eprintln!("  {:<16}{:>10.1} ms", "startup", ms(startup));
+    for (phase, elapsed) in PHASES.lock().unwrap().iter() {
+        eprintln!("  {:<16}{:>10.1} ms", phase, ms(*elapsed));
+    }
+    eprintln!("  {:<16}{:>10.1} ms", "total", ms(total));
+}
+
+fn since_process_creation() -> Duration {
+    let mut creation = FILETIME::default();
+    let (mut exit, mut kernel, mut user) = Default::default();
+    let created = unsafe {
+        GetProcessTimes(
+            GetCurrentProcess(),
+            &mut creation,
+            &mut exit,
+            &mut kernel,
+            &mut user,
+        )
+    };
+    if created.is_err() {
+        return Duration::ZERO;
+    }
+    let created_100ns =
+        (u64::from(creation.dwHighDateTime) << 32) | u64::from(creation.dwLowDateTime);
+    let created =
+        UNIX_EPOCH + Duration::from_nanos(created_100ns.saturating_sub(FILETIME_UNIX_OFFSET) * 100);
+    // Zero, not a failure, when the clock has been turned back since
+    created.elapsed().unwrap_or_default()
+}
@@ -0,0 +1,99 @@
+//! Waiting on WinRT async operations without letting a stuck one wedge the
+//! process.
+//!
+//! `.get()` blocks until the operation finishes, however long that takes. A
+//! Hello request whose prompt never appears, or an HTTP call to a host that
+//! never answers, would then hold ssh forever. [`wait`] instead completes
+//! through the operation's `Completed` handler and cancels the operation
+//! when its time is up or when the prompt's caller has gone away.
+
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use windows::{
+    core::{Error, Result, RuntimeType},
+    Foundation::{
+        AsyncOperationCompletedHandler, AsyncOperationWithProgressCompletedHandler,
+        IAsyncOperation, IAsyncOperationWithProgress,
+    },
+    Win32::Foundation::{ERROR_CANCELLED, ERROR_TIMEOUT},
+};
+
+use crate::ui::watchdog;
+
+/// How often a wait looks at its deadline and the caller.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A WinRT operation that produces a value and can be cancelled.
+pub(crate) trait Operation {
+    type Output;
+    /// Arrange for `done` to be sent to when the operation finishes.
+    fn on_completed(&self, done: mpsc::Sender<()>) -> Result<()>;
+    fn results(&self) -> Result<Self::Output>;
+    fn cancel(&self);
+}
+
+impl<T: RuntimeType + 'static> Operation for IAsyncOperation<T> {
+    type Output = T;
+
+    fn on_completed(&self, done: mpsc::Sender<()>) -> Result<()> {
+        self.SetCompleted(&AsyncOperationCompletedHandler::new(move |_, _| {
+            let _ = done.send(());
+            Ok(())
+        }))
+    }
+
+    fn results(&self) -> Result<T> {
+        self.GetResults()
+    }
+
+    fn cancel(&self) {
+        let _ = self.Cancel();
+    }
+}
+
+impl<T: RuntimeType + 'static, P: RuntimeType + 'static> Operation
+    for IAsyncOperationWithProgress<T, P>
+{
+    type Output = T;
+
+    fn on_completed(&self, done: mpsc::Sender<()>) -> Result<()> {
+        self.SetCompleted(&AsyncOperationWithProgressCompletedHandler::new(
+            move |_, _| {
+                let _ = done.send(());
+                Ok(())
+            },
+        ))
+    }
+
+    fn results(&self) -> Result<T> {
+        self.GetResults()
+    }
+
+    fn cancel(&self) {
+        let _ = self.Cancel();
+    }
+}
+
+/// The operation's result, or an `ERROR_TIMEOUT` error once `timeout` has
+/// passed, or `ERROR_CANCELLED` once the caller has gone away. Either way
+/// the operation is cancelled before returning.
+pub(crate) fn wait<O: Operation>(operation: &O, timeout: Duration) -> Result<O::Output> {
+    let (done_tx, done_rx) = mpsc::channel();
+    operation.on_completed(done_tx)?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        if done_rx.recv_timeout(POLL_INTERVAL).is_ok() {
+            return operation.results();
+        }
+        let error = if watchdog::caller_gone() {
+            ERROR_CANCELLED
+        } else if Instant::now() >= deadline {
+            ERROR_TIMEOUT
+        } else {
+            continue;
+        };
+        operation.cancel();
+        return Err(Error::from(error.to_hresult()));
+    }
+}
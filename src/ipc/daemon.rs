@@ -0,0 +1,595 @@
+//! Long-running `daemon` mode behind a named pipe, and the thin client that
+//! one-shot invocations use to reach it.
+//!
+//! A prompt answered by a fresh process pays for process start-up, WinRT
+//! activation and the Windows Hello availability check every time. With the
+//! daemon running, the askpass forwards the prompt (and the environment
+//! that shapes how it is answered) over the pipe and prints the reply.
+//! When no daemon is listening, or it is busy with another prompt, the
+//! askpass answers by itself exactly as before.
+//!
+//! The pipe carries the same JSON-RPC as `ps-serve`, one length-prefixed
+//! request per connection (see `frame`). The pipe name is tied to the user
+//! and logon session, so people switching users on one machine each get
+//! their own daemon; only the current user may open it, and clients only
+//! talk to a server that is this same executable running as this user.
+//!
+//! Each answer is bound to the process that asked. The daemon notes the
+//! client's process id, executable and start time when it connects and
+//! checks them again before replying: if that process has gone (and its id
+//! perhaps been reused) while the dialog was open, the secret is withheld.
+//! A successful result carries an `origin` with the client's `nonce`, pid
+//! and executable, and the client drops any answer not addressed to it.
+//!
+//! `relay` passes one such request from stdin to the daemon and back, so
+//! the bridge inside WSL can reach it through interop without socat or
+//! npiperelay.
+
+use std::env;
+use std::fs::File;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::os::windows::io::{FromRawHandle, RawHandle};
+
+use serde_json::{json, Map, Value};
+use windows::{
+    core::{w, PCWSTR},
+    Win32::Foundation::{
+        CloseHandle, LocalFree, ERROR_PIPE_BUSY, ERROR_PIPE_CONNECTED, GENERIC_READ, GENERIC_WRITE,
+        HANDLE, HLOCAL,
+    },
+    Win32::Security::Authorization::{
+        ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+    },
+    Win32::Security::{PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES},
+    Win32::Storage::FileSystem::{
+        CreateFileW, FindClose, FindFirstFileW, FindNextFileW, FlushFileBuffers,
+        FILE_FLAGS_AND_ATTRIBUTES, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_SHARE_NONE, OPEN_EXISTING,
+        PIPE_ACCESS_DUPLEX, WIN32_FIND_DATAW,
+    },
+    Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, GetNamedPipeClientProcessId,
+        GetNamedPipeServerProcessId, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+        PIPE_TYPE_BYTE, PIPE_WAIT,
+    },
+};
+
+use crate::core::policy::{self, DistroRule};
+use crate::{
+    core::caller, core::session, ipc::frame, ipc::rpc, random_hex, store::audit, to_wide,
+    ui::hello, ui::watchdog, PromptError, PromptKind, Served,
+};
+
+const BUFFER_SIZE: u32 = 64 * 1024;
+
+/// Set in the daemon's own environment, so a daemon started from a shell
+/// that uses the askpass never forwards prompts to itself.
+const DAEMON_ENV: &str = "WSL_SSH_ASKPASS_IN_DAEMON";
+
+/// Prefix of the settings forwarded with each prompt, besides [`SHARED_ENV`].
+const FORWARDED_PREFIX: &str = "WSL_SSH_ASKPASS_";
+/// Variables WSL shares that change how a prompt is shown or cached.
+const SHARED_ENV: &[&str] = &[DISTRO_ENV, "HOME"];
+/// Which distro is asking, sent as the client's directory names it.
+const DISTRO_ENV: &str = "WSL_DISTRO_NAME";
+/// Who is calling, which a client must not choose for itself.
+const CALLER_ENV: &str = "WSL_SSH_ASKPASS_CALLER";
+
+const PIPE_DIR: &str = r"\\.\pipe\";
+const PIPE_PREFIX: &str = "wsl-ssh-askpass-";
+
+/// `\\.\pipe\wsl-ssh-askpass-<user SID>.<session fingerprint>`
+fn pipe_name() -> String {
+    format!("{}{}{}", PIPE_DIR, PIPE_PREFIX, pipe_id())
+}
+
+fn pipe_id() -> String {
+    format!(
+        "{}.{}",
+        session::user_sid().unwrap_or_default(),
+        session::fingerprint()
+    )
+}
+
+fn is_forwarded(name: &str) -> bool {
+    (name.starts_with(FORWARDED_PREFIX) && name != DAEMON_ENV && name != CALLER_ENV)
+        || SHARED_ENV.contains(&name)
+}
+
+/// Whether this process is the daemon, answering prompts for others.
+pub(crate) fn serving() -> bool {
+    env::var_os(DAEMON_ENV).is_some()
+}
+
+/// The forwarded variables currently set, skipping any that are not Unicode.
+fn forwarded_vars() -> impl Iterator<Item = (String, String)> {
+    env::vars_os().filter_map(|(name, value)| {
+        let name = name.into_string().ok().filter(|name| is_forwarded(name))?;
+        Some((name, value.into_string().ok()?))
+    })
+}
+
+/// Listen on the pipe until the process is stopped, answering one request
+/// per connection. Fails if another daemon already owns the pipe.
+pub fn serve() -> io::Result<()> {
+    env::set_var(DAEMON_ENV, "1");
+    // Warm up WinRT and the cached availability before the first prompt
+    hello::is_available();
+
+    let name = to_wide(&pipe_name());
+    let security = UserOnly::new()?;
+    let attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: security.0 .0,
+        bInheritHandle: false.into(),
+    };
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            PCWSTR(name.as_ptr()),
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            BUFFER_SIZE,
+            BUFFER_SIZE,
+            0,
+            Some(&attributes),
+        )
+    };
+    if pipe.is_invalid() {
+        return Err(io::Error::last_os_error());
+    }
+    loop {
+        let connected = unsafe { ConnectNamedPipe(pipe, None) };
+        // A client that raced in before ConnectNamedPipe is still connected
+        if connected.is_ok()
+            || connected.is_err_and(|e| e.code() == ERROR_PIPE_CONNECTED.to_hresult())
+        {
+            // Borrow the handle; it stays open for the next client
+            let file = ManuallyDrop::new(unsafe { File::from_raw_handle(pipe.0 as RawHandle) });
+            let mut client_pid = 0u32;
+            let known = unsafe { GetNamedPipeClientProcessId(pipe, &mut client_pid) }.is_ok();
+            let _watch = known.then(|| watchdog::watch_client(client_pid));
+            let origin = known.then(|| caller::identity(client_pid)).flatten();
+            let _ = handle_connection(&file, origin.as_ref());
+            unsafe {
+                let _ = FlushFileBuffers(pipe);
+                let _ = DisconnectNamedPipe(pipe);
+            }
+        }
+    }
+}
+
+/// A security descriptor granting the current user, and no one else, full
+/// access. Freed on drop.
+struct UserOnly(PSECURITY_DESCRIPTOR);
+
+impl UserOnly {
+    fn new() -> io::Result<Self> {
+        let sid =
+            session::user_sid().ok_or_else(|| io::Error::other("cannot read the user's SID"))?;
+        // Protected DACL with a single allow-all entry for the user
+        let sddl = to_wide(&format!("D:P(A;;GA;;;{})", sid));
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                PCWSTR(sddl.as_ptr()),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                None,
+            )
+        }
+        .map_err(|e| io::Error::from_raw_os_error(e.code().0))?;
+        Ok(UserOnly(descriptor))
+    }
+}
+
+impl Drop for UserOnly {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = LocalFree(HLOCAL(self.0 .0));
+        }
+    }
+}
+
+/// Answer one request from the client `origin`, as seen when it connected.
+/// A client that cannot be identified is not answered at all.
+fn handle_connection(mut file: &File, origin: Option<&caller::Identity>) -> io::Result<()> {
+    let request = frame::read(&mut file)?;
+    let parsed = serde_json::from_str::<Value>(&request).unwrap_or(Value::Null);
+    let id = parsed.get("id").cloned().unwrap_or(Value::Null);
+    let response = match origin {
+        None => rpc::error(id, rpc::FAILED, UNKNOWN_ORIGIN),
+        Some(origin) => {
+            let response = answer_request(&request);
+            // Whoever holds the pipe now must still be the process that asked
+            let now = caller::identity(origin.pid);
+            let nonce = parsed.pointer("/params/nonce").cloned();
+            bind_origin(response, id, origin, now.as_ref(), nonce)
+        }
+    };
+    frame::write(&mut file, &response.to_string())
+}
+
+const UNKNOWN_ORIGIN: &str = "cannot tell which process is asking";
+const ORIGIN_CHANGED: &str = "the asking process went away while the prompt was open";
+
+/// Address `response` to `origin`, or withhold it if the client is no
+/// longer the process (`now`) that connected.
+fn bind_origin(
+    mut response: Value,
+    id: Value,
+    origin: &caller::Identity,
+    now: Option<&caller::Identity>,
+    nonce: Option<Value>,
+) -> Value {
+    if now != Some(origin) {
+        return rpc::error(id, rpc::FAILED, ORIGIN_CHANGED);
+    }
+    if let Some(result) = response.get_mut("result").and_then(Value::as_object_mut) {
+        result.insert(
+            "origin".into(),
+            json!({
+                "nonce": nonce.unwrap_or(Value::Null),
+                "pid": origin.pid,
+                "image": origin.image,
+            }),
+        );
+    }
+    response
+}
+
+/// Whether `response` was addressed to this process and the request that
+/// carried `nonce`. Errors carry no origin and need none.
+fn addressed_to_us(response: &Value, nonce: &str) -> bool {
+    let Some(result) = response.get("result") else {
+        return true;
+    };
+    result.pointer("/origin/nonce").and_then(Value::as_str) == Some(nonce)
+        && result.pointer("/origin/pid").and_then(Value::as_u64)
+            == Some(u64::from(std::process::id()))
+}
+
+/// The JSON-RPC response to `request`, answered with the environment the
+/// client sent along in `params.env`.
+fn answer_request(request: &str) -> Value {
+    let env = serde_json::from_str::<Value>(request)
+        .ok()
+        .and_then(|request| request.pointer("/params/env").cloned());
+    with_client_env(env.as_ref().and_then(Value::as_object), || {
+        rpc::handle_line(request)
+    })
+}
+
+/// Run `f` with the forwarded variables replaced by the client's, then put
+/// the daemon's own back. Requests are served one at a time, so nothing
+/// else reads the environment meanwhile.
+fn with_client_env<T>(client: Option<&Map<String, Value>>, f: impl FnOnce() -> T) -> T {
+    let own: Vec<(String, String)> = forwarded_vars().collect();
+    for (name, _) in &own {
+        env::remove_var(name);
+    }
+    // Only what was set here is taken away again: a name the client sent
+    // that is not forwarded belongs to the daemon
+    let mut set = Vec::new();
+    for (name, value) in client.into_iter().flatten() {
+        if let Some(value) = value.as_str().filter(|_| is_forwarded(name)) {
+            env::set_var(name, value);
+            set.push(name);
+        }
+    }
+    let result = f();
+    for name in set {
+        env::remove_var(name);
+    }
+    for (name, value) in own {
+        env::set_var(name, value);
+    }
+    result
+}
+
+/// Have a running daemon answer `prompt`. Returns `None` when there is no
+/// daemon to ask (or it is busy), so the caller should answer by itself.
+pub fn forward(prompt: &str, kind: PromptKind) -> Option<Result<String, PromptError>> {
+    if serving() {
+        return None;
+    }
+    match policy::distro_rule() {
+        // Only this process has the terminal to ask on
+        Some((_, DistroRule::Terminal)) => return None,
+        // Nor can the daemon tell the distro from the name
+        Some((_, DistroRule::Unverified)) => return None,
+        _ => {}
+    }
+    let pipe = connect()?;
+    let mut file = unsafe { File::from_raw_handle(pipe.0 as RawHandle) };
+
+    let mut env: Map<String, Value> = forwarded_vars()
+        .map(|(name, value)| (name, Value::String(value)))
+        .collect();
+    if let Some(distro) = policy::directory_distro() {
+        env.insert(DISTRO_ENV.into(), distro.into());
+    }
+    let kind = match kind {
+        PromptKind::Entry => Value::Null,
+        PromptKind::Confirm => "confirm".into(),
+        PromptKind::Notify => "none".into(),
+    };
+    let nonce = random_hex(16).unwrap_or_default();
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "prompt",
+        "params": { "prompt": prompt, "kind": kind, "env": env, "nonce": nonce },
+    });
+    frame::write(&mut file, &request.to_string()).ok()?;
+    let response: Value = serde_json::from_str(&frame::read(&mut file).ok()?).ok()?;
+    if !addressed_to_us(&response, &nonce) {
+        return Some(Err(PromptError::Failed(MISADDRESSED.into())));
+    }
+    if let Some(secret) = response.pointer("/result/secret").and_then(Value::as_str) {
+        if let Some(served) = response.pointer("/result/served") {
+            audit::set_served(Served {
+                key: served["key"].as_str().unwrap_or_default().to_string(),
+                from_cache: served["fromCache"].as_bool().unwrap_or(false),
+                hello_window_secs: served["helloWindow"].as_u64(),
+            });
+        }
+        return Some(Ok(secret.to_string()));
+    }
+    let error = response.get("error")?;
+    let message = error.get("message").and_then(Value::as_str).unwrap_or("");
+    match error.get("code").and_then(Value::as_i64) {
+        Some(rpc::CANCELLED) => Some(Err(PromptError::Cancelled)),
+        Some(rpc::FAILED) => Some(Err(PromptError::Failed(message.to_string()))),
+        // A daemon too old to understand the request
+        _ => None,
+    }
+}
+
+/// Open the daemon's pipe, if one is listening and served by this same
+/// executable rather than something squatting on the name.
+fn connect() -> Option<HANDLE> {
+    match probe() {
+        Probe::Ours(pipe) => Some(pipe),
+        _ => None,
+    }
+}
+
+/// What answers on this user's and session's pipe.
+enum Probe {
+    Absent,
+    /// A daemon is there but busy with another client
+    Busy,
+    Ours(HANDLE),
+    /// Something other than this executable running as this user
+    Foreign,
+}
+
+fn probe() -> Probe {
+    let name = to_wide(&pipe_name());
+    let pipe = match unsafe {
+        CreateFileW(
+            PCWSTR(name.as_ptr()),
+            (GENERIC_READ | GENERIC_WRITE).0,
+            FILE_SHARE_NONE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAGS_AND_ATTRIBUTES(0),
+            None,
+        )
+    } {
+        Ok(pipe) => pipe,
+        Err(e) if e.code() == ERROR_PIPE_BUSY.to_hresult() => return Probe::Busy,
+        Err(_) => return Probe::Absent,
+    };
+    let mut server_pid = 0u32;
+    let ours = unsafe { GetNamedPipeServerProcessId(pipe, &mut server_pid) }.is_ok()
+        && env::current_exe()
+            .ok()
+            .and_then(|exe| {
+                exe.file_name()
+                    .map(|name| name.to_string_lossy().to_lowercase())
+            })
+            .is_some_and(|exe| caller::pids_of(&exe).contains(&server_pid))
+        && session::process_sid(server_pid).is_some_and(|sid| Some(sid) == session::user_sid());
+    if !ours {
+        unsafe {
+            let _ = CloseHandle(pipe);
+        }
+        return Probe::Foreign;
+    }
+    Probe::Ours(pipe)
+}
+
+/// Daemon pipes on this machine besides this session's own.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Neighbours {
+    /// This user's, in other logon sessions or from before a reboot
+    same_user: usize,
+    other_users: usize,
+}
+
+/// Sort the pipe names `pipes` (without `\\.\pipe\`) by whose they are,
+/// `own` being this session's `<user SID>.<fingerprint>`. Pipes of daemons
+/// from before pipe names carried a SID count as other users'.
+fn neighbours<'a>(pipes: impl IntoIterator<Item = &'a str>, own: &str) -> Neighbours {
+    let sid = own.split_once('.').map_or(own, |(sid, _)| sid);
+    let mut found = Neighbours::default();
+    for id in pipes
+        .into_iter()
+        .filter_map(|pipe| pipe.strip_prefix(PIPE_PREFIX))
+    {
+        if id == own {
+            continue;
+        }
+        match id.split_once('.') {
+            Some((theirs, _)) if theirs == sid => found.same_user += 1,
+            _ => found.other_users += 1,
+        }
+    }
+    found
+}
+
+/// The names of all pipes on this machine.
+fn list_pipes() -> Vec<String> {
+    let mut names = Vec::new();
+    let mut data = WIN32_FIND_DATAW::default();
+    unsafe {
+        let Ok(find) = FindFirstFileW(w!(r"\\.\pipe\*"), &mut data) else {
+            return names;
+        };
+        loop {
+            let len = data
+                .cFileName
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(data.cFileName.len());
+            names.push(String::from_utf16_lossy(&data.cFileName[..len]));
+            if FindNextFileW(find, &mut data).is_err() {
+                break;
+            }
+        }
+        let _ = FindClose(find);
+    }
+    names
+}
+
+/// `status` lines for the daemon of this session, and any others.
+pub(crate) fn status() -> String {
+    let daemon = match probe() {
+        Probe::Absent => "not running".to_string(),
+        Probe::Busy => "running (busy with a prompt)".to_string(),
+        Probe::Ours(pipe) => {
+            unsafe {
+                let _ = CloseHandle(pipe);
+            }
+            "running".to_string()
+        }
+        Probe::Foreign => format!(
+            "WARNING: {} is held by a program other than this user's wsl-ssh-askpass; \
+             prompts are answered without the daemon",
+            pipe_name()
+        ),
+    };
+    let others = match neighbours(list_pipes().iter().map(String::as_str), &pipe_id()) {
+        Neighbours {
+            same_user: 0,
+            other_users: 0,
+        } => "none".to_string(),
+        Neighbours {
+            same_user,
+            other_users,
+        } => format!(
+            "{} of this user in other sessions, {} of other users (kept apart)",
+            same_user, other_users
+        ),
+    };
+    format!("Daemon: {}\nOther daemons: {}", daemon, others)
+}
+
+/// `relay`: read one framed request on stdin, have the daemon answer it (or
+/// answer it here when no daemon is running) and write the framed response
+/// to stdout.
+pub fn relay() -> io::Result<()> {
+    // The bridge that started us is gone with its ssh
+    watchdog::exit_when_orphaned();
+    let request = frame::read(&mut io::stdin().lock())?;
+    let response = match connect().filter(|_| !claims_isolated_distro(&request)) {
+        Some(pipe) => {
+            let mut file = unsafe { File::from_raw_handle(pipe.0 as RawHandle) };
+            // The bridge sends no nonce; the hop to the daemon gets one
+            let nonce = random_hex(16).unwrap_or_default();
+            let mut request: Value = serde_json::from_str(&request)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if let Some(params) = request.get_mut("params").and_then(Value::as_object_mut) {
+                params.insert("nonce".into(), nonce.clone().into());
+                // Started by the bridge, we are in the distro's files
+                if let Some(distro) = policy::directory_distro() {
+                    if let Some(env) = params.get_mut("env").and_then(Value::as_object_mut) {
+                        env.insert(DISTRO_ENV.into(), distro.into());
+                    }
+                }
+            }
+            frame::write(&mut file, &request.to_string())?;
+            let mut response: Value = serde_json::from_str(&frame::read(&mut file)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if !addressed_to_us(&response, &nonce) {
+                let id = request.get("id").cloned().unwrap_or(Value::Null);
+                response = rpc::error(id, rpc::FAILED, MISADDRESSED);
+            }
+            response
+        }
+        None => answer_request(&request),
+    };
+    frame::write(&mut io::stdout().lock(), &response.to_string())
+}
+
+/// Whether `request` names an isolated distro that our directory does not
+/// confirm. The daemon would take the name on trust, so it is answered here.
+fn claims_isolated_distro(request: &str) -> bool {
+    if policy::directory_distro().is_some() {
+        return false;
+    }
+    let env = serde_json::from_str::<Value>(request)
+        .ok()
+        .and_then(|request| request.pointer("/params/env").cloned());
+    with_client_env(env.as_ref().and_then(Value::as_object), || {
+        matches!(policy::distro_rule(), Some((_, DistroRule::Unverified)))
+    })
+}
+
+const MISADDRESSED: &str = "the daemon's answer was not addressed to this request";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_other_daemons_by_user() {
+        let own = "S-1-5-21-1-1001.0-3e7a1.2.41";
+        let pipes = [
+            "wsl-ssh-askpass-S-1-5-21-1-1001.0-3e7a1.2.41",
+            "wsl-ssh-askpass-S-1-5-21-1-1001.0-51c02.3.41",
+            "wsl-ssh-askpass-S-1-5-21-1-1002.0-6f0d4.4.41",
+            "wsl-ssh-askpass-0-7a113.5.41",
+            "InitShutdown",
+            "openssh-ssh-agent",
+        ];
+        assert_eq!(
+            neighbours(pipes, own),
+            Neighbours {
+                same_user: 1,
+                other_users: 2,
+            }
+        );
+        assert_eq!(neighbours([own], own), Neighbours::default());
+    }
+
+    #[test]
+    fn forwards_settings_but_not_the_caller() {
+        assert!(is_forwarded("WSL_SSH_ASKPASS_PASSWORD_TTL"));
+        assert!(is_forwarded("WSL_DISTRO_NAME"));
+        assert!(!is_forwarded(CALLER_ENV));
+        assert!(!is_forwarded(DAEMON_ENV));
+        assert!(!is_forwarded("PATH"));
+    }
+
+    #[test]
+    fn clients_cannot_unset_the_daemons_variables() {
+        const OWN: &str = CALLER_ENV;
+        const SENT: &str = "WSL_SSH_ASKPASS_TEST_CLIENT_ENV";
+        env::set_var(OWN, "daemon");
+        let client = json!({ OWN: "client", SENT: "client", "PATH": "" });
+        let seen = with_client_env(client.as_object(), || {
+            (env::var(OWN).ok(), env::var(SENT).ok())
+        });
+        assert_eq!(
+            seen,
+            (Some("daemon".to_string()), Some("client".to_string()))
+        );
+        assert_eq!(env::var(OWN).as_deref(), Ok("daemon"));
+        assert!(env::var_os(SENT).is_none());
+        assert!(env::var_os("PATH").is_some());
+        env::remove_var(OWN);
+    }
+}
@@ -0,0 +1,414 @@
+//! `doctor`: check the pieces a prompt depends on and say which are
+//! missing, for a setup that does not work and no error that explains why.
+//!
+//! Every check is read-only except the Credential Manager one, which writes
+//! and deletes a throwaway session entry (and is skipped in read-only mode).
+
+use serde_json::{json, Value};
+use windows::{
+    core::{w, PCWSTR},
+    Security::Credentials::UI::UserConsentVerifierAvailability,
+    Win32::System::Registry::{
+        RegCloseKey, RegGetValueW, RegOpenKeyExW, HKEY, HKEY_LOCAL_MACHINE, KEY_READ,
+        RRF_RT_REG_DWORD,
+    },
+    Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId},
+};
+
+use crate::{
+    core::caller, core::config, core::mitigation, core::policy, core::session, store::credstore,
+    store::vbs, store::vbs::VbsStatus, to_wide, ui::hello,
+};
+
+/// Processes that start Windows programs on behalf of a distro.
+const INTEROP_HOSTS: &[&str] = &["wsl.exe", "wslhost.exe", "wslrelay.exe"];
+
+/// How a check came out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Works, but not as well as it could, or cannot be told from here
+    Warn,
+    /// Prompts will fail or lose a feature until this is fixed
+    Fail,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        }
+    }
+}
+
+/// One line of the report.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+/// Run every check, in the order they are reported.
+pub fn run() -> Vec<Check> {
+    vec![
+        windows_hello(),
+        credential_manager(),
+        vbs(),
+        mitigations(),
+        interop(),
+        askpass_env(),
+        foreground_window(),
+    ]
+}
+
+/// The report as `{"ok": ..., "checks": [{"name", "status", "detail"}]}`.
+pub fn to_json(checks: &[Check]) -> Value {
+    json!({
+        "ok": !checks.iter().any(|check| check.status == Status::Fail),
+        "checks": checks
+            .iter()
+            .map(|check| json!({
+                "name": check.name,
+                "status": check.status.as_str(),
+                "detail": check.detail,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn check(name: &'static str, status: Status, detail: impl Into<String>) -> Check {
+    Check {
+        name,
+        status,
+        detail: detail.into(),
+    }
+}
+
+fn windows_hello() -> Check {
+    let name = "Windows Hello";
+    let methods = enrolled_methods();
+    let enrolled = if methods.is_empty() {
+        "no enrolled methods found".to_string()
+    } else {
+        format!("enrolled: {}", methods.join(", "))
+    };
+    let (status, state) = match hello::availability() {
+        Ok(UserConsentVerifierAvailability::Available) => (Status::Ok, "available"),
+        Ok(UserConsentVerifierAvailability::DeviceNotPresent) => {
+            (Status::Warn, "no Hello device present")
+        }
+        Ok(UserConsentVerifierAvailability::NotConfiguredForUser) => {
+            (Status::Warn, "not set up for this user")
+        }
+        Ok(UserConsentVerifierAvailability::DisabledByPolicy) => {
+            (Status::Warn, "disabled by policy")
+        }
+        Ok(UserConsentVerifierAvailability::DeviceBusy) => (Status::Warn, "device busy"),
+        Ok(_) => (Status::Warn, "in an unknown state"),
+        Err(e) => return check(name, Status::Warn, format!("cannot ask: {}", e.message())),
+    };
+    let detail = match status {
+        Status::Ok => format!("{}; {}", state, enrolled),
+        _ => format!(
+            "{}; {}; cached secrets cannot be unlocked, so they are typed every time",
+            state, enrolled
+        ),
+    };
+    check(name, status, detail)
+}
+
+/// Hello methods set up for this user, as far as the registry tells.
+fn enrolled_methods() -> Vec<&'static str> {
+    let Some(sid) = session::user_sid() else {
+        return Vec::new();
+    };
+    let factors = registry_dword(
+        &format!(
+            "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\WinBio\\AccountInfo\\{}",
+            sid
+        ),
+        w!("EnrolledFactors"),
+    );
+    let mut methods = biometric_factors(factors.unwrap_or(0));
+    if registry_key_exists(&format!(
+        "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Authentication\\LogonUI\\NgcPin\\Credentials\\{}",
+        sid
+    )) {
+        methods.push("PIN");
+    }
+    methods
+}
+
+/// Names of the `WINBIO_BIOMETRIC_TYPE` bits in an `EnrolledFactors` value.
+fn biometric_factors(bits: u32) -> Vec<&'static str> {
+    [(0x02, "face"), (0x08, "fingerprint"), (0x10, "iris")]
+        .into_iter()
+        .filter(|(bit, _)| bits & bit != 0)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+fn registry_dword(path: &str, value_name: PCWSTR) -> Option<u32> {
+    let path = to_wide(path);
+    let mut value = 0u32;
+    let mut size = std::mem::size_of::<u32>() as u32;
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            PCWSTR(path.as_ptr()),
+            value_name,
+            RRF_RT_REG_DWORD,
+            None,
+            Some(&mut value as *mut u32 as *mut _),
+            Some(&mut size),
+        )
+    };
+    status.is_ok().then_some(value)
+}
+
+fn registry_key_exists(path: &str) -> bool {
+    let path = to_wide(path);
+    let mut key = HKEY::default();
+    unsafe {
+        let opened = RegOpenKeyExW(
+            HKEY_LOCAL_MACHINE,
+            PCWSTR(path.as_ptr()),
+            0,
+            KEY_READ,
+            &mut key,
+        )
+        .is_ok();
+        if opened {
+            let _ = RegCloseKey(key);
+        }
+        opened
+    }
+}
+
+fn credential_manager() -> Check {
+    let name = "Credential Manager";
+    if policy::read_only() {
+        return check(name, Status::Warn, "read-only mode; the cache is not used");
+    }
+    match credstore::probe() {
+        Ok(()) => check(name, Status::Ok, "entries can be written and read back"),
+        Err(e) => check(name, Status::Fail, format!("{}; nothing can be cached", e)),
+    }
+}
+
+fn vbs() -> Check {
+    let (status, detail) = describe_vbs(vbs::query());
+    check("VBS", status, detail)
+}
+
+/// Judge the virtualization-based security state; `None` when WMI could
+/// not be asked.
+fn describe_vbs(vbs: Option<VbsStatus>) -> (Status, String) {
+    match vbs {
+        None => (
+            Status::Warn,
+            "cannot ask WMI for the Device Guard state".into(),
+        ),
+        Some(VbsStatus {
+            vbs_running: true,
+            credential_guard_running: true,
+        }) => (Status::Ok, "running, with Credential Guard".into()),
+        Some(VbsStatus {
+            vbs_running: true, ..
+        }) => (Status::Ok, "running; Credential Guard is not".into()),
+        Some(_) => (
+            Status::Warn,
+            "not running; saved passphrases are protected by DPAPI alone".into(),
+        ),
+    }
+}
+
+fn mitigations() -> Check {
+    let report = mitigation::report();
+    let (status, detail) = describe_mitigations(&report, config::get().signed_images_only);
+    check("Exploit mitigations", status, detail)
+}
+
+/// Judge which mitigations are in force. Microsoft-signed DLLs only is
+/// missed only when `signed_images_only` asks for it.
+fn describe_mitigations(report: &[(&str, bool)], signed_only: bool) -> (Status, String) {
+    let missing: Vec<&str> = report
+        .iter()
+        .filter(|(name, on)| !on && (signed_only || *name != "Microsoft-signed DLLs only"))
+        .map(|(name, _)| *name)
+        .collect();
+    let active: Vec<&str> = report
+        .iter()
+        .filter(|(_, on)| *on)
+        .map(|(name, _)| *name)
+        .collect();
+    if missing.is_empty() {
+        (Status::Ok, active.join(", "))
+    } else {
+        (
+            Status::Warn,
+            format!("not in force: {}", missing.join(", ")),
+        )
+    }
+}
+
+fn interop() -> Check {
+    let name = "WSL interop";
+    let host = caller::ancestry()
+        .iter()
+        .find(|(_, exe)| INTEROP_HOSTS.contains(&exe.as_str()));
+    match host {
+        Some((pid, exe)) => check(
+            name,
+            Status::Ok,
+            format!("started from a distro (through {}, pid {})", exe, pid),
+        ),
+        None => check(
+            name,
+            Status::Warn,
+            "not started from a distro; run `wsl-ssh-askpass.exe doctor` inside WSL to check the \
+             environment ssh sees",
+        ),
+    }
+}
+
+fn askpass_env() -> Check {
+    let askpass = std::env::var("SSH_ASKPASS").ok();
+    let require = std::env::var("SSH_ASKPASS_REQUIRE").ok();
+    let (status, detail) = describe_askpass_env(askpass.as_deref(), require.as_deref());
+    check("SSH_ASKPASS", status, detail)
+}
+
+/// Judge what `SSH_ASKPASS` and `SSH_ASKPASS_REQUIRE` are set to. They only
+/// reach a Windows program when listed in `WSLENV`, so unset here is not
+/// proof they are unset in the distro.
+fn describe_askpass_env(askpass: Option<&str>, require: Option<&str>) -> (Status, String) {
+    let Some(askpass) = askpass.filter(|value| !value.is_empty()) else {
+        return (
+            Status::Warn,
+            "not visible here (share SSH_ASKPASS and SSH_ASKPASS_REQUIRE through WSLENV to \
+             check them, or see `status` for the distros' startup files)"
+                .into(),
+        );
+    };
+    let ours = ["wsl-ssh-askpass", "askpass-terminal", "askpass-http"]
+        .iter()
+        .any(|name| askpass.to_lowercase().contains(name));
+    match require.unwrap_or("") {
+        "force" | "prefer" if ours => (
+            Status::Ok,
+            format!(
+                "SSH_ASKPASS={} SSH_ASKPASS_REQUIRE={}",
+                askpass,
+                require.unwrap_or("")
+            ),
+        ),
+        "force" | "prefer" => (
+            Status::Warn,
+            format!("SSH_ASKPASS={} does not look like this program", askpass),
+        ),
+        "" => (
+            Status::Warn,
+            format!(
+                "SSH_ASKPASS={}, but SSH_ASKPASS_REQUIRE is not visible; without `force` ssh \
+                 only uses it when there is no terminal",
+                askpass
+            ),
+        ),
+        other => (
+            Status::Fail,
+            format!(
+                "SSH_ASKPASS_REQUIRE={} keeps ssh from running SSH_ASKPASS; set it to `force`",
+                other
+            ),
+        ),
+    }
+}
+
+fn foreground_window() -> Check {
+    let name = "Foreground window";
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.is_invalid() {
+        return check(
+            name,
+            Status::Warn,
+            "none (a locked or disconnected session); dialogs open on their own",
+        );
+    }
+    let mut pid = 0u32;
+    unsafe { GetWindowThreadProcessId(hwnd, Some(&mut pid)) };
+    let owner = caller::identity(pid)
+        .map(|id| id.image)
+        .unwrap_or_else(|| format!("pid {}", pid));
+    check(name, Status::Ok, format!("owned by {}", owner))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_enrolled_factors() {
+        assert_eq!(biometric_factors(0), Vec::<&str>::new());
+        assert_eq!(biometric_factors(0x0a), ["face", "fingerprint"]);
+        assert_eq!(biometric_factors(0x10), ["iris"]);
+    }
+
+    #[test]
+    fn judges_vbs() {
+        assert_eq!(describe_vbs(None).0, Status::Warn);
+        assert_eq!(describe_vbs(Some(VbsStatus::default())).0, Status::Warn);
+        let running = VbsStatus {
+            vbs_running: true,
+            credential_guard_running: false,
+        };
+        assert_eq!(describe_vbs(Some(running)).0, Status::Ok);
+    }
+
+    #[test]
+    fn judges_mitigations() {
+        let report = [
+            ("DEP", true),
+            ("CFG", true),
+            ("Microsoft-signed DLLs only", false),
+        ];
+        assert_eq!(
+            describe_mitigations(&report, false),
+            (Status::Ok, "DEP, CFG".to_string())
+        );
+        assert_eq!(describe_mitigations(&report, true).0, Status::Warn);
+        let weak = [("DEP", true), ("CFG", false)];
+        assert_eq!(
+            describe_mitigations(&weak, false),
+            (Status::Warn, "not in force: CFG".to_string())
+        );
+    }
+
+    #[test]
+    fn judges_askpass_env() {
+        assert_eq!(describe_askpass_env(None, None).0, Status::Warn);
+        assert_eq!(
+            describe_askpass_env(Some("/mnt/c/tools/wsl-ssh-askpass.exe"), Some("force")).0,
+            Status::Ok
+        );
+        assert_eq!(
+            describe_askpass_env(Some("/home/me/bin/askpass-terminal"), Some("prefer")).0,
+            Status::Ok
+        );
+        assert_eq!(
+            describe_askpass_env(Some("/usr/bin/ksshaskpass"), Some("force")).0,
+            Status::Warn
+        );
+        assert_eq!(
+            describe_askpass_env(Some("/mnt/c/tools/wsl-ssh-askpass.exe"), None).0,
+            Status::Warn
+        );
+        assert_eq!(
+            describe_askpass_env(Some("/mnt/c/tools/wsl-ssh-askpass.exe"), Some("never")).0,
+            Status::Fail
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Minimal `extern "C"` surface for PowerShell, C# and other non-Rust callers.
+//!
+//! All strings are NUL-terminated UTF-16, matching `CharSet.Unicode` in
+//! P/Invoke declarations. See `include/wsl_ssh_askpass.h`.
+
+use std::slice;
+
+pub const ASKPASS_OK: i32 = 0;
+pub const ASKPASS_CANCELLED: i32 = 1;
+pub const ASKPASS_BUFFER_TOO_SMALL: i32 = 2;
+pub const ASKPASS_INVALID_ARGUMENT: i32 = 3;
+pub const ASKPASS_FAILED: i32 = 4;
+
+unsafe fn from_wide_ptr(ptr: *const u16) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    String::from_utf16(slice::from_raw_parts(ptr, len)).ok()
+}
+
+/// Prompt for the secret requested by `prompt`, using the same cache and
+/// Windows Hello policy as the askpass binary.
+///
+/// `out_len` holds the capacity of `out` in UTF-16 units on entry and the
+/// length required for the answer including its NUL terminator on return.
+/// If the buffer is too small nothing is written and
+/// `ASKPASS_BUFFER_TOO_SMALL` is returned; call again with a larger buffer
+/// (the user is prompted again unless the answer is cached).
+/// `ASKPASS_CANCELLED` means the user dismissed the dialog;
+/// `ASKPASS_FAILED` means it could not be shown.
+///
+/// # Safety
+///
+/// `prompt` must be a valid NUL-terminated UTF-16 string, `out_len` must be
+/// valid for reads and writes, and `out` must be valid for `*out_len` writes.
+#[no_mangle]
+pub unsafe extern "C" fn askpass_prompt(
+    prompt: *const u16,
+    out: *mut u16,
+    out_len: *mut u32,
+) -> i32 {
+    let Some(prompt) = from_wide_ptr(prompt) else {
+        return ASKPASS_INVALID_ARGUMENT;
+    };
+    if out.is_null() || out_len.is_null() {
+        return ASKPASS_INVALID_ARGUMENT;
+    }
+
+    let answer = match crate::answer_prompt(&prompt) {
+        Ok(answer) => answer,
+        Err(crate::PromptError::Cancelled) => return ASKPASS_CANCELLED,
+        Err(crate::PromptError::Failed(_)) => return ASKPASS_FAILED,
+    };
+    let wide = crate::to_wide(&answer);
+    let capacity = *out_len as usize;
+    *out_len = wide.len() as u32;
+    if wide.len() > capacity {
+        return ASKPASS_BUFFER_TOO_SMALL;
+    }
+    std::ptr::copy_nonoverlapping(wide.as_ptr(), out, wide.len());
+    ASKPASS_OK
+}
+
+/// Show a yes/no confirmation. Returns 1 for yes, 0 for no and a negative
+/// value if `prompt` is invalid.
+///
+/// # Safety
+///
+/// `prompt` must be a valid NUL-terminated UTF-16 string.
+#[no_mangle]
+pub unsafe extern "C" fn askpass_confirm(prompt: *const u16) -> i32 {
+    match from_wide_ptr(prompt) {
+        Some(prompt) => i32::from(crate::confirm(&prompt)),
+        None => -ASKPASS_INVALID_ARGUMENT,
+    }
+}
+
+/// Forget the cached secret for the cache key `key`. Returns 1 if an entry
+/// was removed, 0 if none existed and a negative value if `key` is invalid.
+///
+/// # Safety
+///
+/// `key` must be a valid NUL-terminated UTF-16 string.
+#[no_mangle]
+pub unsafe extern "C" fn askpass_clear(key: *const u16) -> i32 {
+    match from_wide_ptr(key) {
+        Some(key) => i32::from(crate::clear_cached(&key)),
+        None => -ASKPASS_INVALID_ARGUMENT,
+    }
+}
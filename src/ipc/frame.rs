@@ -0,0 +1,80 @@
+//! Length-prefixed messages for the daemon pipe and the WSL bridge: a
+//! little-endian `u32` byte count, then that many bytes of UTF-8 JSON-RPC.
+//!
+//! Unlike newline-delimited lines, a frame can be read without scanning for
+//! a terminator, so a peer that sends half a request and stalls is told
+//! apart from one that is done.
+
+use std::io::{self, Read, Write};
+
+/// Largest message accepted; prompts and answers are far smaller.
+pub(crate) const MAX_FRAME: usize = 1024 * 1024;
+
+/// Read one message. A clean end of input before the length is
+/// `UnexpectedEof` like any other truncation.
+pub(crate) fn read(input: &mut impl Read) -> io::Result<String> {
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is too large", len),
+        ));
+    }
+    let mut body = vec![0u8; len];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Write one message and flush it.
+pub(crate) fn write(output: &mut impl Write, message: &str) -> io::Result<()> {
+    if message.len() > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "message is too large",
+        ));
+    }
+    output.write_all(&(message.len() as u32).to_le_bytes())?;
+    output.write_all(message.as_bytes())?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = Vec::new();
+        write(&mut buffer, "{\"id\":1}").unwrap();
+        write(&mut buffer, "").unwrap();
+        assert_eq!(&buffer[..4], &[8, 0, 0, 0]);
+        let mut input = buffer.as_slice();
+        assert_eq!(read(&mut input).unwrap(), "{\"id\":1}");
+        assert_eq!(read(&mut input).unwrap(), "");
+        assert_eq!(
+            read(&mut input).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_frames() {
+        let mut truncated: &[u8] = &[5, 0, 0, 0, b'a', b'b'];
+        assert_eq!(
+            read(&mut truncated).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        let mut huge: &[u8] = &[0xff, 0xff, 0xff, 0x7f];
+        assert_eq!(
+            read(&mut huge).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let mut invalid: &[u8] = &[1, 0, 0, 0, 0xff];
+        assert_eq!(
+            read(&mut invalid).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
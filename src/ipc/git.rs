@@ -0,0 +1,199 @@
+//! `git-credential`: the git credential helper protocol, so the same
+//! Credential Manager cache and Windows Hello gate can back
+//! `credential.helper`.
+//!
+//! git writes `key=value` lines describing the credential, ended by a blank
+//! line or EOF, and names the action as the argument: `get` answers with
+//! `username=` and `password=` lines, `store` is sent after the credential
+//! worked and `erase` after it was rejected. Secrets are cached under
+//! `git:<protocol>://<username>@<host>[/<path>]`.
+
+use std::io::{self, BufRead, Write};
+
+use crate::core::policy::{self, CachePolicy};
+use crate::store::credstore::{self, SavePreference};
+use crate::{handle_passphrase, store::sealed, store_secret, PromptError};
+
+/// The fields of a credential description that name the secret.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Request {
+    protocol: String,
+    host: String,
+    path: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl Request {
+    /// `<host>[/<path>]`
+    fn location(&self) -> String {
+        match &self.path {
+            Some(path) => format!("{}/{}", self.host, path),
+            None => self.host.clone(),
+        }
+    }
+
+    /// `<protocol>://<username>@<location>`, as git itself shows it.
+    fn url(&self, username: &str) -> String {
+        format!("{}://{}@{}", self.protocol, username, self.location())
+    }
+
+    fn cache_key(&self, username: &str) -> String {
+        format!("git:{}", self.url(username))
+    }
+}
+
+/// Read git's `key=value` lines up to the first blank line. Unknown and
+/// repeated (`capability[]`, `wwwauth[]`) attributes are ignored.
+fn parse(input: impl BufRead) -> Result<Request, String> {
+    let mut request = Request::default();
+    for line in input.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.is_empty() {
+            break;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("malformed line '{}'", line));
+        };
+        let value = value.to_string();
+        match key {
+            "protocol" => request.protocol = value,
+            "host" => request.host = value,
+            "path" => request.path = Some(value).filter(|p| !p.is_empty()),
+            "username" => request.username = Some(value).filter(|u| !u.is_empty()),
+            "password" => request.password = Some(value),
+            _ => {}
+        }
+    }
+    if request.protocol.is_empty() || request.host.is_empty() {
+        return Err("protocol and host are required".into());
+    }
+    Ok(request)
+}
+
+/// Run `action` (`get`, `store` or `erase`) on the description in `input`,
+/// writing any answer to `output`.
+pub fn run(action: &str, input: impl BufRead, mut output: impl Write) -> Result<(), String> {
+    let request = parse(input)?;
+    match action {
+        "get" => {
+            let Some(username) = request
+                .username
+                .clone()
+                .or_else(|| cached_username(&request))
+            else {
+                // Nothing to go on: git asks for the username itself
+                return Ok(());
+            };
+            let key = request.cache_key(&username);
+            let prompt = format!("Password for '{}':", request.url(&username));
+            match handle_passphrase(&prompt, &key, None, &policy()) {
+                Ok(password) => writeln!(output, "username={}\npassword={}", username, password)
+                    .map_err(|e| e.to_string()),
+                // git then falls back to its own prompt
+                Err(PromptError::Cancelled) => Ok(()),
+                Err(PromptError::Failed(e)) => Err(e),
+            }
+        }
+        "store" => {
+            let (Some(username), Some(password)) = (&request.username, &request.password) else {
+                return Ok(());
+            };
+            let key = request.cache_key(username);
+            let policy = policy();
+            // Only keep what the user asked to keep, e.g. a password typed at
+            // git's own prompt for a key whose "Remember" box was ticked before
+            let wanted = credstore::get_save_preference(&key) == Some(SavePreference::Save);
+            // Rewriting an unchanged secret would restart its TTL. A sealed
+            // one cannot be compared without Hello, but it is the one that
+            // just worked: a rejected one has already been erased.
+            let unchanged = credstore::get_cached_blob(&key, policy.max_age_secs)
+                .is_some_and(|blob| sealed::is_sealed(&blob) || blob == password.as_bytes());
+            if !policy.store || !wanted || unchanged {
+                return Ok(());
+            }
+            match store_secret(&key, password, &policy) {
+                Err(PromptError::Failed(e)) => Err(e),
+                _ => Ok(()),
+            }
+        }
+        "erase" => {
+            if let Some(username) = &request.username {
+                credstore::remove_cached(&request.cache_key(username));
+            }
+            Ok(())
+        }
+        // git may add actions; helpers must ignore the ones they do not know
+        _ => Ok(()),
+    }
+}
+
+fn policy() -> CachePolicy {
+    let mut policy = CachePolicy::passphrase();
+    if policy::read_only() {
+        policy.read = false;
+        policy.store = false;
+    }
+    policy
+}
+
+/// The username of a secret already cached for this protocol, host and path.
+fn cached_username(request: &Request) -> Option<String> {
+    let prefix = format!("git:{}://", request.protocol);
+    let suffix = format!("@{}", request.location());
+    credstore::list_cached().into_iter().find_map(|secret| {
+        let username = secret.key.strip_prefix(&prefix)?.strip_suffix(&suffix)?;
+        (!username.is_empty() && !username.contains('@')).then(|| username.to_string())
+    })
+}
+
+/// Stdin and stdout, as git connects them.
+pub fn run_stdio(action: &str) -> Result<(), String> {
+    run(action, io::stdin().lock(), io::stdout().lock())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_descriptions() {
+        let request = parse(
+            "protocol=https\nhost=github.com\npath=me/repo.git\nusername=me\n\
+             capability[]=authtype\n\nhost=ignored\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            Request {
+                protocol: "https".into(),
+                host: "github.com".into(),
+                path: Some("me/repo.git".into()),
+                username: Some("me".into()),
+                password: None,
+            }
+        );
+        assert_eq!(
+            request.cache_key("me"),
+            "git:https://me@github.com/me/repo.git"
+        );
+    }
+
+    #[test]
+    fn hosts_with_ports_and_no_path() {
+        let request = parse("protocol=https\nhost=git.example.com:8443\n".as_bytes()).unwrap();
+        assert_eq!(request.location(), "git.example.com:8443");
+        assert_eq!(
+            request.cache_key("ci"),
+            "git:https://ci@git.example.com:8443"
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_descriptions() {
+        assert!(parse("protocol=https\n".as_bytes()).is_err());
+        assert!(parse("host=github.com\n".as_bytes()).is_err());
+        assert!(parse("protocol https\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+//! Loopback HTTP front end to the JSON-RPC API, for callers that cannot
+//! spawn the Windows executable or reach a pipe (containers inside WSL,
+//! sandboxed tools). Each `POST /rpc` carries one JSON-RPC request and must
+//! present the shared secret as `Authorization: Bearer <token>`.
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use serde_json::json;
+
+/// Largest request body accepted, far above any real prompt.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+/// Longest request or header line accepted, and most header lines.
+const MAX_LINE_BYTES: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const TOKEN_ENV: &str = "WSL_SSH_ASKPASS_HTTP_TOKEN";
+
+/// Raised once this process is serving HTTP requests.
+static SERVING: AtomicBool = AtomicBool::new(false);
+
+/// Whether this process is `http-serve`, answering whoever holds the token.
+pub(crate) fn serving() -> bool {
+    SERVING.load(Ordering::Relaxed)
+}
+
+/// Location of the generated bearer token in this user's profile:
+/// `%LOCALAPPDATA%\wsl-ssh-askpass\http-token`.
+pub fn token_path() -> Option<PathBuf> {
+    Some(crate::data_dir()?.join("http-token"))
+}
+
+/// The bearer token from `WSL_SSH_ASKPASS_HTTP_TOKEN`, else the one stored
+/// at [`token_path`], generating and storing a fresh one on first use.
+pub fn load_or_create_token() -> io::Result<String> {
+    if let Ok(token) = env::var(TOKEN_ENV) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    let path = token_path().ok_or_else(|| io::Error::other("LOCALAPPDATA is not set"))?;
+    if let Ok(token) = fs::read_to_string(&path) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    let token = crate::random_hex(32).ok_or_else(|| io::Error::other("no random source"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, &token)?;
+    Ok(token)
+}
+
+/// Serve requests on `addr` until the listener fails. Requests are handled
+/// one at a time, since each may show a modal dialog anyway.
+pub fn serve(addr: SocketAddr, token: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    SERVING.store(true, Ordering::Relaxed);
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let _ = handle_connection(stream, token);
+    }
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, token: &str) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+    let Head {
+        method,
+        path,
+        content_length,
+        authorized,
+    } = read_head(&mut reader, token)?;
+
+    if !authorized {
+        return respond(
+            &mut stream,
+            "401 Unauthorized",
+            &json!({ "error": "unauthorized" }),
+        );
+    }
+    if method != "POST" || path != "/rpc" {
+        return respond(
+            &mut stream,
+            "404 Not Found",
+            &json!({ "error": "not found" }),
+        );
+    }
+    if content_length > MAX_BODY_BYTES {
+        return respond(
+            &mut stream,
+            "413 Payload Too Large",
+            &json!({ "error": "request too large" }),
+        );
+    }
+
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    let response = crate::ipc::rpc::handle_line(&String::from_utf8_lossy(&body));
+    respond(&mut stream, "200 OK", &response)
+}
+
+/// What is needed of a request before its body.
+#[derive(Debug, PartialEq)]
+struct Head {
+    method: String,
+    path: String,
+    content_length: usize,
+    /// It presented `Authorization: Bearer <token>`.
+    authorized: bool,
+}
+
+/// Read the request line and headers, up to the blank line before the body.
+/// A line longer than [`MAX_LINE_BYTES`], or more than [`MAX_HEADERS`] of
+/// them, fails the request rather than filling memory.
+fn read_head<R: BufRead>(reader: &mut R, token: &str) -> io::Result<Head> {
+    let request_line = read_line(reader)?;
+    let mut parts = request_line.split_whitespace();
+    let mut head = Head {
+        method: parts.next().unwrap_or("").to_string(),
+        path: parts.next().unwrap_or("").to_string(),
+        content_length: 0,
+        authorized: false,
+    };
+    for _ in 0..=MAX_HEADERS {
+        let line = read_line(reader)?;
+        let line = line.trim_end();
+        // The blank line before the body, or the end of input
+        if line.is_empty() {
+            return Ok(head);
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            head.content_length = value.parse().unwrap_or(usize::MAX);
+        } else if name.eq_ignore_ascii_case("authorization") {
+            head.authorized = value
+                .strip_prefix("Bearer ")
+                .is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes()));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "too many headers",
+    ))
+}
+
+/// One line including its newline, or an empty string at the end of input.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LINE_BYTES).read_line(&mut line)?;
+    if line.len() as u64 == MAX_LINE_BYTES && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(line)
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &serde_json::Value) -> io::Result<()> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head(request: &str) -> Head {
+        read_head(&mut request.as_bytes(), "s3cret").unwrap()
+    }
+
+    #[test]
+    fn reads_the_head() {
+        let request = "POST /rpc HTTP/1.1\r\nHost: localhost\r\n\
+                       content-length: 8\r\nAuthorization: Bearer s3cret\r\n\r\n{\"id\":1}";
+        assert_eq!(
+            head(request),
+            Head {
+                method: "POST".to_string(),
+                path: "/rpc".to_string(),
+                content_length: 8,
+                authorized: true,
+            }
+        );
+        let mut reader = request.as_bytes();
+        read_head(&mut reader, "s3cret").unwrap();
+        assert_eq!(reader, b"{\"id\":1}");
+    }
+
+    #[test]
+    fn rejects_other_tokens() {
+        assert!(!head("POST /rpc HTTP/1.1\r\nAuthorization: Bearer s3cre\r\n\r\n").authorized);
+        assert!(!head("POST /rpc HTTP/1.1\r\nAuthorization: Basic s3cret\r\n\r\n").authorized);
+        assert!(!head("POST /rpc HTTP/1.1\r\n\r\n").authorized);
+    }
+
+    #[test]
+    fn garbled_lengths_are_too_large() {
+        let garbled = head("POST /rpc HTTP/1.1\r\nContent-Length: lots\r\n\r\n");
+        assert_eq!(garbled.content_length, usize::MAX);
+        assert_eq!(head("").method, "");
+    }
+
+    #[test]
+    fn caps_lines_and_headers() {
+        let long = format!("POST /rpc HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(9000));
+        assert!(read_head(&mut long.as_bytes(), "s3cret").is_err());
+        let many = format!(
+            "POST /rpc HTTP/1.1\r\n{}\r\n",
+            "X: y\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert!(read_head(&mut many.as_bytes(), "s3cret").is_err());
+        let enough = format!(
+            "POST /rpc HTTP/1.1\r\n{}\r\n",
+            "X: y\r\n".repeat(MAX_HEADERS)
+        );
+        assert!(read_head(&mut enough.as_bytes(), "s3cret").is_ok());
+    }
+}
@@ -0,0 +1,18 @@
+//! Ways in other than a command line, and the way out to the distros: the
+//! daemon pipe and its framing, JSON-RPC over lines and loopback HTTP, the
+//! git credential helper and pinentry protocols, and running commands in a
+//! distro through `wsl.exe`. `setup`, `vscode-setup` and `doctor` wire a
+//! distro or editor up to these and check the result.
+
+pub mod daemon;
+pub mod doctor;
+#[cfg(feature = "ffi")]
+pub(crate) mod ffi;
+pub(crate) mod frame;
+pub mod git;
+pub mod http;
+pub mod pinentry;
+pub mod rpc;
+pub mod setup;
+pub mod vscode;
+pub mod wsl;
@@ -0,0 +1,375 @@
+//! `pinentry`: the Assuan protocol gpg-agent speaks to its pinentry
+//! program, so gpg inside WSL can ask for passphrases through the same
+//! credential dialog and Windows Hello gate as ssh.
+//!
+//! gpg-agent starts the pinentry with no arguments and talks to it over
+//! stdin/stdout: `SETDESC`, `SETPROMPT` and friends describe the dialog,
+//! `GETPIN` asks for the passphrase, `CONFIRM` and `MESSAGE` ask a question
+//! or show a notice, and `BYE` ends the session. Arguments and data lines
+//! percent-escape `%`, CR and LF.
+//!
+//! Passphrases are only cached when gpg-agent allows it (`OPTION
+//! allow-external-password-cache`) and names the key with `SETKEYINFO`;
+//! they are stored as `gpg:<keygrip>`.
+
+use std::io::{self, BufRead, Write};
+
+use crate::core::policy::{self, CachePolicy};
+use crate::{core::config, handle_passphrase, store::credstore, ui::dialog, PromptError};
+
+/// GPG_ERR_CANCELED from the pinentry error source.
+const ERR_CANCELLED: &str = "ERR 83886179 Operation cancelled <Pinentry>";
+/// GPG_ERR_GENERAL from the pinentry error source.
+const ERR_GENERAL: u32 = 83886081;
+/// GPG_ERR_ASS_UNKNOWN_CMD.
+const ERR_UNKNOWN_COMMAND: &str = "ERR 536871187 Unknown IPC command <User defined source 1>";
+
+/// What gpg-agent has told us about the next dialog.
+#[derive(Clone, Debug, Default)]
+struct Session {
+    description: Option<String>,
+    prompt: Option<String>,
+    title: Option<String>,
+    /// Shown above the prompt after a wrong passphrase
+    error: Option<String>,
+    /// `SETKEYINFO` without its `n/` or `s/` prefix
+    keygrip: Option<String>,
+    /// `SETREPEAT`: a new passphrase, to be typed twice; the second prompt
+    repeat: Option<String>,
+    allow_cache: bool,
+}
+
+impl Session {
+    /// Description, error and prompt as one dialog message.
+    fn message(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(error) = &self.error {
+            parts.push(error.as_str());
+        }
+        if let Some(description) = &self.description {
+            parts.push(description.as_str());
+        }
+        parts.push(self.prompt.as_deref().unwrap_or("Passphrase:"));
+        parts.join("\n\n")
+    }
+
+    /// The cache key, when caching is allowed for this request.
+    fn cache_key(&self) -> Option<String> {
+        let keygrip = self.keygrip.as_ref().filter(|_| self.allow_cache)?;
+        self.repeat.is_none().then(|| format!("gpg:{}", keygrip))
+    }
+
+    /// Forget the per-dialog settings while keeping the connection options,
+    /// as `RESET` and every finished request do.
+    fn reset(&mut self) {
+        *self = Session {
+            allow_cache: self.allow_cache,
+            ..Session::default()
+        };
+    }
+}
+
+/// How a dialog is shown; the real one is [`Dialogs`], tests fake it.
+trait Ui {
+    fn get_pin(&mut self, session: &Session) -> Result<String, PromptError>;
+    fn confirm(&mut self, session: &Session) -> bool;
+    fn message(&mut self, session: &Session);
+}
+
+/// Serve one gpg-agent session on `input` and `output` until `BYE` or EOF.
+pub fn serve(input: impl BufRead, output: impl Write) -> io::Result<()> {
+    serve_with(input, output, &mut Dialogs)
+}
+
+fn serve_with(input: impl BufRead, mut output: impl Write, ui: &mut impl Ui) -> io::Result<()> {
+    let mut session = Session::default();
+    writeln!(output, "OK Pleased to meet you")?;
+    output.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        let (command, arg) = line.split_once(' ').unwrap_or((&line, ""));
+        let reply = handle(&mut session, &command.to_ascii_uppercase(), arg, ui);
+        writeln!(output, "{}", reply)?;
+        output.flush()?;
+        if command.eq_ignore_ascii_case("BYE") {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn handle(session: &mut Session, command: &str, arg: &str, ui: &mut impl Ui) -> String {
+    let text = || Some(unescape(arg)).filter(|t| !t.is_empty());
+    match command {
+        "SETDESC" => session.description = text(),
+        "SETPROMPT" => session.prompt = text(),
+        "SETTITLE" => session.title = text(),
+        "SETERROR" => session.error = text(),
+        "SETREPEAT" => session.repeat = Some(text().unwrap_or_else(|| "Repeat:".into())),
+        "SETKEYINFO" => {
+            session.keygrip = match arg {
+                "" | "--clear" => None,
+                info => Some(info.split_once('/').map_or(info, |(_, grip)| grip).into()),
+            }
+        }
+        "OPTION" => {
+            let name = arg.split_once('=').map_or(arg, |(name, _)| name);
+            if name.trim_start_matches("--") == "allow-external-password-cache" {
+                session.allow_cache = true;
+            }
+        }
+        "GETINFO" => {
+            let data = match arg {
+                "flavor" => "wsl-ssh-askpass".to_string(),
+                "version" => env!("CARGO_PKG_VERSION").to_string(),
+                "pid" => std::process::id().to_string(),
+                _ => return "OK".into(),
+            };
+            return format!("D {}\nOK", escape(&data));
+        }
+        "GETPIN" => {
+            let reply = get_pin(session, ui);
+            session.reset();
+            return reply;
+        }
+        "CONFIRM" => {
+            let confirmed = if arg == "--one-button" {
+                ui.message(session);
+                true
+            } else {
+                ui.confirm(session)
+            };
+            session.reset();
+            return if confirmed { "OK" } else { ERR_CANCELLED }.into();
+        }
+        "MESSAGE" => {
+            ui.message(session);
+            session.reset();
+        }
+        "RESET" => session.reset(),
+        // Display and terminal settings, quality bars, button labels: none
+        // of them apply to the Windows dialogs
+        "SETOK" | "SETCANCEL" | "SETNOTOK" | "SETQUALITYBAR" | "SETQUALITYBAR_TT" | "SETGENPIN"
+        | "SETGENPIN_TT" | "SETTIMEOUT" | "SETREPEATERROR" | "SETREPEATOK" | "NOP" | "BYE" => {}
+        _ => return ERR_UNKNOWN_COMMAND.into(),
+    }
+    "OK".into()
+}
+
+fn get_pin(session: &mut Session, ui: &mut impl Ui) -> String {
+    loop {
+        let pin = match ui.get_pin(session) {
+            Ok(pin) => pin,
+            Err(PromptError::Cancelled) => return ERR_CANCELLED.into(),
+            Err(PromptError::Failed(e)) => return format!("ERR {} {}", ERR_GENERAL, e),
+        };
+        if let Some(repeat) = &session.repeat {
+            match ui.get_pin(&Session {
+                prompt: Some(repeat.clone()),
+                error: None,
+                ..session.clone()
+            }) {
+                Ok(again) if again == pin => {}
+                Ok(_) => {
+                    // Ask for both again, saying why
+                    session.error = Some("The passphrases do not match.".into());
+                    continue;
+                }
+                Err(PromptError::Cancelled) => return ERR_CANCELLED.into(),
+                Err(PromptError::Failed(e)) => return format!("ERR {} {}", ERR_GENERAL, e),
+            }
+            return format!("S PIN_REPEATED\nD {}\nOK", escape(&pin));
+        }
+        return format!("D {}\nOK", escape(&pin));
+    }
+}
+
+/// The credential dialog and message boxes, with the cache and Hello gate.
+struct Dialogs;
+
+impl Ui for Dialogs {
+    fn get_pin(&mut self, session: &Session) -> Result<String, PromptError> {
+        let mut policy = CachePolicy::passphrase();
+        let key = match session.cache_key() {
+            Some(key) => {
+                if session.error.is_some() {
+                    // gpg-agent is asking again because the last one was wrong
+                    credstore::remove_cached(&key);
+                    policy.read = false;
+                }
+                key
+            }
+            None => {
+                policy.read = false;
+                policy.store = false;
+                "gpg".to_string()
+            }
+        };
+        if policy::read_only() {
+            policy.read = false;
+            policy.store = false;
+        }
+        handle_passphrase(&session.message(), &key, None, &policy)
+    }
+
+    fn confirm(&mut self, session: &Session) -> bool {
+        let title = session
+            .title
+            .as_deref()
+            .unwrap_or(&config::get().captions.confirmation);
+        dialog::ask_yes_no(title, session.description.as_deref().unwrap_or(""))
+    }
+
+    fn message(&mut self, session: &Session) {
+        let title = session
+            .title
+            .as_deref()
+            .unwrap_or(&config::get().captions.notification);
+        dialog::show_info(title, session.description.as_deref().unwrap_or(""), "");
+    }
+}
+
+/// Decode Assuan `%XX` escapes; malformed ones are kept as they are.
+fn unescape(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Escape `%`, CR and LF for a data line.
+fn escape(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every GETPIN from `pins` in turn and records what was shown.
+    #[derive(Default)]
+    struct Fake {
+        pins: Vec<Result<String, PromptError>>,
+        shown: Vec<String>,
+        cache_keys: Vec<Option<String>>,
+        confirm: bool,
+    }
+
+    impl Ui for Fake {
+        fn get_pin(&mut self, session: &Session) -> Result<String, PromptError> {
+            self.shown.push(session.message());
+            self.cache_keys.push(session.cache_key());
+            self.pins.remove(0)
+        }
+
+        fn confirm(&mut self, _: &Session) -> bool {
+            self.confirm
+        }
+
+        fn message(&mut self, session: &Session) {
+            self.shown.push(session.message());
+        }
+    }
+
+    fn run(script: &str, ui: &mut Fake) -> String {
+        let mut output = Vec::new();
+        serve_with(script.as_bytes(), &mut output, ui).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn getpin_session() {
+        let mut ui = Fake {
+            pins: vec![Ok("s3cr%t\n".into())],
+            ..Fake::default()
+        };
+        let output = run(
+            "OPTION allow-external-password-cache\n\
+             SETKEYINFO n/0123ABCD\n\
+             SETDESC Please enter the passphrase to%0Aunlock the key\n\
+             SETPROMPT Passphrase:\n\
+             GETPIN\n\
+             BYE\n",
+            &mut ui,
+        );
+        assert_eq!(
+            output,
+            "OK Pleased to meet you\nOK\nOK\nOK\nOK\nD s3cr%25t%0A\nOK\nOK\n"
+        );
+        assert_eq!(
+            ui.shown,
+            ["Please enter the passphrase to\nunlock the key\n\nPassphrase:"]
+        );
+        assert_eq!(ui.cache_keys, [Some("gpg:0123ABCD".to_string())]);
+    }
+
+    #[test]
+    fn caching_needs_agent_permission() {
+        let mut ui = Fake {
+            pins: vec![Ok("x".into())],
+            ..Fake::default()
+        };
+        run("SETKEYINFO n/0123ABCD\nGETPIN\n", &mut ui);
+        assert_eq!(ui.cache_keys, [None]);
+    }
+
+    #[test]
+    fn cancel_and_unknown_commands() {
+        let mut ui = Fake {
+            pins: vec![Err(PromptError::Cancelled)],
+            ..Fake::default()
+        };
+        let output = run("GETPIN\nFROB\nCONFIRM\n", &mut ui);
+        assert_eq!(
+            output,
+            format!(
+                "OK Pleased to meet you\n{}\n{}\n{}\n",
+                ERR_CANCELLED, ERR_UNKNOWN_COMMAND, ERR_CANCELLED
+            )
+        );
+    }
+
+    #[test]
+    fn repeated_passphrases_must_match() {
+        let mut ui = Fake {
+            pins: vec![
+                Ok("one".into()),
+                Ok("two".into()),
+                Ok("new".into()),
+                Ok("new".into()),
+            ],
+            ..Fake::default()
+        };
+        let output = run("SETREPEAT\nGETPIN\n", &mut ui);
+        assert_eq!(
+            output,
+            "OK Pleased to meet you\nOK\nS PIN_REPEATED\nD new\nOK\n"
+        );
+        assert_eq!(ui.shown[2], "The passphrases do not match.\n\nPassphrase:");
+        assert_eq!(ui.cache_keys, [None, None, None, None]);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(unescape("a%25b%0A%zz%"), "a%b\n%zz%");
+        assert_eq!(escape("a%b\r\n"), "a%25b%0D%0A");
+    }
+}
@@ -0,0 +1,150 @@
+//! Line-delimited JSON-RPC 2.0 front end to the askpass API.
+//!
+//! Each request is one JSON object per line; each response is one line.
+//! Used by `ps-serve` so PowerShell and other automation can request
+//! Hello-verified secrets from the same store the WSL side uses.
+
+use std::io::{self, BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::core::compat::Compat;
+use crate::{PromptError, PromptKind};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The user dismissed the dialog or failed verification.
+pub(crate) const CANCELLED: i64 = 1;
+/// The dialog could not be shown or its answer read.
+pub(crate) const FAILED: i64 = 2;
+
+/// Serve requests from `input` until EOF, writing responses to `output`.
+pub fn serve<R: BufRead, W: Write>(input: R, mut output: W) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_line(&line);
+        writeln!(output, "{}", response)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// Handle a single request line and return the response object.
+pub fn handle_line(line: &str) -> Value {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return error(Value::Null, PARSE_ERROR, &e.to_string()),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        return error(id, INVALID_REQUEST, "missing method");
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    let param = |name: &str| params.get(name).and_then(Value::as_str).map(str::to_string);
+
+    match method {
+        "prompt" => match param("prompt") {
+            Some(prompt) => {
+                // Only a secret handed out by this request may be described
+                crate::take_served();
+                let kind = PromptKind::from_hint(param("kind").as_deref());
+                let compat = Compat::selected();
+                match crate::answer_prompt_as(&prompt, kind) {
+                    Ok(secret) => success(id, prompt_result(secret, kind, compat)),
+                    Err(e) => {
+                        let mut response = match &e {
+                            PromptError::Cancelled => error(id, CANCELLED, "cancelled"),
+                            PromptError::Failed(message) => error(id, FAILED, message),
+                        };
+                        response["error"]["data"] = json!({ "exitCode": compat.exit_code(&e) });
+                        response
+                    }
+                }
+            }
+            None => error(id, INVALID_PARAMS, "missing prompt"),
+        },
+        "confirm" => match param("prompt") {
+            Some(prompt) => success(id, json!({ "confirmed": crate::confirm(&prompt) })),
+            None => error(id, INVALID_PARAMS, "missing prompt"),
+        },
+        "verifyHello" => match param("key") {
+            Some(key) => success(id, json!({ "verified": crate::verify_hello(&key) })),
+            None => error(id, INVALID_PARAMS, "missing key"),
+        },
+        "clear" => match param("key") {
+            Some(key) => success(id, json!({ "removed": crate::clear_cached(&key) })),
+            None => error(id, INVALID_PARAMS, "missing key"),
+        },
+        _ => error(
+            id,
+            METHOD_NOT_FOUND,
+            &format!("unknown method '{}'", method),
+        ),
+    }
+}
+
+/// `{"secret": ..., "output": ...}`, `output` being what the askpass would
+/// print for it in the `compat` mode and with the `newline` setting, with how
+/// it was come by when a secret was handed out: `served` holds its cache
+/// `key`, `fromCache` and the seconds left in its Hello grace window as
+/// `helloWindow` (`null` when none is open). An error carries the exit
+/// status the askpass would use as `data.exitCode`.
+fn prompt_result(secret: String, kind: PromptKind, compat: Compat) -> Value {
+    let output = compat.output(kind, &secret, crate::newline_preference());
+    let mut result = json!({ "secret": secret, "output": output });
+    if let Some(served) = crate::take_served() {
+        result["served"] = json!({
+            "key": served.key,
+            "fromCache": served.from_cache,
+            "helloWindow": served.hello_window_secs,
+        });
+    }
+    result
+}
+
+fn success(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+pub(crate) fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(response: &Value) -> Option<i64> {
+        response["error"]["code"].as_i64()
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let response = handle_line("{not json");
+        assert_eq!(code(&response), Some(PARSE_ERROR));
+        assert_eq!(response["id"], Value::Null);
+
+        let response = handle_line(r#"{"jsonrpc":"2.0","id":7}"#);
+        assert_eq!(code(&response), Some(INVALID_REQUEST));
+        assert_eq!(response["id"], 7);
+
+        let response = handle_line(r#"{"jsonrpc":"2.0","id":"a","method":"reveal"}"#);
+        assert_eq!(code(&response), Some(METHOD_NOT_FOUND));
+        assert_eq!(response["id"], "a");
+    }
+
+    #[test]
+    fn requires_params() {
+        for method in ["prompt", "confirm", "verifyHello", "clear"] {
+            let line = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": {} });
+            let response = handle_line(&line.to_string());
+            assert_eq!(code(&response), Some(INVALID_PARAMS), "{}", method);
+            assert_eq!(response["jsonrpc"], "2.0");
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! `setup`: wire a distro's shell up to this askpass, so that nobody has to
+//! copy exports out of the README.
+//!
+//! A wrapper script at `~/.local/bin/wsl-ssh-askpass` runs the Windows
+//! executable from wherever it is now, and a block between marker lines in
+//! the shell's startup file points `SSH_ASKPASS` at the wrapper. Running
+//! setup again rewrites both, so moving the executable only takes a rerun.
+
+use std::env;
+
+use crate::ipc::wsl::{self, shell_quote};
+
+const BLOCK_START: &str = "# >>> wsl-ssh-askpass >>>";
+const BLOCK_END: &str = "# <<< wsl-ssh-askpass <<<";
+
+/// Where the wrapper goes; the startup file refers to it by this path.
+const WRAPPER: &str = "$HOME/.local/bin/wsl-ssh-askpass";
+
+/// A shell whose startup file setup knows how to write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    /// From a name such as `zsh` or a login shell path such as `/usr/bin/zsh`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().rsplit('/').next()? {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            _ => None,
+        }
+    }
+
+    /// The startup file, as a shell word to be expanded in the distro.
+    fn rc_file(self) -> &'static str {
+        match self {
+            Shell::Bash => "$HOME/.bashrc",
+            Shell::Zsh => "${ZDOTDIR:-$HOME}/.zshrc",
+            // A file of its own, which fish reads before config.fish
+            Shell::Fish => "${XDG_CONFIG_HOME:-$HOME/.config}/fish/conf.d/wsl-ssh-askpass.fish",
+        }
+    }
+}
+
+/// What `SSH_ASKPASS_REQUIRE` is set to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Require {
+    /// The dialog, unless ssh cannot use an askpass at all
+    Prefer,
+    /// Always the dialog, even with a terminal at hand
+    Force,
+}
+
+impl Require {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "prefer" => Some(Require::Prefer),
+            "force" => Some(Require::Force),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Require::Prefer => "prefer",
+            Require::Force => "force",
+        }
+    }
+}
+
+/// Choices for [`setup`].
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    /// The shell to configure; by default the user's login shell
+    pub shell: Option<Shell>,
+    pub require: Require,
+    /// Show a test prompt through the new wrapper afterwards
+    pub test: bool,
+}
+
+/// Set up `distro` (or the default distro), returning the startup file
+/// written as the distro sees it. With `options.test`, a yes/no dialog is
+/// shown through the wrapper, and a "No" or a dialog that never appeared
+/// is an error.
+pub fn setup(distro: Option<&str>, options: Options) -> Result<String, String> {
+    let shell = match options.shell {
+        Some(shell) => shell,
+        None => login_shell(distro)?,
+    };
+    let exe = env::current_exe().map_err(|e| e.to_string())?;
+    let script = format!(
+        "set -e\n\
+         exe=\"$(wslpath -u {exe})\"\n\
+         wrapper=\"{wrapper}\"\n\
+         mkdir -p \"$(dirname \"$wrapper\")\"\n\
+         quoted=\"$(printf '%s' \"$exe\" | sed \"s/'/'\\\\\\\\''/g\")\"\n\
+         printf '#!/bin/sh\\nexec '\\''%s'\\'' \"$@\"\\n' \"$quoted\" > \"$wrapper\"\n\
+         chmod +x \"$wrapper\"\n\
+         rc=\"{rc}\"\n\
+         mkdir -p \"$(dirname \"$rc\")\"\n\
+         touch \"$rc\"\n\
+         sed -i '/^{start}$/,/^{end}$/d' \"$rc\"\n\
+         cat >> \"$rc\"\n\
+         printf '%s\\n' \"$rc\"\n",
+        exe = shell_quote(&exe.to_string_lossy()),
+        wrapper = WRAPPER,
+        rc = shell.rc_file(),
+        start = BLOCK_START,
+        end = BLOCK_END,
+    );
+    let block = rc_block(shell, options.require);
+    let rc = wsl::run(distro, &script, Some(block.as_bytes()))?
+        .trim()
+        .to_string();
+    if options.test {
+        test_prompt(distro)?;
+    }
+    Ok(rc)
+}
+
+/// The user's login shell in `distro`, if setup knows it.
+fn login_shell(distro: Option<&str>) -> Result<Shell, String> {
+    let output = wsl::run(distro, "getent passwd \"$(id -un)\" | cut -d: -f7", None)?;
+    let name = output.trim();
+    Shell::from_name(name).ok_or_else(|| {
+        format!(
+            "the login shell {} is not one setup knows; pass --shell bash, zsh or fish",
+            if name.is_empty() { "(unknown)" } else { name }
+        )
+    })
+}
+
+/// Round trip from the distro through interop to a dialog and back.
+fn test_prompt(distro: Option<&str>) -> Result<(), String> {
+    let script = format!(
+        "SSH_ASKPASS_PROMPT=confirm WSLENV=\"${{WSLENV:+$WSLENV:}}SSH_ASKPASS_PROMPT\" \
+         \"{wrapper}\" {prompt} >/dev/null",
+        wrapper = WRAPPER,
+        prompt = shell_quote(
+            "wsl-ssh-askpass is set up in this distro. This is a test prompt; did it appear?"
+        ),
+    );
+    wsl::run(distro, &script, None)
+        .map(|_| ())
+        .map_err(|e| format!("the test prompt did not succeed ({})", e))
+}
+
+/// The lines for `shell`'s startup file, between the markers.
+fn rc_block(shell: Shell, require: Require) -> String {
+    let body = match shell {
+        Shell::Bash | Shell::Zsh => format!(
+            "export SSH_ASKPASS=\"{wrapper}\"\n\
+             export SSH_ASKPASS_REQUIRE={require}\n\
+             case \":$WSLENV:\" in\n\
+             \x20   *:SSH_ASKPASS_PROMPT:*) ;;\n\
+             \x20   *) export WSLENV=\"${{WSLENV:+$WSLENV:}}SSH_ASKPASS_PROMPT\" ;;\n\
+             esac\n",
+            wrapper = WRAPPER,
+            require = require.as_str()
+        ),
+        Shell::Fish => format!(
+            "set -gx SSH_ASKPASS \"{wrapper}\"\n\
+             set -gx SSH_ASKPASS_REQUIRE {require}\n\
+             if not contains SSH_ASKPASS_PROMPT (string split : -- \"$WSLENV\")\n\
+             \x20   set -gx WSLENV (string join : -- $WSLENV SSH_ASKPASS_PROMPT)\n\
+             end\n",
+            wrapper = WRAPPER,
+            require = require.as_str()
+        ),
+    };
+    format!("{}\n{}{}\n", BLOCK_START, body, BLOCK_END)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_names() {
+        assert_eq!(Shell::from_name("/usr/bin/zsh\n"), Some(Shell::Zsh));
+        assert_eq!(Shell::from_name("bash"), Some(Shell::Bash));
+        assert_eq!(Shell::from_name("/usr/bin/fish"), Some(Shell::Fish));
+        assert_eq!(Shell::from_name("/bin/dash"), None);
+        assert_eq!(Shell::from_name(""), None);
+    }
+
+    #[test]
+    fn startup_blocks() {
+        let bash = rc_block(Shell::Bash, Require::Prefer);
+        assert!(bash.starts_with(BLOCK_START));
+        assert!(bash.ends_with(&format!("{}\n", BLOCK_END)));
+        assert!(bash.contains("export SSH_ASKPASS=\"$HOME/.local/bin/wsl-ssh-askpass\"\n"));
+        assert!(bash.contains("export SSH_ASKPASS_REQUIRE=prefer\n"));
+        let fish = rc_block(Shell::Fish, Require::Force);
+        assert!(fish.contains("set -gx SSH_ASKPASS_REQUIRE force\n"));
+        assert!(!fish.contains("export"));
+    }
+}
@@ -0,0 +1,70 @@
+//! `vscode-setup`: point the VS Code Remote-WSL server at this askpass so
+//! git operations from the editor show the Windows prompt instead of
+//! failing silently in the output panel.
+
+use std::env;
+
+use serde_json::{Map, Value};
+
+use crate::ipc::wsl::{self, shell_quote};
+
+pub(crate) const ENV_SETUP_MARKER: &str = "# wsl-ssh-askpass";
+const MACHINE_SETTINGS: &str = "$HOME/.vscode-server/data/Machine/settings.json";
+
+/// Configure the VS Code server in `distro` (or the default distro):
+///
+/// - `~/.vscode-server/server-env-setup` exports `SSH_ASKPASS` and
+///   `SSH_ASKPASS_REQUIRE=force` for the server and everything it spawns;
+/// - the remote machine settings turn off `git.useIntegratedAskPass`, so the
+///   git extension does not substitute its own askpass.
+///
+/// Takes effect when the VS Code server next starts.
+pub fn setup(distro: Option<&str>) -> Result<(), String> {
+    let exe = env::current_exe().map_err(|e| e.to_string())?;
+    let exe = shell_quote(&exe.to_string_lossy());
+
+    let env_script = format!(
+        "set -e\n\
+         exe=\"$(wslpath -u {exe})\"\n\
+         mkdir -p \"$HOME/.vscode-server\"\n\
+         f=\"$HOME/.vscode-server/server-env-setup\"\n\
+         touch \"$f\"\n\
+         grep -qF '{marker}' \"$f\" || printf '\\n{marker}\\nexport SSH_ASKPASS=\"%s\"\\nexport SSH_ASKPASS_REQUIRE=force\\n' \"$exe\" >> \"$f\"\n",
+        exe = exe,
+        marker = ENV_SETUP_MARKER
+    );
+    wsl::run(distro, &env_script, None)?;
+
+    let current = wsl::run(
+        distro,
+        &format!("cat \"{}\" 2>/dev/null || true", MACHINE_SETTINGS),
+        None,
+    )?;
+    let mut settings = if current.trim().is_empty() {
+        Map::new()
+    } else {
+        match serde_json::from_str::<Value>(&current) {
+            Ok(Value::Object(map)) => map,
+            _ => {
+                return Err(format!(
+                    "{} is not plain JSON; add \"git.useIntegratedAskPass\": false to it by hand",
+                    MACHINE_SETTINGS
+                ))
+            }
+        }
+    };
+    settings.insert("git.useIntegratedAskPass".into(), Value::Bool(false));
+    let updated =
+        serde_json::to_string_pretty(&Value::Object(settings)).map_err(|e| e.to_string())?;
+    wsl::run(
+        distro,
+        &format!(
+            "mkdir -p \"$(dirname \"{path}\")\" && cat > \"{path}\"",
+            path = MACHINE_SETTINGS
+        ),
+        Some(updated.as_bytes()),
+    )?;
+
+    eprintln!("wsl-ssh-askpass: VS Code server configured; restart VS Code (or run 'Kill VS Code Server on Host') to apply");
+    Ok(())
+}
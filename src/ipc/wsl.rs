@@ -0,0 +1,111 @@
+//! Installed WSL distros, and running helper scripts inside them.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// How the askpass is wired up inside one distro.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DistroState {
+    /// A shell startup file points `SSH_ASKPASS` at this program
+    pub shell: bool,
+    /// `vscode-setup` has configured the VS Code server
+    pub vscode: bool,
+}
+
+/// Names of the installed distros, as listed by `wsl.exe -l -q`.
+pub fn distros() -> Result<Vec<String>, String> {
+    let output = Command::new("wsl.exe")
+        .args(["-l", "-q"])
+        .env("WSL_UTF8", "1")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("cannot run wsl.exe: {}", e))?;
+    if !output.status.success() {
+        return Err("wsl.exe -l failed; is WSL installed?".into());
+    }
+    Ok(decode_listing(&output.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+/// Check the startup files and VS Code server setup inside `distro`. This
+/// starts the distro if it is not running.
+pub fn state(distro: &str) -> Result<DistroState, String> {
+    let script = format!(
+        "for f in .bashrc .zshrc .profile .bash_profile .zprofile; do
+           grep -qs 'SSH_ASKPASS=.*wsl-ssh-askpass' \"$HOME/$f\" && {{ echo shell; break; }}
+         done
+         grep -qs 'SSH_ASKPASS .*wsl-ssh-askpass' \
+           \"${{XDG_CONFIG_HOME:-$HOME/.config}}/fish/conf.d/wsl-ssh-askpass.fish\" && echo shell
+         grep -qsF {marker} \"$HOME/.vscode-server/server-env-setup\" && echo vscode
+         true
+",
+        marker = shell_quote(crate::ipc::vscode::ENV_SETUP_MARKER)
+    );
+    let output = run(Some(distro), &script, None)?;
+    let has = |word: &str| output.lines().any(|line| line.trim() == word);
+    Ok(DistroState {
+        shell: has("shell"),
+        vscode: has("vscode"),
+    })
+}
+
+/// Older wsl.exe ignores `WSL_UTF8` and writes UTF-16LE.
+fn decode_listing(bytes: &[u8]) -> String {
+    if bytes.len() >= 2 && bytes.iter().skip(1).step_by(2).all(|&b| b == 0) {
+        let wide: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16_lossy(&wide)
+            .trim_start_matches('\u{feff}')
+            .to_string()
+    } else {
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+/// Run `script` with `sh` in the distro, feeding `stdin`, returning stdout.
+/// `--exec` bypasses the login shell so the script reaches `sh` unmangled.
+pub(crate) fn run(
+    distro: Option<&str>,
+    script: &str,
+    stdin: Option<&[u8]>,
+) -> Result<String, String> {
+    let mut cmd = Command::new("wsl.exe");
+    if let Some(distro) = distro {
+        cmd.args(["-d", distro]);
+    }
+    cmd.args(["--exec", "sh", "-c", script])
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| format!("cannot run wsl.exe: {}", e))?;
+    crate::core::job::adopt(&child);
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input).map_err(|e| e.to_string())?;
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!(
+            "wsl.exe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Quote `s` for a POSIX shell.
+pub(crate) fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
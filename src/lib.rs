@@ -0,0 +1,780 @@
+//! Windows Hello gated SSH askpass prompting and caching.
+//!
+//! The `wsl-ssh-askpass` binary is a thin wrapper around this crate. Other
+//! Windows-side tools can use the same prompts and Credential Manager cache
+//! directly instead of shelling out to the executable:
+//!
+//! ```no_run
+//! let prompt = "Enter passphrase for key '/home/me/.ssh/id_ed25519':";
+//! if let Ok(pass) = wsl_ssh_askpass::prompt_passphrase(prompt) {
+//!     // use the passphrase
+//! #   drop(pass);
+//! }
+//! ```
+
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+
+use windows::Win32::Security::Cryptography::{BCryptGenRandom, BCRYPT_USE_SYSTEM_PREFERRED_RNG};
+
+pub mod core;
+pub mod ipc;
+pub mod store;
+pub mod ui;
+
+use crate::core::{
+    caller, clock, config, diag, hostkey, mitigation, policy, presence, prompt, proximity, timing,
+};
+use crate::ipc::daemon;
+use crate::store::{audit, credstore, keyfile, known_hosts, sealed};
+use crate::ui::{approval, console, dialog, hello};
+
+use credstore::{
+    cache_passphrase, get_cached_blob, get_save_preference, is_hello_valid, update_hello_timestamp,
+};
+pub use credstore::{CachedSecret, CachedSecretDetails, SavePreference};
+use dialog::{prompt_for_password, prompt_host_key, prompt_menu, HostKeyAnswer, MenuChoice};
+use policy::{CachePolicy, DistroRule, Unlock};
+pub use prompt::PromptKind;
+use prompt::{
+    display_prompt, extract_kerberos_target, extract_key_id, extract_key_name, extract_key_path,
+    extract_password_target, extract_sudo_target, is_one_time_code_prompt, is_pin_prompt,
+    is_retry_prompt, is_yes_no_prompt, parse_menu, strip_nocache_hint,
+};
+
+pub(crate) fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// Per-user state directory: `%LOCALAPPDATA%\wsl-ssh-askpass`.
+pub(crate) fn data_dir() -> Option<PathBuf> {
+    Some(PathBuf::from(env::var_os("LOCALAPPDATA")?).join("wsl-ssh-askpass"))
+}
+
+/// `len` random bytes from the system RNG.
+pub(crate) fn random_bytes(len: usize) -> Option<Vec<u8>> {
+    let mut bytes = vec![0u8; len];
+    unsafe { BCryptGenRandom(None, &mut bytes, BCRYPT_USE_SYSTEM_PREFERRED_RNG).ok() }.ok()?;
+    Some(bytes)
+}
+
+/// `len` random bytes from the system RNG, hex encoded.
+pub(crate) fn random_hex(len: usize) -> Option<String> {
+    let bytes = random_bytes(len)?;
+    Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Why a prompt produced no answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PromptError {
+    /// The user dismissed the dialog or declined a verification.
+    Cancelled,
+    /// The dialog could not be shown or its answer read.
+    Failed(String),
+}
+
+impl fmt::Display for PromptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PromptError::Cancelled => f.write_str("cancelled"),
+            PromptError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for PromptError {}
+
+/// How a secret handed out by [`answer_prompt`] was come by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Served {
+    /// The cache key it belongs to
+    pub key: String,
+    /// Released from the cache, rather than typed
+    pub from_cache: bool,
+    /// Seconds left in the key's Windows Hello grace window, if one is open
+    pub hello_window_secs: Option<u64>,
+}
+
+/// How the last secret this process handed out was come by, forgetting it:
+/// `None` if none was since the last call.
+pub fn take_served() -> Option<Served> {
+    audit::take_served()
+}
+
+/// Answer an askpass prompt the way ssh expects, returning the text to print.
+///
+/// Host key questions yield `"yes"`, `"no"` or a fingerprint, `(y/n)` questions `"y"` or
+/// `"n"`, two-factor menus yield the chosen option, one-time code questions the code as
+/// typed (never cached), and everything else goes through [`prompt_passphrase`].
+pub fn answer_prompt(raw_prompt: &str) -> Result<String, PromptError> {
+    let (prompt, _) = strip_nocache_hint(raw_prompt);
+    let classified =
+        |kind: &str| diag::note("prompt", || format!("{}: {}", kind, diag::redact(prompt)));
+    if let Some((_, DistroRule::Terminal)) = policy::distro_rule() {
+        // This distro opted out of dialogs: ask where ssh would have
+        classified("terminal");
+        let secret = !hostkey::is_prompt(prompt) && !is_yes_no_prompt(prompt);
+        return console::ask(prompt, secret);
+    }
+    if hostkey::is_prompt(prompt) {
+        // Host key verification
+        classified("host key");
+        Ok(answer_host_key(prompt))
+    } else if is_yes_no_prompt(prompt) {
+        // "Overwrite (y/n)?" and the like: a question, not a secret
+        classified("yes/no");
+        let yes = timing::time("ui", || confirm(prompt));
+        Ok(if yes { "y" } else { "n" }.into())
+    } else if let Some(menu) = parse_menu(prompt) {
+        // Two-factor menu: answer with the chosen option number or a typed passcode
+        classified("menu");
+        match timing::time("ui", || prompt_menu(&menu))? {
+            MenuChoice::Option(num) => Ok(num),
+            MenuChoice::Passcode => timing::time("ui", || dialog::prompt_for_code(prompt)),
+        }
+    } else if is_one_time_code_prompt(prompt) {
+        // Dead within a minute: one plain field, and never cached
+        classified("one-time code");
+        timing::time("ui", || dialog::prompt_for_code(prompt))
+    } else if is_pin_prompt(prompt) && policy::pin_pad_enabled() {
+        // Smartcard PIN on the randomized on-screen pad; never cached
+        classified("PIN");
+        timing::time("ui", || dialog::prompt_pin_pad(prompt))
+    } else {
+        classified("secret");
+        prompt_passphrase(raw_prompt)
+    }
+}
+
+/// Answer a prompt whose kind ssh has stated through `SSH_ASKPASS_PROMPT`.
+///
+/// Confirmations yield `"yes"` or [`PromptError::Cancelled`] (ssh treats a
+/// non-zero exit as "no"). Notifications stay on screen, answering nothing,
+/// until dismissed or until ssh ends the process. Plain entries go through
+/// [`answer_prompt`].
+pub fn answer_prompt_as(prompt: &str, kind: PromptKind) -> Result<String, PromptError> {
+    match kind {
+        PromptKind::Entry => answer_prompt(prompt),
+        PromptKind::Confirm => {
+            if timing::time("ui", || confirm(prompt)) {
+                Ok("yes".into())
+            } else {
+                Err(PromptError::Cancelled)
+            }
+        }
+        PromptKind::Notify => {
+            dialog::show_info(
+                &config::get().captions.notification,
+                prompt,
+                "This closes by itself once ssh no longer needs it.",
+            );
+            Ok(String::new())
+        }
+    }
+}
+
+/// "yes", "no" or a pasted fingerprint in answer to a host key question.
+/// Keys pinned with "Yes, and remember this host" (or any "Yes", with
+/// `remember_host_keys`) are accepted without asking again until
+/// `host_key_ttl` runs out. A host offering a different key than the one
+/// pinned is always asked about, with a warning.
+fn answer_host_key(prompt: &str) -> String {
+    let pin = policy::host_pinning_enabled()
+        .then(|| hostkey::parse(prompt))
+        .flatten();
+    let config = config::get();
+    let mut previous = None;
+    if let Some(key) = &pin {
+        let pins = credstore::host_key_pins(&key.known_hosts_name());
+        match hostkey::check_pins(&pins, key, config.host_key_ttl, clock::now_secs()) {
+            hostkey::Remembered::Trusted => return "yes".into(),
+            hostkey::Remembered::Unknown => {}
+            hostkey::Remembered::Changed { previous: old } => previous = Some(old),
+        }
+    }
+    let can_paste = hostkey::accepts_fingerprint_answer(prompt);
+    let answer = timing::time("ui", || {
+        prompt_host_key(prompt, previous.as_deref(), pin.is_some(), can_paste)
+    });
+    if let (HostKeyAnswer::Yes | HostKeyAnswer::Remember, Some(key)) = (&answer, &pin) {
+        if answer == HostKeyAnswer::Remember || config.remember_host_keys {
+            let host = key.known_hosts_name();
+            let pins = hostkey::add_pin(&credstore::host_key_pins(&host), key, clock::now_secs());
+            let _ = credstore::set_host_key_pins(&host, &pins);
+        }
+        if policy::update_known_hosts() {
+            if let Err(e) = timing::time("known_hosts", || known_hosts::record(key)) {
+                eprintln!("wsl-ssh-askpass: known_hosts not updated: {}", e);
+            }
+        }
+    }
+    match answer {
+        HostKeyAnswer::Yes | HostKeyAnswer::Remember => "yes".into(),
+        HostKeyAnswer::No => "no".into(),
+        HostKeyAnswer::Fingerprint(fingerprint) => fingerprint,
+    }
+}
+
+/// Prompt for the passphrase or password requested by `prompt`.
+///
+/// A cached secret is returned after Windows Hello verification (or within
+/// the Hello grace window); otherwise the credential dialog is shown and the
+/// answer is cached if the user ticks "Remember". The cache key and policy
+/// are derived from the prompt text, so remote, Kerberos and sudo passwords get
+/// their stricter handling. A leading `[nocache]` disables cache writes. A
+/// question for a one-time code is asked without the cache at all.
+pub fn prompt_passphrase(prompt: &str) -> Result<String, PromptError> {
+    let (prompt, no_store) = strip_nocache_hint(prompt);
+    if is_one_time_code_prompt(prompt) {
+        return timing::time("ui", || dialog::prompt_for_code(prompt));
+    }
+
+    // Passphrase, remote password or Kerberos password request. Key
+    // passphrases were once cached by file name alone.
+    let mut legacy_name = None;
+    let (mut key_name, mut policy, remote_password) =
+        if let Some(target) = extract_password_target(prompt) {
+            (target, CachePolicy::password(), true)
+        } else if let Some(target) = extract_kerberos_target(prompt) {
+            (target, CachePolicy::kerberos(), false)
+        } else if let Some(target) = extract_sudo_target(prompt) {
+            (target, CachePolicy::sudo(), false)
+        } else {
+            legacy_name = Some(extract_key_name(prompt));
+            (extract_key_id(prompt), CachePolicy::passphrase(), false)
+        };
+    let key_path = legacy_name
+        .is_some()
+        .then(|| extract_key_path(prompt))
+        .flatten();
+    // Only key files are stamped, and only those Windows can read
+    let key_file = key_path.and_then(keyfile::locate);
+    if let Some((distro, DistroRule::Isolated(ttl))) = policy::distro_rule() {
+        // Key names never contain '/', so namespaced entries cannot collide
+        key_name = format!("distro:{}/{}", distro, key_name);
+        legacy_name = legacy_name.map(|name| format!("distro:{}/{}", distro, name));
+        if let Some(ttl) = ttl {
+            policy.max_age_secs = Some(policy.max_age_secs.map_or(ttl, |age| age.min(ttl)));
+        }
+    } else if let Some((_, DistroRule::Unverified)) = policy::distro_rule() {
+        policy.read = false;
+        policy.store = false;
+    }
+    if let Some(legacy) = legacy_name.filter(|legacy| *legacy != key_name) {
+        key_name = adopt_legacy_entry(&legacy, key_name);
+    }
+    if no_store {
+        policy.store = false;
+    }
+    if let Some(app) = caller::application() {
+        policy.apply_app_rule(&app);
+    }
+    if policy::snoozable(&key_name) && credstore::snooze_until().is_some() {
+        policy.unlock = Unlock::Snoozed;
+    }
+    if policy::read_only() {
+        policy.read = false;
+        policy.store = false;
+    }
+    let mut shown = display_prompt(prompt);
+    if let Some(path) = key_file.as_deref().filter(|_| policy.read) {
+        if credstore::written_secs(&key_name).is_some() && keyfile::changed(&key_name, path) {
+            credstore::remove_cached(&key_name);
+            shown = format!("{}\n\n{}", KEY_CHANGED_NOTICE, shown);
+        }
+    }
+    if is_retry_prompt(prompt) {
+        // The answer just given was wrong: never serve it again
+        policy.read = false;
+        if !policy::read_only() && evict_rejected(&key_name, RETRY_WINDOW_SECS) {
+            shown = format!("{}\n\n{}", REJECTED_NOTICE, shown);
+        }
+    }
+    if remote_password && !policy::read_only() {
+        let total = policy::password_prompts();
+        let attempt =
+            policy::password_attempt(credstore::last_attempt(&key_name), clock::now_secs(), total);
+        credstore::record_attempt(&key_name, attempt);
+        diag::note("cache", || {
+            format!("password attempt {}/{}", attempt, total)
+        });
+        if attempt > 1 {
+            // ssh only asks again after the server said no: the last attempts
+            // must not go to replaying a password known to be wrong
+            policy.read = false;
+            if evict_rejected(&key_name, RETRY_WINDOW_SECS) {
+                shown = format!("{}\n\n{}", PASSWORD_REJECTED_NOTICE, shown);
+            }
+            shown = format!("Attempt {}/{}\n\n{}", attempt, total, shown);
+        }
+    }
+    if key_name.starts_with("sudo:") && !policy::read_only() {
+        // sudo repeats its prompt after a wrong password, and replaying one
+        // would count towards any lockout
+        if evict_rejected(&key_name, SUDO_RETRY_WINDOW_SECS) {
+            policy.read = false;
+            shown = format!("{}\n\n{}", SUDO_REJECTED_NOTICE, shown);
+        }
+    }
+    diag::note("cache", || {
+        format!(
+            "key {}: read {}, store {}, unlock {:?}",
+            key_name, policy.read, policy.store, policy.unlock
+        )
+    });
+    let started = clock::now_secs();
+    let answer = handle_passphrase(&shown, &key_name, key_path, &policy);
+    if let Some(path) = key_file
+        .as_deref()
+        .filter(|_| answer.is_ok() && policy.store)
+    {
+        // A passphrase saved just now belongs to the file as it is now
+        if credstore::written_secs(&key_name).is_some_and(|written| written >= started) {
+            keyfile::record(&key_name, path);
+        }
+    }
+    answer
+}
+
+const KEY_CHANGED_NOTICE: &str =
+    "The key file has changed since its passphrase was saved, so the saved one has been forgotten.";
+
+/// How long after a cached passphrase was served (or saved) a retry prompt
+/// is taken to be about it.
+const RETRY_WINDOW_SECS: u64 = 120;
+
+/// sudo asks again within seconds of a wrong password, while a right one
+/// keeps it quiet for its own `timestamp_timeout`.
+const SUDO_RETRY_WINDOW_SECS: u64 = 15;
+
+const REJECTED_NOTICE: &str =
+    "The saved passphrase was not accepted, so it has been forgotten. Type the current one.";
+
+const PASSWORD_REJECTED_NOTICE: &str =
+    "The server did not accept the saved password, so it has been forgotten.";
+
+const SUDO_REJECTED_NOTICE: &str =
+    "sudo asked again straight after the saved password was used, so it has been forgotten.";
+
+/// Forget the passphrase cached for `key` if it is the one just rejected:
+/// served from the cache or saved less than `window` seconds ago. One typed
+/// by hand while a good one stays cached is left alone.
+fn evict_rejected(key: &str, window: u64) -> bool {
+    let now = clock::now_secs();
+    let recent = |secs: u64| clock::within(secs, window, now);
+    let rejected = credstore::cached_details(key).is_some_and(|details| {
+        details.last_used_secs.is_some_and(recent) || recent(details.written_secs)
+    });
+    rejected && credstore::remove_cached(key)
+}
+
+/// The cache key to use for `key`, taking over an entry cached under its
+/// bare file name by an earlier version: the first key file of that name
+/// to ask claims it, with its checkbox preference. Hello-sealed entries are
+/// bound to the name they were sealed under, so they keep it until removed.
+fn adopt_legacy_entry(legacy: &str, key: String) -> String {
+    // Looked at without unwrapping, which may take Windows Hello
+    if credstore::cached_details(&key).is_some() {
+        return key;
+    }
+    match credstore::cached_details(legacy) {
+        Some(details) if details.sealed || policy::read_only() => legacy.to_string(),
+        Some(_) if !credstore::rename_cached(legacy, &key) => legacy.to_string(),
+        _ => key,
+    }
+}
+
+/// Ask a yes/no question in a warning dialog. Returns `true` for "Yes".
+pub fn confirm(prompt: &str) -> bool {
+    dialog::ask_yes_no(&config::get().captions.confirmation, prompt)
+}
+
+/// Forget the cached secret for the cache key `key` (for example
+/// `id_ed25519#3c1f9a2e` or `password:user@host`). A key file's bare name
+/// forgets it in every directory. Returns `true` if one existed.
+pub fn clear_cached(key: &str) -> bool {
+    if credstore::remove_cached(key) {
+        return true;
+    }
+    credstore::resolve_cached(key)
+        .iter()
+        .filter(|key| credstore::remove_cached(key))
+        .count()
+        > 0
+}
+
+/// Every cached secret, without revealing any of them.
+pub fn cached_secrets() -> Vec<CachedSecret> {
+    credstore::list_cached()
+}
+
+/// What is known about the secret cached for `key` (when it was stored and
+/// last used, how often, the Hello window left), never the secret itself.
+/// A key file's bare name must match exactly one cached key.
+pub fn cached_secret_details(key: &str) -> Result<CachedSecretDetails, String> {
+    let key = resolve_one(key)?;
+    credstore::cached_details(&key).ok_or_else(|| format!("nothing cached for {}", key))
+}
+
+/// The one cached key that `key` names.
+fn resolve_one(key: &str) -> Result<String, String> {
+    match credstore::resolve_cached(key).as_slice() {
+        [] => Err(format!("nothing cached for {}", key)),
+        [one] => Ok(one.clone()),
+        several => Err(format!(
+            "{} names several keys: {}",
+            key,
+            several.join(", ")
+        )),
+    }
+}
+
+/// The secret cached for `key`, in plain text, for recovering a passphrase
+/// that is needed elsewhere. Asks for on-screen confirmation and then a
+/// fresh Windows Hello verification, whatever the grace window; every
+/// reveal is logged to `reveal.log`.
+pub fn reveal_cached(key: &str) -> Result<String, String> {
+    let key = resolve_one(key)?;
+    let question = format!(
+        "Show the cached secret for {} in plain text?\n\n\
+         Anyone who can see the screen or the terminal's scrollback will be able to read it.",
+        key
+    );
+    let message = format!("Reveal the saved passphrase for {}", key);
+    if !confirm(&question) || !hello::verify_with_hello_message(&message) {
+        audit::append("reveal.log", &["declined", &key]);
+        return Err("reveal was not approved".into());
+    }
+    let blob = get_cached_blob(&key, None).ok_or_else(|| format!("nothing cached for {}", key))?;
+    let secret = if sealed::is_sealed(&blob) {
+        sealed::open(&blob, &key).map_err(|e| match e {
+            PromptError::Cancelled => "reveal was not approved".to_string(),
+            PromptError::Failed(e) => e,
+        })?
+    } else {
+        String::from_utf8_lossy(&blob).to_string()
+    };
+    audit::append("reveal.log", &["revealed", &key]);
+    Ok(secret)
+}
+
+/// Delete everything this tool keeps in Credential Manager: cached secrets,
+/// Hello timestamps, checkbox preferences, host key pins and any snooze.
+/// Returns the number of entries removed.
+pub fn clear_all() -> usize {
+    credstore::remove_all()
+}
+
+/// Set how the "Remember" checkbox is offered for `key`, or pass `None` to
+/// forget the choice so the checkbox starts clear again. With
+/// [`SavePreference::Never`] the checkbox is hidden for that key.
+pub fn set_save_preference(key: &str, pref: Option<SavePreference>) -> bool {
+    match pref {
+        Some(pref) => credstore::set_save_preference(key, pref).is_ok(),
+        None => credstore::remove_save_preference(key),
+    }
+}
+
+/// Longest snooze allowed, whatever was asked for.
+pub const MAX_SNOOZE_SECS: u64 = 12 * 60 * 60;
+
+/// Pre-authorize the keys in `snooze_keys` (or the `SnoozeKeys` policy)
+/// for `secs` seconds (capped at [`MAX_SNOOZE_SECS`]) after one Windows
+/// Hello verification: their cached secrets are then served without
+/// further prompts. Returns the granted length in seconds.
+pub fn snooze(secs: u64) -> Result<u64, String> {
+    let secs = secs.min(MAX_SNOOZE_SECS);
+    let message = format!("Snooze Windows Hello for {} minutes", secs.div_ceil(60));
+    if !hello::verify_with_hello_message(&message) {
+        return Err("snooze was not approved".into());
+    }
+    let until = clock::deadline(clock::now_secs(), secs);
+    credstore::set_snooze_until(Some(until)).map_err(|e| e.to_string())?;
+    audit::append("snooze.log", &["start", &until.to_string()]);
+    Ok(secs)
+}
+
+/// End any snooze period early.
+pub fn end_snooze() {
+    let _ = credstore::set_snooze_until(None);
+    audit::append("snooze.log", &["end"]);
+}
+
+/// A short human-readable summary of the current state: Windows Hello
+/// availability, read-only mode, any active snooze and whether Credential
+/// Manager is answering.
+pub fn status() -> String {
+    let hello = if hello::is_available() {
+        "available"
+    } else {
+        "not available"
+    };
+    let read_only = if policy::read_only() { "on" } else { "off" };
+    let snooze = match credstore::snooze_until() {
+        Some(until) => format!(
+            "active for {} more minutes",
+            until.saturating_sub(clock::now_secs()).div_ceil(60)
+        ),
+        None => "off".to_string(),
+    };
+    // The snooze lookup above doubles as the vault probe
+    let vault = if credstore::degraded() {
+        "not responding (cache bypassed)"
+    } else {
+        "ok"
+    };
+    let (active, missing): (Vec<_>, Vec<_>) =
+        mitigation::report().into_iter().partition(|(_, on)| *on);
+    let names = |list: Vec<(&str, bool)>| {
+        list.iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mitigations = match (active.is_empty(), missing.is_empty()) {
+        (_, true) => names(active),
+        (true, false) => format!("none (not in force: {})", names(missing)),
+        (false, false) => format!("{} (not in force: {})", names(active), names(missing)),
+    };
+    format!(
+        "Windows Hello: {}\nRead-only mode: {}\nSnooze: {}\nCredential Manager: {}\n\
+         Exploit mitigations: {}\n{}",
+        hello,
+        read_only,
+        snooze,
+        vault,
+        mitigations,
+        daemon::status()
+    )
+}
+
+/// Check the config file, returning a description of it or every problem
+/// found, with line numbers.
+pub fn validate_config() -> Result<String, String> {
+    config::validate()
+}
+
+/// Set one config file setting by its dotted path, e.g.
+/// `key.id_prod.require_typing` to `true`. The file keeps its comments and
+/// is left alone if the change would not validate.
+pub fn set_config(path: &str, value: &str) -> Result<String, String> {
+    config::set(path, value)
+}
+
+/// The config file's `timeout` setting: seconds a prompt waits for an
+/// answer before it is given up, if it says.
+pub fn prompt_timeout() -> Option<u64> {
+    config::get().timeout
+}
+
+/// The config file's `newline` setting: whether the printed answer should
+/// end with a newline, if it says.
+pub fn newline_preference() -> Option<bool> {
+    config::get().newline
+}
+
+/// Whether we were started straight from Explorer (a double-click), rather
+/// than by ssh or another program.
+pub fn launched_from_explorer() -> bool {
+    caller::ancestry()
+        .first()
+        .is_some_and(|(_, exe)| exe == "explorer.exe")
+}
+
+/// Whether a terminal window is in front, where the prompt could be
+/// answered instead of in a dialog.
+pub fn terminal_in_front() -> bool {
+    let front = caller::terminal_in_front();
+    diag::note("passthrough", || format!("terminal in front: {}", front));
+    front
+}
+
+/// Show [`status`] and `usage` in an information dialog.
+pub fn show_status(usage: &str) {
+    dialog::show_info(
+        "wsl-ssh-askpass",
+        "wsl-ssh-askpass is meant to be run by ssh",
+        &format!("{}\n\n{}", status(), usage),
+    );
+}
+
+/// Run a Windows Hello verification labelled with `key`.
+///
+/// Returns `false` when Hello is unavailable, declined or fails.
+pub fn verify_hello(key: &str) -> bool {
+    hello::verify_with_hello(key)
+}
+
+/// Cache `secret` under `key_name`, sealed to the Windows Hello key when
+/// `hello_encryption` is on. A secret that cannot be sealed is not stored
+/// at all rather than kept as plain text.
+fn store_secret(key_name: &str, secret: &str, policy: &CachePolicy) -> Result<(), PromptError> {
+    let written = if sealed::enabled() {
+        credstore::cache_blob(key_name, &sealed::seal(secret, key_name)?, policy.persist)
+    } else {
+        cache_passphrase(key_name, secret, policy.persist)
+    };
+    written.map_err(|e| PromptError::Failed(e.message()))
+}
+
+/// Serve the secret for `key_name` from the cache or the dialog, under
+/// `policy` as amended by the key's `[key.<name>]` settings (matched on
+/// `key_path` as well, for a key file).
+fn handle_passphrase(
+    prompt: &str,
+    key_name: &str,
+    key_path: Option<&str>,
+    policy: &CachePolicy,
+) -> Result<String, PromptError> {
+    let mut policy = *policy;
+    policy.apply_key_settings(&config::get().key_settings(key_name, key_path));
+    let policy = &policy;
+    // Try cached passphrase with Windows Hello, but only while any required
+    // hardware token is plugged in
+    let cached = if policy.read && presence::required_device_present() {
+        timing::time("credential read", || {
+            get_cached_blob(key_name, policy.max_age_secs)
+        })
+    } else {
+        None
+    };
+    // An empty answer cached by an earlier version is not worth serving
+    let cached = cached.filter(|blob| {
+        let blank = blob.is_empty();
+        if blank && policy.store {
+            credstore::remove_cached(key_name);
+        }
+        !blank
+    });
+    diag::note("cache", || {
+        match &cached {
+            Some(blob) if sealed::is_sealed(blob) => "hit (sealed)",
+            Some(_) => "hit",
+            None if policy.read => "miss",
+            None => "not read",
+        }
+        .to_string()
+    });
+    if let Some(blob) = cached.as_deref().filter(|blob| sealed::is_sealed(blob)) {
+        // Only a fresh Hello signature opens it: no grace window, no snooze
+        match sealed::open(blob, key_name) {
+            Ok(pass) if pass.is_empty() => {
+                if policy.store {
+                    credstore::remove_cached(key_name);
+                }
+            }
+            Ok(pass) => {
+                if approval::required(key_name) && !approval::request(key_name) {
+                    return Err(PromptError::Cancelled);
+                }
+                if policy.store {
+                    credstore::record_use(key_name);
+                }
+                audit::grant(
+                    key_name,
+                    audit::Grant::Cached {
+                        hello: "verified (sealed)",
+                    },
+                );
+                return Ok(pass);
+            }
+            // Declined: type it instead, as when Hello is declined below
+            Err(PromptError::Cancelled) => {}
+            Err(PromptError::Failed(e)) => eprintln!("wsl-ssh-askpass: {}", e),
+        }
+    } else if let Some(pass) = cached.map(|blob| String::from_utf8_lossy(&blob).to_string()) {
+        let mut hello = "verified";
+        let mut unlocked = match policy.unlock {
+            Unlock::Silent => {
+                hello = "not required by the application policy";
+                true
+            }
+            Unlock::Snoozed => {
+                audit::append("snooze.log", &["release", key_name]);
+                hello = "skipped while snoozed";
+                true
+            }
+            Unlock::Fresh => hello::verify_with_hello(key_name),
+            // The grace window only skips Hello while any configured phone is nearby
+            Unlock::Grace if is_hello_valid(key_name) && proximity::device_in_range() => {
+                hello = "within the grace window";
+                true
+            }
+            Unlock::Grace => hello::verify_with_hello(key_name),
+        };
+        // Hello was declined or failed: offer another try instead of retyping
+        while !unlocked
+            && hello::is_available()
+            && timing::time("ui", || dialog::offer_hello_retry(key_name))?
+        {
+            unlocked = hello::verify_with_hello(key_name);
+        }
+        if unlocked {
+            // Sensitive keys also need a tap on the phone before release
+            if approval::required(key_name) && !approval::request(key_name) {
+                return Err(PromptError::Cancelled);
+            }
+            if policy.store {
+                update_hello_timestamp(key_name);
+                credstore::record_use(key_name);
+            }
+            audit::grant(key_name, audit::Grant::Cached { hello });
+            return Ok(pass);
+        }
+    }
+
+    // Prompt for new passphrase, pre-setting the checkbox from the last choice.
+    // A vault that stopped answering above cannot store anything either.
+    let store = policy.store && !credstore::degraded();
+    let pref = store.then(|| get_save_preference(key_name)).flatten();
+    let offer_save = match pref {
+        _ if !store || credstore::degraded() => None,
+        Some(SavePreference::Never) => None,
+        Some(SavePreference::Save) => Some(true),
+        Some(SavePreference::DontSave) | None => Some(false),
+    };
+    let (pass, mut save) =
+        timing::time("ui", || prompt_for_password(prompt, key_name, offer_save))?;
+    // Only nothing at all: a passphrase of spaces is still a passphrase
+    let blank = pass.is_empty();
+    if blank {
+        diag::note("prompt", || {
+            format!("empty answer: {:?}", config::get().empty_passphrase)
+        });
+        if config::get().empty_passphrase == config::EmptyPassphrase::Cancel {
+            return Err(PromptError::Cancelled);
+        }
+        // Passed on as typed, but neither cached nor remembered as a choice
+        save = false;
+    }
+    if save && pref.is_none() {
+        // First store for this key: explain what that means before doing it
+        save = dialog::confirm_first_store(key_name, &policy.describe_lifetime());
+    }
+    if store {
+        if offer_save.is_some() && !blank {
+            let choice = if save {
+                SavePreference::Save
+            } else {
+                SavePreference::DontSave
+            };
+            if pref != Some(choice) {
+                let _ = credstore::set_save_preference(key_name, choice);
+            }
+        }
+        if save {
+            if let Err(PromptError::Failed(e)) = store_secret(key_name, &pass, policy) {
+                eprintln!("wsl-ssh-askpass: not saved: {}", e);
+            }
+        }
+        update_hello_timestamp(key_name);
+    }
+    audit::grant(
+        key_name,
+        audit::Grant::Typed {
+            saved: store && save,
+        },
+    );
+    Ok(pass)
+}
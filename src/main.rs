@@ -1,327 +1,726 @@
 use std::env;
-use std::io::{self, Write};
-use std::time::{SystemTime, UNIX_EPOCH};
-
-use windows::{
-    core::{Error, Result, HSTRING, PCWSTR, PWSTR},
-    Foundation::IAsyncOperation,
-    Security::Credentials::UI::{
-        UserConsentVerificationResult, UserConsentVerifier, UserConsentVerifierAvailability,
-    },
-    Win32::Foundation::{LocalFree, BOOL, HLOCAL, HWND},
-    Win32::Graphics::Gdi::HBITMAP,
-    Win32::Security::Credentials::{
-        CredFree, CredPackAuthenticationBufferW, CredReadW, CredUIPromptForWindowsCredentialsW,
-        CredUnPackAuthenticationBufferW, CredWriteW, CREDENTIALW, CREDUIWIN_CHECKBOX,
-        CREDUIWIN_GENERIC, CREDUIWIN_IN_CRED_ONLY, CREDUI_INFOW, CRED_FLAGS,
-        CRED_PACK_GENERIC_CREDENTIALS, CRED_PERSIST_LOCAL_MACHINE, CRED_PERSIST_SESSION,
-        CRED_TYPE_GENERIC,
-    },
-    Win32::System::WinRT::IUserConsentVerifierInterop,
-    Win32::UI::WindowsAndMessaging::{
-        GetForegroundWindow, MessageBoxW, SetForegroundWindow, IDYES, MB_DEFBUTTON2,
-        MB_ICONWARNING, MB_SETFOREGROUND, MB_TOPMOST, MB_YESNO,
-    },
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use wsl_ssh_askpass::{
+    core::clock,
+    core::codepage,
+    core::compat::{Compat, PASSTHROUGH_EXIT},
+    PromptError, PromptKind, SavePreference,
 };
 
-// Constants
-const CACHE_PIN_TTL_SECS: u64 = 60 * 5; // 5 minutes
-const CRED_PREFIX: &str = "wsl-ssh-askpass";
+/// Default loopback address for `http-serve`.
+const DEFAULT_HTTP_ADDR: &str = "127.0.0.1:47800";
+
+const USAGE: &str = "\
+Set SSH_ASKPASS to this program and SSH_ASKPASS_REQUIRE=force in WSL; ssh
+runs it with the prompt as its argument, or pass --prompt-file <path>.
+Put --newline or --no-newline first to choose whether the answer ends
+with a newline, --timeout <secs> to give up on an unanswered prompt,
+--verbose to print diagnostics on stderr, --report-expiry to say on
+stderr how long the Hello grace window has left, and --passthrough to
+exit 4 without a dialog while a terminal is in front (see
+wsl/askpass-terminal).
+
+Commands:
+  status
+  doctor [--json]
+  snooze <duration>|off
+  cache list | show <key> | remove <key>... | clear
+  reveal <key> --i-understand
+  scan [--distro <name>]
+  config validate | set <setting> <value>
+  save-pref <save|dont-save|never|ask> <key>...
+  git-credential <get|store|erase>
+  pinentry
+  setup [--distro <name> | --all] [--shell bash|zsh|fish]
+        [--require prefer|force] [--no-test]
+  vscode-setup [--distro <name> | --all]
+  escrow export --recipient <public-key.pem> [--out <file>] <key>...
+  http-serve [--listen <addr:port>]
+  ps-serve
+  daemon
+  relay";
 
-fn to_wide(s: &str) -> Vec<u16> {
-    s.encode_utf16().chain(std::iter::once(0)).collect()
-}
+fn main() {
+    wsl_ssh_askpass::core::mitigation::apply();
+    let mut args: Vec<String> = env::args_os()
+        .skip(1)
+        .map(|arg| codepage::arg_to_string(&arg))
+        .collect();
+    // ssh passes only the prompt, so timing can also be requested through WSLENV
+    let mut time_flag = false;
+    let mut verbose = false;
+    let mut passthrough = false;
+    let mut report_expiry = false;
+    let mut newline = None;
+    let mut timeout = None;
+    while let Some(flag) = args.first() {
+        match flag.as_str() {
+            "--time" => time_flag = true,
+            "--verbose" => verbose = true,
+            "--passthrough" => passthrough = true,
+            "--report-expiry" => report_expiry = true,
+            "--newline" => newline = Some(true),
+            "--no-newline" => newline = Some(false),
+            "--timeout" => {
+                let secs = args.get(1).and_then(|secs| secs.parse::<u64>().ok());
+                match secs.filter(|&secs| secs > 0) {
+                    Some(secs) => timeout = Some(secs),
+                    None => exit_on_error(Err("--timeout needs a number of seconds".into())),
+                }
+                args.remove(0);
+            }
+            _ => break,
+        }
+        args.remove(0);
+    }
+    let options = AnswerOptions {
+        newline: newline.or_else(wsl_ssh_askpass::newline_preference),
+        timeout: timeout.or_else(wsl_ssh_askpass::prompt_timeout),
+        passthrough,
+        report_expiry: report_expiry || env::var_os("WSL_SSH_ASKPASS_REPORT_EXPIRY").is_some(),
+    };
+    if time_flag || env::var_os("WSL_SSH_ASKPASS_TIME").is_some() {
+        wsl_ssh_askpass::core::timing::enable();
+    }
+    if verbose || env::var_os("WSL_SSH_ASKPASS_VERBOSE").is_some() {
+        wsl_ssh_askpass::core::diag::enable();
+    }
+    if args.first().map(String::as_str) == Some("pinentry") || invoked_as_pinentry() {
+        // gpg-agent starts its pinentry without arguments
+        let stdin = io::stdin();
+        if wsl_ssh_askpass::ipc::pinentry::serve(stdin.lock(), io::stdout()).is_err() {
+            std::process::exit(2);
+        }
+        return;
+    }
+    if args.is_empty() {
+        // Double-clicked or run by hand: explain instead of caching a "default" key
+        if wsl_ssh_askpass::launched_from_explorer() {
+            wsl_ssh_askpass::show_status(USAGE);
+            return;
+        }
+        if io::stdout().is_terminal() {
+            println!("{}\n\n{}", wsl_ssh_askpass::status(), USAGE);
+            return;
+        }
+    }
+    if args.first().map(String::as_str) == Some("--prompt-file") {
+        match read_prompt_file(&args[1..]) {
+            Ok(prompt) => answer(&prompt, &options),
+            Err(e) => exit_on_error(Err(e)),
+        }
+        return;
+    }
+    let prompt = args
+        .first()
+        .cloned()
+        .unwrap_or_else(|| "Enter SSH passphrase:".into());
 
-/// Extract key name from prompt like "Enter passphrase for key '/path/to/key':"
-fn extract_key_name(prompt: &str) -> String {
-    if let Some(start) = prompt.find('\'') {
-        if let Some(end) = prompt[start + 1..].find('\'') {
-            let key_path = &prompt[start + 1..start + 1 + end];
-            // Return just the filename
-            return key_path
-                .rsplit('/')
-                .next()
-                .or_else(|| key_path.rsplit('\\').next())
-                .unwrap_or(key_path)
-                .to_string();
+    match prompt.as_str() {
+        "ps-serve" => {
+            start_server();
+            let stdin = io::stdin();
+            if wsl_ssh_askpass::ipc::rpc::serve(stdin.lock(), io::stdout()).is_err() {
+                std::process::exit(1);
+            }
+            return;
         }
+        "daemon" => {
+            start_server();
+            exit_on_error(wsl_ssh_askpass::ipc::daemon::serve().map_err(|e| e.to_string()))
+        }
+        "http-serve" => exit_on_error(http_serve(&args[1..])),
+        "escrow" => exit_on_error(escrow(&args[1..])),
+        "snooze" => exit_on_error(snooze(&args[1..])),
+        "setup" => exit_on_error(setup(&args[1..])),
+        "vscode-setup" => exit_on_error(vscode_setup(&args[1..])),
+        "status" => exit_on_error(status(&args[1..])),
+        "doctor" => exit_on_error(doctor(&args[1..])),
+        "save-pref" => exit_on_error(save_pref(&args[1..])),
+        "cache" => exit_on_error(cache(&args[1..])),
+        "reveal" => exit_on_error(reveal(&args[1..])),
+        "scan" => exit_on_error(scan(&args[1..])),
+        "config" => exit_on_error(config(&args[1..])),
+        "relay" => exit_on_error(wsl_ssh_askpass::ipc::daemon::relay().map_err(|e| e.to_string())),
+        "git-credential" => match args.get(1) {
+            Some(action) => exit_on_error(wsl_ssh_askpass::ipc::git::run_stdio(action)),
+            None => exit_on_error(Err("usage: git-credential <get|store|erase>".into())),
+        },
+        _ => {}
     }
-    "default".to_string()
-}
 
-fn cred_name(key: &str) -> String {
-    format!("{}:{}", CRED_PREFIX, key)
+    answer(&prompt, &options);
 }
 
-fn hello_cred_name(key: &str) -> String {
-    format!("{}:{}:{}", CRED_PREFIX, key, "temp")
+/// Whether this executable was copied under a `pinentry*` name.
+fn invoked_as_pinentry() -> bool {
+    env::current_exe().ok().is_some_and(|exe| {
+        exe.file_stem().is_some_and(|stem| {
+            stem.to_string_lossy()
+                .to_lowercase()
+                .starts_with("pinentry")
+        })
+    })
 }
 
-fn main() {
-    let prompt = env::args()
-        .nth(1)
-        .unwrap_or_else(|| "Enter SSH passphrase:".into());
+/// How to answer a prompt, from the leading flags.
+struct AnswerOptions {
+    /// Whether the answer ends with a newline, when not left to the mode
+    newline: Option<bool>,
+    /// Seconds without an answer before the prompt is given up as failed
+    timeout: Option<u64>,
+    /// Exit [`PASSTHROUGH_EXIT`] instead of prompting for an answer that a
+    /// terminal in front could take
+    passthrough: bool,
+    /// Describe a secret handed out on stderr, for wrappers
+    report_expiry: bool,
+}
 
-    let prompt_lower = prompt.to_lowercase();
+/// Answer `prompt` on stdout, exiting 1 when cancelled and 2 on failure
+/// (or as the selected compatibility mode does).
+fn answer(prompt: &str, options: &AnswerOptions) {
+    let compat = Compat::selected();
+    wsl_ssh_askpass::ui::watchdog::exit_when_orphaned();
+    if let Some(secs) = options.timeout {
+        let code = compat.exit_code(&PromptError::Failed("timed out".into()));
+        wsl_ssh_askpass::ui::watchdog::exit_after(Duration::from_secs(secs), prompt, code);
+    }
+    // OpenSSH 8.4+ says whether it wants an answer, a yes/no or nothing at all
+    let kind = PromptKind::from_hint(env::var("SSH_ASKPASS_PROMPT").ok().as_deref());
+    if options.passthrough && kind == PromptKind::Entry && wsl_ssh_askpass::terminal_in_front() {
+        std::process::exit(PASSTHROUGH_EXIT);
+    }
+    let answer = wsl_ssh_askpass::ipc::daemon::forward(prompt, kind)
+        .unwrap_or_else(|| wsl_ssh_askpass::answer_prompt_as(prompt, kind));
+    wsl_ssh_askpass::core::timing::report();
+    match answer {
+        Ok(answer) => {
+            if let Err(e) = write_answer(&compat.output(kind, &answer, options.newline)) {
+                eprintln!("wsl-ssh-askpass: cannot write the answer: {}", e);
+                std::process::exit(compat.exit_code(&PromptError::Failed(e.to_string())));
+            }
+            if let Some(served) = wsl_ssh_askpass::take_served().filter(|_| options.report_expiry) {
+                // One line in a fixed format, for wrappers to look for
+                eprintln!(
+                    "wsl-ssh-askpass-expiry: source={} hello_window={} key={}",
+                    if served.from_cache { "cache" } else { "typed" },
+                    served.hello_window_secs.unwrap_or(0),
+                    served.key
+                );
+            }
+        }
+        Err(error) => {
+            if let PromptError::Failed(e) = &error {
+                eprintln!("wsl-ssh-askpass: {}", e);
+            }
+            std::process::exit(compat.exit_code(&error));
+        }
+    }
+}
 
-    if prompt_lower.contains("yes/no") || prompt_lower.contains("fingerprint") {
-        // Host key verification
-        let answer = prompt_yes_no(&prompt);
-        print!("{}", answer);
+/// Write the answer for ssh as bytes in the distro's encoding. A pipe gets
+/// them untouched; a console, where someone is reading rather than ssh,
+/// only takes UTF-8 text.
+fn write_answer(answer: &str) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    if stdout.is_terminal() {
+        stdout.write_all(answer.as_bytes())?;
     } else {
-        // Passphrase request
-        if let Some(pass) = handle_passphrase(&prompt) {
-            print!("{}", pass);
-        } else {
-            std::process::exit(1);
-        }
+        stdout.write_all(&codepage::encode(answer))?;
     }
-    io::stdout().flush().ok();
+    stdout.flush()
 }
 
-fn handle_passphrase(prompt: &str) -> Option<String> {
-    let key_name = extract_key_name(prompt);
+/// `--prompt-file <path>`: the exact prompt, for text that interop argv
+/// quoting would mangle (newlines, quotes, non-UTF-8 bytes).
+fn read_prompt_file(args: &[String]) -> Result<String, String> {
+    let path = args.first().ok_or("usage: --prompt-file <path>")?;
+    let bytes = fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    Ok(codepage::decode(&bytes))
+}
 
-    // Try cached passphrase with Windows Hello
-    if let Some(pass) = get_cached_passphrase(&key_name) {
-        if is_hello_valid(&key_name) || verify_with_hello(&key_name) {
-            update_hello_timestamp(&key_name);
-            return Some(pass);
+fn exit_on_error(result: Result<(), String>) -> ! {
+    match result {
+        Ok(()) => std::process::exit(0),
+        Err(e) => {
+            eprintln!("wsl-ssh-askpass: {}", e);
+            std::process::exit(2);
         }
     }
+}
 
-    // Prompt for new passphrase
-    let (pass, save) = prompt_for_password(prompt, &key_name).ok()?;
-    if save {
-        let _ = cache_passphrase(&key_name, &pass);
+/// Background work of the long-running servers: the hotkey (they keep
+/// running without it if it cannot be registered), and ending the Hello
+/// grace window whenever the workstation locks.
+fn start_server() {
+    if let Err(e) = wsl_ssh_askpass::ui::hotkey::spawn() {
+        eprintln!("wsl-ssh-askpass: {}", e);
     }
-    update_hello_timestamp(&key_name);
-    Some(pass)
+    wsl_ssh_askpass::ui::lockwatch::spawn();
 }
 
-fn get_foreground_hwnd() -> HWND {
-    unsafe { GetForegroundWindow() }
+/// `http-serve [--listen <addr:port>]`
+fn http_serve(args: &[String]) -> Result<(), String> {
+    let mut listen = DEFAULT_HTTP_ADDR.to_string();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--listen" => listen = rest.next().cloned().ok_or("--listen needs an address")?,
+            _ => return Err("usage: http-serve [--listen <addr:port>]".into()),
+        }
+    }
+    let addr: SocketAddr = listen
+        .parse()
+        .map_err(|e| format!("invalid address '{}': {}", listen, e))?;
+    if !addr.ip().is_loopback() {
+        // The token is all that guards the API, and it travels in the clear
+        return Err(format!(
+            "{} is not a loopback address; http-serve only listens on this machine",
+            addr
+        ));
+    }
+    let token = wsl_ssh_askpass::ipc::http::load_or_create_token().map_err(|e| e.to_string())?;
+    start_server();
+    if let Some(path) = wsl_ssh_askpass::ipc::http::token_path() {
+        eprintln!(
+            "wsl-ssh-askpass: serving on http://{}/rpc, token in {}",
+            addr,
+            path.display()
+        );
+    }
+    wsl_ssh_askpass::ipc::http::serve(addr, &token).map_err(|e| match e.kind() {
+        // Loopback ports are shared by every user signed in to the machine
+        std::io::ErrorKind::AddrInUse => format!(
+            "{} is already in use, perhaps by another user's http-serve; pick another with --listen",
+            addr
+        ),
+        _ => e.to_string(),
+    })
 }
 
-fn prompt_yes_no(prompt: &str) -> &'static str {
-    let title = to_wide("SSH Host Verification");
-    let content = to_wide(prompt);
-    unsafe {
-        let parent = get_foreground_hwnd();
-        let result = MessageBoxW(
-            parent,
-            PCWSTR(content.as_ptr()),
-            PCWSTR(title.as_ptr()),
-            MB_YESNO | MB_ICONWARNING | MB_DEFBUTTON2 | MB_TOPMOST | MB_SETFOREGROUND,
-        );
-        if result == IDYES {
-            "yes"
-        } else {
-            "no"
-        }
+/// `snooze <duration>|off`, with durations like `90m`, `2h` or `1h30m`
+fn snooze(args: &[String]) -> Result<(), String> {
+    let usage = "usage: snooze <duration, e.g. 2h or 45m>|off";
+    let arg = args.first().ok_or(usage)?;
+    if arg == "off" {
+        wsl_ssh_askpass::end_snooze();
+        return Ok(());
     }
+    let secs = parse_duration(arg).ok_or(usage)?;
+    let granted = wsl_ssh_askpass::snooze(secs)?;
+    eprintln!(
+        "wsl-ssh-askpass: snoozed for {} minutes",
+        granted.div_ceil(60)
+    );
+    Ok(())
 }
 
-fn prompt_for_password(prompt: &str, key_name: &str) -> Result<(String, bool)> {
-    let message = to_wide(prompt);
-    let caption = to_wide("SSH Passphrase");
-    let username = to_wide(key_name);
-    let empty_pass = to_wide("");
-
-    unsafe {
-        let parent = get_foreground_hwnd();
-        // Try to bring our dialog to the foreground
-        if !parent.is_invalid() {
-            let _ = SetForegroundWindow(parent);
+fn parse_duration(s: &str) -> Option<u64> {
+    let mut total = 0u64;
+    let mut digits = String::new();
+    for c in s.trim().chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
         }
-
-        let ui_info = CREDUI_INFOW {
-            cbSize: std::mem::size_of::<CREDUI_INFOW>() as u32,
-            hwndParent: parent,
-            pszMessageText: PCWSTR(message.as_ptr()),
-            pszCaptionText: PCWSTR(caption.as_ptr()),
-            hbmBanner: HBITMAP::default(),
+        let n: u64 = digits.parse().ok()?;
+        digits.clear();
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
         };
+        // Too long to mean anything is as unusable as garbled
+        total = total.checked_add(n.checked_mul(unit)?)?;
+    }
+    // A bare number means minutes
+    if !digits.is_empty() {
+        total = total.checked_add(digits.parse::<u64>().ok()?.checked_mul(60)?)?;
+    }
+    (total > 0).then_some(total)
+}
 
-        // Pack the key name as username to pre-populate the field
-        let mut in_buf_size: u32 = 0;
-        let _ = CredPackAuthenticationBufferW(
-            CRED_PACK_GENERIC_CREDENTIALS,
-            PCWSTR(username.as_ptr()),
-            PCWSTR(empty_pass.as_ptr()),
-            None,
-            &mut in_buf_size,
-        );
-
-        let mut in_buf = vec![0u8; in_buf_size as usize];
-        CredPackAuthenticationBufferW(
-            CRED_PACK_GENERIC_CREDENTIALS,
-            PCWSTR(username.as_ptr()),
-            PCWSTR(empty_pass.as_ptr()),
-            Some(in_buf.as_mut_ptr() as *mut _),
-            &mut in_buf_size,
-        )?;
-
-        let mut auth_package: u32 = 0;
-        let mut out_buf: *mut std::ffi::c_void = std::ptr::null_mut();
-        let mut out_buf_size: u32 = 0;
-        let mut save_checked = BOOL(0);
-
-        // CREDUIWIN_IN_CRED_ONLY makes the username field read-only
-        let result = CredUIPromptForWindowsCredentialsW(
-            Some(&ui_info),
-            0,
-            &mut auth_package,
-            Some(in_buf.as_ptr() as *const _),
-            in_buf_size,
-            &mut out_buf,
-            &mut out_buf_size,
-            Some(&mut save_checked),
-            CREDUIWIN_GENERIC | CREDUIWIN_CHECKBOX | CREDUIWIN_IN_CRED_ONLY,
+/// `setup [--distro <name> | --all] [--shell <shell>] [--require <mode>] [--no-test]`
+fn setup(args: &[String]) -> Result<(), String> {
+    use wsl_ssh_askpass::ipc::setup::{Options, Require, Shell};
+
+    let usage = "usage: setup [--distro <name> | --all] [--shell bash|zsh|fish] \
+                 [--require prefer|force] [--no-test]";
+    let mut options = Options {
+        shell: None,
+        require: Require::Prefer,
+        test: true,
+    };
+    let mut targets = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--all" => {
+                targets = Some(
+                    wsl_ssh_askpass::ipc::wsl::distros()?
+                        .into_iter()
+                        .map(Some)
+                        .collect(),
+                )
+            }
+            "--distro" => match rest.next() {
+                Some(name) => targets = Some(vec![Some(name.clone())]),
+                None => return Err(usage.into()),
+            },
+            "--shell" => match rest.next().and_then(|name| Shell::from_name(name)) {
+                Some(shell) => options.shell = Some(shell),
+                None => return Err(usage.into()),
+            },
+            "--require" => match rest.next().and_then(|name| Require::from_name(name)) {
+                Some(require) => options.require = require,
+                None => return Err(usage.into()),
+            },
+            "--no-test" => options.test = false,
+            _ => return Err(usage.into()),
+        }
+    }
+    let targets: Vec<Option<String>> = match targets {
+        Some(targets) => targets,
+        None => vec![choose_distro()?],
+    };
+    for distro in targets {
+        if let Some(name) = &distro {
+            eprintln!("wsl-ssh-askpass: configuring {}", name);
+        }
+        let rc = wsl_ssh_askpass::ipc::setup::setup(distro.as_deref(), options)?;
+        eprintln!(
+            "wsl-ssh-askpass: SSH_ASKPASS set in {}; open a new shell to use it",
+            rc
         );
+    }
+    Ok(())
+}
 
-        if result != 0 {
-            return Err(Error::from_win32());
+/// `vscode-setup [--distro <name> | --all]`
+fn vscode_setup(args: &[String]) -> Result<(), String> {
+    let usage = "usage: vscode-setup [--distro <name> | --all]";
+    let targets = match args {
+        [] => vec![choose_distro()?],
+        [flag] if flag == "--all" => wsl_ssh_askpass::ipc::wsl::distros()?
+            .into_iter()
+            .map(Some)
+            .collect(),
+        [flag, name] if flag == "--distro" => vec![Some(name.clone())],
+        _ => return Err(usage.into()),
+    };
+    for distro in targets {
+        if let Some(name) = &distro {
+            eprintln!("wsl-ssh-askpass: configuring {}", name);
         }
+        wsl_ssh_askpass::ipc::vscode::setup(distro.as_deref())?;
+    }
+    Ok(())
+}
 
-        let mut username = vec![0u16; 256];
-        let mut username_len: u32 = 256;
-        let mut password = vec![0u16; 256];
-        let mut password_len: u32 = 256;
-
-        let unpack = CredUnPackAuthenticationBufferW(
-            CRED_PACK_GENERIC_CREDENTIALS,
-            out_buf,
-            out_buf_size,
-            PWSTR(username.as_mut_ptr()),
-            &mut username_len,
-            PWSTR::null(),
-            None,
-            PWSTR(password.as_mut_ptr()),
-            &mut password_len,
-        );
-
-        let _ = LocalFree(HLOCAL(out_buf));
+/// With several distros installed and someone at the console, ask which one
+/// to configure; otherwise use the default distro (`None`).
+fn choose_distro() -> Result<Option<String>, String> {
+    let distros = wsl_ssh_askpass::ipc::wsl::distros()?;
+    if distros.len() < 2 || !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    eprintln!("Installed distros:");
+    for (i, name) in distros.iter().enumerate() {
+        eprintln!("  {}) {}", i + 1, name);
+    }
+    eprint!(
+        "Configure which one? [1-{}, Enter for the default] ",
+        distros.len()
+    );
+    io::stderr().flush().ok();
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .map_err(|e| e.to_string())?;
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Ok(None);
+    }
+    answer
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| distros.get(n.wrapping_sub(1)))
+        .cloned()
+        .map(Some)
+        .ok_or_else(|| format!("no distro numbered {}", answer))
+}
 
-        if unpack.is_err() {
-            return Err(Error::from_win32());
+/// `status`: the askpass state, then how each installed distro is set up
+fn status(args: &[String]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("usage: status".into());
+    }
+    println!("{}", wsl_ssh_askpass::status());
+    println!();
+    for distro in wsl_ssh_askpass::ipc::wsl::distros()? {
+        match wsl_ssh_askpass::ipc::wsl::state(&distro) {
+            Ok(state) => println!(
+                "{}: SSH_ASKPASS {}, VS Code server {}",
+                distro,
+                if state.shell { "set" } else { "not set" },
+                if state.vscode {
+                    "configured"
+                } else {
+                    "not configured"
+                }
+            ),
+            Err(e) => println!("{}: {}", distro, e),
         }
+    }
+    Ok(())
+}
 
-        let pass_len = password_len.saturating_sub(1) as usize;
-        let pass = String::from_utf16_lossy(&password[..pass_len]);
-        Ok((pass, save_checked.as_bool()))
+/// `doctor [--json]`: fails when any check does, so scripts can tell.
+fn doctor(args: &[String]) -> Result<(), String> {
+    use wsl_ssh_askpass::ipc::doctor::{self, Status};
+
+    let json = match args {
+        [] => false,
+        [flag] if flag == "--json" => true,
+        _ => return Err("usage: doctor [--json]".into()),
+    };
+    let checks = doctor::run();
+    if json {
+        println!("{}", doctor::to_json(&checks));
+    } else {
+        for check in &checks {
+            println!(
+                "[{}] {}: {}",
+                check.status.as_str(),
+                check.name,
+                check.detail
+            );
+        }
     }
+    if checks.iter().any(|check| check.status == Status::Fail) {
+        return Err("some checks failed".into());
+    }
+    Ok(())
 }
 
-fn get_cached_passphrase(key: &str) -> Option<String> {
-    let name = to_wide(&cred_name(key));
-    unsafe {
-        let mut cred_ptr: *mut CREDENTIALW = std::ptr::null_mut();
-        if CredReadW(PCWSTR(name.as_ptr()), CRED_TYPE_GENERIC, 0, &mut cred_ptr).is_ok() {
-            let cred = &*cred_ptr;
-            let blob =
-                std::slice::from_raw_parts(cred.CredentialBlob, cred.CredentialBlobSize as usize);
-            let pass = String::from_utf8_lossy(blob).to_string();
-            CredFree(cred_ptr as *mut _);
-            return Some(pass);
+/// `escrow export --recipient <pem> [--out <file>] <key>...`
+fn escrow(args: &[String]) -> Result<(), String> {
+    if args.first().map(String::as_str) != Some("export") {
+        return Err(
+            "usage: escrow export --recipient <public-key.pem> [--out <file>] <key>...".into(),
+        );
+    }
+
+    let mut recipient = None;
+    let mut out = None;
+    let mut keys = Vec::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--recipient" => recipient = rest.next().map(PathBuf::from),
+            "--out" => out = rest.next().map(PathBuf::from),
+            _ => keys.push(arg.clone()),
         }
     }
-    None
+    let recipient = recipient.ok_or("--recipient <public-key.pem> is required")?;
+    wsl_ssh_askpass::store::escrow::export(&recipient, &keys, out.as_deref())
 }
 
-fn cache_passphrase(key: &str, passphrase: &str) -> Result<()> {
-    let name = to_wide(&cred_name(key));
-    let username = to_wide(CRED_PREFIX);
-    let blob = passphrase.as_bytes();
-    unsafe {
-        let cred = CREDENTIALW {
-            Flags: CRED_FLAGS(0),
-            Type: CRED_TYPE_GENERIC,
-            TargetName: PWSTR(name.as_ptr() as *mut _),
-            Comment: PWSTR::null(),
-            LastWritten: std::mem::zeroed(),
-            CredentialBlobSize: blob.len() as u32,
-            CredentialBlob: blob.as_ptr() as *mut _,
-            Persist: CRED_PERSIST_LOCAL_MACHINE,
-            AttributeCount: 0,
-            Attributes: std::ptr::null_mut(),
-            TargetAlias: PWSTR::null(),
-            UserName: PWSTR(username.as_ptr() as *mut _),
-        };
-        CredWriteW(&cred, 0)?;
+/// `cache list`, `cache show <key>`, `cache remove <key>...` or `cache clear`
+fn cache(args: &[String]) -> Result<(), String> {
+    let usage = "usage: cache list | show <key> | remove <key>... | clear";
+    let now = clock::now_secs();
+    match args.split_first() {
+        Some((cmd, [])) if cmd == "list" => {
+            for secret in wsl_ssh_askpass::cached_secrets() {
+                println!(
+                    "{}\t{}\tstored {}",
+                    secret.key,
+                    if secret.persistent {
+                        "this machine"
+                    } else {
+                        "this session"
+                    },
+                    describe_since(secret.written_secs, now)
+                );
+            }
+            Ok(())
+        }
+        Some((cmd, [key])) if cmd == "show" => {
+            // Never the secret itself
+            let details = wsl_ssh_askpass::cached_secret_details(key)?;
+            println!("key:          {}", details.key);
+            println!(
+                "stored:       {}, {}",
+                describe_since(details.written_secs, now),
+                if details.persistent {
+                    "on this machine"
+                } else {
+                    "for this session"
+                }
+            );
+            println!(
+                "encryption:   {}",
+                match (details.sealed, details.protected) {
+                    (true, true) => "Windows Hello key, under DPAPI",
+                    (true, false) => "Windows Hello key",
+                    (false, true) => "DPAPI",
+                    (false, false) => "none yet (wrapped with DPAPI when next read)",
+                }
+            );
+            match details.last_used_secs {
+                Some(used) => println!(
+                    "last used:    {} ({} times)",
+                    describe_since(used, now),
+                    details.use_count
+                ),
+                None => println!("last used:    never"),
+            }
+            match details.hello_window_secs {
+                Some(left) => println!("hello window: {} left", describe_age(left)),
+                None => println!("hello window: closed"),
+            }
+            println!(
+                "remember box: {}",
+                match details.save_preference {
+                    Some(SavePreference::Save) => "ticked",
+                    Some(SavePreference::DontSave) => "clear",
+                    Some(SavePreference::Never) => "hidden",
+                    None => "not chosen yet",
+                }
+            );
+            Ok(())
+        }
+        Some((cmd, keys)) if cmd == "remove" && !keys.is_empty() => {
+            for key in keys {
+                if !wsl_ssh_askpass::clear_cached(key) {
+                    return Err(format!("nothing cached for {}", key));
+                }
+            }
+            Ok(())
+        }
+        Some((cmd, [])) if cmd == "clear" => {
+            let removed = wsl_ssh_askpass::clear_all();
+            eprintln!("wsl-ssh-askpass: removed {} entries", removed);
+            Ok(())
+        }
+        _ => Err(usage.into()),
     }
-    Ok(())
 }
 
-fn is_hello_valid(key: &str) -> bool {
-    let name = to_wide(&hello_cred_name(key));
-    unsafe {
-        let mut cred_ptr: *mut CREDENTIALW = std::ptr::null_mut();
-        if CredReadW(PCWSTR(name.as_ptr()), CRED_TYPE_GENERIC, 0, &mut cred_ptr).is_ok() {
-            let cred = &*cred_ptr;
-            let blob =
-                std::slice::from_raw_parts(cred.CredentialBlob, cred.CredentialBlobSize as usize);
-            let ts_str = String::from_utf8_lossy(blob);
-            if let Ok(stored) = ts_str.parse::<u64>() {
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-                CredFree(cred_ptr as *mut _);
-                return now - stored < CACHE_PIN_TTL_SECS;
+/// `scan [--distro <name>]`
+fn scan(args: &[String]) -> Result<(), String> {
+    use wsl_ssh_askpass::store::scan::{Finding, Protection};
+
+    let distro = match args {
+        [] => None,
+        [flag, name] if flag == "--distro" => Some(name.as_str()),
+        _ => return Err("usage: scan [--distro <name>]".into()),
+    };
+    let report = wsl_ssh_askpass::store::scan::scan(distro)?;
+    println!("{}: ~/.ssh", report.distro);
+    if report.findings.is_empty() {
+        println!("no private keys and no cached key passphrases");
+    }
+    for finding in &report.findings {
+        match finding {
+            Finding::KeyFile {
+                path,
+                protection,
+                cached_as,
+            } => {
+                let state = match (protection, cached_as) {
+                    (Protection::Encrypted, Some(key)) => format!("cached as {}", key),
+                    (Protection::Encrypted, None) => "encrypted, not cached".into(),
+                    (Protection::Unencrypted, Some(key)) => {
+                        format!("no passphrase, yet cached as {}", key)
+                    }
+                    (Protection::Unencrypted, None) => "no passphrase".into(),
+                };
+                println!("{}\t{}", path, state);
             }
-            CredFree(cred_ptr as *mut _);
+            Finding::Orphan { key } => println!(
+                "{}\tcached, but no such key in ~/.ssh (deleted, or kept elsewhere)",
+                key
+            ),
         }
     }
-    false
+    Ok(())
 }
 
-fn update_hello_timestamp(key: &str) {
-    let name = to_wide(&hello_cred_name(key));
-    let username = to_wide(CRED_PREFIX);
-    let ts = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
-        .to_string();
-    let blob = ts.as_bytes();
-    unsafe {
-        let cred = CREDENTIALW {
-            Flags: CRED_FLAGS(0),
-            Type: CRED_TYPE_GENERIC,
-            TargetName: PWSTR(name.as_ptr() as *mut _),
-            Comment: PWSTR::null(),
-            LastWritten: std::mem::zeroed(),
-            CredentialBlobSize: blob.len() as u32,
-            CredentialBlob: blob.as_ptr() as *mut _,
-            Persist: CRED_PERSIST_SESSION,
-            AttributeCount: 0,
-            Attributes: std::ptr::null_mut(),
-            TargetAlias: PWSTR::null(),
-            UserName: PWSTR(username.as_ptr() as *mut _),
-        };
-        let _ = CredWriteW(&cred, 0);
+/// `reveal <key> --i-understand`
+fn reveal(args: &[String]) -> Result<(), String> {
+    let [key, flag] = args else {
+        return Err("usage: reveal <key> --i-understand".into());
+    };
+    if flag != "--i-understand" {
+        return Err(
+            "reveal prints the secret in plain text; pass --i-understand to go ahead".into(),
+        );
     }
+    let secret = wsl_ssh_askpass::reveal_cached(key)?;
+    eprintln!("wsl-ssh-askpass: revealing the cached secret for {}", key);
+    println!("{}", secret);
+    Ok(())
 }
 
-fn verify_with_hello(key: &str) -> bool {
-    unsafe { verify_with_hello_inner(key).unwrap_or(false) }
+/// How long ago `stamp` was, or that it cannot be told: a stamp from a
+/// clock since turned back.
+fn describe_since(stamp: u64, now: u64) -> String {
+    match clock::age(stamp, now) {
+        Some(age) => format!("{} ago", describe_age(age)),
+        None => "at an unknown time (the clock was turned back)".to_string(),
+    }
 }
 
-unsafe fn verify_with_hello_inner(key: &str) -> Result<bool> {
-    let availability = UserConsentVerifier::CheckAvailabilityAsync()?.get()?;
-    if availability != UserConsentVerifierAvailability::Available {
-        return Ok(false);
+fn describe_age(secs: u64) -> String {
+    match secs {
+        0..=119 => format!("{} seconds", secs),
+        120..=7199 => format!("{} minutes", secs / 60),
+        7200..=172_799 => format!("{} hours", secs / 3600),
+        _ => format!("{} days", secs / 86_400),
     }
+}
 
-    let hwnd = GetForegroundWindow();
-    let interop = windows::core::factory::<UserConsentVerifier, IUserConsentVerifierInterop>()?;
-    let message = HSTRING::from(format!("Unlock SSH key: {}", key));
-    let result: UserConsentVerificationResult = interop
-        .RequestVerificationForWindowAsync::<_, IAsyncOperation<UserConsentVerificationResult>>(
-            hwnd, &message,
-        )?
-        .get()?;
+/// `config validate` or `config set <setting> <value>`
+fn config(args: &[String]) -> Result<(), String> {
+    match args {
+        [cmd] if cmd == "validate" => {
+            println!("{}", wsl_ssh_askpass::validate_config()?);
+            Ok(())
+        }
+        [cmd, path, value] if cmd == "set" => {
+            println!("{}", wsl_ssh_askpass::set_config(path, value)?);
+            Ok(())
+        }
+        _ => Err("usage: config validate | config set <setting> <value>".into()),
+    }
+}
 
-    Ok(result == UserConsentVerificationResult::Verified)
+/// `save-pref <save|dont-save|never|ask> <key>...`
+fn save_pref(args: &[String]) -> Result<(), String> {
+    let usage = "usage: save-pref <save|dont-save|never|ask> <key>...";
+    let (choice, keys) = args.split_first().ok_or(usage)?;
+    let pref = match choice.as_str() {
+        "save" => Some(SavePreference::Save),
+        "dont-save" => Some(SavePreference::DontSave),
+        "never" => Some(SavePreference::Never),
+        "ask" => None,
+        _ => return Err(usage.into()),
+    };
+    if keys.is_empty() {
+        return Err(usage.into());
+    }
+    for key in keys {
+        if !wsl_ssh_askpass::set_save_preference(key, pref) && pref.is_some() {
+            return Err(format!("could not store the preference for {}", key));
+        }
+    }
+    Ok(())
 }
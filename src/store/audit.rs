@@ -0,0 +1,121 @@
+//! Append-only, tab separated logs under `%LOCALAPPDATA%\wsl-ssh-askpass`,
+//! and, with `event_log = true` in the config file, an event in the Windows
+//! Application log for every secret handed out. Escrow exports are always
+//! put in the Application log.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+use windows::{
+    core::{w, PCWSTR},
+    Win32::Security::PSID,
+    Win32::System::EventLog::{
+        DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_INFORMATION_TYPE,
+    },
+};
+
+use crate::{
+    core::caller, core::clock::now_secs, core::config, data_dir, store::credstore, to_wide, Served,
+};
+
+static SERVED: Mutex<Option<Served>> = Mutex::new(None);
+
+/// Event ids, one per way a secret is handed out.
+const EVENT_FROM_CACHE: u32 = 1;
+const EVENT_TYPED: u32 = 2;
+/// An escrow export, whatever came of it.
+pub(crate) const EVENT_ESCROW: u32 = 3;
+
+/// Append `<unix time>\t<fields...>` to the log file `name`. Failures are
+/// ignored; logging must never block a prompt.
+pub(crate) fn append(name: &str, fields: &[&str]) {
+    let Some(dir) = data_dir() else {
+        return;
+    };
+    let _ = fs::create_dir_all(&dir);
+    if let Ok(mut file) = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(name))
+    {
+        let _ = writeln!(file, "{}\t{}", now_secs(), fields.join("\t"));
+    }
+}
+
+/// How a secret reached the caller.
+pub(crate) enum Grant {
+    /// Released from the cache; `hello` says what Windows Hello had to do
+    /// with it, e.g. "verified" or "within the grace window"
+    Cached { hello: &'static str },
+    /// Typed into the dialog, and saved if `saved`
+    Typed { saved: bool },
+}
+
+/// Record that the secret for `key` was handed out. Like [`append`], a
+/// failure to log is ignored.
+pub(crate) fn grant(key: &str, grant: Grant) {
+    set_served(Served {
+        key: key.to_string(),
+        from_cache: matches!(grant, Grant::Cached { .. }),
+        hello_window_secs: credstore::hello_window_remaining(key),
+    });
+    if !config::get().event_log {
+        return;
+    }
+    let (id, outcome, hello) = match grant {
+        Grant::Cached { hello } => (EVENT_FROM_CACHE, "served from the cache", hello),
+        Grant::Typed { saved: true } => (EVENT_TYPED, "typed and saved", "not used"),
+        Grant::Typed { saved: false } => (EVENT_TYPED, "typed", "not used"),
+    };
+    let caller = match (
+        caller::application().map(|app| app.name),
+        caller::ancestry().first(),
+    ) {
+        (Some(app), Some((pid, exe))) => format!("{} ({}, pid {})", app, exe, pid),
+        (None, Some((pid, exe))) => format!("{} (pid {})", exe, pid),
+        (Some(app), None) => app,
+        (None, None) => "unknown".to_string(),
+    };
+    let message = format!(
+        "Key: {}\r\nOutcome: {}\r\nWindows Hello: {}\r\nCaller: {}\r\nUnix time: {}",
+        key,
+        outcome,
+        hello,
+        caller,
+        now_secs()
+    );
+    report(id, &message);
+}
+
+/// Remember how the secret just handed out was come by, for [`take_served`].
+pub(crate) fn set_served(served: Served) {
+    *SERVED.lock().unwrap_or_else(|e| e.into_inner()) = Some(served);
+}
+
+pub(crate) fn take_served() -> Option<Served> {
+    SERVED.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+/// Write `message` to the Application log as event `id`. Like [`append`],
+/// a failure to log is ignored.
+pub(crate) fn report(id: u32, message: &str) {
+    let message = to_wide(message);
+    unsafe {
+        // Unregistered sources still log; see the README for registering it
+        let Ok(source) = RegisterEventSourceW(PCWSTR::null(), w!("wsl-ssh-askpass")) else {
+            return;
+        };
+        let _ = ReportEventW(
+            source,
+            EVENTLOG_INFORMATION_TYPE,
+            0,
+            id,
+            PSID::default(),
+            0,
+            Some(&[PCWSTR(message.as_ptr())]),
+            None,
+        );
+        let _ = DeregisterEventSource(source);
+    }
+}
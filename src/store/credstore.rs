@@ -0,0 +1,614 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use windows::{
+    core::{Error, Result, PCWSTR, PWSTR},
+    Win32::Foundation::{ERROR_NOT_FOUND, ERROR_TIMEOUT},
+    Win32::Security::Credentials::{
+        CredDeleteW, CredEnumerateW, CredFree, CredReadW, CredWriteW, CREDENTIALW,
+        CRED_ENUMERATE_FLAGS, CRED_FLAGS, CRED_PERSIST, CRED_PERSIST_LOCAL_MACHINE,
+        CRED_PERSIST_SESSION, CRED_TYPE_GENERIC,
+    },
+};
+
+use crate::{
+    core::clock, core::diag, core::policy, core::session, store::dpapi, to_wide, ui::lockwatch,
+    PromptError,
+};
+
+pub(crate) const CRED_PREFIX: &str = "wsl-ssh-askpass";
+
+/// Difference between the FILETIME epoch (1601) and the Unix epoch, in 100ns units.
+const FILETIME_UNIX_OFFSET: u64 = 116_444_736_000_000_000;
+
+/// How long a Credential Manager call may take before the vault is
+/// treated as wedged.
+const VAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Set once a call has timed out; later calls skip the vault entirely.
+static DEGRADED: AtomicBool = AtomicBool::new(false);
+
+/// Whether Credential Manager stopped answering during this run, so the
+/// cache is being bypassed.
+pub(crate) fn degraded() -> bool {
+    DEGRADED.load(Ordering::Relaxed)
+}
+
+/// Run a Credential Manager call on a helper thread and give up on it after
+/// [`VAULT_TIMEOUT`], returning `fallback`. The APIs can block for minutes
+/// when the vault service is wedged; a prompt answered without the cache
+/// beats ssh hanging. The stuck thread is abandoned with the process.
+fn guarded<T: Send + 'static>(fallback: T, call: impl FnOnce() -> T + Send + 'static) -> T {
+    if degraded() {
+        return fallback;
+    }
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(call());
+    });
+    rx.recv_timeout(VAULT_TIMEOUT).unwrap_or_else(|_| {
+        DEGRADED.store(true, Ordering::Relaxed);
+        diag::note("credman", || {
+            "Credential Manager did not answer; bypassing the cache".to_string()
+        });
+        fallback
+    })
+}
+
+fn filetime_to_unix(high: u32, low: u32) -> u64 {
+    let written = (u64::from(high) << 32) | u64::from(low);
+    written.saturating_sub(FILETIME_UNIX_OFFSET) / 10_000_000
+}
+
+fn cred_name(key: &str) -> String {
+    format!("{}:{}", CRED_PREFIX, key)
+}
+
+fn hello_cred_name(key: &str) -> String {
+    format!("{}:{}:{}", CRED_PREFIX, key, "temp")
+}
+
+fn save_pref_cred_name(key: &str) -> String {
+    format!("{}:{}:{}", CRED_PREFIX, key, "save")
+}
+
+fn usage_cred_name(key: &str) -> String {
+    format!("{}:{}:{}", CRED_PREFIX, key, "used")
+}
+
+fn key_file_cred_name(key: &str) -> String {
+    format!("{}:{}:{}", CRED_PREFIX, key, "file")
+}
+
+fn attempt_cred_name(key: &str) -> String {
+    format!("{}:{}:{}", CRED_PREFIX, key, "attempt")
+}
+
+/// The user's remembered answer to the "Remember" checkbox for a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SavePreference {
+    /// The box was ticked last time; pre-tick it.
+    Save,
+    /// The box was left clear last time; pre-clear it.
+    DontSave,
+    /// Never offer to save this key; the checkbox is hidden.
+    Never,
+}
+
+impl SavePreference {
+    fn as_str(self) -> &'static str {
+        match self {
+            SavePreference::Save => "save",
+            SavePreference::DontSave => "dont-save",
+            SavePreference::Never => "never",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "save" => Some(SavePreference::Save),
+            "dont-save" => Some(SavePreference::DontSave),
+            "never" => Some(SavePreference::Never),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn get_save_preference(key: &str) -> Option<SavePreference> {
+    let blob = read_blob(&save_pref_cred_name(key))?;
+    SavePreference::from_str(&String::from_utf8_lossy(&blob))
+}
+
+/// Remember the checkbox choice for `key`. It is not a secret, so it
+/// persists on this machine regardless of the secret's own policy.
+pub(crate) fn set_save_preference(key: &str, pref: SavePreference) -> Result<()> {
+    write_blob(
+        &save_pref_cred_name(key),
+        pref.as_str().as_bytes(),
+        CRED_PERSIST_LOCAL_MACHINE,
+    )
+}
+
+/// Forget the remembered checkbox choice for `key`.
+pub(crate) fn remove_save_preference(key: &str) -> bool {
+    delete(&save_pref_cred_name(key))
+}
+
+fn host_key_cred_name(host: &str) -> String {
+    // Key names never contain '/', so pins cannot collide with them
+    format!("{}:known-host/{}", CRED_PREFIX, host)
+}
+
+/// The host keys pinned for `host`, one per line (a host can offer several
+/// key types); see `hostkey::check_pins` for the format.
+pub(crate) fn host_key_pins(host: &str) -> String {
+    read_blob(&host_key_cred_name(host))
+        .map(|blob| String::from_utf8_lossy(&blob).into_owned())
+        .unwrap_or_default()
+}
+
+/// Replace the host keys pinned for `host`. Pins are public data and
+/// persist on this machine.
+pub(crate) fn set_host_key_pins(host: &str, pins: &str) -> Result<()> {
+    write_blob(
+        &host_key_cred_name(host),
+        pins.as_bytes(),
+        CRED_PERSIST_LOCAL_MACHINE,
+    )
+}
+
+/// End of the current snooze period (Unix seconds), if one is active.
+pub(crate) fn snooze_until() -> Option<u64> {
+    let blob = read_blob(&format!("{}:snooze", CRED_PREFIX))?;
+    let until = String::from_utf8_lossy(&blob).parse::<u64>().ok()?;
+    // One further off than a snooze can be was set before the clock went back
+    clock::until(until, clock::now_secs(), crate::MAX_SNOOZE_SECS).map(|_| until)
+}
+
+/// Start a snooze period ending at `until`, or end it with `None`. Snoozes
+/// never outlive the logon session.
+pub(crate) fn set_snooze_until(until: Option<u64>) -> Result<()> {
+    let name = format!("{}:snooze", CRED_PREFIX);
+    match until {
+        Some(until) => write_blob(&name, until.to_string().as_bytes(), CRED_PERSIST_SESSION),
+        None => {
+            delete(&name);
+            Ok(())
+        }
+    }
+}
+
+fn read_blob(name: &str) -> Option<Vec<u8>> {
+    read_entry(name).map(|(blob, _, _)| blob)
+}
+
+/// The blob of a generic credential, when it was written (Unix seconds)
+/// and how it persists. For a wrapped secret the time is the one recorded
+/// in its DPAPI header, which rewriting the entry leaves alone.
+fn read_entry(name: &str) -> Option<(Vec<u8>, u64, CRED_PERSIST)> {
+    let name = to_wide(name);
+    guarded(None, move || unsafe {
+        let mut cred_ptr: *mut CREDENTIALW = std::ptr::null_mut();
+        CredReadW(PCWSTR(name.as_ptr()), CRED_TYPE_GENERIC, 0, &mut cred_ptr)
+            .inspect_err(|e| {
+                // A missing entry is the usual cache miss
+                if e.code() != ERROR_NOT_FOUND.to_hresult() {
+                    diag::failure("credman", "CredReadW", e);
+                }
+            })
+            .ok()?;
+        let cred = &*cred_ptr;
+        let blob =
+            std::slice::from_raw_parts(cred.CredentialBlob, cred.CredentialBlobSize as usize)
+                .to_vec();
+        let written_secs = dpapi::written_secs(&blob).unwrap_or_else(|| {
+            filetime_to_unix(
+                cred.LastWritten.dwHighDateTime,
+                cred.LastWritten.dwLowDateTime,
+            )
+        });
+        let persist = cred.Persist;
+        CredFree(cred_ptr as *mut _);
+        Some((blob, written_secs, persist))
+    })
+}
+
+fn write_blob(name: &str, blob: &[u8], persist: CRED_PERSIST) -> Result<()> {
+    let name = to_wide(name);
+    let blob = blob.to_vec();
+    let timed_out = Err(Error::from(ERROR_TIMEOUT.to_hresult()));
+    guarded(timed_out, move || unsafe {
+        let username = to_wide(CRED_PREFIX);
+        let cred = CREDENTIALW {
+            Flags: CRED_FLAGS(0),
+            Type: CRED_TYPE_GENERIC,
+            TargetName: PWSTR(name.as_ptr() as *mut _),
+            Comment: PWSTR::null(),
+            LastWritten: std::mem::zeroed(),
+            CredentialBlobSize: blob.len() as u32,
+            CredentialBlob: blob.as_ptr() as *mut _,
+            Persist: persist,
+            AttributeCount: 0,
+            Attributes: std::ptr::null_mut(),
+            TargetAlias: PWSTR::null(),
+            UserName: PWSTR(username.as_ptr() as *mut _),
+        };
+        CredWriteW(&cred, 0).inspect_err(|e| diag::failure("credman", "CredWriteW", e))
+    })
+}
+
+/// Delete a generic credential. Returns `true` if it existed.
+fn delete(name: &str) -> bool {
+    let name = to_wide(name);
+    guarded(false, move || unsafe {
+        CredDeleteW(PCWSTR(name.as_ptr()), CRED_TYPE_GENERIC, 0).is_ok()
+    })
+}
+
+/// Read a cached secret, discarding it when it is older than `max_age_secs`.
+/// The bytes are the secret itself, or a sealed entry (see `sealed`), with
+/// the DPAPI layer taken off. An entry written before that layer existed is
+/// wrapped in place as it is read, keeping its write time. One that cannot be unwrapped (or whose
+/// Hello entropy was declined) reads as a miss.
+pub(crate) fn get_cached_blob(key: &str, max_age_secs: Option<u64>) -> Option<Vec<u8>> {
+    let name = cred_name(key);
+    let (blob, written_secs, persist) = read_entry(&name)?;
+    if max_age_secs.is_some_and(|ttl| !clock::within(written_secs, ttl, clock::now_secs())) {
+        delete(&name);
+        return None;
+    }
+    if !dpapi::is_protected(&blob) {
+        if !policy::read_only()
+            && dpapi::protect(&blob, written_secs)
+                .and_then(|wrapped| write_blob(&name, &wrapped, persist))
+                .is_ok()
+        {
+            diag::note("dpapi", || format!("wrapped the plain entry for {}", key));
+        }
+        return Some(blob);
+    }
+    match dpapi::unprotect(&blob) {
+        Ok((secret, verified)) => {
+            // Signing for the entropy was a Windows Hello verification
+            if verified {
+                update_hello_timestamp(key);
+            }
+            Some(secret)
+        }
+        Err(PromptError::Cancelled) => None,
+        Err(PromptError::Failed(e)) => {
+            diag::note("dpapi", || format!("{}: {}", key, e));
+            None
+        }
+    }
+}
+
+pub(crate) fn cache_passphrase(key: &str, passphrase: &str, persist: CRED_PERSIST) -> Result<()> {
+    cache_blob(key, passphrase.as_bytes(), persist)
+}
+
+/// Store a secret or sealed entry, wrapped with DPAPI. If it cannot be
+/// wrapped it is not stored.
+pub(crate) fn cache_blob(key: &str, blob: &[u8], persist: CRED_PERSIST) -> Result<()> {
+    write_blob(
+        &cred_name(key),
+        &dpapi::protect(blob, clock::now_secs())?,
+        persist,
+    )
+}
+
+/// Move the cached secret for `from`, and its checkbox preference, to `to`,
+/// keeping how it persists and when it was written; a plain entry is
+/// wrapped on the way. Its Hello timestamp is dropped. Returns `true` when
+/// a secret was moved.
+pub(crate) fn rename_cached(from: &str, to: &str) -> bool {
+    let Some((mut blob, written_secs, persist)) = read_entry(&cred_name(from)) else {
+        return false;
+    };
+    if !dpapi::is_protected(&blob) {
+        // Only the header can carry the write time over
+        match dpapi::protect(&blob, written_secs) {
+            Ok(wrapped) => blob = wrapped,
+            Err(_) => return false,
+        }
+    }
+    if write_blob(&cred_name(to), &blob, persist).is_err() {
+        return false;
+    }
+    if let Some(pref) = read_blob(&save_pref_cred_name(from)) {
+        if write_blob(&save_pref_cred_name(to), &pref, CRED_PERSIST_LOCAL_MACHINE).is_ok() {
+            delete(&save_pref_cred_name(from));
+        }
+    }
+    remove_cached(from);
+    true
+}
+
+/// A cached secret, as listed by `cache list`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedSecret {
+    /// The cache key, e.g. `id_ed25519` or `password:user@host`
+    pub key: String,
+    /// Stored on this machine, rather than for the logon session only
+    pub persistent: bool,
+    /// When it was stored, in Unix seconds
+    pub written_secs: u64,
+}
+
+/// Every cached secret, leaving out timestamps, preferences and host pins.
+pub(crate) fn list_cached() -> Vec<CachedSecret> {
+    let prefix = format!("{}:", CRED_PREFIX);
+    enumerate()
+        .into_iter()
+        .filter_map(|(name, persist, written_secs)| {
+            let key = name.strip_prefix(&prefix)?;
+            let bookkeeping = key == "snooze"
+                || key == "doctor"
+                || key == "session-locked"
+                || key.starts_with("known-host/")
+                || key.ends_with(":temp")
+                || key.ends_with(":save")
+                || key.ends_with(":used")
+                || key.ends_with(":file")
+                || key.ends_with(":attempt");
+            (!bookkeeping).then(|| CachedSecret {
+                key: key.to_string(),
+                persistent: persist == CRED_PERSIST_LOCAL_MACHINE,
+                written_secs,
+            })
+        })
+        .collect()
+}
+
+/// The cached keys that `key` names: itself, or when nothing is cached
+/// under it, every key file of that bare name (`id_ed25519`).
+pub(crate) fn resolve_cached(key: &str) -> Vec<String> {
+    let cached = list_cached();
+    if cached.iter().any(|secret| secret.key == key) {
+        return vec![key.to_string()];
+    }
+    cached
+        .into_iter()
+        .filter(|secret| crate::core::prompt::key_label(&secret.key) == key)
+        .map(|secret| secret.key)
+        .collect()
+}
+
+/// Delete every entry this tool has written: secrets, Hello timestamps,
+/// checkbox preferences, host key pins and any snooze. Returns how many.
+pub(crate) fn remove_all() -> usize {
+    enumerate()
+        .into_iter()
+        .filter(|(name, _, _)| delete(name))
+        .count()
+}
+
+/// Target name, persistence and write time of each `wsl-ssh-askpass:*` entry.
+fn enumerate() -> Vec<(String, CRED_PERSIST, u64)> {
+    let filter = to_wide(&format!("{}:*", CRED_PREFIX));
+    guarded(Vec::new(), move || unsafe {
+        let mut entries = Vec::new();
+        let mut count = 0u32;
+        let mut creds: *mut *mut CREDENTIALW = std::ptr::null_mut();
+        if CredEnumerateW(
+            PCWSTR(filter.as_ptr()),
+            CRED_ENUMERATE_FLAGS(0),
+            &mut count,
+            &mut creds,
+        )
+        .is_err()
+        {
+            return entries;
+        }
+        for &cred_ptr in std::slice::from_raw_parts(creds, count as usize) {
+            let cred = &*cred_ptr;
+            if cred.Type != CRED_TYPE_GENERIC {
+                continue;
+            }
+            let Ok(name) = cred.TargetName.to_string() else {
+                continue;
+            };
+            let blob: &[u8] = if cred.CredentialBlob.is_null() {
+                &[]
+            } else {
+                std::slice::from_raw_parts(cred.CredentialBlob, cred.CredentialBlobSize as usize)
+            };
+            let written_secs = dpapi::written_secs(blob).unwrap_or_else(|| {
+                filetime_to_unix(
+                    cred.LastWritten.dwHighDateTime,
+                    cred.LastWritten.dwLowDateTime,
+                )
+            });
+            entries.push((name, cred.Persist, written_secs));
+        }
+        CredFree(creds as *mut _);
+        entries
+    })
+}
+
+/// When the secret cached for `key` was written, if one is.
+pub(crate) fn written_secs(key: &str) -> Option<u64> {
+    read_entry(&cred_name(key)).map(|(_, written_secs, _)| written_secs)
+}
+
+/// The stamp of the key file whose passphrase is cached for `key` (see
+/// `keyfile`).
+pub(crate) fn key_file_stamp(key: &str) -> Option<String> {
+    read_blob(&key_file_cred_name(key)).map(|blob| String::from_utf8_lossy(&blob).into_owned())
+}
+
+/// Record the key file stamp for `key`. Not a secret, so it persists on
+/// this machine like the checkbox choice.
+pub(crate) fn set_key_file_stamp(key: &str, stamp: &str) -> Result<()> {
+    write_blob(
+        &key_file_cred_name(key),
+        stamp.as_bytes(),
+        CRED_PERSIST_LOCAL_MACHINE,
+    )
+}
+
+/// Delete the cached secret, Hello timestamp, use count and key file stamp
+/// for `key`.
+/// Returns `true` when a cached secret existed.
+pub(crate) fn remove_cached(key: &str) -> bool {
+    delete(&hello_cred_name(key));
+    delete(&usage_cred_name(key));
+    delete(&key_file_cred_name(key));
+    delete(&cred_name(key))
+}
+
+/// Everything known about a cached secret except the secret, as shown by
+/// `cache show`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedSecretDetails {
+    pub key: String,
+    /// Stored on this machine, rather than for the logon session only
+    pub persistent: bool,
+    /// When it was stored, in Unix seconds
+    pub written_secs: u64,
+    /// When it was last served from the cache, in Unix seconds
+    pub last_used_secs: Option<u64>,
+    /// How many times it has been served from the cache
+    pub use_count: u64,
+    /// Seconds left in this session's Windows Hello grace window
+    pub hello_window_secs: Option<u64>,
+    /// Wrapped with DPAPI; only entries from before that layer are not
+    pub protected: bool,
+    /// Encrypted to the Windows Hello key (`hello_encryption`)
+    pub sealed: bool,
+    /// The remembered "Remember" checkbox choice
+    pub save_preference: Option<SavePreference>,
+}
+
+/// The details of the secret cached for `key`, without its value.
+pub(crate) fn cached_details(key: &str) -> Option<CachedSecretDetails> {
+    let (blob, written_secs, persist) = read_entry(&cred_name(key))?;
+    let (last_used_secs, use_count) = usage(key);
+    Some(CachedSecretDetails {
+        key: key.to_string(),
+        persistent: persist == CRED_PERSIST_LOCAL_MACHINE,
+        written_secs,
+        last_used_secs,
+        use_count,
+        hello_window_secs: hello_window_remaining(key),
+        protected: dpapi::is_protected(&blob),
+        sealed: dpapi::wraps_sealed(&blob) || crate::store::sealed::is_sealed(&blob),
+        save_preference: get_save_preference(key),
+    })
+}
+
+/// When `key` was last served from the cache and how often; the stored
+/// value is `<timestamp>:<count>`.
+fn usage(key: &str) -> (Option<u64>, u64) {
+    let Some(blob) = read_blob(&usage_cred_name(key)) else {
+        return (None, 0);
+    };
+    let value = String::from_utf8_lossy(&blob);
+    let (last, count) = value.split_once(':').unwrap_or((&value, ""));
+    (last.parse().ok(), count.parse().unwrap_or(0))
+}
+
+/// Count one release of the secret cached for `key`. Not a secret, so it
+/// persists on this machine like the checkbox choice.
+pub(crate) fn record_use(key: &str) {
+    let (_, count) = usage(key);
+    let value = format!("{}:{}", clock::now_secs(), count.saturating_add(1));
+    let _ = write_blob(
+        &usage_cred_name(key),
+        value.as_bytes(),
+        CRED_PERSIST_LOCAL_MACHINE,
+    );
+}
+
+/// When the last password prompt for `key` was and which attempt it was;
+/// the stored value is `<timestamp>:<attempt>`.
+pub(crate) fn last_attempt(key: &str) -> Option<(u64, u32)> {
+    let blob = read_blob(&attempt_cred_name(key))?;
+    let value = String::from_utf8_lossy(&blob);
+    let (at, attempt) = value.split_once(':')?;
+    Some((at.parse().ok()?, attempt.parse().ok()?))
+}
+
+/// Note that a password prompt for `key`, attempt number `attempt`, is
+/// being answered now. Kept for the logon session only.
+pub(crate) fn record_attempt(key: &str, attempt: u32) {
+    let value = format!("{}:{}", clock::now_secs(), attempt);
+    let _ = write_blob(
+        &attempt_cred_name(key),
+        value.as_bytes(),
+        CRED_PERSIST_SESSION,
+    );
+}
+
+/// Seconds left in the Hello grace window for `key`. The window is only
+/// honoured in the desktop session that recorded it, and closes when that
+/// session is locked; the stored value is `<timestamp>:<session fingerprint>`.
+pub(crate) fn hello_window_remaining(key: &str) -> Option<u64> {
+    let stored = session_stamp(&hello_cred_name(key))?;
+    if lockwatch::is_locked() || last_session_lock().is_some_and(|locked| locked >= stored) {
+        return None;
+    }
+    clock::remaining(
+        stored,
+        crate::core::config::get().hello_window_secs(),
+        clock::now_secs(),
+    )
+}
+
+/// Whether the Hello grace window for `key` is still open.
+pub(crate) fn is_hello_valid(key: &str) -> bool {
+    hello_window_remaining(key).is_some()
+}
+
+pub(crate) fn update_hello_timestamp(key: &str) {
+    let ts = format!("{}:{}", clock::now_secs(), session::fingerprint());
+    let _ = write_blob(&hello_cred_name(key), ts.as_bytes(), CRED_PERSIST_SESSION);
+}
+
+/// The time in a `<timestamp>:<session fingerprint>` entry, if it was
+/// written in this desktop session.
+fn session_stamp(name: &str) -> Option<u64> {
+    let blob = read_blob(name)?;
+    let value = String::from_utf8_lossy(&blob);
+    let (ts_str, fingerprint) = value.split_once(':').unwrap_or((&value, ""));
+    let stored = ts_str.parse::<u64>().ok()?;
+    (fingerprint == session::fingerprint()).then_some(stored)
+}
+
+fn session_lock_cred_name() -> String {
+    format!("{}:session-locked", CRED_PREFIX)
+}
+
+/// Note that the workstation was locked just now, closing every Hello grace
+/// window opened before it.
+pub(crate) fn record_session_lock() {
+    let ts = format!("{}:{}", clock::now_secs(), session::fingerprint());
+    let _ = write_blob(
+        &session_lock_cred_name(),
+        ts.as_bytes(),
+        CRED_PERSIST_SESSION,
+    );
+}
+
+/// Write, read back and delete a throwaway entry, for `doctor`.
+pub(crate) fn probe() -> std::result::Result<(), String> {
+    let name = format!("{}:doctor", CRED_PREFIX);
+    let token = clock::now_secs().to_string();
+    write_blob(&name, token.as_bytes(), CRED_PERSIST_SESSION)
+        .map_err(|e| format!("cannot write: {}", e.message()))?;
+    let read = read_blob(&name);
+    delete(&name);
+    match read {
+        Some(blob) if blob == token.as_bytes() => Ok(()),
+        Some(_) => Err("read back something else than was written".into()),
+        None if degraded() => Err("not responding".into()),
+        None => Err("cannot read back what was written".into()),
+    }
+}
+
+/// When this desktop session was last seen locking, if it was.
+fn last_session_lock() -> Option<u64> {
+    session_stamp(&session_lock_cred_name())
+}
@@ -0,0 +1,207 @@
+//! DPAPI wrapping of cached secrets.
+//!
+//! Credential Manager hands a generic credential's blob to any process of
+//! the user that asks, and tools that dump the vault print it as is. Every
+//! secret is therefore passed through `CryptProtectData` before it is
+//! written, so a dump shows an opaque DPAPI blob that only this user's
+//! logon can open. With `hello_entropy = true` the blob is also bound to an
+//! entropy value derived from a Windows Hello key signature (see `sealed`),
+//! so opening it takes the Hello key as well as the user's DPAPI keys.
+//!
+//! Layout: magic, one flags byte, the write time, the DPAPI blob. The flags
+//! say whether Hello entropy was mixed in and whether the wrapped bytes are
+//! a sealed entry, so neither needs the entry opened to be known. The write
+//! time (Unix seconds, little-endian `u64`) is when the secret was first
+//! cached: Credential Manager's own `LastWritten` starts over whenever the
+//! entry is rewritten, as when a plain entry is wrapped or moved to another
+//! name, and a TTL must not start over with it.
+
+use std::sync::OnceLock;
+
+use windows::{
+    core::{w, Result},
+    Win32::Foundation::{LocalFree, HLOCAL},
+    Win32::Security::Cryptography::{
+        CryptProtectData, CryptUnprotectData, CRYPTPROTECT_UI_FORBIDDEN, CRYPT_INTEGER_BLOB,
+    },
+};
+
+use crate::{core::config, core::diag, store::sealed, PromptError};
+
+const MAGIC: &[u8] = b"wsl-ssh-askpass dpapi 1\0";
+
+/// The DPAPI blob was made with entropy from the Hello key.
+const HELLO_ENTROPY: u8 = 0x01;
+/// The wrapped bytes are a sealed entry.
+const SEALED: u8 = 0x02;
+
+/// The Hello entropy, once a signature has been had in this process.
+static ENTROPY: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Whether `blob` was written by [`protect`].
+pub(crate) fn is_protected(blob: &[u8]) -> bool {
+    header(blob).is_some()
+}
+
+/// Whether a [`protect`]ed `blob` wraps a sealed entry.
+pub(crate) fn wraps_sealed(blob: &[u8]) -> bool {
+    header(blob).is_some_and(|header| header.flags & SEALED != 0)
+}
+
+/// When the secret in a [`protect`]ed `blob` was first cached.
+pub(crate) fn written_secs(blob: &[u8]) -> Option<u64> {
+    header(blob).map(|header| header.written_secs)
+}
+
+/// Wrap `secret` for the current user, recording `written_secs` as when it
+/// was first cached. With `hello_entropy` on, asks for Windows Hello the
+/// first time in a process; if that fails the secret is still wrapped, only
+/// without the entropy.
+pub(crate) fn protect(secret: &[u8], written_secs: u64) -> Result<Vec<u8>> {
+    let mut flags = if sealed::is_sealed(secret) { SEALED } else { 0 };
+    let entropy = if config::get().hello_entropy {
+        match hello_entropy() {
+            Ok(entropy) => {
+                flags |= HELLO_ENTROPY;
+                Some(entropy)
+            }
+            Err(e) => {
+                diag::note("dpapi", || {
+                    format!("no Hello entropy, wrapping without it: {}", describe(&e))
+                });
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let wrapped = unsafe {
+        let input = blob_of(secret);
+        let entropy = entropy.as_deref().map(blob_of);
+        let mut output = CRYPT_INTEGER_BLOB::default();
+        CryptProtectData(
+            &input,
+            w!("wsl-ssh-askpass"),
+            entropy.as_ref().map(|e| e as *const _),
+            None,
+            None,
+            CRYPTPROTECT_UI_FORBIDDEN,
+            &mut output,
+        )
+        .inspect_err(|e| diag::failure("dpapi", "CryptProtectData", e))?;
+        take(output)
+    };
+    Ok([MAGIC, &[flags], &written_secs.to_le_bytes(), &wrapped].concat())
+}
+
+/// Unwrap a [`protect`]ed `blob`, asking for Windows Hello when it was made
+/// with Hello entropy and no signature has been had in this process yet.
+/// The second value is `true` when that just happened, so the caller can
+/// count it as a Hello verification.
+pub(crate) fn unprotect(blob: &[u8]) -> std::result::Result<(Vec<u8>, bool), PromptError> {
+    let header = header(blob).ok_or_else(|| failed("not a DPAPI entry"))?;
+    let mut verified = false;
+    let entropy = if header.flags & HELLO_ENTROPY != 0 {
+        verified = ENTROPY.get().is_none();
+        Some(hello_entropy()?)
+    } else {
+        None
+    };
+    let secret = unsafe {
+        let input = blob_of(header.wrapped);
+        let entropy = entropy.as_deref().map(blob_of);
+        let mut output = CRYPT_INTEGER_BLOB::default();
+        CryptUnprotectData(
+            &input,
+            None,
+            entropy.as_ref().map(|e| e as *const _),
+            None,
+            None,
+            CRYPTPROTECT_UI_FORBIDDEN,
+            &mut output,
+        )
+        .inspect_err(|e| diag::failure("dpapi", "CryptUnprotectData", e))
+        // Another user, a reset password or a different Hello key
+        .map_err(|_| failed("the entry cannot be opened by this user"))?;
+        take(output)
+    };
+    Ok((secret, verified))
+}
+
+/// What precedes the DPAPI blob of a protected entry, and the blob.
+#[derive(Debug, PartialEq)]
+struct Header<'a> {
+    flags: u8,
+    written_secs: u64,
+    wrapped: &'a [u8],
+}
+
+fn header(blob: &[u8]) -> Option<Header<'_>> {
+    let (&flags, rest) = blob.strip_prefix(MAGIC)?.split_first()?;
+    let (written, wrapped) = rest.split_first_chunk::<8>()?;
+    Some(Header {
+        flags,
+        written_secs: u64::from_le_bytes(*written),
+        wrapped,
+    })
+}
+
+fn hello_entropy() -> std::result::Result<Vec<u8>, PromptError> {
+    if let Some(entropy) = ENTROPY.get() {
+        return Ok(entropy.clone());
+    }
+    let entropy = sealed::entropy()?;
+    Ok(ENTROPY.get_or_init(|| entropy).clone())
+}
+
+fn blob_of(data: &[u8]) -> CRYPT_INTEGER_BLOB {
+    CRYPT_INTEGER_BLOB {
+        cbData: data.len() as u32,
+        pbData: data.as_ptr() as *mut _,
+    }
+}
+
+/// Copy out and free a blob DPAPI allocated.
+unsafe fn take(blob: CRYPT_INTEGER_BLOB) -> Vec<u8> {
+    let data = std::slice::from_raw_parts(blob.pbData, blob.cbData as usize).to_vec();
+    let _ = LocalFree(HLOCAL(blob.pbData as *mut _));
+    data
+}
+
+fn describe(error: &PromptError) -> String {
+    match error {
+        PromptError::Cancelled => "Windows Hello was declined".to_string(),
+        PromptError::Failed(e) => e.clone(),
+    }
+}
+
+fn failed(message: &str) -> PromptError {
+    PromptError::Failed(format!("DPAPI: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_header() {
+        let written = 1_700_000_000u64.to_le_bytes();
+        let blob = [MAGIC, &[HELLO_ENTROPY | SEALED], &written, b"opaque"].concat();
+        assert_eq!(
+            header(&blob),
+            Some(Header {
+                flags: HELLO_ENTROPY | SEALED,
+                written_secs: 1_700_000_000,
+                wrapped: b"opaque",
+            })
+        );
+        assert!(is_protected(&blob));
+        assert!(wraps_sealed(&blob));
+        assert_eq!(written_secs(&blob), Some(1_700_000_000));
+        assert!(!wraps_sealed(&[MAGIC, &[0], &written, b"opaque"].concat()));
+        // Plain text from before the DPAPI layer, a bare magic, a cut-off time
+        assert!(!is_protected(b"correct horse battery staple"));
+        assert!(!is_protected(MAGIC));
+        assert!(!is_protected(&[MAGIC, &[0], &written[..4]].concat()));
+    }
+}
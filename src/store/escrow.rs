@@ -0,0 +1,266 @@
+//! Break-glass escrow: wrap selected cached passphrases to an organization
+//! RSA public key so they can be sealed away and recovered by an admin.
+
+use std::fs;
+use std::path::Path;
+
+use serde_json::json;
+use windows::{
+    core::PWSTR,
+    Win32::Foundation::{CloseHandle, LocalFree, HANDLE, HLOCAL},
+    Win32::Security::Cryptography::{
+        BCryptDestroyKey, BCryptEncrypt, CryptBinaryToStringW, CryptDecodeObjectEx,
+        CryptImportPublicKeyInfoEx2, CryptStringToBinaryW, BCRYPT_KEY_HANDLE,
+        BCRYPT_OAEP_PADDING_INFO, BCRYPT_PAD_OAEP, BCRYPT_SHA256_ALGORITHM, CERT_PUBLIC_KEY_INFO,
+        CRYPT_DECODE_ALLOC_FLAG, CRYPT_IMPORT_PUBLIC_KEY_FLAGS, CRYPT_STRING, CRYPT_STRING_BASE64,
+        CRYPT_STRING_BASE64HEADER, CRYPT_STRING_NOCRLF, X509_ASN_ENCODING, X509_PUBLIC_KEY_INFO,
+    },
+    Win32::Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY},
+    Win32::System::Threading::{GetCurrentProcess, OpenProcessToken},
+};
+
+use crate::{core::clock::now_secs, store::audit, store::credstore, store::sealed, PromptError};
+
+const ALGORITHM: &str = "RSA-OAEP-256";
+const NOT_APPROVED: &str = "escrow export was not approved";
+
+/// Export the cached passphrases for `keys`, encrypted to the PEM public key
+/// at `recipient`, as a JSON document written to `out` (or stdout).
+///
+/// Requires an elevated process, an on-screen confirmation and a fresh
+/// Windows Hello verification. Every attempt, declined and failed ones too,
+/// is appended to `escrow.log` and written to the Application log, and an
+/// export is announced on stderr.
+pub fn export(recipient: &Path, keys: &[String], out: Option<&Path>) -> Result<(), String> {
+    let result = export_to(recipient, keys, out);
+    let (outcome, detail) = match &result {
+        Ok(exported) => ("exported", exported.join(", ")),
+        Err(e) if e == NOT_APPROVED => ("declined", String::new()),
+        Err(e) => ("failed", e.clone()),
+    };
+    let recipient = recipient.display().to_string();
+    let requested = keys.join(", ");
+    audit::append("escrow.log", &[outcome, &requested, &recipient, &detail]);
+    audit::report(
+        audit::EVENT_ESCROW,
+        &format!(
+            "Escrow export\r\nOutcome: {}\r\nKeys: {}\r\nRecipient: {}\r\nDetail: {}\r\nUnix time: {}",
+            outcome,
+            requested,
+            recipient,
+            detail,
+            now_secs()
+        ),
+    );
+    let exported = result?;
+    eprintln!(
+        "wsl-ssh-askpass: ESCROW EXPORT of {} passphrase(s) [{}] to {} at {}",
+        exported.len(),
+        exported.join(", "),
+        recipient,
+        now_secs()
+    );
+    Ok(())
+}
+
+/// [`export`], returning the cache keys that were exported.
+fn export_to(recipient: &Path, keys: &[String], out: Option<&Path>) -> Result<Vec<String>, String> {
+    if !is_elevated() {
+        return Err("escrow export must be run from an elevated (administrator) prompt".into());
+    }
+    if keys.is_empty() {
+        return Err("no keys given to export".into());
+    }
+
+    // A bare file name exports that key from every directory
+    let keys: Vec<String> = keys
+        .iter()
+        .flat_map(|key| match credstore::resolve_cached(key) {
+            resolved if resolved.is_empty() => vec![key.clone()],
+            resolved => resolved,
+        })
+        .collect();
+
+    let pem = fs::read_to_string(recipient)
+        .map_err(|e| format!("cannot read {}: {}", recipient.display(), e))?;
+    let public_key = RsaPublicKey::from_pem(&pem)?;
+
+    let question = format!(
+        "Export the cached passphrases for {} to the escrow key {}?\n\n\
+         Anyone holding the matching private key will be able to recover them.",
+        keys.join(", "),
+        recipient.display()
+    );
+    if !crate::confirm(&question) || !crate::verify_hello("escrow export") {
+        return Err(NOT_APPROVED.into());
+    }
+
+    let mut entries = Vec::new();
+    for key in &keys {
+        let blob = credstore::get_cached_blob(key, None)
+            .ok_or_else(|| format!("no cached passphrase for '{}'", key))?;
+        let pass = if sealed::is_sealed(&blob) {
+            sealed::open(&blob, key).map_err(|e| match e {
+                PromptError::Cancelled => NOT_APPROVED.to_string(),
+                PromptError::Failed(e) => e,
+            })?
+        } else {
+            String::from_utf8_lossy(&blob).to_string()
+        };
+        let sealed = public_key.encrypt(pass.as_bytes())?;
+        entries.push(json!({ "key": key, "ciphertext": to_base64(&sealed)? }));
+    }
+
+    let document = json!({
+        "version": 1,
+        "algorithm": ALGORITHM,
+        "created": now_secs(),
+        "entries": entries,
+    });
+    let text = serde_json::to_string_pretty(&document).map_err(|e| e.to_string())? + "\n";
+    match out {
+        Some(path) => {
+            fs::write(path, text).map_err(|e| format!("cannot write {}: {}", path.display(), e))?
+        }
+        None => print!("{}", text),
+    }
+    Ok(keys)
+}
+
+fn is_elevated() -> bool {
+    unsafe {
+        let mut token = HANDLE::default();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token).is_err() {
+            return false;
+        }
+        let mut elevation = TOKEN_ELEVATION::default();
+        let mut len = 0u32;
+        let ok = GetTokenInformation(
+            token,
+            TokenElevation,
+            Some(&mut elevation as *mut _ as *mut _),
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut len,
+        )
+        .is_ok();
+        let _ = CloseHandle(token);
+        ok && elevation.TokenIsElevated != 0
+    }
+}
+
+struct RsaPublicKey(BCRYPT_KEY_HANDLE);
+
+impl RsaPublicKey {
+    /// Import a `-----BEGIN PUBLIC KEY-----` (SubjectPublicKeyInfo) PEM.
+    fn from_pem(pem: &str) -> Result<Self, String> {
+        let pem: Vec<u16> = pem.encode_utf16().collect();
+        let invalid = |_| "recipient is not a PEM public key".to_string();
+        unsafe {
+            let mut der_len = 0u32;
+            CryptStringToBinaryW(
+                &pem,
+                CRYPT_STRING_BASE64HEADER,
+                None,
+                &mut der_len,
+                None,
+                None,
+            )
+            .map_err(invalid)?;
+            let mut der = vec![0u8; der_len as usize];
+            CryptStringToBinaryW(
+                &pem,
+                CRYPT_STRING_BASE64HEADER,
+                Some(der.as_mut_ptr()),
+                &mut der_len,
+                None,
+                None,
+            )
+            .map_err(invalid)?;
+
+            let mut info: *mut CERT_PUBLIC_KEY_INFO = std::ptr::null_mut();
+            let mut info_len = 0u32;
+            CryptDecodeObjectEx(
+                X509_ASN_ENCODING,
+                X509_PUBLIC_KEY_INFO,
+                &der[..der_len as usize],
+                CRYPT_DECODE_ALLOC_FLAG,
+                None,
+                Some(&mut info as *mut _ as *mut _),
+                &mut info_len,
+            )
+            .map_err(invalid)?;
+
+            let mut key = BCRYPT_KEY_HANDLE::default();
+            let imported = CryptImportPublicKeyInfoEx2(
+                X509_ASN_ENCODING,
+                info,
+                CRYPT_IMPORT_PUBLIC_KEY_FLAGS(0),
+                None,
+                &mut key,
+            );
+            let _ = LocalFree(HLOCAL(info as *mut _));
+            imported.map_err(|e| format!("unsupported recipient key: {}", e))?;
+            Ok(RsaPublicKey(key))
+        }
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        let padding = BCRYPT_OAEP_PADDING_INFO {
+            pszAlgId: BCRYPT_SHA256_ALGORITHM,
+            pbLabel: std::ptr::null_mut(),
+            cbLabel: 0,
+        };
+        let padding_ptr = Some(&padding as *const _ as *const _);
+        unsafe {
+            let mut len = 0u32;
+            BCryptEncrypt(
+                self.0,
+                Some(plaintext),
+                padding_ptr,
+                None,
+                None,
+                &mut len,
+                BCRYPT_PAD_OAEP,
+            )
+            .ok()
+            .map_err(|e| format!("cannot wrap passphrase (too long for the key?): {}", e))?;
+            let mut out = vec![0u8; len as usize];
+            BCryptEncrypt(
+                self.0,
+                Some(plaintext),
+                padding_ptr,
+                None,
+                Some(&mut out),
+                &mut len,
+                BCRYPT_PAD_OAEP,
+            )
+            .ok()
+            .map_err(|e| format!("cannot wrap passphrase: {}", e))?;
+            out.truncate(len as usize);
+            Ok(out)
+        }
+    }
+}
+
+impl Drop for RsaPublicKey {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = BCryptDestroyKey(self.0);
+        }
+    }
+}
+
+fn to_base64(data: &[u8]) -> Result<String, String> {
+    let flags = CRYPT_STRING(CRYPT_STRING_BASE64.0 | CRYPT_STRING_NOCRLF);
+    unsafe {
+        let mut len = 0u32;
+        if !CryptBinaryToStringW(data, flags, PWSTR::null(), &mut len).as_bool() {
+            return Err("base64 encoding failed".into());
+        }
+        let mut buf = vec![0u16; len as usize];
+        if !CryptBinaryToStringW(data, flags, PWSTR(buf.as_mut_ptr()), &mut len).as_bool() {
+            return Err("base64 encoding failed".into());
+        }
+        Ok(String::from_utf16_lossy(&buf[..len as usize]))
+    }
+}
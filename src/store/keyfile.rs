@@ -0,0 +1,140 @@
+//! Noticing that a key file changed under its cached passphrase.
+//!
+//! When a passphrase is saved, the key file's size, modification time and
+//! SHA-256 are saved beside it. A key that was since rotated or given a new
+//! passphrase no longer matches, and its stale passphrase is forgotten
+//! instead of being replayed until ssh gives up. Key files are read through
+//! their Windows path: `C:\...` for keys on a Windows drive, `\\wsl$\...`
+//! for keys inside the distro when `WSL_DISTRO_NAME` (and `HOME`, for `~/`
+//! paths) is shared through WSLENV. Keys that cannot be reached are not
+//! checked.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use windows::Win32::Security::Cryptography::{BCryptHash, BCRYPT_SHA256_ALG_HANDLE};
+
+use crate::{core::clock, core::prompt, store::credstore};
+
+const DISTRO_ENV: &str = "WSL_DISTRO_NAME";
+
+/// Larger files are not keys, and are not hashed.
+const MAX_KEY_FILE: u64 = 1024 * 1024;
+
+/// What identifies one version of a key file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Stamp {
+    size: u64,
+    /// Modification time in Unix seconds
+    modified: u64,
+    /// Lowercase hex SHA-256 of the contents
+    digest: String,
+}
+
+impl fmt::Display for Stamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.size, self.modified, self.digest)
+    }
+}
+
+impl Stamp {
+    /// Read back a stamp written with `to_string`.
+    pub(crate) fn parse(text: &str) -> Option<Stamp> {
+        let mut parts = text.splitn(3, ':');
+        let size = parts.next()?.parse().ok()?;
+        let modified = parts.next()?.parse().ok()?;
+        let digest = parts.next()?;
+        (digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit())).then(|| Stamp {
+            size,
+            modified,
+            digest: digest.to_string(),
+        })
+    }
+
+    /// The same size and time, so the file need not be hashed again.
+    fn same_metadata(&self, size: u64, modified: u64) -> bool {
+        self.size == size && self.modified == modified
+    }
+}
+
+/// Where Windows can read the key file that ssh named `key_path`.
+pub(crate) fn locate(key_path: &str) -> Option<PathBuf> {
+    if let Some(path) = prompt::windows_drive_form(key_path) {
+        return Some(path.into());
+    }
+    let distro = env::var(DISTRO_ENV).ok()?;
+    let home = env::var("HOME").ok();
+    prompt::unc_form(key_path, &distro, home.as_deref()).map(PathBuf::from)
+}
+
+/// The stamp of the file at `path` as it is now. `known` is reused when
+/// size and time still match, sparing a hash over the network share.
+fn current(path: &Path, known: Option<&Stamp>) -> Option<Stamp> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_file() || metadata.len() > MAX_KEY_FILE {
+        return None;
+    }
+    let modified = clock::unix_secs(metadata.modified().ok()?);
+    if let Some(known) = known.filter(|known| known.same_metadata(metadata.len(), modified)) {
+        return Some(known.clone());
+    }
+    let contents = fs::read(path).ok()?;
+    let mut digest = [0u8; 32];
+    unsafe { BCryptHash(BCRYPT_SHA256_ALG_HANDLE, None, &contents, &mut digest) }
+        .ok()
+        .ok()?;
+    Some(Stamp {
+        size: contents.len() as u64,
+        modified,
+        digest: digest.iter().map(|b| format!("{:02x}", b)).collect(),
+    })
+}
+
+/// Whether the key file at `path` differs from the one whose passphrase is
+/// cached for `key`. An entry saved before stamps were kept, or by a run
+/// that could not reach the file, adopts the file as it is now.
+pub(crate) fn changed(key: &str, path: &Path) -> bool {
+    let stored = credstore::key_file_stamp(key).and_then(|text| Stamp::parse(&text));
+    let Some(now) = current(path, stored.as_ref()) else {
+        return false;
+    };
+    match stored {
+        Some(stored) if stored.digest != now.digest => true,
+        Some(stored) if stored == now => false,
+        // Touched but not changed: remember the new time
+        _ => {
+            let _ = credstore::set_key_file_stamp(key, &now.to_string());
+            false
+        }
+    }
+}
+
+/// Stamp the cached entry for `key` with the file at `path`, after its
+/// passphrase was saved.
+pub(crate) fn record(key: &str, path: &Path) {
+    if let Some(now) = current(path, None) {
+        let _ = credstore::set_key_file_stamp(key, &now.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamps_round_trip() {
+        let stamp = Stamp {
+            size: 464,
+            modified: 1_700_000_000,
+            digest: "ab".repeat(32),
+        };
+        assert_eq!(Stamp::parse(&stamp.to_string()), Some(stamp.clone()));
+        assert!(stamp.same_metadata(464, 1_700_000_000));
+        assert!(!stamp.same_metadata(464, 1_700_000_001));
+        assert_eq!(Stamp::parse("464:1700000000:abcd"), None);
+        assert_eq!(Stamp::parse("464:1700000000"), None);
+        assert_eq!(Stamp::parse(""), None);
+    }
+}
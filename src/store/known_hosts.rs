@@ -0,0 +1,115 @@
+//! Recording accepted host keys in the distro's `~/.ssh/known_hosts`, so
+//! later connections from inside WSL (including ones that do not go through
+//! this askpass) skip the question.
+//!
+//! The prompt only carries a fingerprint, so the key itself is fetched with
+//! `ssh-keyscan` inside the distro and kept only if its fingerprint matches
+//! the one the user accepted. Keys `ssh-keygen -F` already finds for the
+//! host are left out: a hashed entry is salted afresh by every scan, so it
+//! cannot be told from an existing one by comparing lines. The file is then
+//! appended to through `\\wsl$`.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::core::hostkey::HostKey;
+use crate::ipc::wsl::{self, shell_quote};
+
+const DISTRO_ENV: &str = "WSL_DISTRO_NAME";
+
+/// Append the scanned entries for `key` to the distro's `known_hosts`,
+/// hashing host names when the distro's ssh is set to `HashKnownHosts yes`.
+/// Entries already present are not added again.
+pub(crate) fn record(key: &HostKey) -> Result<(), String> {
+    let distro = std::env::var(DISTRO_ENV).ok().filter(|d| !d.is_empty());
+    let (hash, expected) = match key.fingerprint.strip_prefix("MD5:") {
+        Some(md5) => ("md5", md5),
+        None if !key.fingerprint.contains("SHA256:") && key.fingerprint.contains(':') => {
+            // Pre-6.8 OpenSSH shows bare MD5 hex
+            ("md5", key.fingerprint.as_str())
+        }
+        None => ("sha256", key.fingerprint.as_str()),
+    };
+    let key_type = key
+        .key_type
+        .as_deref()
+        .map_or("rsa,ecdsa,ed25519".to_string(), str::to_lowercase);
+
+    // Prints $HOME, the distro name, then "<fingerprint>\t<known_hosts line>" per
+    // key, the line left empty for a key already known for the host
+    let script = format!(
+        "printf '%s\\n' \"$HOME\" \"$WSL_DISTRO_NAME\"\n\
+         h=\n\
+         [ \"$(ssh -G {host} 2>/dev/null | awk '$1==\"hashknownhosts\"{{print $2}}')\" = yes ] && h=-H\n\
+         known=$(ssh-keygen -F {name} -f \"$HOME/.ssh/known_hosts\" 2>/dev/null | awk '$1 !~ /^#/ {{print $2, $3}}')\n\
+         ssh-keyscan $h -T 5 -p {port} -t {key_type} {host} 2>/dev/null | while IFS= read -r line; do\n\
+           case \"$line\" in ''|'#'*) continue;; esac\n\
+           fp=$(printf '%s\\n' \"$line\" | ssh-keygen -l -E {hash} -f /dev/stdin 2>/dev/null | awk '{{print $2}}')\n\
+           k=$(printf '%s\\n' \"$line\" | awk '{{print $2, $3}}')\n\
+           printf '%s\\n' \"$known\" | grep -qxF -- \"$k\" && line=\n\
+           printf '%s\\t%s\\n' \"$fp\" \"$line\"\n\
+         done\n",
+        host = shell_quote(&key.host),
+        name = shell_quote(&key.known_hosts_name()),
+        port = key.port.unwrap_or(22),
+        key_type = shell_quote(&key_type),
+        hash = hash,
+    );
+    let output = wsl::run(distro.as_deref(), &script, None)?;
+    let mut lines = output.lines();
+    let home = lines.next().unwrap_or_default();
+    let distro = lines.next().unwrap_or_default();
+    if !home.starts_with('/') || distro.is_empty() {
+        return Err("could not locate the distro's home directory".into());
+    }
+
+    let mut entries: Vec<&str> = lines
+        .filter_map(|line| {
+            let (fingerprint, entry) = line.split_once('\t')?;
+            let fingerprint = fingerprint.strip_prefix("MD5:").unwrap_or(fingerprint);
+            (fingerprint == expected).then_some(entry)
+        })
+        .collect();
+    if entries.is_empty() {
+        return Err(format!(
+            "no key for {} matching {} was found",
+            key.known_hosts_name(),
+            key.fingerprint
+        ));
+    }
+    entries.retain(|entry| !entry.is_empty());
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let path = ssh_dir(distro, home).join("known_hosts");
+    if !path.parent().is_some_and(|dir| dir.is_dir()) {
+        // Leave creating ~/.ssh (with its permissions) to ssh itself
+        return Err(format!("{} does not exist", path.display()));
+    }
+    let existing = fs::read_to_string(&path).unwrap_or_default();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("cannot open {}: {}", path.display(), e))?;
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        writeln!(file).map_err(|e| e.to_string())?;
+    }
+    for entry in entries {
+        if !existing.lines().any(|line| line.trim() == entry) {
+            writeln!(file, "{}", entry).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// `\\wsl$\<distro>\<home>\.ssh`
+fn ssh_dir(distro: &str, home: &str) -> PathBuf {
+    PathBuf::from(format!(
+        r"\\wsl$\{}{}\.ssh",
+        distro,
+        home.trim_end_matches('/').replace('/', r"\")
+    ))
+}
@@ -0,0 +1,15 @@
+//! Where things are kept: the Credential Manager cache and its bookkeeping
+//! entries, DPAPI wrapping and Hello-bound sealing of the secrets, key file
+//! fingerprints, escrow exports, accepted host keys written to a distro's
+//! `known_hosts`, the audit logs, and the checks on how well Windows
+//! protects all of it (`vbs`, `scan`).
+
+pub(crate) mod audit;
+pub(crate) mod credstore;
+pub(crate) mod dpapi;
+pub mod escrow;
+pub(crate) mod keyfile;
+pub(crate) mod known_hosts;
+pub mod scan;
+pub(crate) mod sealed;
+pub(crate) mod vbs;
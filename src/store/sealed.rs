@@ -0,0 +1,194 @@
+//! Hello-bound encryption of cached secrets.
+//!
+//! With `hello_encryption = true` in the config file, secrets are stored
+//! encrypted rather than as plain generic credentials. The key comes from
+//! a Windows Hello key credential (`KeyCredentialManager`): its private key
+//! never leaves the TPM or Hello container and only signs after a
+//! biometric or PIN assertion. Each entry has a random salt; the RSA
+//! signature of the salt is hashed into an AES-GCM key, so reading the
+//! Credential Manager entry alone yields nothing usable. PKCS#1 v1.5
+//! signatures are deterministic, so the same salt always gives the same key.
+//!
+//! Layout: magic, 32-byte salt, 12-byte nonce, 16-byte tag, ciphertext. The
+//! cache key is the associated data, so an entry cannot be moved to
+//! another name.
+
+use std::time::Duration;
+
+use windows::{
+    core::{Array, HSTRING},
+    Security::Credentials::{
+        KeyCredential, KeyCredentialCreationOption, KeyCredentialManager, KeyCredentialStatus,
+    },
+    Security::Cryptography::{
+        Core::{
+            CryptographicEngine, CryptographicKey, HashAlgorithmNames, HashAlgorithmProvider,
+            SymmetricAlgorithmNames, SymmetricKeyAlgorithmProvider,
+        },
+        CryptographicBuffer,
+    },
+    Storage::Streams::IBuffer,
+};
+
+use crate::{core::config, core::timing, core::winrt, random_bytes, PromptError};
+
+const MAGIC: &[u8] = b"wsl-ssh-askpass sealed 1\0";
+const SALT_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// Signed for the DPAPI entropy; unlike a salt challenge it has no `MAGIC`
+/// prefix, so it can never derive an entry's key.
+const ENTROPY_CHALLENGE: &[u8] = b"wsl-ssh-askpass dpapi entropy 1\0";
+
+/// The Hello key credential shared by every entry.
+const CREDENTIAL_NAME: &str = "wsl-ssh-askpass";
+
+/// Longest a Hello assertion may stay up before it is taken down.
+const SIGN_TIMEOUT: Duration = Duration::from_secs(120);
+/// Opening or checking the key credential normally answers at once.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether new secrets should be sealed.
+pub(crate) fn enabled() -> bool {
+    config::get().hello_encryption
+}
+
+/// Whether `blob` was written by [`seal`].
+pub(crate) fn is_sealed(blob: &[u8]) -> bool {
+    blob.starts_with(MAGIC)
+}
+
+/// Encrypt `secret` for the cache entry `key_name`. Asks for Windows Hello.
+pub(crate) fn seal(secret: &str, key_name: &str) -> Result<Vec<u8>, PromptError> {
+    let salt = random_bytes(SALT_LEN).ok_or_else(|| failed("no randomness"))?;
+    let nonce = random_bytes(NONCE_LEN).ok_or_else(|| failed("no randomness"))?;
+    let key = derive_key(&salt)?;
+    let sealed = CryptographicEngine::EncryptAndAuthenticate(
+        &key,
+        &buffer(secret.as_bytes())?,
+        &buffer(&nonce)?,
+        &buffer(key_name.as_bytes())?,
+    )
+    .map_err(|e| failed(&e.message()))?;
+    let tag = bytes(
+        &sealed
+            .AuthenticationTag()
+            .map_err(|e| failed(&e.message()))?,
+    )?;
+    let ciphertext = bytes(&sealed.EncryptedData().map_err(|e| failed(&e.message()))?)?;
+    if tag.len() != TAG_LEN {
+        return Err(failed("unexpected authentication tag"));
+    }
+    Ok([MAGIC, &salt, &nonce, &tag, &ciphertext].concat())
+}
+
+/// Decrypt a sealed entry for `key_name`. Asks for Windows Hello; declining
+/// it is [`PromptError::Cancelled`].
+pub(crate) fn open(blob: &[u8], key_name: &str) -> Result<String, PromptError> {
+    let (salt, rest) = blob[MAGIC.len()..]
+        .split_at_checked(SALT_LEN)
+        .ok_or_else(|| failed("truncated entry"))?;
+    let (nonce, rest) = rest
+        .split_at_checked(NONCE_LEN)
+        .ok_or_else(|| failed("truncated entry"))?;
+    let (tag, ciphertext) = rest
+        .split_at_checked(TAG_LEN)
+        .ok_or_else(|| failed("truncated entry"))?;
+    let key = derive_key(salt)?;
+    let plain = CryptographicEngine::DecryptAndAuthenticate(
+        &key,
+        &buffer(ciphertext)?,
+        &buffer(nonce)?,
+        &buffer(tag)?,
+        &buffer(key_name.as_bytes())?,
+    )
+    // A different Hello key (re-enrolled, another machine) cannot open it
+    .map_err(|_| failed("the entry does not match this Windows Hello key"))?;
+    String::from_utf8(bytes(&plain)?).map_err(|_| failed("the entry is not text"))
+}
+
+/// Entropy for the DPAPI layer (`hello_entropy`): SHA-256 of the Hello
+/// key's signature of a fixed challenge. Asks for Windows Hello.
+pub(crate) fn entropy() -> Result<Vec<u8>, PromptError> {
+    bytes(&signature_digest(ENTROPY_CHALLENGE)?)
+}
+
+/// The AES-GCM key for `salt`: SHA-256 of the Hello key's signature of it.
+fn derive_key(salt: &[u8]) -> Result<CryptographicKey, PromptError> {
+    let digest = signature_digest(&[MAGIC, salt].concat())?;
+    SymmetricAlgorithmNames::AesGcm()
+        .and_then(|name| SymmetricKeyAlgorithmProvider::OpenAlgorithm(&name))
+        .and_then(|aes| aes.CreateSymmetricKey(&digest))
+        .map_err(|e| failed(&e.message()))
+}
+
+/// SHA-256 of the Hello key's signature of `challenge`.
+fn signature_digest(challenge: &[u8]) -> Result<IBuffer, PromptError> {
+    let credential = credential()?;
+    let challenge = buffer(challenge)?;
+    let signed = timing::time("hello", || {
+        let operation = credential
+            .RequestSignAsync(&challenge)
+            .map_err(|e| failed(&e.message()))?;
+        winrt::wait(&operation, SIGN_TIMEOUT).map_err(|e| failed(&e.message()))
+    })?;
+    match signed.Status().map_err(|e| failed(&e.message()))? {
+        KeyCredentialStatus::Success => {}
+        KeyCredentialStatus::UserCanceled | KeyCredentialStatus::UserPrefersPassword => {
+            return Err(PromptError::Cancelled);
+        }
+        status => return Err(failed(&format!("signing failed ({:?})", status))),
+    }
+    let signature = signed.Result().map_err(|e| failed(&e.message()))?;
+    HashAlgorithmNames::Sha256()
+        .and_then(|name| HashAlgorithmProvider::OpenAlgorithm(&name))
+        .and_then(|sha256| sha256.HashData(&signature))
+        .map_err(|e| failed(&e.message()))
+}
+
+/// Open the Hello key credential, creating it (with its own Hello prompt)
+/// the first time.
+fn credential() -> Result<KeyCredential, PromptError> {
+    let name = HSTRING::from(CREDENTIAL_NAME);
+    let supported = KeyCredentialManager::IsSupportedAsync()
+        .and_then(|op| winrt::wait(&op, LOOKUP_TIMEOUT))
+        .unwrap_or(false);
+    if !supported {
+        return Err(failed("Windows Hello keys are not available"));
+    }
+    let opened = KeyCredentialManager::OpenAsync(&name)
+        .and_then(|op| winrt::wait(&op, LOOKUP_TIMEOUT))
+        .map_err(|e| failed(&e.message()))?;
+    let retrieved = match opened.Status().map_err(|e| failed(&e.message()))? {
+        KeyCredentialStatus::NotFound => {
+            let operation = KeyCredentialManager::RequestCreateAsync(
+                &name,
+                KeyCredentialCreationOption::FailIfExists,
+            )
+            .map_err(|e| failed(&e.message()))?;
+            winrt::wait(&operation, SIGN_TIMEOUT).map_err(|e| failed(&e.message()))?
+        }
+        _ => opened,
+    };
+    match retrieved.Status().map_err(|e| failed(&e.message()))? {
+        KeyCredentialStatus::Success => retrieved.Credential().map_err(|e| failed(&e.message())),
+        KeyCredentialStatus::UserCanceled | KeyCredentialStatus::UserPrefersPassword => {
+            Err(PromptError::Cancelled)
+        }
+        status => Err(failed(&format!("no Windows Hello key ({:?})", status))),
+    }
+}
+
+fn buffer(data: &[u8]) -> Result<IBuffer, PromptError> {
+    CryptographicBuffer::CreateFromByteArray(data).map_err(|e| failed(&e.message()))
+}
+
+fn bytes(buffer: &IBuffer) -> Result<Vec<u8>, PromptError> {
+    let mut array = Array::<u8>::new();
+    CryptographicBuffer::CopyToByteArray(buffer, &mut array).map_err(|e| failed(&e.message()))?;
+    Ok(array.to_vec())
+}
+
+fn failed(message: &str) -> PromptError {
+    PromptError::Failed(format!("Hello encryption: {}", message))
+}
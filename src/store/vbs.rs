@@ -0,0 +1,104 @@
+//! Virtualization-based security / Credential Guard status, queried from
+//! the `Win32_DeviceGuard` WMI class.
+
+use windows::{
+    core::{w, BSTR, VARIANT},
+    Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CoSetProxyBlanket, CLSCTX_INPROC_SERVER,
+        COINIT_MULTITHREADED, EOAC_NONE, RPC_C_AUTHN_LEVEL_CALL, RPC_C_IMP_LEVEL_IMPERSONATE,
+    },
+    Win32::System::Rpc::{RPC_C_AUTHN_WINNT, RPC_C_AUTHZ_NONE},
+    Win32::System::Variant::VariantToInt32Array,
+    Win32::System::Wmi::{
+        IWbemClassObject, IWbemContext, IWbemLocator, WbemLocator, WBEM_FLAG_FORWARD_ONLY,
+        WBEM_FLAG_RETURN_IMMEDIATELY, WBEM_INFINITE,
+    },
+};
+
+// Win32_DeviceGuard values
+const VBS_STATUS_RUNNING: i32 = 2;
+const SERVICE_CREDENTIAL_GUARD: i32 = 1;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct VbsStatus {
+    pub(crate) vbs_running: bool,
+    pub(crate) credential_guard_running: bool,
+}
+
+/// Query the current VBS state; `None` when WMI is unavailable.
+pub(crate) fn query() -> Option<VbsStatus> {
+    unsafe { query_inner().ok() }
+}
+
+unsafe fn query_inner() -> windows::core::Result<VbsStatus> {
+    // Already-initialized apartments are fine; WMI works from either.
+    let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+
+    let locator: IWbemLocator = CoCreateInstance(&WbemLocator, None, CLSCTX_INPROC_SERVER)?;
+    let services = locator.ConnectServer(
+        &BSTR::from("ROOT\\Microsoft\\Windows\\DeviceGuard"),
+        &BSTR::new(),
+        &BSTR::new(),
+        &BSTR::new(),
+        0,
+        &BSTR::new(),
+        None::<&IWbemContext>,
+    )?;
+    CoSetProxyBlanket(
+        &services,
+        RPC_C_AUTHN_WINNT,
+        RPC_C_AUTHZ_NONE,
+        None,
+        RPC_C_AUTHN_LEVEL_CALL,
+        RPC_C_IMP_LEVEL_IMPERSONATE,
+        None,
+        EOAC_NONE,
+    )?;
+
+    let results = services.ExecQuery(
+        &BSTR::from("WQL"),
+        &BSTR::from(
+            "SELECT VirtualizationBasedSecurityStatus, SecurityServicesRunning FROM Win32_DeviceGuard",
+        ),
+        WBEM_FLAG_FORWARD_ONLY | WBEM_FLAG_RETURN_IMMEDIATELY,
+        None::<&IWbemContext>,
+    )?;
+
+    let mut objects: [Option<IWbemClassObject>; 1] = [None];
+    let mut returned = 0u32;
+    results
+        .Next(WBEM_INFINITE, &mut objects, &mut returned)
+        .ok()?;
+    let Some(object) = objects[0].take() else {
+        return Ok(VbsStatus::default());
+    };
+
+    let mut status = VARIANT::new();
+    object.Get(
+        w!("VirtualizationBasedSecurityStatus"),
+        0,
+        &mut status,
+        None,
+        None,
+    )?;
+    let vbs_running = i32::try_from(&status).is_ok_and(|s| s == VBS_STATUS_RUNNING);
+
+    let mut services_running = VARIANT::new();
+    object.Get(
+        w!("SecurityServicesRunning"),
+        0,
+        &mut services_running,
+        None,
+        None,
+    )?;
+    let mut running = [0i32; 16];
+    let mut count = 0u32;
+    let credential_guard_running = VariantToInt32Array(&services_running, &mut running, &mut count)
+        .is_ok()
+        && running[..count as usize].contains(&SERVICE_CREDENTIAL_GUARD);
+
+    Ok(VbsStatus {
+        vbs_running,
+        credential_guard_running,
+    })
+}
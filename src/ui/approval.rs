@@ -0,0 +1,155 @@
+//! Second-channel approval: before a cached secret for a sensitive key is
+//! released, push an ntfy-style notification to the user's phone and wait for
+//! them to tap "Approve" on it.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use windows::{
+    core::{h, Result, HSTRING},
+    Foundation::Uri,
+    Web::Http::{HttpClient, HttpMethod, HttpRequestMessage, HttpStringContent},
+};
+
+use crate::{
+    core::clock::now_secs, core::config, core::policy, core::winrt, store::audit, ui::watchdog,
+};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Longest a single call to the notification server may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Outcome of an approval request, as recorded in `approvals.log`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    Approved,
+    Denied,
+    TimedOut,
+    Failed,
+}
+
+impl Outcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Approved => "approved",
+            Outcome::Denied => "denied",
+            Outcome::TimedOut => "timeout",
+            Outcome::Failed => "error",
+        }
+    }
+}
+
+/// The ntfy topic from the `ApprovalUrl` policy, else `approval_url` in
+/// the config file. Like the keys, never from the environment: a caller
+/// could leave it out, or point it at a topic it answers itself.
+fn topic_url() -> Option<String> {
+    policy::policy_text("ApprovalUrl").or_else(|| config::get().approval_url.clone())
+}
+
+/// Keys listed in the `ApprovalKeys` policy, else `approval_keys` in the
+/// config file (globs such as `password:*`, see [`policy::key_matches`]),
+/// need phone approval when there is an ntfy topic to send it to.
+pub(crate) fn required(key: &str) -> bool {
+    if topic_url().is_none() {
+        return false;
+    }
+    let keys = policy::policy_list("ApprovalKeys", &config::get().approval_keys);
+    policy::key_matches(&keys, key)
+}
+
+/// Ask for approval of releasing `key` and block until it is approved,
+/// denied or `approval_timeout` seconds pass. Every request is appended to
+/// the approval audit log. Returns `true` only on approval.
+pub(crate) fn request(key: &str) -> bool {
+    let Some(url) = topic_url() else {
+        return false;
+    };
+    let timeout = config::get()
+        .approval_timeout
+        .unwrap_or(DEFAULT_TIMEOUT_SECS);
+
+    let outcome = run(url.trim_end_matches('/'), key, Duration::from_secs(timeout))
+        .unwrap_or(Outcome::Failed);
+    audit::append("approvals.log", &[key, outcome.as_str()]);
+    outcome == Outcome::Approved
+}
+
+fn run(topic_url: &str, key: &str, timeout: Duration) -> Result<Outcome> {
+    let Some(nonce) = crate::random_hex(16) else {
+        return Ok(Outcome::Failed);
+    };
+    let reply_url = format!("{}-reply", topic_url);
+    let since = now_secs();
+    let client = HttpClient::new()?;
+
+    // The notification's buttons post "approve-<nonce>" or "deny-<nonce>" to the reply topic
+    let actions = format!(
+        "http, Approve, {reply}, method=POST, body=approve-{nonce}, clear=true; \
+         http, Deny, {reply}, method=POST, body=deny-{nonce}, clear=true",
+        reply = reply_url,
+        nonce = nonce
+    );
+    let request = HttpRequestMessage::Create(
+        &HttpMethod::Post()?,
+        &Uri::CreateUri(&HSTRING::from(topic_url))?,
+    )?;
+    let headers = request.Headers()?;
+    headers.TryAppendWithoutValidation(h!("Title"), h!("SSH secret requested"))?;
+    headers.TryAppendWithoutValidation(h!("Priority"), h!("high"))?;
+    headers.TryAppendWithoutValidation(h!("Actions"), &HSTRING::from(actions))?;
+    request.SetContent(&HttpStringContent::CreateFromString(&HSTRING::from(
+        format!("Release the cached secret for {}?", key),
+    ))?)?;
+    let response = winrt::wait(&client.SendRequestAsync(&request)?, REQUEST_TIMEOUT)?;
+    if !response.IsSuccessStatusCode()? {
+        return Ok(Outcome::Failed);
+    }
+
+    let poll_uri = Uri::CreateUri(&HSTRING::from(format!(
+        "{}/json?poll=1&since={}",
+        reply_url, since
+    )))?;
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline && !watchdog::caller_gone() {
+        thread::sleep(POLL_INTERVAL);
+        let Ok(body) = winrt::wait(&client.GetStringAsync(&poll_uri)?, REQUEST_TIMEOUT) else {
+            continue;
+        };
+        if let Some(outcome) = find_reply(&body.to_string_lossy(), &nonce) {
+            return Ok(outcome);
+        }
+    }
+    Ok(Outcome::TimedOut)
+}
+
+/// Scan ntfy's newline-delimited JSON poll output for our reply.
+fn find_reply(body: &str, nonce: &str) -> Option<Outcome> {
+    body.lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|event| event["message"].as_str().map(str::to_owned))
+        .find_map(|message| match message.trim().split_once('-') {
+            Some(("approve", n)) if n == nonce => Some(Outcome::Approved),
+            Some(("deny", n)) if n == nonce => Some(Outcome::Denied),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_reply_to_this_request() {
+        let body = concat!(
+            "{\"event\":\"open\"}\n",
+            "{\"event\":\"message\",\"message\":\"Release the cached secret for id?\"}\n",
+            "{\"event\":\"message\",\"message\":\"approve-0ld\"}\n",
+            "{\"event\":\"message\",\"message\":\" deny-n0nce \\n\"}\n",
+        );
+        assert_eq!(find_reply(body, "n0nce"), Some(Outcome::Denied));
+        assert_eq!(find_reply(body, "0ld"), Some(Outcome::Approved));
+        assert_eq!(find_reply(body, "other"), None);
+        assert_eq!(find_reply("not json\n", "n0nce"), None);
+    }
+}
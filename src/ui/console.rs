@@ -0,0 +1,105 @@
+//! Asking on the invoking terminal instead of in a dialog.
+//!
+//! ssh connects our stdout to a pipe but leaves stdin and stderr on the
+//! terminal, which WSL interop presents to us as a console. Reading through
+//! `CONIN$` lets the echo be turned off for secrets.
+
+use windows::{
+    core::w,
+    Win32::Foundation::{CloseHandle, GENERIC_READ, GENERIC_WRITE, HANDLE},
+    Win32::Storage::FileSystem::{
+        CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+    },
+    Win32::System::Console::{
+        GetConsoleMode, ReadConsoleW, SetConsoleMode, WriteConsoleW, CONSOLE_MODE,
+        ENABLE_ECHO_INPUT,
+    },
+};
+
+use crate::{to_wide, PromptError};
+
+/// Longest answer read, far beyond any passphrase.
+const MAX_ANSWER_CHARS: usize = 4096;
+
+/// Show `prompt` on the terminal and read one line, without echo when
+/// `secret`.
+pub(crate) fn ask(prompt: &str, secret: bool) -> Result<String, PromptError> {
+    let input = open(w!("CONIN$"))?;
+    let output = open(w!("CONOUT$"));
+    let result = unsafe { ask_on(input, output.as_ref().ok().copied(), prompt, secret) };
+    unsafe {
+        let _ = CloseHandle(input);
+        if let Ok(output) = output {
+            let _ = CloseHandle(output);
+        }
+    }
+    result
+}
+
+fn open(name: windows::core::PCWSTR) -> Result<HANDLE, PromptError> {
+    unsafe {
+        CreateFileW(
+            name,
+            (GENERIC_READ | GENERIC_WRITE).0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAGS_AND_ATTRIBUTES(0),
+            None,
+        )
+    }
+    .map_err(|_| PromptError::Failed("no terminal to prompt on".into()))
+}
+
+unsafe fn ask_on(
+    input: HANDLE,
+    output: Option<HANDLE>,
+    prompt: &str,
+    secret: bool,
+) -> Result<String, PromptError> {
+    let write = |text: &str| {
+        if let Some(output) = output {
+            let wide = to_wide(text);
+            let _ = WriteConsoleW(output, &wide[..wide.len() - 1], None, None);
+        }
+    };
+    write(prompt);
+    if !prompt.ends_with(' ') {
+        write(" ");
+    }
+
+    let mut mode = CONSOLE_MODE::default();
+    GetConsoleMode(input, &mut mode).map_err(|e| PromptError::Failed(e.message()))?;
+    if secret {
+        let _ = SetConsoleMode(input, mode & !ENABLE_ECHO_INPUT);
+    }
+    let mut line: Vec<u16> = Vec::new();
+    let mut read_result = Ok(());
+    while line.len() < MAX_ANSWER_CHARS && !line.contains(&u16::from(b'\n')) {
+        let mut buf = [0u16; 256];
+        let mut read = 0u32;
+        read_result = ReadConsoleW(
+            input,
+            buf.as_mut_ptr() as *mut _,
+            buf.len() as u32,
+            &mut read,
+            None,
+        );
+        if read_result.is_err() || read == 0 {
+            break;
+        }
+        line.extend_from_slice(&buf[..read as usize]);
+    }
+    if secret {
+        let _ = SetConsoleMode(input, mode);
+        // The user's Enter was not echoed either
+        write("\r\n");
+    }
+    read_result.map_err(|e| PromptError::Failed(e.message()))?;
+    if line.is_empty() {
+        return Err(PromptError::Cancelled);
+    }
+
+    let text = String::from_utf16_lossy(&line);
+    Ok(text.trim_end_matches(['\r', '\n']).to_string())
+}
@@ -0,0 +1,916 @@
+use windows::{
+    core::{Error, PCWSTR, PWSTR},
+    Win32::Foundation::{
+        GlobalFree, LocalFree, BOOL, ERROR_CANCELLED, ERROR_INSUFFICIENT_BUFFER, HANDLE, HGLOBAL,
+        HLOCAL, HWND, LPARAM, S_FALSE, S_OK, WIN32_ERROR, WPARAM,
+    },
+    Win32::Graphics::Gdi::HBITMAP,
+    Win32::Security::Credentials::{
+        CredPackAuthenticationBufferW, CredUIPromptForWindowsCredentialsW,
+        CredUnPackAuthenticationBufferW, CREDUIWIN_CHECKBOX, CREDUIWIN_GENERIC,
+        CREDUIWIN_IN_CRED_ONLY, CREDUI_INFOW, CRED_PACK_GENERIC_CREDENTIALS,
+    },
+    Win32::System::DataExchange::{
+        CloseClipboard, EmptyClipboard, GetClipboardData, OpenClipboard, SetClipboardData,
+    },
+    Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE},
+    Win32::System::Ole::CF_UNICODETEXT,
+    Win32::UI::Controls::{
+        TaskDialogIndirect, TASKDIALOGCONFIG, TASKDIALOGCONFIG_0, TASKDIALOG_BUTTON,
+        TASKDIALOG_NOTIFICATIONS, TDCBF_CANCEL_BUTTON, TDCBF_OK_BUTTON, TDE_CONTENT,
+        TDF_ALLOW_DIALOG_CANCELLATION, TDF_POSITION_RELATIVE_TO_WINDOW, TDF_USE_COMMAND_LINKS,
+        TDM_SET_ELEMENT_TEXT, TDN_BUTTON_CLICKED, TD_INFORMATION_ICON, TD_SHIELD_ICON,
+        TD_WARNING_ICON,
+    },
+    Win32::UI::WindowsAndMessaging::{
+        FlashWindowEx, GetForegroundWindow, IsIconic, IsWindowVisible, MessageBoxW, SendMessageW,
+        SetForegroundWindow, FLASHWINFO, FLASHW_TIMERNOFG, FLASHW_TRAY, IDYES, MB_DEFBUTTON2,
+        MB_ICONWARNING, MB_SETFOREGROUND, MB_TOPMOST, MB_YESNO,
+    },
+};
+
+use crate::core::caller;
+use crate::core::config::{self, PassphraseDialog};
+use crate::core::prompt::MenuPrompt;
+use crate::{core::diag, core::hostkey, ui::hostbox, ui::passbox};
+use crate::{to_wide, PromptError};
+
+/// How many times to re-query CredPack's buffer size before giving up.
+const CRED_PACK_ATTEMPTS: u32 = 4;
+/// Wide chars first offered to CredUnPack for the username and password;
+/// longer ones get the size it asks for.
+const CRED_UNPACK_START: usize = 256;
+
+// Task dialog button ids for menu prompts; options start at MENU_OPTION_ID_BASE
+const MENU_PASSCODE_ID: i32 = 100;
+const MENU_OPTION_ID_BASE: i32 = 101;
+
+// Task dialog button ids for the PIN pad; digits are PIN_DIGIT_ID_BASE + digit
+const PIN_DIGIT_ID_BASE: i32 = 400;
+const PIN_BACKSPACE_ID: i32 = 410;
+const PIN_ENTER_ID: i32 = 411;
+
+// Task dialog button ids for the first-store explanation
+const STORE_ID: i32 = 300;
+const DONT_STORE_ID: i32 = 301;
+
+// Task dialog button ids for host key verification
+const HOST_YES_ID: i32 = 500;
+const HOST_NO_ID: i32 = 501;
+const HOST_REMEMBER_ID: i32 = 502;
+const HOST_FINGERPRINT_ID: i32 = 503;
+
+// Task dialog button ids for the saved-passphrase retry prompt
+const RETRY_HELLO_ID: i32 = 200;
+const RETRY_TYPE_ID: i32 = 201;
+
+/// Answer to the host key dialog.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) enum HostKeyAnswer {
+    Yes,
+    No,
+    /// Accept and pin the key so the host is not asked about again
+    Remember,
+    /// The expected fingerprint, for ssh itself to compare
+    Fingerprint(String),
+}
+
+pub(crate) enum MenuChoice {
+    Option(String),
+    Passcode,
+}
+
+/// Dialogs are parented on the JetBrains IDE that triggered them, if any,
+/// else on whatever window has the focus.
+///
+/// A minimized or hidden parent (a minimized terminal, a dismissed quake-mode
+/// Windows Terminal) would take the dialog out of sight with it, so such a
+/// window is flashed in the taskbar instead and the dialog opens on its own.
+pub(crate) fn get_foreground_hwnd() -> HWND {
+    let hwnd = match caller::jetbrains_ide() {
+        Some(ide) => ide.hwnd(),
+        None => unsafe { GetForegroundWindow() },
+    };
+    unsafe {
+        if hwnd.is_invalid() || (IsWindowVisible(hwnd).as_bool() && !IsIconic(hwnd).as_bool()) {
+            return hwnd;
+        }
+        let flash = FLASHWINFO {
+            cbSize: std::mem::size_of::<FLASHWINFO>() as u32,
+            hwnd,
+            dwFlags: FLASHW_TRAY | FLASHW_TIMERNOFG,
+            uCount: 0,
+            dwTimeout: 0,
+        };
+        let _ = FlashWindowEx(&flash);
+    }
+    HWND::default()
+}
+
+/// Window title, naming the IDE so its prompts don't look orphaned.
+pub(crate) fn caption(title: &str) -> Vec<u16> {
+    match caller::jetbrains_ide() {
+        Some(ide) => to_wide(&format!("{} - {}", title, ide.name)),
+        None => to_wide(title),
+    }
+}
+
+/// Host key verification with "Yes", "No" and, when `can_remember`, "Yes,
+/// and remember this host". When ssh accepts a fingerprint as the answer,
+/// the expected one can be pasted from the clipboard instead, leaving the
+/// comparison to ssh. Dismissing the dialog counts as "No".
+///
+/// A first-connection prompt is shown in [`hostbox`], which sets out the
+/// host, key type and fingerprint; other questions, or a window that cannot
+/// be made, get a task dialog with ssh's text. `previous` is the different
+/// key remembered for the host, which turns the question into a warning.
+pub(crate) fn prompt_host_key(
+    prompt: &str,
+    previous: Option<&str>,
+    can_remember: bool,
+    can_paste: bool,
+) -> HostKeyAnswer {
+    if let Some(key) = hostkey::parse(prompt) {
+        if let Ok(answer) = hostbox::prompt(prompt, &key, previous, can_remember, can_paste) {
+            return answer;
+        }
+    }
+    let title = caption(&config::get().captions.host_key);
+    let content = match previous {
+        Some(previous) => to_wide(&format!(
+            "{}\n\n{}",
+            hostbox::changed_warning(previous),
+            prompt
+        )),
+        None => to_wide(prompt),
+    };
+    let yes = to_wide("Yes");
+    let no = to_wide("No");
+    let remember = to_wide("Yes, and remember this host");
+    let paste = to_wide("Paste expected fingerprint");
+    let mut buttons = vec![
+        TASKDIALOG_BUTTON {
+            nButtonID: HOST_YES_ID,
+            pszButtonText: PCWSTR(yes.as_ptr()),
+        },
+        TASKDIALOG_BUTTON {
+            nButtonID: HOST_NO_ID,
+            pszButtonText: PCWSTR(no.as_ptr()),
+        },
+    ];
+    if can_remember {
+        buttons.insert(
+            1,
+            TASKDIALOG_BUTTON {
+                nButtonID: HOST_REMEMBER_ID,
+                pszButtonText: PCWSTR(remember.as_ptr()),
+            },
+        );
+    }
+    if can_paste {
+        buttons.push(TASKDIALOG_BUTTON {
+            nButtonID: HOST_FINGERPRINT_ID,
+            pszButtonText: PCWSTR(paste.as_ptr()),
+        });
+    }
+
+    let config = TASKDIALOGCONFIG {
+        cbSize: std::mem::size_of::<TASKDIALOGCONFIG>() as u32,
+        hwndParent: get_foreground_hwnd(),
+        dwFlags: TDF_ALLOW_DIALOG_CANCELLATION | TDF_POSITION_RELATIVE_TO_WINDOW,
+        pszWindowTitle: PCWSTR(title.as_ptr()),
+        Anonymous1: TASKDIALOGCONFIG_0 {
+            pszMainIcon: TD_WARNING_ICON,
+        },
+        pszContent: PCWSTR(content.as_ptr()),
+        cButtons: buttons.len() as u32,
+        pButtons: buttons.as_ptr(),
+        nDefaultButton: HOST_NO_ID,
+        ..Default::default()
+    };
+
+    let mut pressed: i32 = 0;
+    let _ = unsafe { TaskDialogIndirect(&config, Some(&mut pressed), None, None) };
+    match pressed {
+        HOST_YES_ID => HostKeyAnswer::Yes,
+        HOST_REMEMBER_ID => HostKeyAnswer::Remember,
+        HOST_FINGERPRINT_ID => pasted_fingerprint(),
+        _ => HostKeyAnswer::No,
+    }
+}
+
+/// The fingerprint on the clipboard as the answer, once it has been shown
+/// and confirmed, or "No" without one.
+pub(crate) fn pasted_fingerprint() -> HostKeyAnswer {
+    // Only the first line, and only a fingerprint, so a stray paste cannot
+    // inject other input
+    let pasted = clipboard_text().and_then(|text| {
+        let line = text.lines().next()?.trim();
+        is_fingerprint(line).then(|| line.to_string())
+    });
+    match pasted {
+        Some(fingerprint)
+            if ask_yes_no(
+                &config::get().captions.host_key,
+                &format!("Answer with the pasted fingerprint?\n\n{}", fingerprint),
+            ) =>
+        {
+            HostKeyAnswer::Fingerprint(fingerprint)
+        }
+        _ => HostKeyAnswer::No,
+    }
+}
+
+/// Whether `text` is a fingerprint as ssh prints one: `SHA256:` and 43
+/// base64 characters, or `MD5:` and 16 colon-separated hex pairs.
+fn is_fingerprint(text: &str) -> bool {
+    if let Some(hash) = text.strip_prefix("SHA256:") {
+        return hash.len() == 43
+            && hash
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/');
+    }
+    if let Some(hash) = text.strip_prefix("MD5:") {
+        let pairs: Vec<&str> = hash.split(':').collect();
+        return pairs.len() == 16
+            && pairs
+                .iter()
+                .all(|pair| pair.len() == 2 && pair.bytes().all(|b| b.is_ascii_hexdigit()));
+    }
+    false
+}
+
+/// Put `text` on the clipboard. Returns `false` if it could not be.
+pub(crate) fn set_clipboard_text(owner: HWND, text: &str) -> bool {
+    let wide = to_wide(text);
+    unsafe {
+        if OpenClipboard(owner).is_err() {
+            return false;
+        }
+        let copied = EmptyClipboard().is_ok()
+            && GlobalAlloc(GMEM_MOVEABLE, wide.len() * 2).is_ok_and(|memory| {
+                let ptr = GlobalLock(memory) as *mut u16;
+                if ptr.is_null() {
+                    let _ = GlobalFree(memory);
+                    return false;
+                }
+                std::ptr::copy_nonoverlapping(wide.as_ptr(), ptr, wide.len());
+                let _ = GlobalUnlock(memory);
+                // The clipboard owns the memory once it is set
+                let set = SetClipboardData(CF_UNICODETEXT.0 as u32, HANDLE(memory.0)).is_ok();
+                if !set {
+                    let _ = GlobalFree(memory);
+                }
+                set
+            });
+        let _ = CloseClipboard();
+        copied
+    }
+}
+
+/// Unicode text on the clipboard, if any.
+fn clipboard_text() -> Option<String> {
+    unsafe {
+        OpenClipboard(None).ok()?;
+        let text = GetClipboardData(CF_UNICODETEXT.0 as u32)
+            .ok()
+            .and_then(|handle| {
+                let memory = HGLOBAL(handle.0);
+                let ptr = GlobalLock(memory) as *const u16;
+                if ptr.is_null() {
+                    return None;
+                }
+                let text = PCWSTR(ptr).to_string().ok();
+                let _ = GlobalUnlock(memory);
+                text
+            });
+        let _ = CloseClipboard();
+        text
+    }
+}
+
+/// Warning-style yes/no box defaulting to "No".
+pub(crate) fn ask_yes_no(title: &str, text: &str) -> bool {
+    let title = caption(title);
+    let content = to_wide(text);
+    unsafe {
+        let parent = get_foreground_hwnd();
+        let result = MessageBoxW(
+            parent,
+            PCWSTR(content.as_ptr()),
+            PCWSTR(title.as_ptr()),
+            MB_YESNO | MB_ICONWARNING | MB_DEFBUTTON2 | MB_TOPMOST | MB_SETFOREGROUND,
+        );
+        result == IDYES
+    }
+}
+
+pub(crate) fn prompt_menu(menu: &MenuPrompt) -> Result<MenuChoice, PromptError> {
+    let title = caption(&config::get().captions.two_factor);
+    let instruction = to_wide("Choose how to verify your login");
+    let content = to_wide(&menu.header);
+    let labels: Vec<Vec<u16>> = menu
+        .options
+        .iter()
+        .map(|(num, label)| to_wide(&format!("{}. {}", num, label)))
+        .chain(std::iter::once(to_wide("Enter a passcode")))
+        .collect();
+
+    let mut buttons: Vec<TASKDIALOG_BUTTON> = labels[..menu.options.len()]
+        .iter()
+        .enumerate()
+        .map(|(i, label)| TASKDIALOG_BUTTON {
+            nButtonID: MENU_OPTION_ID_BASE + i as i32,
+            pszButtonText: PCWSTR(label.as_ptr()),
+        })
+        .collect();
+    if menu.accepts_passcode {
+        buttons.push(TASKDIALOG_BUTTON {
+            nButtonID: MENU_PASSCODE_ID,
+            pszButtonText: PCWSTR(labels[menu.options.len()].as_ptr()),
+        });
+    }
+
+    let config = TASKDIALOGCONFIG {
+        cbSize: std::mem::size_of::<TASKDIALOGCONFIG>() as u32,
+        hwndParent: get_foreground_hwnd(),
+        dwFlags: TDF_USE_COMMAND_LINKS
+            | TDF_ALLOW_DIALOG_CANCELLATION
+            | TDF_POSITION_RELATIVE_TO_WINDOW,
+        dwCommonButtons: TDCBF_CANCEL_BUTTON,
+        pszWindowTitle: PCWSTR(title.as_ptr()),
+        Anonymous1: TASKDIALOGCONFIG_0 {
+            pszMainIcon: TD_SHIELD_ICON,
+        },
+        pszMainInstruction: PCWSTR(instruction.as_ptr()),
+        pszContent: PCWSTR(content.as_ptr()),
+        cButtons: buttons.len() as u32,
+        pButtons: buttons.as_ptr(),
+        nDefaultButton: MENU_OPTION_ID_BASE,
+        ..Default::default()
+    };
+
+    let mut pressed: i32 = 0;
+    unsafe { TaskDialogIndirect(&config, Some(&mut pressed), None, None) }
+        .map_err(|e| PromptError::Failed(e.message()))?;
+
+    match pressed {
+        MENU_PASSCODE_ID => Ok(MenuChoice::Passcode),
+        id if id >= MENU_OPTION_ID_BASE => menu
+            .options
+            .get((id - MENU_OPTION_ID_BASE) as usize)
+            .map(|(num, _)| MenuChoice::Option(num.clone()))
+            .ok_or(PromptError::Cancelled),
+        _ => Err(PromptError::Cancelled),
+    }
+}
+
+struct PinPad {
+    pin: String,
+    display: Vec<u16>,
+}
+
+/// On-screen PIN pad for smartcard PINs: digits are clicked or tapped on
+/// buttons in a random order each time, so neither keystrokes nor click
+/// positions reveal the PIN.
+pub(crate) fn prompt_pin_pad(prompt: &str) -> Result<String, PromptError> {
+    let title = caption(&config::get().captions.pin);
+    let instruction = to_wide(prompt);
+    let mut order: Vec<u8> = (0..10).collect();
+    shuffle(&mut order).ok_or_else(|| PromptError::Failed("no random source".into()))?;
+
+    let labels: Vec<(i32, Vec<u16>)> = order
+        .iter()
+        .map(|&d| (PIN_DIGIT_ID_BASE + i32::from(d), to_wide(&d.to_string())))
+        .chain([
+            (PIN_BACKSPACE_ID, to_wide("\u{232B}")),
+            (PIN_ENTER_ID, to_wide("Enter")),
+        ])
+        .collect();
+    let buttons: Vec<TASKDIALOG_BUTTON> = labels
+        .iter()
+        .map(|(id, label)| TASKDIALOG_BUTTON {
+            nButtonID: *id,
+            pszButtonText: PCWSTR(label.as_ptr()),
+        })
+        .collect();
+
+    let mut pad = PinPad {
+        pin: String::new(),
+        display: to_wide(" "),
+    };
+    let config = TASKDIALOGCONFIG {
+        cbSize: std::mem::size_of::<TASKDIALOGCONFIG>() as u32,
+        hwndParent: get_foreground_hwnd(),
+        dwFlags: TDF_ALLOW_DIALOG_CANCELLATION | TDF_POSITION_RELATIVE_TO_WINDOW,
+        dwCommonButtons: TDCBF_CANCEL_BUTTON,
+        pszWindowTitle: PCWSTR(title.as_ptr()),
+        Anonymous1: TASKDIALOGCONFIG_0 {
+            pszMainIcon: TD_SHIELD_ICON,
+        },
+        pszMainInstruction: PCWSTR(instruction.as_ptr()),
+        pszContent: PCWSTR(pad.display.as_ptr()),
+        cButtons: buttons.len() as u32,
+        pButtons: buttons.as_ptr(),
+        nDefaultButton: PIN_ENTER_ID,
+        pfCallback: Some(pin_pad_callback),
+        lpCallbackData: &mut pad as *mut PinPad as isize,
+        ..Default::default()
+    };
+
+    let mut pressed: i32 = 0;
+    unsafe { TaskDialogIndirect(&config, Some(&mut pressed), None, None) }
+        .map_err(|e| PromptError::Failed(e.message()))?;
+    if pressed == PIN_ENTER_ID {
+        Ok(std::mem::take(&mut pad.pin))
+    } else {
+        Err(PromptError::Cancelled)
+    }
+}
+
+unsafe extern "system" fn pin_pad_callback(
+    hwnd: HWND,
+    msg: TASKDIALOG_NOTIFICATIONS,
+    wparam: WPARAM,
+    _lparam: LPARAM,
+    data: isize,
+) -> windows::core::HRESULT {
+    if msg != TDN_BUTTON_CLICKED {
+        return S_OK;
+    }
+    let pad = &mut *(data as *mut PinPad);
+    match wparam.0 as i32 {
+        id if (PIN_DIGIT_ID_BASE..PIN_DIGIT_ID_BASE + 10).contains(&id) => {
+            pad.pin.push_str(&(id - PIN_DIGIT_ID_BASE).to_string())
+        }
+        PIN_BACKSPACE_ID => {
+            pad.pin.pop();
+        }
+        // Enter and Cancel close the dialog
+        _ => return S_OK,
+    }
+    // Show one bullet per digit entered
+    let masked = "\u{25CF} ".repeat(pad.pin.len());
+    pad.display = to_wide(if masked.is_empty() { " " } else { &masked });
+    SendMessageW(
+        hwnd,
+        TDM_SET_ELEMENT_TEXT.0 as u32,
+        WPARAM(TDE_CONTENT.0 as usize),
+        LPARAM(pad.display.as_ptr() as isize),
+    );
+    S_FALSE
+}
+
+/// Fisher-Yates shuffle driven by the system RNG, of at most 256 items.
+fn shuffle(items: &mut [u8]) -> Option<()> {
+    let mut pool = Vec::new();
+    let mut next_byte = || {
+        if pool.is_empty() {
+            pool = crate::random_bytes(32)?;
+        }
+        pool.pop()
+    };
+    for i in (1..items.len()).rev() {
+        let j = uniform_below(i + 1, &mut next_byte)?;
+        items.swap(i, j);
+    }
+    Some(())
+}
+
+/// A draw from `0..bound` (`bound` at most 256) in which every value is as
+/// likely: bytes past the last whole multiple of `bound` are thrown away
+/// rather than folded onto the low values.
+fn uniform_below(bound: usize, next_byte: &mut impl FnMut() -> Option<u8>) -> Option<usize> {
+    assert!((1..=256).contains(&bound));
+    let limit = 256 - 256 % bound;
+    loop {
+        let byte = usize::from(next_byte()?);
+        if byte < limit {
+            return Some(byte % bound);
+        }
+    }
+}
+
+/// Plain information dialog with an OK button.
+pub(crate) fn show_info(title: &str, instruction: &str, text: &str) {
+    let title = caption(title);
+    let instruction = to_wide(instruction);
+    let content = to_wide(text);
+    let config = TASKDIALOGCONFIG {
+        cbSize: std::mem::size_of::<TASKDIALOGCONFIG>() as u32,
+        hwndParent: get_foreground_hwnd(),
+        dwFlags: TDF_ALLOW_DIALOG_CANCELLATION | TDF_POSITION_RELATIVE_TO_WINDOW,
+        dwCommonButtons: TDCBF_OK_BUTTON,
+        pszWindowTitle: PCWSTR(title.as_ptr()),
+        Anonymous1: TASKDIALOGCONFIG_0 {
+            pszMainIcon: TD_INFORMATION_ICON,
+        },
+        pszMainInstruction: PCWSTR(instruction.as_ptr()),
+        pszContent: PCWSTR(content.as_ptr()),
+        ..Default::default()
+    };
+    let _ = unsafe { TaskDialogIndirect(&config, None, None, None) };
+}
+
+/// One-time explanation shown the first time a key's secret would be
+/// stored: where it goes, how long it stays and how to remove it, with
+/// "Don't store" as an equal choice (and the default). Returns `true` to store.
+pub(crate) fn confirm_first_store(key_name: &str, lifetime: &str) -> bool {
+    let title = caption(&config::get().captions.passphrase);
+    let instruction = to_wide(&format!("Store the passphrase for {}?", key_name));
+    let content = to_wide(&format!(
+        "It will be kept in Windows Credential Manager as \"{prefix}:{key}\", {lifetime}. \
+         Using it later requires Windows Hello.\n\n\
+         To remove it, run: cmdkey /delete:{prefix}:{key}",
+        prefix = crate::store::credstore::CRED_PREFIX,
+        key = key_name,
+        lifetime = lifetime
+    ));
+    let store = to_wide("Store");
+    let dont_store = to_wide("Don't store");
+    let buttons = [
+        TASKDIALOG_BUTTON {
+            nButtonID: STORE_ID,
+            pszButtonText: PCWSTR(store.as_ptr()),
+        },
+        TASKDIALOG_BUTTON {
+            nButtonID: DONT_STORE_ID,
+            pszButtonText: PCWSTR(dont_store.as_ptr()),
+        },
+    ];
+
+    let config = TASKDIALOGCONFIG {
+        cbSize: std::mem::size_of::<TASKDIALOGCONFIG>() as u32,
+        hwndParent: get_foreground_hwnd(),
+        dwFlags: TDF_ALLOW_DIALOG_CANCELLATION | TDF_POSITION_RELATIVE_TO_WINDOW,
+        pszWindowTitle: PCWSTR(title.as_ptr()),
+        Anonymous1: TASKDIALOGCONFIG_0 {
+            pszMainIcon: TD_INFORMATION_ICON,
+        },
+        pszMainInstruction: PCWSTR(instruction.as_ptr()),
+        pszContent: PCWSTR(content.as_ptr()),
+        cButtons: buttons.len() as u32,
+        pButtons: buttons.as_ptr(),
+        nDefaultButton: DONT_STORE_ID,
+        ..Default::default()
+    };
+
+    let mut pressed: i32 = 0;
+    let shown = unsafe { TaskDialogIndirect(&config, Some(&mut pressed), None, None) }.is_ok();
+    shown && pressed == STORE_ID
+}
+
+/// After Windows Hello was declined for a key with a saved passphrase, offer
+/// to try Hello again rather than retyping. Returns `true` to retry and
+/// `false` to type the passphrase instead.
+pub(crate) fn offer_hello_retry(key_name: &str) -> Result<bool, PromptError> {
+    let title = caption(&config::get().captions.passphrase);
+    let instruction = to_wide("Windows Hello was not completed");
+    let content = to_wide(&format!(
+        "A passphrase for {} is saved on this PC.",
+        key_name
+    ));
+    let retry = to_wide("Use saved passphrase\nVerify with Windows Hello again");
+    let type_it = to_wide("Type the passphrase");
+    let buttons = [
+        TASKDIALOG_BUTTON {
+            nButtonID: RETRY_HELLO_ID,
+            pszButtonText: PCWSTR(retry.as_ptr()),
+        },
+        TASKDIALOG_BUTTON {
+            nButtonID: RETRY_TYPE_ID,
+            pszButtonText: PCWSTR(type_it.as_ptr()),
+        },
+    ];
+
+    let config = TASKDIALOGCONFIG {
+        cbSize: std::mem::size_of::<TASKDIALOGCONFIG>() as u32,
+        hwndParent: get_foreground_hwnd(),
+        dwFlags: TDF_USE_COMMAND_LINKS
+            | TDF_ALLOW_DIALOG_CANCELLATION
+            | TDF_POSITION_RELATIVE_TO_WINDOW,
+        dwCommonButtons: TDCBF_CANCEL_BUTTON,
+        pszWindowTitle: PCWSTR(title.as_ptr()),
+        Anonymous1: TASKDIALOGCONFIG_0 {
+            pszMainIcon: TD_SHIELD_ICON,
+        },
+        pszMainInstruction: PCWSTR(instruction.as_ptr()),
+        pszContent: PCWSTR(content.as_ptr()),
+        cButtons: buttons.len() as u32,
+        pButtons: buttons.as_ptr(),
+        nDefaultButton: RETRY_HELLO_ID,
+        ..Default::default()
+    };
+
+    let mut pressed: i32 = 0;
+    unsafe { TaskDialogIndirect(&config, Some(&mut pressed), None, None) }
+        .map_err(|e| PromptError::Failed(e.message()))?;
+    match pressed {
+        RETRY_HELLO_ID => Ok(true),
+        RETRY_TYPE_ID => Ok(false),
+        _ => Err(PromptError::Cancelled),
+    }
+}
+
+/// Show the passphrase dialog. `save` pre-sets the "Remember" checkbox;
+/// `None` hides it.
+pub(crate) fn prompt_for_password(
+    prompt: &str,
+    key_name: &str,
+    save: Option<bool>,
+) -> Result<(String, bool), PromptError> {
+    if config::get().passphrase_dialog == PassphraseDialog::Builtin {
+        match passbox::prompt(prompt, key_name, save) {
+            // The window could not be made; CredUI still can be
+            Err(PromptError::Failed(_)) => {}
+            answer => return answer,
+        }
+    }
+    prompt_with_credui(prompt, key_name, save)
+}
+
+/// Ask for a one-time code in the cut-down built-in dialog, whatever
+/// `passphrase_dialog` says, or in CredUI if that cannot be shown. There is
+/// never a "Remember" checkbox.
+pub(crate) fn prompt_for_code(prompt: &str) -> Result<String, PromptError> {
+    match passbox::prompt_code(prompt) {
+        Err(PromptError::Failed(_)) => {
+            prompt_with_credui(prompt, "one-time code", None).map(|(code, _)| code)
+        }
+        answer => answer,
+    }
+}
+
+/// Run `unpack` (CredUnPackAuthenticationBufferW on username and password
+/// buffers and their lengths) until the buffers are big enough, growing them
+/// to the lengths it asks for. Returns the password without its NUL.
+fn unpack_password(
+    mut unpack: impl FnMut(&mut [u16], &mut u32, &mut [u16], &mut u32) -> windows::core::Result<()>,
+) -> Result<Vec<u16>, PromptError> {
+    let mut username = vec![0u16; CRED_UNPACK_START];
+    let mut password = vec![0u16; CRED_UNPACK_START];
+    for _ in 0..=CRED_PACK_ATTEMPTS {
+        let mut username_len = username.len() as u32;
+        let mut password_len = password.len() as u32;
+        match unpack(
+            &mut username,
+            &mut username_len,
+            &mut password,
+            &mut password_len,
+        ) {
+            Ok(()) => {
+                password.truncate((password_len as usize).saturating_sub(1));
+                return Ok(password);
+            }
+            Err(e) if e.code() == ERROR_INSUFFICIENT_BUFFER.to_hresult() => {
+                grow(&mut username, username_len);
+                grow(&mut password, password_len);
+            }
+            Err(e) => return Err(PromptError::Failed(e.message())),
+        }
+    }
+    Err(PromptError::Failed(
+        "could not size the password buffer".into(),
+    ))
+}
+
+/// Make room for `needed` wide chars. A length that was left as it was
+/// (only the other buffer was short) keeps its buffer.
+fn grow(buffer: &mut Vec<u16>, needed: u32) {
+    let needed = needed as usize;
+    if needed > buffer.len() {
+        buffer.resize(needed, 0);
+    }
+}
+
+/// The CredUI credential dialog, with the key name as a read-only username.
+fn prompt_with_credui(
+    prompt: &str,
+    key_name: &str,
+    save: Option<bool>,
+) -> Result<(String, bool), PromptError> {
+    let message = to_wide(prompt);
+    let caption = caption(&config::get().captions.passphrase);
+    let username = to_wide(key_name);
+    let empty_pass = to_wide("");
+
+    unsafe {
+        let parent = get_foreground_hwnd();
+        // Try to bring our dialog to the foreground
+        if !parent.is_invalid() {
+            let _ = SetForegroundWindow(parent);
+        }
+
+        let ui_info = CREDUI_INFOW {
+            cbSize: std::mem::size_of::<CREDUI_INFOW>() as u32,
+            hwndParent: parent,
+            pszMessageText: PCWSTR(message.as_ptr()),
+            pszCaptionText: PCWSTR(caption.as_ptr()),
+            hbmBanner: HBITMAP::default(),
+        };
+
+        // Pack the key name as username to pre-populate the field. The size
+        // query is not trusted to be final: grow and retry while it reports
+        // ERROR_INSUFFICIENT_BUFFER.
+        let mut in_buf: Vec<u8> = Vec::new();
+        let mut in_buf_size: u32 = 0;
+        let mut attempts = 0;
+        loop {
+            let buf = (!in_buf.is_empty()).then_some(in_buf.as_mut_ptr() as *mut _);
+            match CredPackAuthenticationBufferW(
+                CRED_PACK_GENERIC_CREDENTIALS,
+                PCWSTR(username.as_ptr()),
+                PCWSTR(empty_pass.as_ptr()),
+                buf,
+                &mut in_buf_size,
+            ) {
+                Ok(()) if buf.is_some() => break,
+                Err(e) if e.code() != ERROR_INSUFFICIENT_BUFFER.to_hresult() => {
+                    diag::failure("credui", "CredPackAuthenticationBufferW", &e);
+                    return Err(PromptError::Failed(e.message()));
+                }
+                _ => {}
+            }
+            attempts += 1;
+            if attempts > CRED_PACK_ATTEMPTS || in_buf_size == 0 {
+                return Err(PromptError::Failed(
+                    "could not size the credential buffer".into(),
+                ));
+            }
+            in_buf = vec![0u8; in_buf_size as usize];
+        }
+        in_buf.truncate(in_buf_size as usize);
+
+        let mut auth_package: u32 = 0;
+        let mut out_buf: *mut std::ffi::c_void = std::ptr::null_mut();
+        let mut out_buf_size: u32 = 0;
+        let mut save_checked = BOOL::from(save.unwrap_or(false));
+
+        // CREDUIWIN_IN_CRED_ONLY makes the username field read-only
+        let mut flags = CREDUIWIN_GENERIC | CREDUIWIN_IN_CRED_ONLY;
+        if save.is_some() {
+            flags |= CREDUIWIN_CHECKBOX;
+        }
+        let result = CredUIPromptForWindowsCredentialsW(
+            Some(&ui_info),
+            0,
+            &mut auth_package,
+            Some(in_buf.as_ptr() as *const _),
+            in_buf_size,
+            &mut out_buf,
+            &mut out_buf_size,
+            Some(&mut save_checked),
+            flags,
+        );
+
+        // The prompt returns its error code directly; only Cancel is not a failure
+        if result == ERROR_CANCELLED.0 {
+            return Err(PromptError::Cancelled);
+        }
+        if result != 0 {
+            let error = Error::from(WIN32_ERROR(result));
+            diag::failure("credui", "CredUIPromptForWindowsCredentialsW", &error);
+            return Err(PromptError::Failed(error.message()));
+        }
+
+        let password = unpack_password(|username, username_len, password, password_len| {
+            CredUnPackAuthenticationBufferW(
+                CRED_PACK_GENERIC_CREDENTIALS,
+                out_buf,
+                out_buf_size,
+                PWSTR(username.as_mut_ptr()),
+                username_len,
+                PWSTR::null(),
+                None,
+                PWSTR(password.as_mut_ptr()),
+                password_len,
+            )
+        });
+
+        let _ = LocalFree(HLOCAL(out_buf));
+
+        Ok((String::from_utf16_lossy(&password?), save_checked.as_bool()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Behaves like CredUnPackAuthenticationBufferW holding `secret`:
+    /// short buffers get ERROR_INSUFFICIENT_BUFFER and the sizes needed.
+    fn unpacker<'a>(
+        secret: &str,
+        calls: &'a mut u32,
+    ) -> impl FnMut(&mut [u16], &mut u32, &mut [u16], &mut u32) -> windows::core::Result<()> + 'a
+    {
+        let user: Vec<u16> = "id_ed25519\0".encode_utf16().collect();
+        let pass: Vec<u16> = secret.encode_utf16().chain([0]).collect();
+        move |username, username_len, password, password_len| {
+            *calls += 1;
+            if (*username_len as usize) < user.len() || (*password_len as usize) < pass.len() {
+                *username_len = user.len() as u32;
+                *password_len = pass.len() as u32;
+                return Err(Error::from(ERROR_INSUFFICIENT_BUFFER));
+            }
+            username[..user.len()].copy_from_slice(&user);
+            password[..pass.len()].copy_from_slice(&pass);
+            *username_len = user.len() as u32;
+            *password_len = pass.len() as u32;
+            Ok(())
+        }
+    }
+
+    fn unpacked(secret: &str) -> (String, u32) {
+        let mut calls = 0;
+        let password = unpack_password(unpacker(secret, &mut calls)).ok().unwrap();
+        (String::from_utf16(&password).unwrap(), calls)
+    }
+
+    #[test]
+    fn short_passphrases_unpack_at_once() {
+        assert_eq!(unpacked("hunter2"), ("hunter2".to_string(), 1));
+        assert_eq!(unpacked(""), (String::new(), 1));
+        let exact = "x".repeat(CRED_UNPACK_START - 1);
+        assert_eq!(unpacked(&exact), (exact, 1));
+    }
+
+    #[test]
+    fn long_passphrases_are_not_truncated() {
+        let diceware = ["correct", "horse", "battery", "staple"]
+            .join(" ")
+            .repeat(20);
+        assert!(diceware.len() > 256);
+        assert_eq!(unpacked(&diceware), (diceware, 2));
+        let exact = "x".repeat(CRED_UNPACK_START);
+        assert_eq!(unpacked(&exact), (exact, 2));
+        // Counted in UTF-16 units, not bytes or chars
+        let emoji = "🔑".repeat(200);
+        assert_eq!(unpacked(&emoji), (emoji, 2));
+    }
+
+    #[test]
+    fn only_fingerprints_are_pasted() {
+        assert!(is_fingerprint(
+            "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s"
+        ));
+        assert!(is_fingerprint(
+            "MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48"
+        ));
+        // Cut short, one too many, padded, not base64, not hex, a pair short,
+        // no algorithm
+        assert!(!is_fingerprint(
+            "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2"
+        ));
+        assert!(!is_fingerprint(
+            "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2sx"
+        ));
+        assert!(!is_fingerprint(
+            "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2="
+        ));
+        assert!(!is_fingerprint(
+            "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD-s"
+        ));
+        assert!(!is_fingerprint(
+            "MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:4g"
+        ));
+        assert!(!is_fingerprint(
+            "MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6"
+        ));
+        assert!(!is_fingerprint(
+            "uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s"
+        ));
+        assert!(!is_fingerprint("yes"));
+    }
+
+    #[test]
+    fn draws_are_uniform() {
+        // Every byte once: each value of 0..10 comes out 25 times, and the
+        // six bytes past 250 are thrown away
+        let mut bytes = 0..=255u8;
+        let mut counts = [0; 10];
+        while let Some(value) = uniform_below(10, &mut || bytes.next()) {
+            counts[value] += 1;
+        }
+        assert_eq!(counts, [25; 10]);
+        assert_eq!(uniform_below(10, &mut || None), None);
+        let mut bytes = [250, 251, 7].into_iter();
+        assert_eq!(uniform_below(10, &mut || bytes.next()), Some(7));
+        assert_eq!(uniform_below(256, &mut || Some(255)), Some(255));
+    }
+
+    #[test]
+    fn other_failures_are_reported() {
+        let result = unpack_password(|_, _, _, _| Err(Error::from(ERROR_CANCELLED)));
+        assert!(matches!(result, Err(PromptError::Failed(_))));
+        // One that is never satisfied is given up on
+        let mut calls = 0;
+        let result = unpack_password(|_, _, _, len| {
+            calls += 1;
+            *len += 1;
+            Err(Error::from(ERROR_INSUFFICIENT_BUFFER))
+        });
+        assert!(matches!(result, Err(PromptError::Failed(_))));
+        assert_eq!(calls, CRED_PACK_ATTEMPTS + 1);
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use windows::{
+    core::{Result, HSTRING},
+    Foundation::IAsyncOperation,
+    Security::Credentials::UI::{
+        UserConsentVerificationResult, UserConsentVerifier, UserConsentVerifierAvailability,
+    },
+    Win32::System::WinRT::IUserConsentVerifierInterop,
+};
+
+use crate::ui::dialog::get_foreground_hwnd;
+use crate::{core::clock, core::diag, core::timing, core::winrt};
+
+/// How long a Hello availability check is reused, sparing the long-running
+/// servers a WinRT round trip on every prompt.
+const AVAILABILITY_TTL_SECS: u64 = 60;
+
+/// The availability check normally answers at once.
+const AVAILABILITY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest a Hello prompt may stay up before it is taken down.
+const VERIFICATION_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Whether Windows Hello is set up and usable on this machine.
+pub(crate) fn is_available() -> bool {
+    static CHECKED: Mutex<Option<(u64, bool)>> = Mutex::new(None);
+    let now = clock::now_secs();
+    let mut checked = CHECKED.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((at, available)) = *checked {
+        if clock::within(at, AVAILABILITY_TTL_SECS, now) {
+            return available;
+        }
+    }
+    let availability = availability();
+    match &availability {
+        Ok(availability) => diag::note("hello", || format!("availability: {:?}", availability)),
+        Err(e) => diag::failure("hello", "CheckAvailabilityAsync", e),
+    }
+    let available = availability.is_ok_and(|a| a == UserConsentVerifierAvailability::Available);
+    *checked = Some((now, available));
+    available
+}
+
+/// What Windows Hello says about itself right now, uncached, for `doctor`.
+pub(crate) fn availability() -> Result<UserConsentVerifierAvailability> {
+    UserConsentVerifier::CheckAvailabilityAsync()
+        .and_then(|op| winrt::wait(&op, AVAILABILITY_TIMEOUT))
+}
+
+/// Ask for Windows Hello before unlocking the secret cached for `key`.
+pub(crate) fn verify_with_hello(key: &str) -> bool {
+    verify_with_hello_message(&format!("Unlock SSH key: {}", key))
+}
+
+/// Ask for Windows Hello with `message` in the dialog, for anything other
+/// than unlocking a key.
+pub(crate) fn verify_with_hello_message(message: &str) -> bool {
+    timing::time("hello", || unsafe {
+        verify_with_hello_inner(message).unwrap_or_else(|e| {
+            diag::failure("hello", "RequestVerificationForWindowAsync", &e);
+            false
+        })
+    })
+}
+
+unsafe fn verify_with_hello_inner(message: &str) -> Result<bool> {
+    if !is_available() {
+        return Ok(false);
+    }
+
+    let hwnd = get_foreground_hwnd();
+    let interop = windows::core::factory::<UserConsentVerifier, IUserConsentVerifierInterop>()?;
+    let message = HSTRING::from(message);
+    let operation = interop
+        .RequestVerificationForWindowAsync::<_, IAsyncOperation<UserConsentVerificationResult>>(
+            hwnd, &message,
+        )?;
+    // Taken down when ignored for too long or when ssh has gone away
+    let result = winrt::wait(&operation, VERIFICATION_TIMEOUT)?;
+    diag::note("hello", || format!("verification: {:?}", result));
+
+    Ok(result == UserConsentVerificationResult::Verified)
+}
@@ -0,0 +1,400 @@
+//! The host key dialog for first connections.
+//!
+//! ssh's own text buries the fingerprint mid-sentence in a proportional
+//! font, which makes comparing it with a published one error-prone. This
+//! window sets out the host, key type and fingerprint on their own lines,
+//! the fingerprint in a monospace box that can be selected or copied with
+//! one click, and with `host_key_randomart = true` (or `VisualHostKey yes`
+//! in ssh) the key's randomart. Whatever else ssh said, such as other names
+//! the key is known by, is kept below. Like [`passbox`](crate::ui::passbox) it
+//! is a plain Win32 window run modally on the calling thread.
+
+use std::cell::Cell;
+
+use windows::{
+    core::{w, PCWSTR},
+    Win32::Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM},
+    Win32::Graphics::Gdi::{
+        CreateFontIndirectW, DeleteObject, COLOR_BTNFACE, FF_MODERN, FIXED_PITCH, HBRUSH, HFONT,
+        LOGFONTW,
+    },
+    Win32::System::LibraryLoader::GetModuleHandleW,
+    Win32::UI::HiDpi::{AdjustWindowRectExForDpi, GetDpiForSystem, GetDpiForWindow},
+    Win32::UI::Input::KeyboardAndMouse::SetFocus,
+    Win32::UI::WindowsAndMessaging::{
+        CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetDlgItem, GetMessageW,
+        GetWindowTextLengthW, GetWindowTextW, IsDialogMessageW, LoadCursorW, RegisterClassExW,
+        SendMessageW, SetForegroundWindow, SetWindowTextW, ShowWindow, TranslateMessage,
+        BS_DEFPUSHBUTTON, BS_PUSHBUTTON, ES_AUTOHSCROLL, ES_READONLY, HMENU, IDCANCEL, IDC_ARROW,
+        IDOK, MSG, SW_SHOW, WINDOW_EX_STYLE, WINDOW_STYLE, WM_CLOSE, WM_COMMAND, WM_SETFONT,
+        WNDCLASSEXW, WS_CAPTION, WS_CHILD, WS_EX_CLIENTEDGE, WS_EX_CONTROLPARENT,
+        WS_EX_DLGMODALFRAME, WS_POPUP, WS_SYSMENU, WS_TABSTOP, WS_VISIBLE,
+    },
+};
+
+use crate::core::hostkey::{self, HostKey};
+use crate::ui::dialog::{
+    caption, get_foreground_hwnd, pasted_fingerprint, set_clipboard_text, HostKeyAnswer,
+};
+use crate::ui::passbox::{centre, message_font, text_height};
+use crate::{core::config, to_wide, PromptError};
+
+const CLASS_NAME: PCWSTR = w!("WslSshAskpassHostKey");
+
+// Control ids; Esc (IDCANCEL) and a bare Enter (IDOK) both answer "No"
+const FINGERPRINT_ID: i32 = 700;
+const COPY_ID: i32 = 701;
+const PASTE_ID: i32 = 702;
+const REMEMBER_ID: i32 = 703;
+const YES_ID: i32 = 704;
+const NO_ID: i32 = 705;
+
+/// winuser.h; not worth pulling in the SystemServices feature for
+const SS_NOPREFIX: u32 = 0x0080;
+
+/// Point size of the monospace font.
+const MONO_POINTS: i32 = 10;
+
+// Layout at 96 DPI
+const CLIENT_WIDTH: i32 = 460;
+const MARGIN: i32 = 12;
+const GAP: i32 = 8;
+const ROW_HEIGHT: i32 = 24;
+const LABEL_WIDTH: i32 = 80;
+const BUTTON_WIDTH: i32 = 84;
+const WIDE_BUTTON_WIDTH: i32 = 170;
+
+thread_local! {
+    /// The button that closed the open dialog.
+    static PRESSED: Cell<Option<i32>> = const { Cell::new(None) };
+}
+
+/// Why a host offering a different key than the one remembered, `previous`,
+/// may be under attack.
+pub(crate) fn changed_warning(previous: &str) -> String {
+    format!(
+        "WARNING: THIS IS NOT THE KEY YOU ACCEPTED FOR THIS HOST BEFORE ({}). Someone could \
+         be intercepting the connection, or the host was reinstalled. Do not accept the new key \
+         unless its fingerprint was confirmed through another channel.",
+        previous
+    )
+}
+
+/// Ask whether to accept `key`, parsed from `prompt`. Fails only when the
+/// window cannot be created, so the caller can fall back to a task dialog.
+pub(crate) fn prompt(
+    prompt: &str,
+    key: &HostKey,
+    previous: Option<&str>,
+    can_remember: bool,
+    can_paste: bool,
+) -> Result<HostKeyAnswer, PromptError> {
+    let art = hostkey::shown_randomart(prompt).or_else(|| {
+        config::get()
+            .host_key_randomart
+            .then(|| hostkey::randomart(key))
+            .flatten()
+    });
+    let remarks = hostkey::remarks(prompt).join("\n");
+    let host = match key.port {
+        Some(port) => format!("{} (port {})", key.host, port),
+        None => key.host.clone(),
+    };
+
+    unsafe {
+        let instance = GetModuleHandleW(None).map_err(|e| PromptError::Failed(e.message()))?;
+        let class = WNDCLASSEXW {
+            cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+            lpfnWndProc: Some(window_proc),
+            hInstance: instance.into(),
+            hCursor: LoadCursorW(None, IDC_ARROW).unwrap_or_default(),
+            hbrBackground: HBRUSH((COLOR_BTNFACE.0 + 1) as usize as *mut _),
+            lpszClassName: CLASS_NAME,
+            ..Default::default()
+        };
+        // Already registered by an earlier prompt in this process is fine
+        RegisterClassExW(&class);
+
+        let parent = get_foreground_hwnd();
+        let dpi = if parent.is_invalid() {
+            GetDpiForSystem()
+        } else {
+            GetDpiForWindow(parent)
+        };
+        let scale = |value: i32| value * dpi as i32 / 96;
+        let font = message_font(dpi);
+        let mono = monospace_font(dpi);
+
+        let intro = match previous {
+            Some(previous) => changed_warning(previous),
+            None => format!(
+                "This is the first connection to {}. Check that the fingerprint matches one \
+                 you trust, such as the one the server's administrator published, before \
+                 accepting it.",
+                host
+            ),
+        };
+        let question = "Accept this key and continue connecting?";
+
+        // Lay the controls out top to bottom, measuring the texts
+        let width = scale(CLIENT_WIDTH);
+        let margin = scale(MARGIN);
+        let gap = scale(GAP);
+        let row = scale(ROW_HEIGHT);
+        let label_width = scale(LABEL_WIDTH);
+        let inner = width - 2 * margin;
+        let line = text_height(font, "Ag", inner);
+        let intro_height = text_height(font, &intro, inner);
+        let mut y = margin;
+        let intro_y = y;
+        y += intro_height + gap;
+        let host_y = y;
+        y += line + gap / 2;
+        let type_y = y;
+        y += line + gap;
+        let fingerprint_y = y;
+        y += row + gap;
+        let art_height = art.as_ref().map_or(0, |art| text_height(mono, art, inner));
+        let art_y = y;
+        if art.is_some() {
+            y += art_height + gap;
+        }
+        let remarks_height = text_height(font, &remarks, inner);
+        let remarks_y = y;
+        if !remarks.is_empty() {
+            y += remarks_height + gap;
+        }
+        let question_y = y;
+        y += line + gap;
+        let tools_y = y;
+        y += row + gap;
+        let buttons_y = y;
+        let height = y + row + margin;
+
+        let style = WS_POPUP | WS_CAPTION | WS_SYSMENU;
+        let ex_style = WS_EX_DLGMODALFRAME | WS_EX_CONTROLPARENT;
+        let mut frame = RECT {
+            left: 0,
+            top: 0,
+            right: width,
+            bottom: height,
+        };
+        let _ = AdjustWindowRectExForDpi(&mut frame, style, false, ex_style, dpi);
+        let (outer_width, outer_height) = (frame.right - frame.left, frame.bottom - frame.top);
+        let (x, top) = centre(parent, outer_width, outer_height);
+
+        let title = caption(&config::get().captions.host_key);
+        let release_fonts = || {
+            let _ = DeleteObject(font);
+            let _ = DeleteObject(mono);
+        };
+        let hwnd = CreateWindowExW(
+            ex_style,
+            CLASS_NAME,
+            PCWSTR(title.as_ptr()),
+            style,
+            x,
+            top,
+            outer_width,
+            outer_height,
+            parent,
+            None,
+            instance,
+            None,
+        )
+        .map_err(|e| {
+            release_fonts();
+            PromptError::Failed(e.message())
+        })?;
+
+        // `bounds` is left, top, width and height in client pixels
+        let control =
+            |class: PCWSTR, text: &str, style: u32, ex_style, bounds, id: i32, font: HFONT| {
+                let (left, top, width, height) = bounds;
+                let text = to_wide(text);
+                let child = CreateWindowExW(
+                    ex_style,
+                    class,
+                    PCWSTR(text.as_ptr()),
+                    WS_CHILD | WS_VISIBLE | WINDOW_STYLE(style),
+                    left,
+                    top,
+                    width,
+                    height,
+                    hwnd,
+                    HMENU(id as isize as *mut _),
+                    instance,
+                    None,
+                )
+                .unwrap_or_default();
+                SendMessageW(child, WM_SETFONT, WPARAM(font.0 as usize), LPARAM(1));
+                child
+            };
+        let none = WINDOW_EX_STYLE(0);
+        let text = |text: &str, bounds, font| {
+            control(w!("STATIC"), text, SS_NOPREFIX, none, bounds, -1, font);
+        };
+        let value_x = margin + label_width;
+        let value_width = inner - label_width;
+        text(&intro, (margin, intro_y, inner, intro_height), font);
+        text("Host:", (margin, host_y, label_width, line), font);
+        text(&host, (value_x, host_y, value_width, line), font);
+        text("Key type:", (margin, type_y, label_width, line), font);
+        let key_type = key.key_type.as_deref().unwrap_or("not shown by ssh");
+        text(key_type, (value_x, type_y, value_width, line), font);
+        // Centre the label on the box beside it
+        text(
+            "Fingerprint:",
+            (margin, fingerprint_y + (row - line) / 2, label_width, line),
+            font,
+        );
+        control(
+            w!("EDIT"),
+            &key.fingerprint,
+            WS_TABSTOP.0 | (ES_READONLY | ES_AUTOHSCROLL) as u32,
+            WS_EX_CLIENTEDGE,
+            (value_x, fingerprint_y, value_width, row),
+            FINGERPRINT_ID,
+            mono,
+        );
+        if let Some(art) = &art {
+            text(art, (value_x, art_y, value_width, art_height), mono);
+        }
+        if !remarks.is_empty() {
+            text(&remarks, (margin, remarks_y, inner, remarks_height), font);
+        }
+        text(question, (margin, question_y, inner, line), font);
+
+        let button_width = scale(BUTTON_WIDTH);
+        let wide_width = scale(WIDE_BUTTON_WIDTH);
+        let button = |label: &str, bounds, id: i32, style: i32| {
+            control(
+                w!("BUTTON"),
+                label,
+                WS_TABSTOP.0 | style as u32,
+                none,
+                bounds,
+                id,
+                font,
+            )
+        };
+        button(
+            "Copy fingerprint",
+            (margin, tools_y, wide_width, row),
+            COPY_ID,
+            BS_PUSHBUTTON,
+        );
+        if can_paste {
+            button(
+                "Paste expected fingerprint",
+                (margin + wide_width + gap, tools_y, wide_width, row),
+                PASTE_ID,
+                BS_PUSHBUTTON,
+            );
+        }
+        // Right to left: No (the default), Yes, then Yes and remember
+        let mut right = width - margin - button_width;
+        let no = button(
+            "No",
+            (right, buttons_y, button_width, row),
+            NO_ID,
+            BS_DEFPUSHBUTTON,
+        );
+        right -= gap + button_width;
+        button(
+            "Yes",
+            (right, buttons_y, button_width, row),
+            YES_ID,
+            BS_PUSHBUTTON,
+        );
+        if can_remember {
+            right -= gap + wide_width;
+            button(
+                "Yes, and remember this host",
+                (right, buttons_y, wide_width, row),
+                REMEMBER_ID,
+                BS_PUSHBUTTON,
+            );
+        }
+
+        // Try to bring our dialog to the foreground
+        if !parent.is_invalid() {
+            let _ = SetForegroundWindow(parent);
+        }
+        let _ = ShowWindow(hwnd, SW_SHOW);
+        let _ = SetForegroundWindow(hwnd);
+        let _ = SetFocus(no);
+
+        PRESSED.set(None);
+        let mut msg = MSG::default();
+        while PRESSED.get().is_none() && GetMessageW(&mut msg, None, 0, 0).as_bool() {
+            if !IsDialogMessageW(hwnd, &msg).as_bool() {
+                let _ = TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+        }
+        let pressed = PRESSED.take();
+
+        let _ = DestroyWindow(hwnd);
+        release_fonts();
+
+        Ok(match pressed {
+            Some(YES_ID) => HostKeyAnswer::Yes,
+            Some(REMEMBER_ID) => HostKeyAnswer::Remember,
+            Some(PASTE_ID) => pasted_fingerprint(),
+            _ => HostKeyAnswer::No,
+        })
+    }
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_COMMAND => match (wparam.0 & 0xFFFF) as i32 {
+            COPY_ID => copy_fingerprint(hwnd),
+            id @ (YES_ID | REMEMBER_ID | NO_ID | PASTE_ID) => PRESSED.set(Some(id)),
+            id if id == IDOK.0 || id == IDCANCEL.0 => PRESSED.set(Some(NO_ID)),
+            _ => {}
+        },
+        // The title bar's close button is "No"
+        WM_CLOSE => PRESSED.set(Some(NO_ID)),
+        _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+    LRESULT(0)
+}
+
+/// Copy the fingerprint box to the clipboard, saying so on the button.
+fn copy_fingerprint(hwnd: HWND) {
+    unsafe {
+        let (Ok(edit), Ok(copy)) = (GetDlgItem(hwnd, FINGERPRINT_ID), GetDlgItem(hwnd, COPY_ID))
+        else {
+            return;
+        };
+        let mut text = vec![0u16; GetWindowTextLengthW(edit) as usize + 1];
+        let len = GetWindowTextW(edit, &mut text) as usize;
+        let fingerprint = String::from_utf16_lossy(&text[..len]);
+        let label = if set_clipboard_text(hwnd, &fingerprint) {
+            w!("Copied")
+        } else {
+            w!("Could not copy")
+        };
+        let _ = SetWindowTextW(copy, label);
+    }
+}
+
+/// Consolas (or the system's fixed-pitch fallback) at `dpi`.
+fn monospace_font(dpi: u32) -> HFONT {
+    let mut font = LOGFONTW {
+        lfHeight: -(MONO_POINTS * dpi as i32 / 72),
+        lfPitchAndFamily: FIXED_PITCH.0 | FF_MODERN.0,
+        ..Default::default()
+    };
+    for (slot, unit) in font.lfFaceName.iter_mut().zip("Consolas".encode_utf16()) {
+        *slot = unit;
+    }
+    unsafe { CreateFontIndirectW(&font) }
+}
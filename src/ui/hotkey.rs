@@ -0,0 +1,136 @@
+//! Global hotkey for the long-running servers that brings a pending prompt
+//! back to the front when focus juggling has buried it.
+//!
+//! The key comes from `hotkey` in the config file, e.g. `hotkey = "Ctrl+Alt+P"`.
+//! Any askpass dialog counts, whether shown by the server itself or by a
+//! one-shot invocation started from ssh.
+
+use std::env;
+use std::thread;
+
+use windows::{
+    Win32::Foundation::HWND,
+    Win32::UI::Input::KeyboardAndMouse::{
+        RegisterHotKey, HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT, MOD_WIN,
+    },
+    Win32::UI::WindowsAndMessaging::{
+        GetMessageW, IsIconic, SetForegroundWindow, ShowWindow, MSG, SW_RESTORE, WM_HOTKEY,
+    },
+};
+
+use crate::{core::caller, core::config};
+
+const HOTKEY_ID: i32 = 1;
+
+/// Start listening for the configured hotkey on a background thread.
+/// Returns an error when the setting is invalid or the key is taken.
+pub fn spawn() -> Result<(), String> {
+    let Some(spec) = config::get().hotkey.as_deref() else {
+        return Ok(());
+    };
+    let (modifiers, vk) = parse(spec).ok_or_else(|| format!("invalid hotkey '{}'", spec))?;
+    let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+    let spec = spec.to_string();
+    thread::spawn(move || unsafe {
+        // The hotkey belongs to this thread, whose queue receives WM_HOTKEY
+        if RegisterHotKey(None, HOTKEY_ID, modifiers | MOD_NOREPEAT, vk).is_err() {
+            let _ = ready_tx.send(Err(format!("hotkey {} is already in use", spec)));
+            return;
+        }
+        let _ = ready_tx.send(Ok(()));
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, None, 0, 0).as_bool() {
+            if msg.message == WM_HOTKEY {
+                raise_pending_prompt();
+            }
+        }
+    });
+    ready_rx
+        .recv()
+        .map_err(|_| "hotkey thread failed".to_string())?
+}
+
+/// Restore and focus the first visible window of any askpass process.
+fn raise_pending_prompt() {
+    let Some(exe) = env::current_exe().ok().and_then(|path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+    }) else {
+        return;
+    };
+    let Some(hwnd) = caller::pids_of(&exe)
+        .into_iter()
+        .find_map(caller::main_window)
+    else {
+        return;
+    };
+    raise(hwnd);
+}
+
+fn raise(hwnd: HWND) {
+    unsafe {
+        if IsIconic(hwnd).as_bool() {
+            let _ = ShowWindow(hwnd, SW_RESTORE);
+        }
+        let _ = SetForegroundWindow(hwnd);
+    }
+}
+
+/// `Ctrl+Alt+P`, `win+shift+F9` and the like, into modifiers and a virtual key.
+pub(crate) fn parse(spec: &str) -> Option<(HOT_KEY_MODIFIERS, u32)> {
+    let mut modifiers = HOT_KEY_MODIFIERS(0);
+    let mut key = None;
+    for part in spec.split('+').map(str::trim) {
+        match part.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => modifiers |= MOD_CONTROL,
+            "alt" => modifiers |= MOD_ALT,
+            "shift" => modifiers |= MOD_SHIFT,
+            "win" | "super" => modifiers |= MOD_WIN,
+            name if key.is_none() => key = Some(virtual_key(name)?),
+            _ => return None,
+        }
+    }
+    // A bare key would swallow ordinary typing
+    if modifiers.0 == 0 {
+        return None;
+    }
+    Some((modifiers, key?))
+}
+
+fn virtual_key(name: &str) -> Option<u32> {
+    let upper = name.to_ascii_uppercase();
+    let mut chars = upper.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphanumeric() => Some(c as u32),
+        (Some('F'), Some(_)) => {
+            let n: u32 = upper[1..].parse().ok()?;
+            // VK_F1 is 0x70, through VK_F24
+            (1..=24).contains(&n).then_some(0x6F + n)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hotkeys() {
+        assert_eq!(
+            parse("Ctrl+Alt+P"),
+            Some((MOD_CONTROL | MOD_ALT, u32::from(b'P')))
+        );
+        assert_eq!(parse("win + shift + f9"), Some((MOD_WIN | MOD_SHIFT, 0x78)));
+        assert_eq!(parse("ctrl+1"), Some((MOD_CONTROL, u32::from(b'1'))));
+    }
+
+    #[test]
+    fn rejects_bad_hotkeys() {
+        assert_eq!(parse("P"), None);
+        assert_eq!(parse("Ctrl+Alt"), None);
+        assert_eq!(parse("Ctrl+P+Q"), None);
+        assert_eq!(parse("Ctrl+F25"), None);
+        assert_eq!(parse("Ctrl+Esc"), None);
+    }
+}
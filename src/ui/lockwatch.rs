@@ -0,0 +1,110 @@
+//! Ending the Windows Hello grace window when the workstation locks.
+//!
+//! A verification should not outlive the lock screen: whoever unlocks the
+//! machine next has proved nothing to Hello. The long-running servers watch
+//! for session lock notifications and record the time of each lock in
+//! Credential Manager, which every process checks against the time of the
+//! last verification. A one-shot invocation cannot see locks that happened
+//! while nothing was watching, but it never trusts the window while the
+//! session is locked at that moment.
+
+use std::thread;
+
+use windows::{
+    core::{w, PCWSTR, PWSTR},
+    Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+    Win32::System::LibraryLoader::GetModuleHandleW,
+    Win32::System::RemoteDesktop::{
+        WTSFreeMemory, WTSQuerySessionInformationW, WTSRegisterSessionNotification,
+        WTSSessionInfoEx, NOTIFY_FOR_THIS_SESSION, WTSINFOEXW, WTS_CURRENT_SERVER_HANDLE,
+        WTS_CURRENT_SESSION, WTS_SESSIONSTATE_LOCK,
+    },
+    Win32::UI::WindowsAndMessaging::{
+        CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, RegisterClassExW,
+        HWND_MESSAGE, MSG, WINDOW_EX_STYLE, WINDOW_STYLE, WM_WTSSESSION_CHANGE, WNDCLASSEXW,
+        WTS_SESSION_LOCK,
+    },
+};
+
+use crate::store::credstore;
+
+const CLASS_NAME: PCWSTR = w!("WslSshAskpassLockWatch");
+
+/// Record session locks from a background thread for as long as the
+/// process runs. Does nothing if the notifications cannot be had.
+pub fn spawn() {
+    thread::spawn(|| unsafe {
+        let Ok(instance) = GetModuleHandleW(None) else {
+            return;
+        };
+        let class = WNDCLASSEXW {
+            cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+            lpfnWndProc: Some(window_proc),
+            hInstance: instance.into(),
+            lpszClassName: CLASS_NAME,
+            ..Default::default()
+        };
+        RegisterClassExW(&class);
+        // Message-only: never shown, but gets session notifications
+        let Ok(hwnd) = CreateWindowExW(
+            WINDOW_EX_STYLE(0),
+            CLASS_NAME,
+            PCWSTR::null(),
+            WINDOW_STYLE(0),
+            0,
+            0,
+            0,
+            0,
+            HWND_MESSAGE,
+            None,
+            instance,
+            None,
+        ) else {
+            return;
+        };
+        if WTSRegisterSessionNotification(hwnd, NOTIFY_FOR_THIS_SESSION).is_err() {
+            return;
+        }
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, None, 0, 0).as_bool() {
+            DispatchMessageW(&msg);
+        }
+    });
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if msg == WM_WTSSESSION_CHANGE && wparam.0 as u32 == WTS_SESSION_LOCK {
+        credstore::record_session_lock();
+    }
+    DefWindowProcW(hwnd, msg, wparam, lparam)
+}
+
+/// Whether this desktop session is locked right now. A session whose state
+/// cannot be read counts as unlocked.
+pub(crate) fn is_locked() -> bool {
+    unsafe {
+        let mut buffer = PWSTR::null();
+        let mut len = 0u32;
+        if WTSQuerySessionInformationW(
+            WTS_CURRENT_SERVER_HANDLE,
+            WTS_CURRENT_SESSION,
+            WTSSessionInfoEx,
+            &mut buffer,
+            &mut len,
+        )
+        .is_err()
+        {
+            return false;
+        }
+        let info = &*(buffer.0 as *const WTSINFOEXW);
+        let locked = info.Level == 1
+            && info.Data.WTSInfoExLevel1.SessionFlags == WTS_SESSIONSTATE_LOCK as i32;
+        WTSFreeMemory(buffer.0 as *mut _);
+        locked
+    }
+}
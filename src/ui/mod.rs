@@ -0,0 +1,15 @@
+//! What the user sees and answers: the dialogs, terminal prompts and
+//! notifications, Windows Hello verification, phone approval, and the
+//! window and session plumbing behind them (hotkey, lock watch, and the
+//! watchdog that takes a prompt down once nobody is waiting for it).
+
+pub(crate) mod approval;
+pub(crate) mod console;
+pub(crate) mod dialog;
+pub(crate) mod hello;
+pub(crate) mod hostbox;
+pub mod hotkey;
+pub mod lockwatch;
+pub(crate) mod passbox;
+pub(crate) mod toast;
+pub mod watchdog;
@@ -0,0 +1,449 @@
+//! The built-in passphrase dialog, used instead of the CredUI prompt unless
+//! `passphrase_dialog = "credui"` is configured.
+//!
+//! CredUI insists on a username field (shown read-only with the key name),
+//! cannot reveal what was typed and says nothing about Caps Lock. This is a
+//! plain Win32 window with a masked box, a "Show" toggle, a Caps Lock
+//! warning, the key name and the optional "Remember" checkbox, run modally
+//! on the calling thread. One-time codes get the same window cut down to
+//! the prompt and one plain field.
+
+use std::cell::Cell;
+
+use windows::{
+    core::{w, PCWSTR},
+    Win32::Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM},
+    Win32::Graphics::Gdi::{
+        CreateFontIndirectW, DeleteObject, DrawTextW, GetDC, InvalidateRect, ReleaseDC,
+        SelectObject, COLOR_BTNFACE, DT_CALCRECT, DT_NOPREFIX, DT_WORDBREAK, HBRUSH, HFONT,
+    },
+    Win32::System::LibraryLoader::GetModuleHandleW,
+    Win32::UI::Controls::{BST_CHECKED, EM_SETPASSWORDCHAR},
+    Win32::UI::HiDpi::{
+        AdjustWindowRectExForDpi, GetDpiForSystem, GetDpiForWindow, SystemParametersInfoForDpi,
+    },
+    Win32::UI::Input::KeyboardAndMouse::{GetKeyState, SetFocus, VK_CAPITAL},
+    Win32::UI::WindowsAndMessaging::{
+        CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetDlgItem, GetMessageW,
+        GetSystemMetrics, GetWindowRect, GetWindowTextLengthW, GetWindowTextW, IsDialogMessageW,
+        KillTimer, LoadCursorW, RegisterClassExW, SendMessageW, SetForegroundWindow, SetTimer,
+        SetWindowTextW, ShowWindow, TranslateMessage, BM_GETCHECK, BM_SETCHECK, BS_AUTOCHECKBOX,
+        BS_DEFPUSHBUTTON, BS_PUSHBUTTON, ES_AUTOHSCROLL, ES_PASSWORD, HMENU, IDCANCEL, IDC_ARROW,
+        IDOK, MSG, NONCLIENTMETRICSW, SM_CXSCREEN, SM_CYSCREEN, SPI_GETNONCLIENTMETRICS, SW_HIDE,
+        SW_SHOW, WINDOW_EX_STYLE, WINDOW_STYLE, WM_CLOSE, WM_COMMAND, WM_SETFONT, WM_TIMER,
+        WNDCLASSEXW, WS_CAPTION, WS_CHILD, WS_EX_CLIENTEDGE, WS_EX_CONTROLPARENT,
+        WS_EX_DLGMODALFRAME, WS_POPUP, WS_SYSMENU, WS_TABSTOP, WS_VISIBLE,
+    },
+};
+
+use crate::ui::dialog::{caption, get_foreground_hwnd};
+use crate::{core::config, to_wide, PromptError};
+
+const CLASS_NAME: PCWSTR = w!("WslSshAskpassPassphrase");
+
+// Control ids; OK and Cancel use IDOK and IDCANCEL so Enter and Esc work
+const PASSPHRASE_ID: i32 = 600;
+const REVEAL_ID: i32 = 601;
+const CAPS_LOCK_ID: i32 = 602;
+const REMEMBER_ID: i32 = 603;
+
+/// winuser.h; not worth pulling in the SystemServices feature for
+const SS_NOPREFIX: u32 = 0x0080;
+
+/// How often the Caps Lock warning is brought up to date.
+const CAPS_LOCK_TIMER: usize = 1;
+const CAPS_LOCK_INTERVAL_MS: u32 = 200;
+
+/// Masking character while the passphrase is hidden (a black circle).
+const MASK: usize = 0x25CF;
+
+// Layout at 96 DPI
+const CLIENT_WIDTH: i32 = 400;
+const MARGIN: i32 = 12;
+const GAP: i32 = 8;
+const ROW_HEIGHT: i32 = 24;
+const REVEAL_WIDTH: i32 = 64;
+const BUTTON_WIDTH: i32 = 84;
+
+thread_local! {
+    /// How the open dialog was closed: `Some(true)` for OK.
+    static CLOSED: Cell<Option<bool>> = const { Cell::new(None) };
+}
+
+/// Ask for a passphrase. `save` pre-sets the "Remember" checkbox; `None`
+/// hides it. Fails only when the window cannot be created, so the caller
+/// can fall back to CredUI.
+pub(crate) fn prompt(
+    prompt: &str,
+    key_name: &str,
+    save: Option<bool>,
+) -> Result<(String, bool), PromptError> {
+    show(prompt, Some(key_name), save)
+}
+
+/// Ask for a one-time code: no key name, no checkbox, and no masking or
+/// Caps Lock warning, since the code is on the user's phone for anyone to
+/// read and is useless within a minute. Fails as [`prompt`] does.
+pub(crate) fn prompt_code(prompt: &str) -> Result<String, PromptError> {
+    show(prompt, None, None).map(|(code, _)| code)
+}
+
+/// The passphrase window, or the code window when there is no `key_name`.
+fn show(
+    prompt: &str,
+    key_name: Option<&str>,
+    save: Option<bool>,
+) -> Result<(String, bool), PromptError> {
+    let passphrase = key_name.is_some();
+    unsafe {
+        let instance = GetModuleHandleW(None).map_err(|e| PromptError::Failed(e.message()))?;
+        let class = WNDCLASSEXW {
+            cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+            lpfnWndProc: Some(window_proc),
+            hInstance: instance.into(),
+            hCursor: LoadCursorW(None, IDC_ARROW).unwrap_or_default(),
+            hbrBackground: HBRUSH((COLOR_BTNFACE.0 + 1) as usize as *mut _),
+            lpszClassName: CLASS_NAME,
+            ..Default::default()
+        };
+        // Already registered by an earlier prompt in this process is fine
+        RegisterClassExW(&class);
+
+        let parent = get_foreground_hwnd();
+        let dpi = if parent.is_invalid() {
+            GetDpiForSystem()
+        } else {
+            GetDpiForWindow(parent)
+        };
+        let scale = |value: i32| value * dpi as i32 / 96;
+        let font = message_font(dpi);
+
+        // Lay the controls out top to bottom, measuring the prompt text
+        let width = scale(CLIENT_WIDTH);
+        let margin = scale(MARGIN);
+        let gap = scale(GAP);
+        let row = scale(ROW_HEIGHT);
+        let inner = width - 2 * margin;
+        let line = text_height(font, "Ag", inner);
+        let prompt_height = text_height(font, prompt, inner);
+        let mut y = margin;
+        let prompt_y = y;
+        y += prompt_height + gap;
+        let key_y = y;
+        if passphrase {
+            y += line + gap;
+        }
+        let edit_y = y;
+        y += row + gap / 2;
+        let caps_y = y;
+        if passphrase {
+            y += line + gap;
+        } else {
+            y += gap / 2;
+        }
+        let remember_y = y;
+        if save.is_some() {
+            y += row + gap;
+        }
+        let buttons_y = y;
+        let height = y + row + margin;
+
+        let style = WS_POPUP | WS_CAPTION | WS_SYSMENU;
+        let ex_style = WS_EX_DLGMODALFRAME | WS_EX_CONTROLPARENT;
+        let mut frame = RECT {
+            left: 0,
+            top: 0,
+            right: width,
+            bottom: height,
+        };
+        let _ = AdjustWindowRectExForDpi(&mut frame, style, false, ex_style, dpi);
+        let (outer_width, outer_height) = (frame.right - frame.left, frame.bottom - frame.top);
+        let (x, top) = centre(parent, outer_width, outer_height);
+
+        let captions = &config::get().captions;
+        let title = caption(if passphrase {
+            &captions.passphrase
+        } else {
+            &captions.two_factor
+        });
+        let hwnd = CreateWindowExW(
+            ex_style,
+            CLASS_NAME,
+            PCWSTR(title.as_ptr()),
+            style,
+            x,
+            top,
+            outer_width,
+            outer_height,
+            parent,
+            None,
+            instance,
+            None,
+        )
+        .map_err(|e| {
+            let _ = DeleteObject(font);
+            PromptError::Failed(e.message())
+        })?;
+
+        // `bounds` is left, top, width and height in client pixels
+        let control = |class: PCWSTR, text: &str, style: u32, ex_style, bounds, id: i32| {
+            let (left, top, width, height) = bounds;
+            let text = to_wide(text);
+            let child = CreateWindowExW(
+                ex_style,
+                class,
+                PCWSTR(text.as_ptr()),
+                WS_CHILD | WS_VISIBLE | WINDOW_STYLE(style),
+                left,
+                top,
+                width,
+                height,
+                hwnd,
+                HMENU(id as isize as *mut _),
+                instance,
+                None,
+            )
+            .unwrap_or_default();
+            SendMessageW(child, WM_SETFONT, WPARAM(font.0 as usize), LPARAM(1));
+            child
+        };
+        let none = WINDOW_EX_STYLE(0);
+        control(
+            w!("STATIC"),
+            prompt,
+            SS_NOPREFIX,
+            none,
+            (margin, prompt_y, inner, prompt_height),
+            -1,
+        );
+        let reveal_width = scale(REVEAL_WIDTH);
+        let edit = if let Some(key_name) = key_name {
+            control(
+                w!("STATIC"),
+                &format!("Key: {}", key_name),
+                SS_NOPREFIX,
+                none,
+                (margin, key_y, inner, line),
+                -1,
+            );
+            let edit = control(
+                w!("EDIT"),
+                "",
+                WS_TABSTOP.0 | (ES_PASSWORD | ES_AUTOHSCROLL) as u32,
+                WS_EX_CLIENTEDGE,
+                (margin, edit_y, inner - reveal_width - gap, row),
+                PASSPHRASE_ID,
+            );
+            SendMessageW(edit, EM_SETPASSWORDCHAR, WPARAM(MASK), LPARAM(0));
+            control(
+                w!("BUTTON"),
+                "Show",
+                WS_TABSTOP.0 | BS_AUTOCHECKBOX as u32,
+                none,
+                (width - margin - reveal_width, edit_y, reveal_width, row),
+                REVEAL_ID,
+            );
+            let caps = control(
+                w!("STATIC"),
+                "\u{26A0} Caps Lock is on",
+                SS_NOPREFIX,
+                none,
+                (margin, caps_y, inner, line),
+                CAPS_LOCK_ID,
+            );
+            let _ = ShowWindow(caps, if caps_lock_on() { SW_SHOW } else { SW_HIDE });
+            SetTimer(hwnd, CAPS_LOCK_TIMER, CAPS_LOCK_INTERVAL_MS, None);
+            edit
+        } else {
+            control(
+                w!("EDIT"),
+                "",
+                WS_TABSTOP.0 | ES_AUTOHSCROLL as u32,
+                WS_EX_CLIENTEDGE,
+                (margin, edit_y, inner, row),
+                PASSPHRASE_ID,
+            )
+        };
+        let remember = save.map(|checked| {
+            let checkbox = control(
+                w!("BUTTON"),
+                "Remember this passphrase",
+                WS_TABSTOP.0 | BS_AUTOCHECKBOX as u32,
+                none,
+                (margin, remember_y, inner, row),
+                REMEMBER_ID,
+            );
+            if checked {
+                SendMessageW(
+                    checkbox,
+                    BM_SETCHECK,
+                    WPARAM(BST_CHECKED.0 as usize),
+                    LPARAM(0),
+                );
+            }
+            checkbox
+        });
+        let button_width = scale(BUTTON_WIDTH);
+        control(
+            w!("BUTTON"),
+            "OK",
+            WS_TABSTOP.0 | BS_DEFPUSHBUTTON as u32,
+            none,
+            (
+                width - margin - 2 * button_width - gap,
+                buttons_y,
+                button_width,
+                row,
+            ),
+            IDOK.0,
+        );
+        control(
+            w!("BUTTON"),
+            "Cancel",
+            WS_TABSTOP.0 | BS_PUSHBUTTON as u32,
+            none,
+            (width - margin - button_width, buttons_y, button_width, row),
+            IDCANCEL.0,
+        );
+        // Try to bring our dialog to the foreground
+        if !parent.is_invalid() {
+            let _ = SetForegroundWindow(parent);
+        }
+        let _ = ShowWindow(hwnd, SW_SHOW);
+        let _ = SetForegroundWindow(hwnd);
+        let _ = SetFocus(edit);
+
+        CLOSED.set(None);
+        let mut msg = MSG::default();
+        while CLOSED.get().is_none() && GetMessageW(&mut msg, None, 0, 0).as_bool() {
+            if !IsDialogMessageW(hwnd, &msg).as_bool() {
+                let _ = TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+        }
+        let ok = CLOSED.take() == Some(true);
+
+        let mut text = vec![0u16; GetWindowTextLengthW(edit) as usize + 1];
+        let len = GetWindowTextW(edit, &mut text) as usize;
+        let pass = String::from_utf16_lossy(&text[..len]);
+        // Leave no copy of the passphrase behind in this process
+        text.fill(0);
+        let _ = SetWindowTextW(edit, w!(""));
+        let save_checked = remember.is_some_and(is_checked);
+
+        let _ = KillTimer(hwnd, CAPS_LOCK_TIMER);
+        let _ = DestroyWindow(hwnd);
+        let _ = DeleteObject(font);
+
+        if ok {
+            Ok((pass, save_checked))
+        } else {
+            Err(PromptError::Cancelled)
+        }
+    }
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_COMMAND => match (wparam.0 & 0xFFFF) as i32 {
+            id if id == IDOK.0 => CLOSED.set(Some(true)),
+            id if id == IDCANCEL.0 => CLOSED.set(Some(false)),
+            REVEAL_ID => {
+                if let (Ok(edit), Ok(reveal)) =
+                    (GetDlgItem(hwnd, PASSPHRASE_ID), GetDlgItem(hwnd, REVEAL_ID))
+                {
+                    let mask = if is_checked(reveal) { 0 } else { MASK };
+                    SendMessageW(edit, EM_SETPASSWORDCHAR, WPARAM(mask), LPARAM(0));
+                    let _ = InvalidateRect(edit, None, true);
+                    let _ = SetFocus(edit);
+                }
+            }
+            _ => {}
+        },
+        WM_TIMER => {
+            if let Ok(caps) = GetDlgItem(hwnd, CAPS_LOCK_ID) {
+                let _ = ShowWindow(caps, if caps_lock_on() { SW_SHOW } else { SW_HIDE });
+            }
+        }
+        // The title bar's close button is Cancel; the window is destroyed
+        // once the passphrase has been read back
+        WM_CLOSE => CLOSED.set(Some(false)),
+        _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+    LRESULT(0)
+}
+
+fn caps_lock_on() -> bool {
+    unsafe { GetKeyState(VK_CAPITAL.0 as i32) & 1 != 0 }
+}
+
+fn is_checked(button: HWND) -> bool {
+    unsafe { SendMessageW(button, BM_GETCHECK, WPARAM(0), LPARAM(0)).0 == BST_CHECKED.0 as isize }
+}
+
+/// The font Windows uses for message boxes, at `dpi`.
+pub(crate) fn message_font(dpi: u32) -> HFONT {
+    unsafe {
+        let mut metrics = NONCLIENTMETRICSW {
+            cbSize: std::mem::size_of::<NONCLIENTMETRICSW>() as u32,
+            ..Default::default()
+        };
+        let _ = SystemParametersInfoForDpi(
+            SPI_GETNONCLIENTMETRICS.0,
+            metrics.cbSize,
+            Some(&mut metrics as *mut _ as *mut _),
+            0,
+            dpi,
+        );
+        CreateFontIndirectW(&metrics.lfMessageFont)
+    }
+}
+
+/// Height of `text` word-wrapped to `width` in `font`.
+pub(crate) fn text_height(font: HFONT, text: &str, width: i32) -> i32 {
+    let mut wide: Vec<u16> = text.encode_utf16().collect();
+    let mut rect = RECT {
+        left: 0,
+        top: 0,
+        right: width,
+        bottom: 0,
+    };
+    unsafe {
+        let dc = GetDC(None);
+        let old = SelectObject(dc, font);
+        DrawTextW(
+            dc,
+            &mut wide,
+            &mut rect,
+            DT_CALCRECT | DT_WORDBREAK | DT_NOPREFIX,
+        );
+        SelectObject(dc, old);
+        ReleaseDC(None, dc);
+    }
+    rect.bottom
+}
+
+/// Top-left corner that centres a `width` x `height` window on `parent`, or
+/// on the primary screen without one.
+pub(crate) fn centre(parent: HWND, width: i32, height: i32) -> (i32, i32) {
+    let mut area = RECT::default();
+    unsafe {
+        if parent.is_invalid() || GetWindowRect(parent, &mut area).is_err() {
+            area = RECT {
+                left: 0,
+                top: 0,
+                right: GetSystemMetrics(SM_CXSCREEN),
+                bottom: GetSystemMetrics(SM_CYSCREEN),
+            };
+        }
+    }
+    (
+        area.left + (area.right - area.left - width) / 2,
+        (area.top + (area.bottom - area.top - height) / 2).max(0),
+    )
+}
@@ -0,0 +1,41 @@
+//! A Windows notification for a prompt nobody answered.
+//!
+//! A prompt given up after its timeout (see `watchdog::exit_after`) leaves
+//! no trace on the desktop; the toast tells the user one was missed, and
+//! stays in the notification center after the process has exited.
+
+use windows::{
+    core::{Result, HSTRING},
+    Data::Xml::Dom::XmlDocument,
+    Win32::System::Com::{CoInitializeEx, COINIT_MULTITHREADED},
+    UI::Notifications::{ToastNotification, ToastNotificationManager},
+};
+
+/// Shown as the sender. An unpackaged program names itself with any
+/// application ID; nothing has to be registered for the toast to appear.
+const APP_ID: &str = "wsl-ssh-askpass";
+
+/// Raise a toast with a `title` line and `text` below it.
+pub(crate) fn show(title: &str, text: &str) -> Result<()> {
+    unsafe {
+        let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+    }
+    let xml = format!(
+        "<toast><visual><binding template=\"ToastGeneric\">\
+         <text>{}</text><text>{}</text>\
+         </binding></visual></toast>",
+        escape(title),
+        escape(text)
+    );
+    let document = XmlDocument::new()?;
+    document.LoadXml(&HSTRING::from(xml))?;
+    let toast = ToastNotification::CreateToastNotification(&document)?;
+    ToastNotificationManager::CreateToastNotifierWithId(&HSTRING::from(APP_ID))?.Show(&toast)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,136 @@
+//! Give up on a prompt whose caller has gone away. If ssh is killed (Ctrl-C)
+//! while a dialog is open, nobody will read the answer; leaving the dialog
+//! on the desktop would only confuse, so the process exits as cancelled.
+//! A prompt nobody answers within its timeout is given up the same way.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use windows::{
+    Win32::Foundation::{CloseHandle, WAIT_OBJECT_0},
+    Win32::Storage::FileSystem::{GetFileType, WriteFile, FILE_TYPE_PIPE},
+    Win32::System::Console::{GetStdHandle, STD_OUTPUT_HANDLE},
+    Win32::System::Threading::{OpenProcess, WaitForSingleObject, PROCESS_SYNCHRONIZE},
+};
+
+use crate::{core::caller, core::config, ui::toast};
+
+const POLL_INTERVAL_MS: u32 = 500;
+
+/// Raised once the askpass binary's own caller has exited.
+static CALLER_GONE: AtomicBool = AtomicBool::new(false);
+
+/// The flag of the client a server is answering now, raised when that
+/// client exits. Each watch has its own, so one ended late cannot mark the
+/// next client as gone.
+static CLIENT_GONE: Mutex<Option<Arc<AtomicBool>>> = Mutex::new(None);
+
+/// Whether the prompt's caller has gone away, so nobody will read the
+/// answer. Long waits (Windows Hello, network calls) give up when it is.
+pub(crate) fn caller_gone() -> bool {
+    CALLER_GONE.load(Ordering::Relaxed)
+        || CLIENT_GONE
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|gone| gone.load(Ordering::Relaxed))
+}
+
+/// Watch, on a background thread, for the parent process exiting or our
+/// stdout pipe breaking, and exit with the cancel status (1) when either
+/// happens. Meant for the askpass binary; library callers keep control of
+/// their own process.
+pub fn exit_when_orphaned() {
+    let parent = caller::ancestry().first().map(|(pid, _)| *pid);
+    thread::spawn(move || unsafe {
+        let parent = parent.and_then(|pid| OpenProcess(PROCESS_SYNCHRONIZE, false, pid).ok());
+        let stdout = GetStdHandle(STD_OUTPUT_HANDLE)
+            .ok()
+            .filter(|h| !h.is_invalid() && GetFileType(*h) == FILE_TYPE_PIPE);
+        if parent.is_none() && stdout.is_none() {
+            return;
+        }
+        loop {
+            let parent_exited = match parent {
+                Some(handle) => WaitForSingleObject(handle, POLL_INTERVAL_MS) == WAIT_OBJECT_0,
+                None => {
+                    thread::sleep(std::time::Duration::from_millis(POLL_INTERVAL_MS.into()));
+                    false
+                }
+            };
+            // A zero-byte write fails once the reading end has been closed
+            let stdout_broken =
+                stdout.is_some_and(|h| WriteFile(h, Some(&[]), None, None).is_err());
+            if parent_exited || stdout_broken {
+                CALLER_GONE.store(true, Ordering::Relaxed);
+                if let Some(handle) = parent {
+                    let _ = CloseHandle(handle);
+                }
+                std::process::exit(1);
+            }
+        }
+    });
+}
+
+/// Give up on the prompt after `timeout`, on a background thread: say so on
+/// stderr, raise a notification naming `prompt` (unless the config file
+/// turns that off) and exit with `code`. Like [`exit_when_orphaned`], meant
+/// for the askpass binary.
+pub fn exit_after(timeout: Duration, prompt: &str, code: i32) {
+    let prompt = prompt.lines().next().unwrap_or_default().trim().to_string();
+    thread::spawn(move || {
+        thread::sleep(timeout);
+        eprintln!(
+            "wsl-ssh-askpass: no answer within {} seconds",
+            timeout.as_secs()
+        );
+        if config::get().timeout_toast() {
+            let _ = toast::show("Missed SSH prompt", &prompt);
+        }
+        std::process::exit(code);
+    });
+}
+
+/// Watches one client of a long-running server; see [`watch_client`].
+pub(crate) struct ClientWatch {
+    stop: Arc<AtomicBool>,
+    gone: Arc<AtomicBool>,
+}
+
+impl Drop for ClientWatch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let mut current = CLIENT_GONE.lock().unwrap();
+        if current
+            .as_ref()
+            .is_some_and(|gone| Arc::ptr_eq(gone, &self.gone))
+        {
+            *current = None;
+        }
+    }
+}
+
+/// Raise [`caller_gone`] if process `pid` exits before the returned guard
+/// is dropped. For servers, whose own parent says nothing about the client
+/// they are answering.
+pub(crate) fn watch_client(pid: u32) -> ClientWatch {
+    let stop = Arc::new(AtomicBool::new(false));
+    let gone = Arc::new(AtomicBool::new(false));
+    *CLIENT_GONE.lock().unwrap() = Some(Arc::clone(&gone));
+    let (watching, exited) = (Arc::clone(&stop), Arc::clone(&gone));
+    thread::spawn(move || unsafe {
+        let Ok(handle) = OpenProcess(PROCESS_SYNCHRONIZE, false, pid) else {
+            return;
+        };
+        while !watching.load(Ordering::Relaxed) {
+            if WaitForSingleObject(handle, POLL_INTERVAL_MS) == WAIT_OBJECT_0 {
+                exited.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+        let _ = CloseHandle(handle);
+    });
+    ClientWatch { stop, gone }
+}
@@ -0,0 +1,385 @@
+//! WSL-side SSH askpass for the `wsl-ssh-askpass` daemon.
+//!
+//! As `SSH_ASKPASS`, it sends the prompt as one length-prefixed JSON-RPC
+//! request (a little-endian `u32` byte count, then the JSON) and prints the
+//! secret from the response. The request goes to the bridge socket when
+//! one is being served, else through `wsl-ssh-askpass.exe relay` over
+//! interop, which hands it to the daemon's named pipe.
+//!
+//! `serve` listens on the Unix socket for processes that cannot run Windows
+//! executables themselves, such as containers with the socket mounted, and
+//! relays each connection the same way.
+//!
+//!   WSL_SSH_ASKPASS_SOCKET  socket path (default $XDG_RUNTIME_DIR/wsl-ssh-askpass.sock,
+//!                           else $HOME/wsl-ssh-askpass.sock)
+//!   WSL_SSH_ASKPASS_COMPAT  output and exit status conventions, or the program's name
+//!   WSL_SSH_ASKPASS_EXE     Windows executable (default wsl-ssh-askpass.exe on PATH)
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::raw::{c_int, c_short, c_uint, c_ulong};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process::{self, Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde_json::{json, Map, Value};
+
+const SOCKET_ENV: &str = "WSL_SSH_ASKPASS_SOCKET";
+const EXE_ENV: &str = "WSL_SSH_ASKPASS_EXE";
+const COMPAT_ENV: &str = "WSL_SSH_ASKPASS_COMPAT";
+/// Program names that select a compatibility mode, as on the Windows side.
+const COMPAT_NAMES: &[&str] = &["x11-ssh-askpass", "ksshaskpass"];
+const DEFAULT_EXE: &str = "wsl-ssh-askpass.exe";
+
+/// JSON-RPC error code for a cancelled prompt, as the Windows side sends it.
+const CANCELLED: i64 = 1;
+
+/// Largest message accepted, matching the Windows side.
+const MAX_FRAME: usize = 1024 * 1024;
+
+/// Prefix of the settings sent with each prompt, besides `SHARED_ENV`.
+const FORWARDED_PREFIX: &str = "WSL_SSH_ASKPASS_";
+/// Variables that change how a prompt is shown or cached. Sent directly, so
+/// nothing needs to be shared through WSLENV.
+const SHARED_ENV: &[&str] = &["WSL_DISTRO_NAME", "HOME"];
+/// Who is calling, which the Windows side refuses from a client, so not
+/// worth sending.
+const CALLER_ENV: &str = "WSL_SSH_ASKPASS_CALLER";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("serve") => serve(),
+        prompt => ask(prompt.unwrap_or("Enter SSH passphrase:")),
+    };
+    if let Err(e) = result {
+        eprintln!("wsl-ssh-askpass-bridge: {}", e);
+        process::exit(2);
+    }
+}
+
+/// The socket in a directory of this user's own. There is no shared
+/// fallback such as `/tmp`, where anyone could stand in for the bridge.
+fn socket_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(SOCKET_ENV) {
+        return Some(path.into());
+    }
+    let dir = env::var_os("XDG_RUNTIME_DIR").or_else(|| env::var_os("HOME"))?;
+    Some(PathBuf::from(dir).join("wsl-ssh-askpass.sock"))
+}
+
+/// Ask for `prompt` and print the answer as the Windows side shaped it for
+/// the compatibility mode and `newline` setting, exiting with the status it
+/// sent when there is none.
+fn ask(prompt: &str) -> io::Result<()> {
+    let kind = env::var("SSH_ASKPASS_PROMPT").ok();
+    let mut env = forwarded_vars();
+    if let Some(name) = compat_name().filter(|_| !env.contains_key(COMPAT_ENV)) {
+        env.insert(COMPAT_ENV.into(), name.into());
+    }
+    let request = request(prompt, kind.as_deref(), env);
+    let stream = socket_path().and_then(|path| UnixStream::connect(path).ok());
+    let response = match stream {
+        Some(mut stream) => {
+            write_frame(&mut stream, &request)?;
+            read_frame(&mut stream)?
+        }
+        None => relay(&request, &Mutex::new(None))?,
+    };
+    match parse_response(&response)? {
+        Answer::Output(output) => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(output.as_bytes())?;
+            stdout.flush()
+        }
+        Answer::Exit(code, message) => {
+            if let Some(message) = message {
+                eprintln!("wsl-ssh-askpass-bridge: {}", message);
+            }
+            process::exit(code)
+        }
+    }
+}
+
+/// The compatibility mode this program was installed as, by its name.
+fn compat_name() -> Option<&'static str> {
+    let exe = env::current_exe().ok()?;
+    let stem = exe.file_stem()?.to_str()?;
+    COMPAT_NAMES
+        .iter()
+        .copied()
+        .find(|name| name.eq_ignore_ascii_case(stem))
+}
+
+/// The `prompt` request, with the settings that shape how it is answered.
+fn request(prompt: &str, kind: Option<&str>, env: Map<String, Value>) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "prompt",
+        "params": { "prompt": prompt, "kind": kind, "env": env },
+    })
+    .to_string()
+}
+
+fn forwarded_vars() -> Map<String, Value> {
+    env::vars_os()
+        .filter_map(|(name, value)| {
+            let name = name.into_string().ok().filter(|name| is_forwarded(name))?;
+            Some((name, Value::String(value.into_string().ok()?)))
+        })
+        .collect()
+}
+
+fn is_forwarded(name: &str) -> bool {
+    // The socket and executable only matter on this side
+    let local = name == SOCKET_ENV || name == EXE_ENV || name == CALLER_ENV;
+    (name.starts_with(FORWARDED_PREFIX) || SHARED_ENV.contains(&name)) && !local
+}
+
+/// How a prompt ended.
+#[derive(Debug, PartialEq, Eq)]
+enum Answer {
+    /// What to print for ssh
+    Output(String),
+    /// The status to exit with, and why when it was not cancelled
+    Exit(i32, Option<String>),
+}
+
+/// The answer in a response. A Windows side too old to shape the output
+/// only sends the secret and leaves the exit status to us.
+fn parse_response(response: &str) -> io::Result<Answer> {
+    let response: Value = serde_json::from_str(response)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if let Some(result) = response.get("result") {
+        let output = result.get("output").or_else(|| result.get("secret"));
+        if let Some(output) = output.and_then(Value::as_str) {
+            return Ok(Answer::Output(output.to_string()));
+        }
+    }
+    let Some(error) = response.get("error") else {
+        return Err(io::Error::other("malformed response"));
+    };
+    let exit_code = error
+        .pointer("/data/exitCode")
+        .and_then(Value::as_i64)
+        .and_then(|code| i32::try_from(code).ok());
+    if error.get("code").and_then(Value::as_i64) == Some(CANCELLED) {
+        return Ok(Answer::Exit(exit_code.unwrap_or(1), None));
+    }
+    let message = error
+        .get("message")
+        .and_then(Value::as_str)
+        .unwrap_or("malformed response");
+    Ok(Answer::Exit(
+        exit_code.unwrap_or(2),
+        Some(message.to_string()),
+    ))
+}
+
+/// Pass `request` through `wsl-ssh-askpass.exe relay`. The child is
+/// published in `running` so a caller that goes away can stop it.
+fn relay(request: &str, running: &Mutex<Option<Child>>) -> io::Result<String> {
+    let exe = env::var_os(EXE_ENV).unwrap_or_else(|| DEFAULT_EXE.into());
+    let mut child = Command::new(&exe)
+        .arg("relay")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot run {:?}: {}", exe, e)))?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    let mut stdout = child.stdout.take().expect("piped stdout");
+    *running.lock().unwrap() = Some(child);
+    write_frame(&mut stdin, request)?;
+    drop(stdin);
+    let response = read_frame(&mut stdout);
+    let finished = running.lock().unwrap().take();
+    if let Some(mut child) = finished {
+        let _ = child.wait();
+    }
+    response
+}
+
+/// Listen on the socket, relaying every connection on its own thread.
+fn serve() -> io::Result<()> {
+    let path = socket_path().ok_or_else(|| {
+        io::Error::other(format!(
+            "no XDG_RUNTIME_DIR or HOME for the socket; set {}",
+            SOCKET_ENV
+        ))
+    })?;
+    // A socket left behind by an earlier run refuses connections
+    if UnixStream::connect(&path).is_err() {
+        let _ = fs::remove_file(&path);
+    }
+    // Only this user may ask for secrets: the socket is created 0600, not
+    // narrowed after bind while another user could already connect
+    let previous = unsafe { umask(0o177) };
+    let listener = UnixListener::bind(&path);
+    unsafe { umask(previous) };
+    let listener =
+        listener.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    eprintln!("wsl-ssh-askpass-bridge: listening on {}", path.display());
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(stream) {
+                eprintln!("wsl-ssh-askpass-bridge: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(mut stream: UnixStream) -> io::Result<()> {
+    let request = read_frame(&mut stream)?;
+    let running = Arc::new(Mutex::new(None::<Child>));
+    // A client that hangs up (its ssh was interrupted) takes the prompt down.
+    // One that only shuts down its writing side still waits for the answer.
+    let watch = stream.try_clone()?;
+    let watched = Arc::clone(&running);
+    thread::spawn(move || {
+        if !matches!(hung_up(&watch, -1), Ok(false)) {
+            if let Some(child) = watched.lock().unwrap().as_mut() {
+                let _ = child.kill();
+            }
+        }
+    });
+    let response = relay(&request, &running)?;
+    write_frame(&mut stream, &response)
+}
+
+/// Whether the peer of `stream` has closed its end entirely, waiting up to
+/// `timeout_ms` (-1 for as long as it takes) for that to happen.
+fn hung_up(stream: &UnixStream, timeout_ms: c_int) -> io::Result<bool> {
+    // No events asked for: POLLHUP and POLLERR are reported regardless,
+    // and a half-close, which only makes the socket readable, is not one
+    let mut fd = PollFd {
+        fd: stream.as_raw_fd(),
+        events: 0,
+        revents: 0,
+    };
+    loop {
+        match unsafe { poll(&mut fd, 1, timeout_ms) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error()),
+            0 => return Ok(false),
+            _ => return Ok(fd.revents & (POLLHUP | POLLERR | POLLNVAL) != 0),
+        }
+    }
+}
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+const POLLERR: c_short = 0x08;
+const POLLHUP: c_short = 0x10;
+const POLLNVAL: c_short = 0x20;
+
+extern "C" {
+    fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+    fn umask(mask: c_uint) -> c_uint;
+}
+
+fn read_frame(input: &mut impl Read) -> io::Result<String> {
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is too large", len),
+        ));
+    }
+    let mut body = vec![0u8; len];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_frame(output: &mut impl Write, message: &str) -> io::Result<()> {
+    output.write_all(&(message.len() as u32).to_le_bytes())?;
+    output.write_all(message.as_bytes())?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_carry_kind_and_env() {
+        let mut env = Map::new();
+        env.insert("WSL_DISTRO_NAME".into(), "Ubuntu".into());
+        let request: Value =
+            serde_json::from_str(&request("Allow use of key?", Some("confirm"), env)).unwrap();
+        assert_eq!(request["method"], "prompt");
+        assert_eq!(request["params"]["prompt"], "Allow use of key?");
+        assert_eq!(request["params"]["kind"], "confirm");
+        assert_eq!(request["params"]["env"]["WSL_DISTRO_NAME"], "Ubuntu");
+    }
+
+    #[test]
+    fn forwards_settings_but_not_the_caller() {
+        assert!(is_forwarded("WSL_SSH_ASKPASS_PASSWORD_TTL"));
+        assert!(is_forwarded("HOME"));
+        assert!(!is_forwarded(SOCKET_ENV));
+        assert!(!is_forwarded(CALLER_ENV));
+        assert!(!is_forwarded("PATH"));
+    }
+
+    #[test]
+    fn a_half_close_is_not_a_hang_up() {
+        let (client, server) = UnixStream::pair().unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        assert!(!hung_up(&server, 0).unwrap());
+        drop(client);
+        assert!(hung_up(&server, 1000).unwrap());
+    }
+
+    #[test]
+    fn responses() {
+        assert_eq!(
+            parse_response(r#"{"jsonrpc":"2.0","id":1,"result":{"secret":"s3crét"}}"#).unwrap(),
+            Answer::Output("s3crét".to_string())
+        );
+        assert_eq!(
+            parse_response(r#"{"id":1,"result":{"secret":"s3crét","output":"s3crét\n"}}"#).unwrap(),
+            Answer::Output("s3crét\n".to_string())
+        );
+        assert_eq!(
+            parse_response(r#"{"id":1,"error":{"code":1,"message":"cancelled"}}"#).unwrap(),
+            Answer::Exit(1, None)
+        );
+        assert_eq!(
+            parse_response(r#"{"id":1,"error":{"code":2,"message":"no display"}}"#).unwrap(),
+            Answer::Exit(2, Some("no display".to_string()))
+        );
+        assert_eq!(
+            parse_response(
+                r#"{"id":1,"error":{"code":2,"message":"no display","data":{"exitCode":3}}}"#
+            )
+            .unwrap(),
+            Answer::Exit(3, Some("no display".to_string()))
+        );
+        assert!(parse_response(r#"{"id":1}"#).is_err());
+        assert!(parse_response("not json").is_err());
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, "{}").unwrap();
+        assert_eq!(buffer, [2, 0, 0, 0, b'{', b'}']);
+        assert_eq!(read_frame(&mut buffer.as_slice()).unwrap(), "{}");
+        let mut huge: &[u8] = &[0xff, 0xff, 0xff, 0xff];
+        assert!(read_frame(&mut huge).is_err());
+    }
+}